@@ -0,0 +1,180 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const FILE_SIZE: usize = 4 * 1024 * 1024;
+
+/// xorshift noise, doesn't compress
+fn noise(len: usize) -> Vec<u8> {
+    let mut x = 0x2545_F491_4F6C_DD1Du64;
+    let mut buf = Vec::with_capacity(len + 8);
+    while buf.len() < len {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+    buf.truncate(len);
+    buf
+}
+
+/// Repetitive text, compresses well
+fn text(len: usize) -> Vec<u8> {
+    let line = b"local value = GetValue(\"setting\", 42) -- some game script\n";
+    line.iter().copied().cycle().take(len).collect()
+}
+
+/// An archive with a single file, `.bin` files are compressed, `.dat` files are stored
+fn archive(options: &hpk::CreateOptions, name: &str, content: &[u8]) -> Vec<u8> {
+    let mut w = hpk::HpkWriter::new(options, Cursor::new(vec![])).unwrap();
+    w.add_file(name, content).unwrap();
+    w.finish().unwrap().into_inner()
+}
+
+/// Walks the archive and returns the walk with the entry of its single file
+fn single_file(data: &[u8]) -> (hpk::HpkIter, hpk::DirEntry) {
+    let mut walk = hpk::walk_reader(Cursor::new(data.to_vec())).unwrap();
+    let entry = walk
+        .by_ref()
+        .map(Result::unwrap)
+        .find(|e| !e.is_dir())
+        .unwrap();
+    (walk, entry)
+}
+
+fn fragmented_reader(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragmented reader");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    for &fragments in &[1, 8] {
+        let mut options = hpk::CreateOptions::new();
+        options.with_fragments_per_file(fragments);
+        let data = archive(&options, "file.dat", &noise(FILE_SIZE));
+        let (walk, entry) = single_file(&data);
+
+        group.bench_function(format!("sequential, {} fragments", fragments), |b| {
+            b.iter(|| {
+                walk.read_file(&entry, |mut r| {
+                    io::copy(&mut r, &mut io::sink())?;
+                    Ok(())
+                })
+                .unwrap()
+            })
+        });
+        group.bench_function(format!("seek heavy, {} fragments", fragments), |b| {
+            let mut buf = [0; 4096];
+            b.iter(|| {
+                walk.read_file(&entry, |mut r| {
+                    // 1024 reads of 4 KiB at scattered positions
+                    let mut pos = 0u64;
+                    for _ in 0..1024 {
+                        pos = (pos + 1_234_567) % (FILE_SIZE - buf.len()) as u64;
+                        r.seek(SeekFrom::Start(pos))?;
+                        r.read_exact(&mut buf)?;
+                    }
+                    Ok(())
+                })
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn inflate(c: &mut Criterion) {
+    let mut codecs = vec![hpk::Compression::Zlib, hpk::Compression::Lz4];
+    if cfg!(feature = "zstd") {
+        codecs.push(hpk::Compression::Zstd);
+    }
+
+    let mut group = c.benchmark_group("inflate");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(20);
+    for codec in codecs {
+        let mut options = hpk::CreateOptions::new();
+        options.with_compressor(codec);
+        let data = archive(&options, "file.bin", &text(FILE_SIZE));
+        let (walk, entry) = single_file(&data);
+
+        for &threads in &[1, 4] {
+            group.bench_function(format!("{}, {} threads", codec, threads), |b| {
+                b.iter(|| {
+                    walk.read_file(&entry, |r| {
+                        let length = r.len();
+                        let mut r = hpk::ChunkedDecoder::new(r, length)?.with_threads(threads);
+                        io::copy(&mut r, &mut io::sink())?;
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// 10 directories with 20 files of 64 KiB each, half of them compressible text
+fn create_tree(dir: &Path) {
+    for d in 0..10 {
+        let sub = dir.join(format!("directory_{}", d));
+        fs::create_dir_all(&sub).unwrap();
+        for f in 0..20 {
+            let (name, content) = if f % 2 == 0 {
+                (format!("file_{}.lst", f), text(64 * 1024))
+            } else {
+                (format!("file_{}.dat", f), noise(64 * 1024))
+            };
+            fs::write(sub.join(name), content).unwrap();
+        }
+    }
+}
+
+fn pack_and_extract(c: &mut Criterion) {
+    let root = tempfile::Builder::new()
+        .prefix("hpk-bench")
+        .tempdir()
+        .unwrap();
+    let dir = root.path().join("tree");
+    create_tree(&dir);
+    let file = root.path().join("tree.hpk");
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    let mut group = c.benchmark_group("pack and extract");
+    group.throughput(Throughput::Bytes(200 * 64 * 1024));
+    group.sample_size(10);
+    for &threads in &[1, 4] {
+        group.bench_function(format!("pack, {} threads", threads), |b| {
+            let mut options = hpk::CreateOptions::new();
+            options.with_threads(threads);
+            b.iter(|| {
+                let mut out = Cursor::new(vec![]);
+                hpk::write_hpk(&options, &dir, &mut out).unwrap();
+                out
+            })
+        });
+        group.bench_function(format!("extract, {} threads", threads), |b| {
+            let mut options = hpk::ExtractOptions::new();
+            options.with_threads(threads);
+            b.iter_batched(
+                || {
+                    tempfile::Builder::new()
+                        .prefix("hpk-bench")
+                        .tempdir()
+                        .unwrap()
+                },
+                |dest| {
+                    hpk::extract(&options, file.as_path(), dest.path()).unwrap();
+                    dest
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fragmented_reader, inflate, pack_and_extract);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+/// An archive with 100 directories of 30 subdirectories holding 10 files each
+fn archive() -> Vec<u8> {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, Cursor::new(vec![])).unwrap();
+    for a in 0..100 {
+        for b in 0..30 {
+            for c in 0..10 {
+                let path = format!("directory_{}/subdirectory_{}/file_{}.txt", a, b, c);
+                w.add_file(path, &b""[..]).unwrap();
+            }
+        }
+    }
+    w.finish().unwrap().into_inner()
+}
+
+fn walk(c: &mut Criterion) {
+    let data = archive();
+    let mut group = c.benchmark_group("walk");
+    group.sample_size(20);
+    group.bench_function("full paths", |b| {
+        b.iter_batched(
+            || hpk::walk_reader(Cursor::new(data.clone())).unwrap(),
+            |walk| walk.map(Result::unwrap).count(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("names only", |b| {
+        b.iter_batched(
+            || {
+                let walk = hpk::walk_reader(Cursor::new(data.clone())).unwrap();
+                walk.names_only()
+            },
+            |walk| walk.map(Result::unwrap).count(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, walk);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+//! `no_std` definitions of the HPK archive format
+//!
+//! The header, the fragments, the compression header and the directory entries are
+//! parsed from byte slices and only need `alloc`. The module is shared with the `hpk`
+//! crate where it's available as `hpk::format`.
+//!
+#![no_std]
+
+extern crate alloc;
+
+#[path = "../../src/hpk/format.rs"]
+mod format;
+
+pub use crate::format::*;
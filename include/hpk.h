@@ -0,0 +1,30 @@
+/* C interface of the hpk library, built with the `ffi` feature */
+#ifndef HPK_H
+#define HPK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct HpkArchive HpkArchive;
+
+const char *hpk_last_error(void);
+
+HpkArchive *hpk_open(const char *path);
+void hpk_close(HpkArchive *archive);
+
+size_t hpk_entry_count(const HpkArchive *archive);
+int64_t hpk_entry_path(const HpkArchive *archive, size_t index, char *buf, size_t len);
+int hpk_entry_is_dir(const HpkArchive *archive, size_t index);
+int64_t hpk_read_entry(const HpkArchive *archive, const char *path, uint8_t *buf, size_t len);
+
+int hpk_create_from_dir(const char *dir, const char *file);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
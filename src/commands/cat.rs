@@ -0,0 +1,51 @@
+use std::fs;
+use std::io::{self, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        match fs::metadata(value) {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    SubCommand::with_name("cat")
+        .about("Write the content of archive members to stdout")
+        .display_order(15)
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(Arg::from_usage(
+            "<paths>... 'Archive members, separated by spaces'",
+        ))
+        .arg(Arg::from_usage(
+            "-i, --ignore-case 'Match the paths case-insensitively like the games on Windows'",
+        ))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let input = value_t!(matches, "file", String)?;
+    let paths = values_t!(matches, "paths", String)?;
+    let mut archive = hpk::HpkArchive::open(input)?;
+    if matches.is_present("ignore-case") {
+        archive.set_path_lookup(hpk::PathLookup::CaseInsensitive);
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for path in &paths {
+        let result = archive
+            .copy_file(path, &mut out)
+            .and_then(|_| out.flush().map_err(hpk::HpkError::Io));
+        match result {
+            // the reading end of a pipe was closed, e.g. by `head`
+            Err(hpk::HpkError::Io(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            result => result?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    SubCommand::with_name("convert")
+        .about("Convert a hpk archive into a zip or tar archive and back")
+        .display_order(90)
+        .arg(Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'").display_order(10))
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11),
+        )
+        .arg(
+            Arg::from_usage("[cripple_lua] --cripple-lua-files")
+                .help("Cripple bytecode header for Victor Vran or Surviving Mars"),
+        )
+        .arg(Arg::from_usage(
+            "[filedates] --with-filedates 'Stores the modification times in a _filedates file'",
+        ))
+        .arg(
+            Arg::from_usage("<src> 'input archive (.hpk, .zip or .tar)'").validator(validate_input),
+        )
+        .arg(Arg::from_usage(
+            "<dest> 'output archive (.hpk, .zip or .tar)'",
+        ))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let src = value_t!(matches, "src", String)?;
+    let dest = value_t!(matches, "dest", String)?;
+
+    let mut options = hpk::CreateOptions::new();
+    if matches.is_present("lz4") {
+        options.use_lz4();
+    }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
+    if matches.is_present("cripple_lua") {
+        options.cripple_lua_files();
+    }
+    if matches.is_present("filedates") {
+        options.with_default_filedates_format();
+    }
+
+    hpk::convert(&options, src, dest)?;
+    Ok(())
+}
@@ -13,6 +13,25 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum SymlinkPolicy {
+        follow,
+        skip,
+        error
+    }
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum SortOrder {
+        bytes,
+        nocase
+    }
+}
+
 const FILETIME_FMT_HELP: &str = "Specifies the format of the stored filedates.
 
 default: 'Windows file time' used by Tropico 3 and Grand Ages: Rome
@@ -21,6 +40,12 @@ short: 'Windows file time / 2000' used by Tropico 4 and Omerta";
 const EXTENSIONS_HELP: &str = "Specifies the file extensions to be compressed. \
                                default: [lst,lua,xml,tga,dds,xtex,bin,csv]";
 
+const LEVEL_HELP: &str = "Sets the compression level of the encoder.
+
+ZLIB: 0-9 (default: 9)
+ZSTD: 1-22 (default: 3)
+LZ4: not supported";
+
 pub fn clap<'a, 'b>() -> App<'a, 'b> {
     #[allow(clippy::needless_pass_by_value)]
     fn validate_chunk_size(value: String) -> Result<(), String> {
@@ -30,6 +55,41 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         }
     }
     #[allow(clippy::needless_pass_by_value)]
+    fn validate_level(value: String) -> Result<(), String> {
+        match value.parse::<u32>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(String::from("Invalid value for compression level")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_data_offset(value: String) -> Result<(), String> {
+        match value.parse::<u32>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(String::from("Invalid value for data offset")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_alignment(value: String) -> Result<(), String> {
+        match value.parse::<u64>() {
+            Ok(n) if n.is_power_of_two() => Ok(()),
+            _ => Err(String::from("Invalid alignment, must be a power of two")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_fragments(value: String) -> Result<(), String> {
+        match value.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("Invalid number of fragments")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_threads(value: String) -> Result<(), String> {
+        match value.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("Invalid number of threads")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
     fn validate_dir(value: String) -> Result<(), String> {
         if let Ok(md) = fs::metadata(value) {
             if md.is_dir() {
@@ -39,7 +99,7 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         Err(String::from("Not a valid directory"))
     }
 
-    SubCommand::with_name("create")
+    let app = SubCommand::with_name("create")
         .about("Create a new hpk archive")
         .display_order(0)
         .arg(
@@ -50,9 +110,20 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
             Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'")
                 .display_order(10)
         )
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11)
+        )
         .arg(Arg::from_usage("[chunk_size] --chunk-size <SIZE> 'Default chunk size: 32768'")
                 .next_line_help(true)
                 .validator(validate_chunk_size))
+        .arg(Arg::from_usage("[level] --level <LEVEL> 'Compression level of the encoder'")
+                .long_help(LEVEL_HELP)
+                .next_line_help(true)
+                .validator(validate_level))
+        .arg(Arg::from_usage("[threads] -j, --threads <N> 'Number of threads used for compressing'")
+                .validator(validate_threads))
         .arg(Arg::from_usage("[cripple_lua] --cripple-lua-files")
                 .help("Cripple bytecode header for Victor Vran or Surviving Mars")
         )
@@ -67,12 +138,82 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
                 .next_line_help(true)
                 .long_help(FILETIME_FMT_HELP),
         )
+        .arg(Arg::from_usage(
+            "[wide_offsets] --wide-offsets 'Stores 64-bit offsets for archives larger than 4 GiB'",
+        ))
+        .arg(Arg::from_usage(
+            "[big_endian] --big-endian 'Stores the header and the directory tree in big-endian byte order'",
+        ))
+        .arg(
+            Arg::from_usage(
+                "[data_offset] --data-offset <OFFSET> 'Starts the file contents at OFFSET for extended headers'",
+            )
+            .validator(validate_data_offset),
+        )
+        .arg(
+            Arg::from_usage(
+                "[align] --align <BYTES> 'Aligns the content of uncompressed files to BYTES, e.g. 4096'",
+            )
+            .validator(validate_alignment),
+        )
+        .arg(
+            Arg::from_usage(
+                "[fragments] --fragments-per-file <N> 'Splits every file into N fragments (default: 1)'",
+            )
+            .validator(validate_fragments),
+        )
+        .arg(Arg::from_usage(
+            "[deterministic] --deterministic 'Stores fixed filedates to create reproducible archives'",
+        ))
+        .arg(Arg::from_usage(
+            "[checksums] --with-checksums 'Stores CRC32 checksums of the file contents which are verified during extraction'",
+        ))
+        .arg(Arg::from_usage(
+            "[dedup] --dedup 'Stores the content of identical files only once'",
+        ))
+        .arg(
+            Arg::from_usage("[xor_key] --xor-key <HEX> 'XOR key of a scrambled archive'")
+                .validator(|v| super::parse_xor_key(&v).map(|_| ())),
+        )
+        .arg(Arg::from_usage(
+            "[dry_run] -n, --dry-run 'List the files which would be packed without writing the hpk file'",
+        ))
+        .arg(
+            Arg::from_usage(
+                "[exclude] --exclude <PATTERN>... 'Skip files and directories matching PATTERN'",
+            )
+            .number_of_values(1),
+        )
+        .arg(
+            Arg::from_usage("[symlinks] --symlinks <POLICY> 'How symbolic links are handled'")
+                .default_value("skip")
+                .possible_values(&SymlinkPolicy::variants()),
+        )
+        .arg(
+            Arg::from_usage("[sort] --sort <ORDER> 'Order of the entries in a directory, nocase ignores the case'")
+                .default_value("bytes")
+                .possible_values(&SortOrder::variants()),
+        )
+        .arg(Arg::from_usage(
+            "[no_default_excludes] --no-default-excludes 'Pack files like .git, Thumbs.db or *.swp which are skipped by default'",
+        ))
         .arg(Arg::from_usage("[extensions] --extensions=<EXT>...")
                 .next_line_help(true)
                 .long_help(EXTENSIONS_HELP))
+        .arg(Arg::from_usage(
+            "[access_profile] --access-profile <FILE> 'Stores the data of the files listed in FILE first and in its order'",
+        ))
+        .arg(Arg::from_usage(
+            "[group_extensions] --group-extensions=<EXT>... 'Stores the data of the files grouped by extension, EXT first'",
+        ).conflicts_with("access_profile"))
         .arg(Arg::from_usage("<dir> 'input directory'")
                 .validator(validate_dir))
-        .arg(Arg::from_usage("<file> 'hpk output file'"))
+        .arg(Arg::from_usage("<file> 'hpk output file'"));
+    #[cfg(feature = "sha256")]
+    let app = app.arg(Arg::from_usage(
+        "[sha256] --sha256 'Writes the SHA-256 manifest <file>.sha256 for mirrors'",
+    ));
+    app
 }
 
 pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
@@ -86,21 +227,104 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
     if matches.is_present("lz4") {
         options.use_lz4();
     }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
     if matches.is_present("cripple_lua") {
         options.cripple_lua_files();
     }
     if let Ok(chunk_size) = value_t!(matches, "chunk_size", u32) {
         options.with_chunk_size(chunk_size);
     }
+    if let Ok(level) = value_t!(matches, "level", u32) {
+        options.with_compression_level(level);
+    }
+    if let Ok(threads) = value_t!(matches, "threads", usize) {
+        options.with_threads(threads);
+    }
     if let Ok(fmt) = value_t!(matches, "filedate-fmt", FileDateFormat) {
         match fmt {
             FileDateFormat::default => options.with_default_filedates_format(),
             FileDateFormat::short => options.with_short_filedates_format(),
         }
     }
+    if matches.is_present("wide_offsets") {
+        options.with_wide_offsets();
+    }
+    if let Ok(offset) = value_t!(matches, "data_offset", u32) {
+        options.with_data_offset(offset);
+    }
+    if let Ok(alignment) = value_t!(matches, "align", u64) {
+        options.with_alignment(alignment);
+    }
+    if let Ok(count) = value_t!(matches, "fragments", u32) {
+        options.with_fragments_per_file(count);
+    }
+    if matches.is_present("big_endian") {
+        options.with_big_endian();
+    }
+    if matches.is_present("deterministic") {
+        options.deterministic();
+    }
+    if matches.is_present("checksums") {
+        options.with_checksums();
+    }
+    if matches.is_present("dedup") {
+        options.deduplicate();
+    }
+    if let Ok(key) = value_t!(matches, "xor_key", String) {
+        options.with_transform(super::parse_xor_key(&key).expect("validated key"));
+    }
+    options.set_excludes(&values_t!(matches, "exclude", String).unwrap_or_default());
+    if matches.is_present("no_default_excludes") {
+        options.no_default_excludes();
+    }
+    match value_t!(matches, "symlinks", SymlinkPolicy)? {
+        SymlinkPolicy::follow => options.set_symlink_policy(hpk::SymlinkPolicy::Follow),
+        SymlinkPolicy::skip => options.set_symlink_policy(hpk::SymlinkPolicy::Skip),
+        SymlinkPolicy::error => options.set_symlink_policy(hpk::SymlinkPolicy::Error),
+    }
+    match value_t!(matches, "sort", SortOrder)? {
+        SortOrder::bytes => options.set_sort_order(hpk::SortOrder::Bytes),
+        SortOrder::nocase => options.set_sort_order(hpk::SortOrder::CaseInsensitive),
+    }
     if let Ok(extensions) = values_t!(matches, "extensions", String) {
         options.with_extensions(extensions);
     }
+    if let Ok(profile) = value_t!(matches, "access_profile", String) {
+        options.set_data_order(hpk::DataOrder::read_profile(profile)?);
+    }
+    if let Ok(extensions) = values_t!(matches, "group_extensions", String) {
+        options.set_data_order(hpk::DataOrder::Extensions(extensions));
+    }
+    #[cfg(feature = "sha256")]
+    if matches.is_present("sha256") {
+        options.with_sha256_manifest();
+    }
+
+    if matches.is_present("dry_run") {
+        let entries = hpk::dry_run(&options, input)?;
+        let (mut total, mut stored) = (0, 0);
+        for entry in entries.iter().filter(|e| !e.is_dir) {
+            let mode = if entry.compress { "compress" } else { "store" };
+            println!(
+                "{:<8} {:>10} {:>10} {}",
+                mode,
+                entry.size,
+                entry.stored,
+                entry.path.display()
+            );
+            total += entry.size;
+            stored += entry.stored;
+        }
+        let files = entries.iter().filter(|e| !e.is_dir).count();
+        let dirs = entries.len() - files;
+        println!(
+            "{} files, {} directories, {} bytes, about {} bytes stored",
+            files, dirs, total, stored
+        );
+        return Ok(());
+    }
 
     hpk::create(&options, input, file)?;
     Ok(())
@@ -0,0 +1,29 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    SubCommand::with_name("defrag")
+        .about("Rewrite a hpk archive with every file in a single fragment")
+        .display_order(80)
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let file = value_t!(matches, "file", String)?;
+
+    hpk::defrag(file)?;
+    Ok(())
+}
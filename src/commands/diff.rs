@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use hpk::DiffKind;
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    SubCommand::with_name("diff")
+        .about("Show the entries which differ between two hpk archives")
+        .display_order(50)
+        .arg(Arg::from_usage("<a> 'old hpk archive'").validator(validate_input))
+        .arg(Arg::from_usage("<b> 'new hpk archive'").validator(validate_input))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let a = value_t!(matches, "a", String)?;
+    let b = value_t!(matches, "b", String)?;
+
+    for entry in hpk::diff(a, b)? {
+        let kind = match entry.kind {
+            DiffKind::Added => 'A',
+            DiffKind::Removed => 'D',
+            DiffKind::Modified => 'M',
+        };
+        let slash = if entry.is_dir { "/" } else { "" };
+        println!("{} {}{}", kind, entry.path.display(), slash);
+    }
+    Ok(())
+}
@@ -1,14 +1,57 @@
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 
 use crate::CliResult;
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum AuditFormat {
+        jsonl,
+        tsv
+    }
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum DuplicatePolicy {
+        extract,
+        hardlink,
+        copy
+    }
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum ExistingPolicy {
+        overwrite,
+        skip,
+        rename
+    }
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum NameDecoding {
+        strict,
+        lossy,
+        raw
+    }
+}
+
 pub fn clap<'a, 'b>() -> App<'a, 'b> {
     #[allow(clippy::needless_pass_by_value)]
     fn validate_input(value: String) -> Result<(), String> {
+        if value == "-" {
+            return Ok(());
+        }
         if let Ok(md) = fs::metadata(value) {
             if md.is_file() {
                 return Ok(());
@@ -17,6 +60,13 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         Err(String::from("Not a valid file"))
     }
     #[allow(clippy::needless_pass_by_value)]
+    fn validate_threads(value: String) -> Result<(), String> {
+        match value.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("Invalid number of threads")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
     fn validate_dest(value: String) -> Result<(), String> {
         match fs::metadata(value) {
             Ok(ref md) if md.is_file() => Err(String::from("Not a valid directory")),
@@ -24,29 +74,90 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         }
     }
 
-    SubCommand::with_name("extract")
+    let app = SubCommand::with_name("extract")
         .about("Extract files from a hpk archive")
         .display_order(10)
-        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(
+            Arg::from_usage("<file> 'hpk archive, - reads it from stdin'")
+                .validator(validate_input),
+        )
         .arg(Arg::from_usage("<dest> 'destination folder'").validator(validate_dest))
         .arg(
             Arg::from_usage("[paths]...")
                 .help("An optional list of archive members to be processed, separated by spaces."),
         )
+        .arg(
+            Arg::from_usage(
+                "[exclude] --exclude <PATTERN>... 'Skip archive members matching PATTERN'",
+            )
+            .number_of_values(1),
+        )
         .arg(
             Arg::from_usage("[filedates] --ignore-filedates")
                 .help("Skip processing of a _filedates file and just extract it"),
         )
+        .arg(
+            Arg::from_usage("[checksums] --ignore-checksums")
+                .help("Skip verifying the checksums of a _checksums file and just extract it"),
+        )
+        .arg(
+            Arg::from_usage("[names] --names <DECODING> 'How non-UTF-8 entry names are decoded'")
+                .default_value("strict")
+                .possible_values(&NameDecoding::variants()),
+        )
         .arg(
             Arg::from_usage("[fix_lua] --fix-lua-files")
                 .help("Fix the bytecode header of Victor Vran's or Surviving Mars' Lua files"),
         )
+        .arg(Arg::from_usage(
+            "[sparse] --sparse 'Skip writing blocks of zeros to create sparse files'",
+        ))
+        .arg(
+            Arg::from_usage(
+                "[duplicates] --duplicates <POLICY> 'How files sharing their content with an extracted file are created'",
+            )
+            .default_value("extract")
+            .possible_values(&DuplicatePolicy::variants()),
+        )
+        .arg(
+            Arg::from_usage(
+                "[existing] --existing <POLICY> 'How files which already exist in the destination are handled'",
+            )
+            .default_value("overwrite")
+            .possible_values(&ExistingPolicy::variants()),
+        )
+        .arg(Arg::from_usage(
+            "[state] --resume <STATE> 'Record the extracted files in STATE and skip those of a previous run'",
+        ))
+        .arg(
+            Arg::from_usage("[xor_key] --xor-key <HEX> 'XOR key of a scrambled archive'")
+                .validator(|v| super::parse_xor_key(&v).map(|_| ())),
+        )
         .arg(Arg::from_usage(
             "[force] --force 'Force extraction if destination folder is not empty'",
         ))
         .arg(Arg::from_usage(
             "[verbose] -v 'Verbosely list files processed'",
         ))
+        .arg(
+            Arg::from_usage(
+                "[threads] -j, --threads <N> 'Number of threads used for decompressing'",
+            )
+            .validator(validate_threads),
+        )
+        .arg(Arg::from_usage(
+            "[audit] --audit <FILE> 'Write a record of every processed entry to FILE'",
+        ))
+        .arg(
+            Arg::from_usage("[audit-fmt] --audit-format <FORMAT> 'Format of the audit records'")
+                .default_value("jsonl")
+                .possible_values(&AuditFormat::variants()),
+        );
+    #[cfg(feature = "sha256")]
+    let app = app.arg(Arg::from_usage(
+        "[audit-digests] --audit-digests 'Add the SHA-256 digest of every written file to the audit records'",
+    ));
+    app
 }
 
 pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
@@ -56,7 +167,9 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
     let verbose = matches.is_present("verbose");
 
     if let Ok(dir) = dest.read_dir() {
-        if !force && dir.count() > 0 {
+        // a resumed extraction continues in the same directory
+        let resume = value_t!(matches, "state", String).is_ok_and(|s| Path::new(&s).exists());
+        if !force && !resume && dir.count() > 0 {
             eprintln!("error: Directory is not empty");
             process::exit(1);
         }
@@ -64,13 +177,63 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
 
     let mut options = hpk::ExtractOptions::new();
     options.set_paths(&values_t!(matches, "paths", String).unwrap_or_default());
+    options.set_excludes(&values_t!(matches, "exclude", String).unwrap_or_default());
     options.set_verbose(verbose);
     if matches.is_present("filedates") {
         options.skip_filedates();
     }
+    if matches.is_present("checksums") {
+        options.skip_checksums();
+    }
     if matches.is_present("fix_lua") {
         options.fix_lua_files();
     }
-    hpk::extract(&options, input, dest)?;
+    if matches.is_present("sparse") {
+        options.sparse_files();
+    }
+    match value_t!(matches, "duplicates", DuplicatePolicy)? {
+        DuplicatePolicy::extract => options.set_duplicate_policy(hpk::DuplicatePolicy::Extract),
+        DuplicatePolicy::hardlink => options.set_duplicate_policy(hpk::DuplicatePolicy::HardLink),
+        DuplicatePolicy::copy => options.set_duplicate_policy(hpk::DuplicatePolicy::Copy),
+    }
+    match value_t!(matches, "existing", ExistingPolicy)? {
+        ExistingPolicy::overwrite => options.set_existing_policy(hpk::ExistingPolicy::Overwrite),
+        ExistingPolicy::skip => options.set_existing_policy(hpk::ExistingPolicy::Skip),
+        ExistingPolicy::rename => options.set_existing_policy(hpk::ExistingPolicy::Rename),
+    }
+    if let Ok(state) = value_t!(matches, "state", String) {
+        options.resume_state(state);
+    }
+    options.set_name_decoding(name_decoding(matches)?);
+    if let Ok(key) = value_t!(matches, "xor_key", String) {
+        options.with_transform(super::parse_xor_key(&key).expect("validated key"));
+    }
+    if let Ok(threads) = value_t!(matches, "threads", usize) {
+        options.with_threads(threads);
+    }
+    if let Ok(audit) = value_t!(matches, "audit", String) {
+        options.audit_manifest(fs::File::create(audit).map_err(hpk::HpkError::Io)?);
+        match value_t!(matches, "audit-fmt", AuditFormat)? {
+            AuditFormat::jsonl => options.set_audit_format(hpk::AuditFormat::JsonLines),
+            AuditFormat::tsv => options.set_audit_format(hpk::AuditFormat::Tsv),
+        }
+        #[cfg(feature = "sha256")]
+        if matches.is_present("audit-digests") {
+            options.audit_digests();
+        }
+    }
+    if input == Path::new("-") {
+        hpk::extract_reader(&options, io::stdin().lock(), dest)?;
+    } else {
+        hpk::extract(&options, input, dest)?;
+    }
     Ok(())
 }
+
+fn name_decoding(matches: &ArgMatches<'_>) -> Result<hpk::NameDecoding, clap::Error> {
+    Ok(match value_t!(matches, "names", NameDecoding)? {
+        NameDecoding::strict => hpk::NameDecoding::Strict,
+        NameDecoding::lossy => hpk::NameDecoding::Lossy,
+        NameDecoding::raw => hpk::NameDecoding::Raw,
+    })
+}
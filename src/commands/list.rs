@@ -1,4 +1,5 @@
 use std::fs;
+use std::io;
 use std::path::Path;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
@@ -6,6 +7,16 @@ use glob::Pattern;
 
 use crate::CliResult;
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(PartialEq, Debug)]
+    enum NameDecoding {
+        strict,
+        lossy,
+        raw
+    }
+}
+
 pub fn clap<'a, 'b>() -> App<'a, 'b> {
     #[allow(clippy::needless_pass_by_value)]
     fn validate_input(value: String) -> Result<(), String> {
@@ -20,6 +31,22 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         .about("List the content of a hpk archive")
         .display_order(20)
         .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(Arg::from_usage(
+            "[long] -l 'Show the compression, the chunk count and the sizes of the files'",
+        ))
+        .arg(
+            Arg::from_usage("[tree] --tree 'Show the entries as an indented tree'")
+                .conflicts_with("paths"),
+        )
+        .arg(
+            Arg::from_usage("[json] --json 'Print one JSON object per entry'")
+                .conflicts_with_all(&["long", "tree"]),
+        )
+        .arg(
+            Arg::from_usage("[names] --names <DECODING> 'How non-UTF-8 entry names are decoded'")
+                .default_value("strict")
+                .possible_values(&NameDecoding::variants()),
+        )
         .arg(Arg::from_usage("[paths]..."))
 }
 
@@ -31,7 +58,20 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
         .filter_map(|s| Pattern::new(s).ok())
         .collect::<Vec<_>>();
 
-    let walk = hpk::walk(input)?;
+    let long = matches.is_present("long");
+    let walk = hpk::walk(input)?.name_decoding(name_decoding(matches)?);
+    let archive = hpk::HpkArchive::from_walk(walk)?;
+
+    if matches.is_present("tree") {
+        let mut options = hpk::TreeOptions::new();
+        if long {
+            options.show_sizes();
+            options.show_compression();
+        }
+        let stdout = io::stdout();
+        archive.print_tree(&mut stdout.lock(), &options)?;
+        return Ok(());
+    }
 
     fn matches_path(path: &Path, paths: &[Pattern]) -> bool {
         if paths.is_empty() {
@@ -45,15 +85,50 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
         false
     }
 
-    for dent in walk {
-        if let Ok(dent) = dent {
-            if !matches_path(dent.path(), &paths) {
-                continue;
-            }
-            if !dent.is_dir() {
-                println!("{}", dent.path().display());
-            }
+    if matches.is_present("json") {
+        let entries = archive
+            .entries()
+            .iter()
+            .filter(|dent| dent.depth() > 0 && matches_path(dent.path(), &paths));
+        let stdout = io::stdout();
+        archive.print_json(&mut stdout.lock(), entries)?;
+        return Ok(());
+    }
+
+    if long {
+        println!(
+            "{:<5} {:>6} {:>10} {:>10} path",
+            "codec", "chunks", "size", "stored"
+        );
+    }
+    for dent in archive.entries() {
+        if !matches_path(dent.path(), &paths) {
+            continue;
+        }
+        if dent.is_dir() {
+            continue;
+        }
+        if long {
+            let md = archive.metadata(dent)?;
+            println!(
+                "{:<5} {:>6} {:>10} {:>10} {}",
+                md.compression.to_string(),
+                md.chunks,
+                md.size,
+                md.compressed_size,
+                dent.path().display()
+            );
+        } else {
+            println!("{}", dent.path().display());
         }
     }
     Ok(())
 }
+
+fn name_decoding(matches: &ArgMatches<'_>) -> Result<hpk::NameDecoding, clap::Error> {
+    Ok(match value_t!(matches, "names", NameDecoding)? {
+        NameDecoding::strict => hpk::NameDecoding::Strict,
+        NameDecoding::lossy => hpk::NameDecoding::Lossy,
+        NameDecoding::raw => hpk::NameDecoding::Raw,
+    })
+}
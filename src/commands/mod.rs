@@ -1,4 +1,36 @@
+pub mod cat;
+#[cfg(feature = "convert")]
+pub mod convert;
 pub mod create;
+pub mod defrag;
+pub mod diff;
 pub mod extract;
 pub mod list;
+#[cfg(feature = "fuse")]
+pub mod mount;
+#[cfg(feature = "pack")]
+pub mod pack;
+pub mod patch;
 pub mod print;
+pub mod remove;
+pub mod update;
+pub mod verify;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+/// Parses the hex-encoded key of the `--xor-key` option
+pub fn parse_xor_key(value: &str) -> Result<hpk::XorKey, String> {
+    let invalid = || String::from("Not a valid hex key");
+    if value.is_empty() {
+        return Err(invalid());
+    }
+    let key = value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 => u8::from_str_radix(pair, 16).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(hpk::XorKey::new(key))
+}
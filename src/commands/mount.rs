@@ -0,0 +1,48 @@
+use std::fs;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        match fs::metadata(value) {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_dir(value: String) -> Result<(), String> {
+        if let Ok(md) = fs::metadata(value) {
+            if md.is_dir() {
+                return Ok(());
+            }
+        }
+        Err(String::from("Not a valid directory"))
+    }
+
+    SubCommand::with_name("mount")
+        .about("Mount a hpk archive as a read-only file system")
+        .display_order(35)
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(
+            Arg::from_usage("<mountpoint> 'empty directory to mount the archive on'")
+                .validator(validate_dir),
+        )
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let input = value_t!(matches, "file", String)?;
+    let mountpoint = value_t!(matches, "mountpoint", String)?;
+    let archive = hpk::HpkArchive::open(input)?;
+
+    println!(
+        "mounted on {}, unmount with `fusermount -u {}`",
+        mountpoint, mountpoint
+    );
+    hpk::mount(archive, mountpoint)?;
+    Ok(())
+}
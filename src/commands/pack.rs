@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_manifest(value: String) -> Result<(), String> {
+        match Path::new(&value).metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    let app = SubCommand::with_name("pack")
+        .about("Create a new hpk archive from a TOML or JSON manifest of source files")
+        .display_order(5)
+        .arg(Arg::from_usage("[compress] --compress 'Compress the whole hpk file'"))
+        .arg(Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'").display_order(10))
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11),
+        )
+        .arg(
+            Arg::from_usage("[cripple_lua] --cripple-lua-files")
+                .help("Cripple bytecode header for Victor Vran or Surviving Mars"),
+        )
+        .arg(Arg::from_usage(
+            "[checksums] --with-checksums 'Stores CRC32 checksums of the file contents which are verified during extraction'",
+        ))
+        .arg(
+            Arg::from_usage("<manifest> 'manifest file (.toml or .json)'")
+                .validator(validate_manifest),
+        )
+        .arg(Arg::from_usage("<file> 'hpk output file'"));
+    #[cfg(feature = "sha256")]
+    let app = app.arg(Arg::from_usage(
+        "[sha256] --sha256 'Writes the SHA-256 manifest <file>.sha256 for mirrors'",
+    ));
+    app
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let manifest = value_t!(matches, "manifest", String)?;
+    let file = value_t!(matches, "file", String)?;
+
+    let mut options = hpk::CreateOptions::new();
+    if matches.is_present("compress") {
+        options.compress();
+    }
+    if matches.is_present("lz4") {
+        options.use_lz4();
+    }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
+    if matches.is_present("cripple_lua") {
+        options.cripple_lua_files();
+    }
+    if matches.is_present("checksums") {
+        options.with_checksums();
+    }
+    #[cfg(feature = "sha256")]
+    if matches.is_present("sha256") {
+        options.with_sha256_manifest();
+    }
+
+    let manifest = hpk::PackManifest::read(manifest)?;
+    hpk::pack(&options, &manifest, file)?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use hpk::DiffKind;
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        if Path::new(&value).exists() {
+            Ok(())
+        } else {
+            Err(String::from("Not a valid file or directory"))
+        }
+    }
+
+    SubCommand::with_name("patch")
+        .about("Create a patch archive with the changed entries of two archives or directories")
+        .display_order(55)
+        .arg(Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'").display_order(10))
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11),
+        )
+        .arg(
+            Arg::from_usage("[cripple_lua] --cripple-lua-files")
+                .help("Cripple bytecode header for Victor Vran or Surviving Mars"),
+        )
+        .arg(Arg::from_usage("<old> 'old hpk archive or directory'").validator(validate_input))
+        .arg(Arg::from_usage("<new> 'new hpk archive or directory'").validator(validate_input))
+        .arg(Arg::from_usage("<file> 'hpk output file'"))
+        .arg(Arg::from_usage(
+            "[verbose] -v 'List the added, changed and removed entries'",
+        ))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let old = value_t!(matches, "old", String)?;
+    let new = value_t!(matches, "new", String)?;
+    let file = value_t!(matches, "file", String)?;
+
+    let mut options = hpk::CreateOptions::new();
+    if matches.is_present("lz4") {
+        options.use_lz4();
+    }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
+    if matches.is_present("cripple_lua") {
+        options.cripple_lua_files();
+    }
+
+    let changes = hpk::patch(&options, old, new, file)?;
+    let removed = changes
+        .iter()
+        .filter(|c| c.kind == DiffKind::Removed)
+        .count();
+    if matches.is_present("verbose") {
+        for entry in &changes {
+            let kind = match entry.kind {
+                DiffKind::Added => 'A',
+                DiffKind::Removed => 'D',
+                DiffKind::Modified => 'M',
+            };
+            let slash = if entry.is_dir { "/" } else { "" };
+            println!("{} {}{}", kind, entry.path.display(), slash);
+        }
+    }
+    println!(
+        "{} entries patched, {} removed entries can't be removed by the patch",
+        changes.len() - removed,
+        removed
+    );
+    Ok(())
+}
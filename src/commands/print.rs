@@ -1,3 +1,4 @@
+use std::io;
 use std::path::Path;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
@@ -22,16 +23,30 @@ pub fn clap<'a, 'b>() -> App<'a, 'b> {
         .arg(Arg::from_usage(
             "[header] --header-only 'Print only the header informations'",
         ))
+        .arg(Arg::from_usage(
+            "[debug] --debug 'Print a dump of all raw archive structures instead'",
+        ))
 }
 
 pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
     let input = value_t!(matches, "file", String)?;
+    if matches.is_present("debug") {
+        let archive = hpk::HpkArchive::open(input)?;
+        archive.debug().dump(&mut io::stdout().lock())?;
+        return Ok(());
+    }
     let mut walk = hpk::walk(input)?;
 
     println!("reading file: {}", walk.path().display());
     if walk.is_compressed() {
         println!("file is compressed");
     }
+    if walk.header().is_wide() {
+        println!("file uses 64-bit offsets");
+    }
+    if walk.header().is_big_endian() {
+        println!("file uses big-endian byte order");
+    }
     println!("header:");
     println!("  data_offset: 0x{:X}", walk.header().data_offset);
     println!(
@@ -58,7 +73,7 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
     }
 
     println!("filesystem fragments:");
-    for chunk in &walk.fragments {
+    for chunk in walk.fragments() {
         let mut start = if walk.header().fragments_per_file == 1 {
             None
         } else {
@@ -84,7 +99,7 @@ pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
             dent.depth(),
             dent.path().display(),
         );
-        let fragment = &walk.fragments[dent.index()][0];
+        let fragment = &walk.fragments()[dent.index()][0];
         println!(
             " fragment: 0x{:X} len: {}",
             fragment.offset, fragment.length
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    SubCommand::with_name("remove")
+        .about("Remove files and directories from a hpk archive")
+        .display_order(70)
+        .arg(Arg::from_usage(
+            "[compact] --compact 'Rewrite the archive without the removed content'",
+        ))
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(Arg::from_usage("<paths>... 'entries to remove'"))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let file = value_t!(matches, "file", String)?;
+    let paths = values_t!(matches, "paths", String)?;
+
+    hpk::remove(file, paths, matches.is_present("compact"))?;
+    Ok(())
+}
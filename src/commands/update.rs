@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_dir(value: String) -> Result<(), String> {
+        if let Ok(md) = fs::metadata(value) {
+            if md.is_dir() {
+                return Ok(());
+            }
+        }
+        Err(String::from("Not a valid directory"))
+    }
+
+    SubCommand::with_name("update")
+        .about("Add or replace files in a hpk archive or synchronize it with a directory")
+        .display_order(60)
+        .arg(Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'").display_order(10))
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11),
+        )
+        .arg(
+            Arg::from_usage("[cripple_lua] --cripple-lua-files")
+                .help("Cripple bytecode header for Victor Vran or Surviving Mars"),
+        )
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input))
+        .arg(Arg::from_usage("<dir> 'input directory'").validator(validate_dir))
+        .arg(Arg::from_usage(
+            "[paths]... 'files relative to the input directory, without them the archive is synchronized with the whole directory'",
+        ))
+        .arg(Arg::from_usage(
+            "[verbose] -v 'List the added, changed and removed files'",
+        ))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let file = value_t!(matches, "file", String)?;
+    let dir = value_t!(matches, "dir", String)?;
+
+    let mut options = hpk::CreateOptions::new();
+    if matches.is_present("lz4") {
+        options.use_lz4();
+    }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
+    if matches.is_present("cripple_lua") {
+        options.cripple_lua_files();
+    }
+
+    let dir = Path::new(&dir);
+    if let Ok(paths) = values_t!(matches, "paths", String) {
+        let files = paths.iter().map(|p| (Path::new(p), dir.join(p)));
+        hpk::update(&options, file, files)?;
+        return Ok(());
+    }
+
+    // only the new and changed files are compressed
+    let summary = hpk::sync_dir(&options, dir, file)?;
+    if matches.is_present("verbose") {
+        let changes = [
+            ("A", &summary.added),
+            ("M", &summary.changed),
+            ("D", &summary.removed),
+        ];
+        for (tag, paths) in &changes {
+            for path in paths.iter() {
+                println!("{} {}", tag, path.display());
+            }
+        }
+    }
+    println!(
+        "{} added, {} changed, {} removed, {} unchanged",
+        summary.added.len(),
+        summary.changed.len(),
+        summary.removed.len(),
+        summary.unchanged
+    );
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_input(value: String) -> Result<(), String> {
+        let path = Path::new(&value);
+        match path.metadata() {
+            Ok(ref md) if md.is_file() => Ok(()),
+            Ok(_) => Err(String::from("Not a valid file")),
+            Err(_) => Err(String::from("Not a valid file")),
+        }
+    }
+
+    let app = SubCommand::with_name("verify")
+        .about("Check the integrity of a hpk archive")
+        .display_order(40)
+        .arg(Arg::from_usage("<file> 'hpk archive'").validator(validate_input));
+    #[cfg(feature = "sha256")]
+    let app = app.arg(
+        Arg::from_usage(
+            "[manifest] --manifest=[MANIFEST] 'Check the SHA-256 digests of a manifest (default: <file>.sha256)'",
+        )
+        .min_values(0)
+        .require_equals(true),
+    );
+    app
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let input = value_t!(matches, "file", String)?;
+    #[cfg(feature = "sha256")]
+    if matches.is_present("manifest") {
+        return verify_manifest(&input, matches.value_of("manifest"));
+    }
+    let archive = hpk::HpkArchive::open(input)?;
+    let corrupt = archive.verify()?;
+
+    for entry in &corrupt {
+        println!("{}: {}", entry.path.display(), entry.corruption);
+    }
+    println!(
+        "{} entries checked, {} corrupt",
+        archive.entries().len(),
+        corrupt.len()
+    );
+    if !corrupt.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sha256")]
+fn verify_manifest(input: &str, manifest: Option<&str>) -> CliResult {
+    let manifest = match manifest {
+        Some(manifest) => manifest.into(),
+        None => hpk::sha256_manifest_path(input),
+    };
+    let corrupt = hpk::verify_sha256_manifest(input, &manifest)?;
+
+    for entry in &corrupt {
+        println!("{}: {}", entry.path.display(), entry.corruption);
+    }
+    println!(
+        "checked against {}, {} mismatches",
+        manifest.display(),
+        corrupt.len()
+    );
+    if !corrupt.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::fs;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::CliResult;
+
+pub fn clap<'a, 'b>() -> App<'a, 'b> {
+    #[allow(clippy::needless_pass_by_value)]
+    fn validate_dir(value: String) -> Result<(), String> {
+        if let Ok(md) = fs::metadata(value) {
+            if md.is_dir() {
+                return Ok(());
+            }
+        }
+        Err(String::from("Not a valid directory"))
+    }
+
+    SubCommand::with_name("watch")
+        .about("Update a hpk archive whenever the files of a directory change")
+        .display_order(70)
+        .arg(Arg::from_usage("[lz4] --lz4 'Sets LZ4 as encoder'").display_order(10))
+        .arg(
+            Arg::from_usage("[zstd] --zstd 'Sets ZSTD as encoder'")
+                .conflicts_with("lz4")
+                .display_order(11),
+        )
+        .arg(
+            Arg::from_usage("[cripple_lua] --cripple-lua-files")
+                .help("Cripple bytecode header for Victor Vran or Surviving Mars"),
+        )
+        .arg(Arg::from_usage(
+            "[filedates] --with-filedates 'Stores the last modification times in a _filedates file'",
+        ))
+        .arg(Arg::from_usage("<dir> 'input directory'").validator(validate_dir))
+        .arg(Arg::from_usage(
+            "<file> 'hpk archive, created if it does not exist'",
+        ))
+}
+
+pub fn execute(matches: &ArgMatches<'_>) -> CliResult {
+    let dir = value_t!(matches, "dir", String)?;
+    let file = value_t!(matches, "file", String)?;
+
+    let mut options = hpk::CreateOptions::new();
+    if matches.is_present("lz4") {
+        options.use_lz4();
+    }
+    if matches.is_present("zstd") {
+        options.use_zstd();
+    }
+    if matches.is_present("cripple_lua") {
+        options.cripple_lua_files();
+    }
+    if matches.is_present("filedates") {
+        options.with_default_filedates_format();
+    }
+
+    println!("watching {} for changes, press Ctrl-C to stop", dir);
+    hpk::watch(&options, dir, file, |result| match result {
+        Ok(summary) if summary.is_empty() => {}
+        Ok(summary) => println!(
+            "{} added, {} changed, {} removed",
+            summary.added.len(),
+            summary.changed.len(),
+            summary.removed.len()
+        ),
+        Err(e) => eprintln!("error: {}", e),
+    })?;
+    Ok(())
+}
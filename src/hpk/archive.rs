@@ -0,0 +1,965 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::checksum::{self, ChecksumMap};
+use crate::compression::compress_spooled;
+#[cfg(feature = "fs")]
+use crate::delta;
+use crate::diff::{self, DiffEntry};
+use crate::get_compression;
+use crate::list;
+use crate::read::{chunk_len, copy_cancellable, read_exact_vec};
+use crate::read::{EntryReader, FragmentedReader, SourceReader};
+#[cfg(feature = "sign")]
+use crate::sign;
+use crate::tree::{self, TreeOptions};
+use crate::verify::{self, CorruptEntry};
+use crate::walk::{walk_reader, HpkIter};
+#[cfg(feature = "fs")]
+use crate::{
+    audit::{AuditRecord, AuditSummary, DigestWriter},
+    checksum::{ChecksumWriter, Checksummer},
+    extract::{is_safe_path, process_filedates},
+    lua,
+    progress::ProgressWriter,
+    resume::ExtractState,
+    sparse::SparseWriter,
+    walk::walk_at,
+    AuditStatus, CancelToken, DuplicatePolicy, ExistingPolicy, ExtractOptions, VolumeReader,
+};
+use crate::{ArchiveDebug, ArchiveManifest, CompressionHeader, Fragment, Header, HpkWriter};
+use crate::{Compression, CreateOptions, DirEntry};
+use crate::{HpkError, HpkResult};
+
+/// Sizes and compression of a file entry
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EntryMetadata {
+    /// The compression method of the content
+    pub compression: Compression,
+    /// Stored size inside of the archive
+    pub compressed_size: u64,
+    /// Size of the decompressed content
+    pub size: u64,
+    /// Number of compressed chunks
+    pub chunks: usize,
+}
+
+/// How `HpkArchive::find` and `OverlayArchive` compare the paths of the entries
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PathLookup {
+    /// The paths must be equal
+    #[default]
+    Exact,
+    /// The paths are compared in lowercase like the engine resolves the asset paths
+    /// on Windows
+    CaseInsensitive,
+}
+
+impl PathLookup {
+    /// Returns the normalized path which is compared
+    pub(crate) fn key<'a>(self, path: &'a Path) -> Cow<'a, Path> {
+        match self {
+            PathLookup::Exact => Cow::Borrowed(path),
+            PathLookup::CaseInsensitive => Cow::Owned(
+                path.iter()
+                    .map(|name| name.to_string_lossy().to_lowercase())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// A chunk of a file as it's stored in the archive, see `HpkArchive::chunks`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawChunk {
+    /// Offset of the chunk inside of the stored content of the file
+    pub offset: u64,
+    /// Length of the stored chunk
+    pub length: u64,
+    /// Length of the chunk after decompressing it
+    pub inflated_length: u64,
+}
+
+impl RawChunk {
+    /// Returns `true` if the chunk is stored without compression
+    pub fn is_stored(&self) -> bool {
+        self.length == self.inflated_length
+    }
+}
+
+/// An opened hpk archive
+///
+/// The header, the fragment table and the directory tree are parsed once when the
+/// archive is opened. The entries can be read from several threads at the same time,
+/// the readers share the file handle and use positioned reads.
+///
+pub struct HpkArchive {
+    walk: HpkIter,
+    entries: Vec<DirEntry>,
+    /// Index of `entries` by the key of their path, built by the first `find`
+    index: OnceLock<HashMap<PathBuf, usize>>,
+    lookup: PathLookup,
+    #[cfg(feature = "mmap")]
+    map: Option<memmap2::Mmap>,
+}
+
+impl HpkArchive {
+    #[cfg(feature = "fs")]
+    pub fn open<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        HpkArchive::open_at(file, 0)
+    }
+
+    #[cfg(feature = "fs")]
+    /// Opens an archive which starts at `offset` inside of `file`
+    pub fn open_at<P: AsRef<Path>>(file: P, offset: u64) -> HpkResult<Self> {
+        HpkArchive::from_walk(walk_at(file, offset)?)
+    }
+
+    /// Opens the archive of `walk` and collects its remaining entries
+    ///
+    /// This allows to open an archive with a walk configured by e.g.
+    /// `HpkIter::name_decoding`.
+    ///
+    pub fn from_walk(mut walk: HpkIter) -> HpkResult<Self> {
+        let entries = walk.by_ref().collect::<HpkResult<Vec<_>>>()?;
+        Ok(HpkArchive::with_entries(walk, entries))
+    }
+
+    /// Opens the archive of `walk` and skips the entries which can't be read, e.g. a
+    /// directory whose fragment reaches beyond the end of the archive
+    #[cfg(feature = "fs")]
+    pub(crate) fn from_walk_lenient(mut walk: HpkIter) -> Self {
+        let entries = walk.by_ref().filter_map(Result::ok).collect();
+        HpkArchive::with_entries(walk, entries)
+    }
+
+    fn with_entries(walk: HpkIter, entries: Vec<DirEntry>) -> Self {
+        HpkArchive {
+            walk,
+            entries,
+            index: OnceLock::new(),
+            lookup: PathLookup::default(),
+            #[cfg(feature = "mmap")]
+            map: None,
+        }
+    }
+
+    /// Opens an archive which was split into volumes, see `CreateOptions::split_volumes`
+    #[cfg(feature = "fs")]
+    pub fn open_volumes<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        HpkArchive::from_reader(VolumeReader::open(file)?)
+    }
+
+    /// Opens an archive which is read from `r`, e.g. an in-memory buffer
+    pub fn from_reader<R>(r: R) -> HpkResult<Self>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        HpkArchive::from_walk(walk_reader(r)?)
+    }
+
+    /// Opens the archive and maps the file into memory
+    ///
+    /// The entries are read by slicing the map instead of seeking and reading the
+    /// file. The file must not be modified while the archive is open.
+    ///
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        let mut archive = HpkArchive::open(file)?;
+        // SAFETY: the map is read-only and owned by the archive, modifying the
+        // file while it's mapped is documented as not allowed.
+        if let Some(f) = archive.walk.file() {
+            archive.map = Some(unsafe { memmap2::Mmap::map(f)? });
+        }
+        Ok(archive)
+    }
+
+    /// Returns `true` if the archive was opened with `open_mmap`
+    pub fn is_mapped(&self) -> bool {
+        #[cfg(feature = "mmap")]
+        return self.map.is_some();
+        #[cfg(not(feature = "mmap"))]
+        return false;
+    }
+
+    pub fn path(&self) -> &Path {
+        self.walk.path()
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.walk.is_compressed()
+    }
+
+    pub fn header(&self) -> &Header {
+        self.walk.header()
+    }
+
+    pub fn fragments(&self) -> &[Vec<Fragment>] {
+        self.walk.fragments()
+    }
+
+    /// Fragments of the residual table which aren't referenced by the directory tree
+    pub fn residuals(&self) -> &[Fragment] {
+        &self.walk.residuals
+    }
+
+    /// All entries of the directory tree in the order they are stored, including
+    /// the root directory.
+    pub fn entries(&self) -> &[DirEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry at `path`, see `find`
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&DirEntry> {
+        self.find(path)
+    }
+
+    /// Looks up the entry at `path` in a hash index of the entries
+    ///
+    /// The index is built by the first lookup, the following lookups don't scan the
+    /// entries. The first entry wins if an archive contains a path twice, see
+    /// `set_path_lookup` for the comparison of the paths.
+    ///
+    pub fn find<P: AsRef<Path>>(&self, path: P) -> Option<&DirEntry> {
+        let lookup = self.lookup;
+        let index = self.index.get_or_init(|| {
+            let mut index = HashMap::with_capacity(self.entries.len());
+            for (i, entry) in self.entries.iter().enumerate() {
+                let key = lookup.key(entry.path()).into_owned();
+                index.entry(key).or_insert(i);
+            }
+            index
+        });
+        index
+            .get(lookup.key(path.as_ref()).as_ref())
+            .map(|&i| &self.entries[i])
+    }
+
+    /// Sets how the paths passed to `find`, `read_file` etc. are compared with the
+    /// paths of the entries, the default is `PathLookup::Exact`
+    pub fn set_path_lookup(&mut self, lookup: PathLookup) {
+        if self.lookup != lookup {
+            self.lookup = lookup;
+            self.index = OnceLock::new();
+        }
+    }
+
+    pub fn path_lookup(&self) -> PathLookup {
+        self.lookup
+    }
+
+    /// Returns the entries of the directory at `path`, an empty path is the root
+    ///
+    /// See `HpkIter::read_dir` to list a directory without parsing the whole tree.
+    ///
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<&DirEntry>> {
+        let path = path.as_ref();
+        // the path of the entry, `path` may differ in case
+        let path = match self.entry(path) {
+            Some(entry) if entry.is_dir() => entry.path(),
+            _ => return Err(HpkError::EntryNotFound(path.to_path_buf())),
+        };
+        Ok(self
+            .entries
+            .iter()
+            .filter(|e| e.depth() > 0 && e.path().parent() == Some(path))
+            .collect())
+    }
+
+    /// Reads the decompressed content of the file at `path`
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<u8>> {
+        let mut buf = vec![];
+        self.copy_file(path, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Writes the decompressed content of the file at `path` to `w`
+    ///
+    /// The content is decompressed while writing, returns the number of bytes written.
+    ///
+    pub fn copy_file<P: AsRef<Path>, W: Write>(&self, path: P, w: &mut W) -> HpkResult<u64> {
+        let path = path.as_ref();
+        let entry = match self.entry(path) {
+            Some(entry) if !entry.is_dir() => entry,
+            _ => return Err(HpkError::EntryNotFound(path.to_path_buf())),
+        };
+        copy_cancellable(&mut self.reader(entry), w, None, 1)
+    }
+
+    /// Reads the decompressed content of all files into memory
+    ///
+    /// Fails with `HpkError::SizeLimitExceeded` before reading the files if their
+    /// total decompressed size is larger than `limit` bytes.
+    ///
+    pub fn read_all(&self, limit: u64) -> HpkResult<HashMap<PathBuf, Vec<u8>>> {
+        let mut total = 0u64;
+        for entry in self.entries.iter().filter(|e| !e.is_dir()) {
+            total = total.saturating_add(self.metadata(entry)?.size);
+            if total > limit {
+                return Err(HpkError::SizeLimitExceeded(limit));
+            }
+        }
+        let mut files = HashMap::new();
+        // the compression headers could be lying about the sizes
+        let mut remaining = limit;
+        for entry in self.entries.iter().filter(|e| !e.is_dir()) {
+            let mut buf = vec![];
+            let mut r = self.open_entry(entry)?.take(remaining.saturating_add(1));
+            r.read_to_end(&mut buf)?;
+            remaining = remaining
+                .checked_sub(buf.len() as u64)
+                .ok_or(HpkError::SizeLimitExceeded(limit))?;
+            files.insert(entry.path().to_path_buf(), buf);
+        }
+        Ok(files)
+    }
+
+    /// Reads the compression header of the file `entry`
+    pub fn metadata(&self, entry: &DirEntry) -> HpkResult<EntryMetadata> {
+        if entry.is_dir() {
+            return Err(HpkError::EntryNotFound(entry.path().to_path_buf()));
+        }
+        let mut r = self.reader(entry);
+        let compressed_size = r.len();
+        let compression = get_compression(&mut r)?;
+        if !compression.is_compressed() {
+            return Ok(EntryMetadata {
+                compression,
+                compressed_size,
+                size: compressed_size,
+                chunks: 0,
+            });
+        }
+        let hdr = CompressionHeader::read_from(compressed_size, &mut r)?;
+        Ok(EntryMetadata {
+            compression,
+            compressed_size,
+            size: u64::from(hdr.inflated_length),
+            chunks: hdr.chunks.len(),
+        })
+    }
+
+    /// Returns the stored chunks of the file `entry` without decompressing them
+    ///
+    /// The chunks can be copied into another archive as they are, e.g. to repack or
+    /// to deduplicate files without decompressing and compressing them again. An
+    /// uncompressed file is a single stored chunk.
+    ///
+    pub fn chunks(&self, entry: &DirEntry) -> HpkResult<Vec<RawChunk>> {
+        if entry.is_dir() {
+            return Err(HpkError::EntryNotFound(entry.path().to_path_buf()));
+        }
+        let mut r = self.reader(entry);
+        let length = r.len();
+        if !get_compression(&mut r)?.is_compressed() {
+            let chunk = RawChunk {
+                offset: 0,
+                length,
+                inflated_length: length,
+            };
+            return Ok(if length == 0 { vec![] } else { vec![chunk] });
+        }
+        let hdr = CompressionHeader::read_from(length, &mut r)?;
+        let chunk_size = u64::from(hdr.chunk_size);
+        let inflated_length = u64::from(hdr.inflated_length);
+        Ok(hdr
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| RawChunk {
+                offset: chunk.offset,
+                length: chunk.length,
+                inflated_length: chunk_len(chunk_size, inflated_length, index),
+            })
+            .collect())
+    }
+
+    /// Reads the stored bytes of a chunk of the file `entry`
+    ///
+    /// The bytes are borrowed from the map of an archive opened with `open_mmap` if
+    /// the chunk isn't split across fragments, otherwise they are read.
+    ///
+    pub fn chunk_bytes(&self, entry: &DirEntry, chunk: &RawChunk) -> HpkResult<Cow<'_, [u8]>> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            let data = self
+                .walk
+                .source_range(entry, chunk.offset, chunk.length)
+                .and_then(|range| map.get(range));
+            if let Some(data) = data {
+                return Ok(Cow::Borrowed(data));
+            }
+        }
+        let mut r = self.reader(entry);
+        r.seek(SeekFrom::Start(chunk.offset))?;
+        Ok(Cow::Owned(read_exact_vec(&mut r, chunk.length)?))
+    }
+
+    /// Reads the stored bytes of `fragment` without interpreting them
+    ///
+    /// Compressed content isn't decompressed, only the transform of a scrambled archive
+    /// is decoded. Together with `HpkWriter::write_fragment_raw` the content of a file
+    /// is copied byte for byte, even with an unknown encoder. The stored content of a
+    /// file is the concatenation of its fragments in `fragments()`.
+    ///
+    pub fn read_fragment_raw(&self, fragment: &Fragment) -> HpkResult<Vec<u8>> {
+        let mut r = self.walk.stored_reader(fragment);
+        Ok(read_exact_vec(&mut r, fragment.length)?)
+    }
+
+    /// Opens a reader which decompresses the content of the file `entry` while reading
+    pub fn open_entry(
+        &self,
+        entry: &DirEntry,
+    ) -> HpkResult<EntryReader<FragmentedReader<SourceReader<'_>>>> {
+        if entry.is_dir() {
+            return Err(HpkError::EntryNotFound(entry.path().to_path_buf()));
+        }
+        let r = self.reader(entry);
+        let length = r.len();
+        EntryReader::new(r, length)
+    }
+
+    /// Extracts all entries into `dest` with the default options
+    #[cfg(feature = "fs")]
+    pub fn extract_to<P: AsRef<Path>>(&self, dest: P) -> HpkResult<()> {
+        self.extract_with(&ExtractOptions::default(), dest)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn extract_with<P: AsRef<Path>>(&self, options: &ExtractOptions, dest: P) -> HpkResult<()> {
+        let dest = dest.as_ref();
+        let _filedates = Path::new("_filedates");
+        let mut summary = AuditSummary::default();
+        let cancel = options.cancel.as_ref();
+        let mut completed = vec![];
+        let mut rejected = vec![];
+        let progress = options.progress.as_deref();
+        let mut state = match &options.state {
+            Some(path) => Some(ExtractState::open(path)?),
+            None => None,
+        };
+        let checksums = if options.skip_checksums {
+            None
+        } else {
+            self.checksums()?
+        };
+        // the checksums are verified instead of being extracted
+        let is_checksums = |e: &DirEntry| {
+            checksums.is_some() && e.depth() == 1 && checksum::is_checksums(e.path())
+        };
+        let total = self
+            .entries
+            .iter()
+            .filter(|e| !e.is_dir() && !is_checksums(e) && options.matches(e.path()))
+            .count();
+        // the extracted files by their fragments and whether the lua header is fixed
+        let mut extracted: HashMap<_, (PathBuf, Compression, u64, Option<String>)> = HashMap::new();
+
+        for entry in &self.entries {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                return Err(HpkError::Cancelled(completed));
+            }
+            if is_checksums(entry) {
+                continue;
+            }
+            let mut path = dest.join(entry.path());
+            if !is_safe_path(entry.path()) {
+                // the entries of a rejected directory are rejected as well
+                let rec = AuditRecord::unwritten(entry.path(), &path, AuditStatus::Rejected);
+                options.audit(&rec, &mut summary)?;
+                rejected.push(entry.path().to_path_buf());
+                continue;
+            }
+            if !options.matches(entry.path()) {
+                if !entry.is_dir() {
+                    let rec = AuditRecord::unwritten(entry.path(), &path, AuditStatus::Skipped);
+                    options.audit(&rec, &mut summary)?;
+                }
+                continue;
+            }
+            if entry.is_dir() {
+                if !path.exists() {
+                    fs::create_dir_all(&path)?;
+                }
+                continue;
+            }
+            let is_filedates =
+                !options.skip_filedates && entry.depth() == 1 && entry.path().eq(_filedates);
+            // the filedates are applied again, they could refer to files extracted later
+            let offset = match &state {
+                Some(state) if !is_filedates => {
+                    if state.is_done(entry.path()) {
+                        completed.push(entry.path().to_path_buf());
+                        continue;
+                    }
+                    let offset = state.offset(entry.path());
+                    match fs::metadata(&path) {
+                        Ok(md) if md.len() >= offset => offset,
+                        _ => 0,
+                    }
+                }
+                _ => 0,
+            };
+            if let Some(parent) = path.parent() {
+                if !parent.exists() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
+            let mut status = if path.exists() && offset == 0 {
+                AuditStatus::Overwritten
+            } else {
+                AuditStatus::Extracted
+            };
+            if status == AuditStatus::Overwritten && !is_filedates {
+                match options.existing {
+                    ExistingPolicy::Overwrite => {}
+                    ExistingPolicy::Skip => {
+                        let rec = AuditRecord::unwritten(entry.path(), &path, AuditStatus::Skipped);
+                        options.audit(&rec, &mut summary)?;
+                        continue;
+                    }
+                    ExistingPolicy::Rename => {
+                        path = renamed_path(&path);
+                        status = AuditStatus::Renamed;
+                    }
+                }
+            }
+            if let Some(progress) = progress {
+                progress.on_entry(entry.path(), completed.len(), total);
+            }
+            let fix_lua = options.fix_lua_files
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("lua"));
+            let fragments = &self.fragments()[entry.index()];
+            let key = (
+                fragments
+                    .iter()
+                    .map(|f| (f.offset, f.length))
+                    .collect::<Vec<_>>(),
+                fix_lua,
+            );
+            let is_duplicate = options.duplicates != DuplicatePolicy::Extract
+                && !is_filedates
+                && fragments.iter().any(|f| f.length > 0);
+            let duplicate = extracted.get(&key).filter(|_| is_duplicate);
+            if let Some((src, codec, written, digest)) = duplicate {
+                if options.verbose {
+                    println!("{}", path.display());
+                }
+                link_duplicate(src, &path, options.duplicates)?;
+                let rec = AuditRecord {
+                    path: entry.path(),
+                    dest: &path,
+                    bytes: *written,
+                    codec: *codec,
+                    digest: digest.as_deref(),
+                    status,
+                };
+                options.audit(&rec, &mut summary)?;
+                if let Some(state) = &mut state {
+                    state.done(entry.path())?;
+                }
+                completed.push(entry.path().to_path_buf());
+                continue;
+            }
+            let mut codec = Compression::None;
+            let mut written = 0;
+            let expected = match &checksums {
+                Some(checksums) if !is_filedates => checksums.get(entry.path()),
+                _ => None,
+            };
+            let mut sums = expected.map(|e| Checksummer::new(e.chunk_size));
+            let mut hasher = options.audit_hasher();
+            let result = self.read_entry(entry, |mut r| {
+                if options.verbose {
+                    println!("{}", path.display());
+                }
+                codec = get_compression(&mut r)?;
+                if is_filedates {
+                    process_filedates(dest, &mut r)
+                } else {
+                    if offset > 0 && hasher.is_enabled() {
+                        // the digest covers the content written by the previous run
+                        io::copy(&mut File::open(&path)?.take(offset), &mut hasher)?;
+                    }
+                    let file = if offset > 0 {
+                        let mut file = OpenOptions::new().write(true).open(&path)?;
+                        file.set_len(offset)?;
+                        file.seek(SeekFrom::Start(offset))?;
+                        file
+                    } else {
+                        File::create(&path)?
+                    };
+                    let out = SparseWriter::new(file, options.sparse).resume_at(offset);
+                    let out = BufWriter::new(out);
+                    let out = ProgressWriter {
+                        inner: out,
+                        progress,
+                    };
+                    let out = DigestWriter {
+                        inner: out,
+                        hasher: &mut hasher,
+                    };
+                    // the checksums cover the stored content before the lua header is fixed
+                    if fix_lua {
+                        let mut out = ChecksumWriter {
+                            inner: lua::fix_header(out),
+                            sums: sums.as_mut(),
+                        };
+                        written = copy_cancellable(&mut r, &mut out, cancel, options.threads)?;
+                        out.flush()?;
+                    } else {
+                        let mut out = ChecksumWriter {
+                            inner: out,
+                            sums: sums.as_mut(),
+                        };
+                        written = copy_cancellable(&mut r, &mut out, cancel, options.threads)?;
+                        out.flush()?;
+                    }
+                    Ok(())
+                }
+            });
+            match result {
+                Err(HpkError::Cancelled(_)) => {
+                    match &mut state {
+                        // the next extraction continues behind the written content,
+                        // a renamed file isn't found again
+                        Some(state) if status != AuditStatus::Renamed => {
+                            let len = fs::metadata(&path).map_or(0, |md| md.len());
+                            state.partial(entry.path(), len)?;
+                        }
+                        // discard the incomplete file
+                        _ => {
+                            let _ = fs::remove_file(&path);
+                        }
+                    }
+                    return Err(HpkError::Cancelled(completed));
+                }
+                result => result?,
+            }
+            if let (Some(expected), Some(sums)) = (expected, sums) {
+                if expected.first_mismatch(&sums.finish()).is_some() {
+                    // don't leave a broken file behind
+                    let _ = fs::remove_file(&path);
+                    return Err(HpkError::ChecksumMismatch(entry.path().to_path_buf()));
+                }
+            }
+            let digest = hasher.finish();
+            // the filedates are applied to the extracted files, they aren't written
+            if !is_filedates {
+                let rec = AuditRecord {
+                    path: entry.path(),
+                    dest: &path,
+                    bytes: written,
+                    codec,
+                    digest: digest.as_deref(),
+                    status,
+                };
+                options.audit(&rec, &mut summary)?;
+            }
+            if is_duplicate {
+                extracted.insert(key, (path.clone(), codec, written, digest));
+            }
+            if let Some(state) = &mut state {
+                if !is_filedates {
+                    state.done(entry.path())?;
+                }
+            }
+            completed.push(entry.path().to_path_buf());
+        }
+        if let Some(state) = state {
+            state.finish()?;
+        }
+        if let Some(log) = &options.audit {
+            log.borrow_mut().summary(&summary)?;
+        }
+        if !rejected.is_empty() {
+            return Err(HpkError::UnsafePaths(rejected));
+        }
+        Ok(())
+    }
+
+    /// Writes all entries decompressed into a new archive with the settings of `options`
+    ///
+    /// Works like `write_hpk`, the current position of `w` is the start of the new archive.
+    /// An archive compressed as a whole is spooled in memory before it's compressed.
+    ///
+    pub fn repack_into<W: Write + Seek>(
+        &self,
+        options: &CreateOptions,
+        w: &mut W,
+    ) -> HpkResult<()> {
+        if options.compress {
+            let spool = Cursor::new(vec![]);
+            compress_spooled(&options.compress_options, spool, w, |spooled| {
+                self.write_entries(options, spooled)
+            })
+        } else {
+            self.write_entries(options, w)
+        }
+    }
+
+    fn write_entries<W: Write + Seek>(&self, options: &CreateOptions, w: &mut W) -> HpkResult<()> {
+        let mut writer = HpkWriter::new(options, w)?;
+        for entry in self.entries.iter().filter(|e| e.depth() > 0) {
+            if entry.is_dir() {
+                writer.add_dir(entry.path())?;
+            } else {
+                writer.add_file(entry.path(), self.open_entry(entry)?)?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Writes all entries into a new archive and leaves out the unreferenced data
+    ///
+    /// The stored content is copied as is, the current position of `w` is the start of
+    /// the new archive.
+    ///
+    pub fn compact_into<W: Write + Seek>(&self, w: &mut W) -> HpkResult<()> {
+        let mut options = CreateOptions::new();
+        // the content is stored scrambled again
+        options.transform = self.walk.transform_ref().cloned();
+        if self.header().is_wide() {
+            options.with_wide_offsets();
+        }
+        if self.header().is_big_endian() {
+            options.with_big_endian();
+        }
+        // keep the space of an extended header
+        if self.header().data_offset > u32::from(self.header().layout().header_length()) {
+            options.with_data_offset(self.header().data_offset);
+        }
+        let mut writer = HpkWriter::new(&options, w)?;
+        for entry in self.entries.iter().filter(|e| e.depth() > 0) {
+            if entry.is_dir() {
+                writer.add_dir(entry.path())?;
+            } else {
+                writer.add_uncompressed(entry.path(), self.reader(entry))?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Compares this archive with `other`, see `hpk::diff`
+    pub fn diff(&self, other: &HpkArchive) -> HpkResult<Vec<DiffEntry>> {
+        diff::diff_archives(self, other)
+    }
+
+    /// Writes the entries which were added or modified compared to the archive `old`
+    /// into a patch archive, see `hpk::patch`
+    ///
+    /// Works like `repack_into`, the current position of `w` is the start of the patch.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn patch_into<W: Write + Seek>(
+        &self,
+        old: &HpkArchive,
+        options: &CreateOptions,
+        w: &mut W,
+    ) -> HpkResult<Vec<DiffEntry>> {
+        diff::write_patch(old, self, options, w)
+    }
+
+    /// Extracts this patch archive into `dest`, see `hpk::apply_patch`
+    #[cfg(feature = "fs")]
+    pub fn apply_patch_to<P: AsRef<Path>>(&self, old: &HpkArchive, dest: P) -> HpkResult<()> {
+        diff::extract_patch(self, old, dest.as_ref())
+    }
+
+    /// Reads the file `path` of this patch archive and reconstructs it if it's stored
+    /// as a delta against the archive `old`
+    #[cfg(feature = "fs")]
+    pub fn read_patched<P: AsRef<Path>>(&self, old: &HpkArchive, path: P) -> HpkResult<Vec<u8>> {
+        diff::read_patched(self, old, path.as_ref())
+    }
+
+    /// Collects the header, the fragment tables and the metadata of every entry
+    pub fn manifest(&self) -> HpkResult<ArchiveManifest> {
+        ArchiveManifest::new(self)
+    }
+
+    /// Checks the fragments and the compressed content of every entry
+    ///
+    /// Returns the corrupt entries, an empty list means the archive is intact.
+    ///
+    pub fn verify(&self) -> HpkResult<Vec<CorruptEntry>> {
+        verify::verify_archive(self)
+    }
+
+    /// Returns a detailed dump of the raw archive structures, see `ArchiveDebug`
+    pub fn debug(&self) -> ArchiveDebug<'_> {
+        ArchiveDebug::new(self)
+    }
+
+    /// Writes the entries as an indented tree like `tree(1)`, see `TreeOptions` for
+    /// the size and compression columns
+    pub fn print_tree(&self, w: &mut dyn Write, options: &TreeOptions) -> HpkResult<()> {
+        tree::print_tree(self, w, options)
+    }
+
+    /// Writes one JSON object per line for each of `entries`
+    ///
+    /// Every object has the `path`, the `type` (`"file"` or `"dir"`) and the
+    /// `fragments` with their `offset` and `length`. Files additionally have the
+    /// `codec`, the uncompressed `size`, the `stored` size and the number of `chunks`.
+    ///
+    pub fn print_json<'a, I>(&self, w: &mut dyn Write, entries: I) -> HpkResult<()>
+    where
+        I: IntoIterator<Item = &'a DirEntry>,
+    {
+        list::print_json(self, w, entries)
+    }
+
+    /// Checks the Ed25519 signature of `CreateOptions::sign_with` against `public_key`
+    ///
+    /// Fails with `HpkError::Unsigned` if the archive has no `_signature` entry and with
+    /// `HpkError::InvalidSignature` if the data, the directory tree or the fragment
+    /// table were changed after signing or the archive was signed with another key.
+    /// Check the signature before extracting an archive of an untrusted source.
+    ///
+    #[cfg(feature = "sign")]
+    pub fn verify_signature(&self, public_key: &[u8; 32]) -> HpkResult<()> {
+        sign::verify_signature(self, public_key)
+    }
+
+    /// Reads the `_checksums` entry of the root directory if there's one
+    pub(crate) fn checksums(&self) -> HpkResult<Option<ChecksumMap>> {
+        let entry = match self.entry(checksum::CHECKSUMS) {
+            Some(entry) if !entry.is_dir() => entry,
+            _ => return Ok(None),
+        };
+        Ok(Some(checksum::parse(self.open_entry(entry)?)?))
+    }
+
+    /// Reads the paths of the `_deltas` entry of a patch archive
+    #[cfg(feature = "fs")]
+    pub(crate) fn deltas(&self) -> HpkResult<Vec<PathBuf>> {
+        match self.entry(delta::DELTAS) {
+            Some(entry) if !entry.is_dir() => Ok(delta::parse(self.open_entry(entry)?)?),
+            _ => Ok(vec![]),
+        }
+    }
+
+    pub(crate) fn archive_len(&self) -> HpkResult<u64> {
+        self.walk.archive_len()
+    }
+
+    #[cfg(feature = "sign")]
+    pub(crate) fn raw_reader(
+        &self,
+        offset: u64,
+        length: u64,
+    ) -> FragmentedReader<SourceReader<'_>> {
+        self.walk.raw_reader(offset, length)
+    }
+
+    pub(crate) fn reader(&self, entry: &DirEntry) -> FragmentedReader<SourceReader<'_>> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            return self
+                .walk
+                .fragment_reader_from(SourceReader::map(map), entry);
+        }
+        self.walk.fragment_reader(entry)
+    }
+
+    #[cfg(feature = "fs")]
+    fn read_entry<F>(&self, entry: &DirEntry, op: F) -> HpkResult<()>
+    where
+        F: FnOnce(FragmentedReader<SourceReader<'_>>) -> HpkResult<()>,
+    {
+        if !entry.is_dir() {
+            op(self.reader(entry))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the first free path `name.N.ext` next to the existing file `path`
+#[cfg(feature = "fs")]
+fn renamed_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().map(|ext| ext.to_string_lossy());
+    (1..)
+        .map(|n| match &ext {
+            Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, n, ext)),
+            None => path.with_file_name(format!("{}.{}", stem, n)),
+        })
+        .find(|p| p.symlink_metadata().is_err())
+        .expect("a free file name")
+}
+
+/// Creates `path` with the content of the extracted file `src`
+#[cfg(feature = "fs")]
+fn link_duplicate(src: &Path, path: &Path, policy: DuplicatePolicy) -> io::Result<()> {
+    // a hard link doesn't replace an existing file, a copy would write through it
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)?;
+    }
+    if policy == DuplicatePolicy::HardLink && fs::hard_link(src, path).is_ok() {
+        return Ok(());
+    }
+    fs::copy(src, path)?;
+    Ok(())
+}
+
+impl<'a> IntoIterator for &'a HpkArchive {
+    type Item = &'a DirEntry;
+    type IntoIter = std::slice::Iter<'a, DirEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl IntoIterator for HpkArchive {
+    type Item = DirEntry;
+    type IntoIter = std::vec::IntoIter<DirEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// Largest decompressed size of a compressed archive accepted by `parse_bytes`
+const PARSE_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Parses the archive in `data`, e.g. the input of a fuzz target
+///
+/// Besides the header, the fragment tables and the directory tree, the compression
+/// headers of all files are parsed. Malformed input is returned as an error. The
+/// allocations are bounded by the length of `data`, a compressed archive is only
+/// decompressed if its decompressed size is at most 256 MiB.
+///
+pub fn parse_bytes(data: &[u8]) -> HpkResult<HpkArchive> {
+    let mut r = Cursor::new(data);
+    if get_compression(&mut r)?.is_compressed() {
+        let hdr = CompressionHeader::read_from(data.len() as u64, &mut r)?;
+        if u64::from(hdr.inflated_length) > PARSE_LIMIT {
+            return Err(HpkError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed archive exceeds the parse limit",
+            )));
+        }
+    }
+    let archive = HpkArchive::from_reader(Cursor::new(data.to_vec()))?;
+    for entry in archive.entries().iter().filter(|e| !e.is_dir()) {
+        archive.metadata(entry)?;
+    }
+    Ok(archive)
+}
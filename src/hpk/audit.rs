@@ -0,0 +1,355 @@
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "sha256")]
+use sha2::{Digest, Sha256};
+
+use crate::list::escape_json;
+use crate::Compression;
+
+/// Output format of the extraction audit manifest
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AuditFormat {
+    /// One JSON object per line
+    #[default]
+    JsonLines,
+    /// Tab separated values with a header row
+    Tsv,
+}
+
+/// What happened to an archive entry during the extraction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditStatus {
+    /// The file was written to a new destination path
+    Extracted,
+    /// The file was written over an existing file
+    Overwritten,
+    /// The file was written to a new name next to an existing file, see
+    /// `ExistingPolicy::Rename`
+    Renamed,
+    /// The entry didn't match the requested paths or its destination already existed
+    Skipped,
+    /// The path of the entry would leave the destination directory
+    Rejected,
+}
+
+impl AuditStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuditStatus::Extracted => "extracted",
+            AuditStatus::Overwritten => "overwritten",
+            AuditStatus::Renamed => "renamed",
+            AuditStatus::Skipped => "skipped",
+            AuditStatus::Rejected => "rejected",
+        }
+    }
+}
+
+pub(crate) struct AuditRecord<'a> {
+    pub path: &'a Path,
+    pub dest: &'a Path,
+    pub bytes: u64,
+    pub codec: Compression,
+    /// Hex encoded SHA-256 digest of the written file
+    pub digest: Option<&'a str>,
+    pub status: AuditStatus,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// Returns the record of an entry which wasn't written
+    pub fn unwritten(path: &'a Path, dest: &'a Path, status: AuditStatus) -> Self {
+        AuditRecord {
+            path,
+            dest,
+            bytes: 0,
+            codec: Compression::None,
+            digest: None,
+            status,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct AuditSummary {
+    entries: u64,
+    extracted: u64,
+    overwritten: u64,
+    renamed: u64,
+    skipped: u64,
+    rejected: u64,
+    bytes: u64,
+}
+
+/// Writes one record per entry and flushes after every record, so an interrupted
+/// extraction still leaves a usable partial log.
+pub(crate) struct AuditLog {
+    w: Box<dyn Write + Send>,
+    format: AuditFormat,
+    header_written: bool,
+}
+
+impl AuditLog {
+    pub fn new(w: Box<dyn Write + Send>, format: AuditFormat) -> Self {
+        Self {
+            w,
+            format,
+            header_written: false,
+        }
+    }
+
+    pub fn format(&self) -> AuditFormat {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: AuditFormat) {
+        self.format = format;
+    }
+
+    pub fn record(&mut self, rec: &AuditRecord<'_>, summary: &mut AuditSummary) -> io::Result<()> {
+        summary.entries += 1;
+        summary.bytes += rec.bytes;
+        match rec.status {
+            AuditStatus::Extracted => summary.extracted += 1,
+            AuditStatus::Overwritten => summary.overwritten += 1,
+            AuditStatus::Renamed => summary.renamed += 1,
+            AuditStatus::Skipped => summary.skipped += 1,
+            AuditStatus::Rejected => summary.rejected += 1,
+        }
+
+        let path = rec.path.to_string_lossy();
+        let dest = rec.dest.to_string_lossy();
+        match self.format {
+            AuditFormat::JsonLines => writeln!(
+                self.w,
+                r#"{{"type":"entry","timestamp":{},"path":"{}","dest":"{}","bytes":{},"codec":"{}","digest":{},"status":"{}"}}"#,
+                timestamp(),
+                escape_json(&path),
+                escape_json(&dest),
+                rec.bytes,
+                rec.codec,
+                rec.digest
+                    .map_or_else(|| "null".into(), |d| format!(r#""{}""#, d)),
+                rec.status.as_str(),
+            )?,
+            AuditFormat::Tsv => {
+                self.write_tsv_header()?;
+                writeln!(
+                    self.w,
+                    "entry\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    timestamp(),
+                    rec.status.as_str(),
+                    escape_tsv(&path),
+                    escape_tsv(&dest),
+                    rec.bytes,
+                    rec.codec,
+                    rec.digest.unwrap_or_default(),
+                )?
+            }
+        }
+        self.w.flush()
+    }
+
+    pub fn summary(&mut self, summary: &AuditSummary) -> io::Result<()> {
+        match self.format {
+            AuditFormat::JsonLines => writeln!(
+                self.w,
+                r#"{{"type":"summary","timestamp":{},"entries":{},"extracted":{},"overwritten":{},"renamed":{},"skipped":{},"rejected":{},"bytes":{}}}"#,
+                timestamp(),
+                summary.entries,
+                summary.extracted,
+                summary.overwritten,
+                summary.renamed,
+                summary.skipped,
+                summary.rejected,
+                summary.bytes,
+            )?,
+            AuditFormat::Tsv => {
+                self.write_tsv_header()?;
+                writeln!(
+                    self.w,
+                    "summary\t{}\tentries={} extracted={} overwritten={} renamed={} skipped={} rejected={}\t\t\t{}\t\t",
+                    timestamp(),
+                    summary.entries,
+                    summary.extracted,
+                    summary.overwritten,
+                    summary.renamed,
+                    summary.skipped,
+                    summary.rejected,
+                    summary.bytes,
+                )?
+            }
+        }
+        self.w.flush()
+    }
+
+    fn write_tsv_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.header_written = true;
+            writeln!(
+                self.w,
+                "type\ttimestamp\tstatus\tpath\tdest\tbytes\tcodec\tdigest"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes the written content of a file for the digest of its audit record
+///
+/// Only hashes with the `sha256` feature, `finish` returns `None` otherwise.
+///
+pub(crate) struct AuditHasher {
+    #[cfg(feature = "sha256")]
+    sha256: Option<Sha256>,
+}
+
+impl AuditHasher {
+    #[cfg(feature = "sha256")]
+    pub fn new(enabled: bool) -> Self {
+        AuditHasher {
+            sha256: enabled.then(Sha256::new),
+        }
+    }
+
+    #[cfg(not(feature = "sha256"))]
+    pub fn new(_enabled: bool) -> Self {
+        AuditHasher {}
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "sha256")]
+        return self.sha256.is_some();
+        #[cfg(not(feature = "sha256"))]
+        false
+    }
+
+    /// Returns the hex encoded digest
+    pub fn finish(self) -> Option<String> {
+        #[cfg(feature = "sha256")]
+        return self.sha256.map(|h| crate::sha256::hex(&h.finalize()));
+        #[cfg(not(feature = "sha256"))]
+        None
+    }
+}
+
+impl Write for AuditHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "sha256")]
+        if let Some(h) = &mut self.sha256 {
+            h.update(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Passes every write to an `AuditHasher`
+pub(crate) struct DigestWriter<'a, W> {
+    pub inner: W,
+    pub hasher: &'a mut AuditHasher,
+}
+
+impl<W: Write> Write for DigestWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn escape_tsv(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let buf = Arc::new(Mutex::new(vec![]));
+        let mut log = AuditLog::new(Box::new(Shared(buf.clone())), AuditFormat::JsonLines);
+        let mut summary = AuditSummary::default();
+        let rec = AuditRecord {
+            path: Path::new("scripts/a\"b.lua"),
+            dest: Path::new("out/scripts/a\"b.lua"),
+            bytes: 42,
+            codec: Compression::Zlib,
+            digest: Some("abc"),
+            status: AuditStatus::Extracted,
+        };
+        log.record(&rec, &mut summary).unwrap();
+        log.summary(&summary).unwrap();
+
+        let out = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""path":"scripts/a\"b.lua""#));
+        assert!(
+            lines[0].contains(r#""bytes":42,"codec":"ZLIB","digest":"abc","status":"extracted""#)
+        );
+        assert!(lines[1].starts_with(r#"{"type":"summary""#));
+        assert!(lines[1].ends_with(
+            r#""entries":1,"extracted":1,"overwritten":0,"renamed":0,"skipped":0,"rejected":0,"bytes":42}"#
+        ));
+    }
+
+    #[test]
+    fn tsv() {
+        let buf = Arc::new(Mutex::new(vec![]));
+        let mut log = AuditLog::new(Box::new(Shared(buf.clone())), AuditFormat::Tsv);
+        let mut summary = AuditSummary::default();
+        let rec = AuditRecord {
+            path: Path::new("a.lst"),
+            dest: Path::new("out/a.lst"),
+            bytes: 7,
+            codec: Compression::None,
+            digest: None,
+            status: AuditStatus::Overwritten,
+        };
+        log.record(&rec, &mut summary).unwrap();
+        log.summary(&summary).unwrap();
+
+        let out = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "type\ttimestamp\tstatus\tpath\tdest\tbytes\tcodec\tdigest"
+        );
+        assert!(lines[1].ends_with("\toverwritten\ta.lst\tout/a.lst\t7\tNone\t"));
+        assert!(lines[2].starts_with("summary\t"));
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{HpkError, HpkResult};
+
+/// A shareable flag to stop a running extraction or creation
+///
+/// The token is checked once per entry and once per chunk while compressing or
+/// decompressing. A cancelled operation returns `HpkError::Cancelled`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(token: Option<&CancelToken>) -> HpkResult<()> {
+        match token {
+            Some(token) if token.is_cancelled() => Err(HpkError::Cancelled(vec![])),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Name of the entry in the root directory which stores the checksums
+pub(crate) const CHECKSUMS: &str = "_checksums";
+
+/// CRC32 checksums of the decompressed content in blocks of `chunk_size` bytes
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChunkSums {
+    pub chunk_size: u32,
+    pub sums: Vec<u32>,
+}
+
+impl ChunkSums {
+    /// Returns the index of the first block which differs
+    pub fn first_mismatch(&self, other: &ChunkSums) -> Option<usize> {
+        if self == other {
+            return None;
+        }
+        let n = self
+            .sums
+            .iter()
+            .zip(&other.sums)
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(n)
+    }
+}
+
+pub(crate) type ChecksumMap = BTreeMap<PathBuf, ChunkSums>;
+
+/// Parses the lines `path=chunk_size:crc,crc,...` of a `_checksums` entry
+pub(crate) fn parse<R: Read>(r: R) -> io::Result<ChecksumMap> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid checksum line: {}", line),
+        )
+    };
+    let mut map = ChecksumMap::new();
+    for line in io::BufReader::new(r).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (path, value) = line.rsplit_once('=').ok_or_else(|| invalid(&line))?;
+        let (chunk_size, sums) = value.split_once(':').ok_or_else(|| invalid(&line))?;
+        let chunk_size = chunk_size.parse().map_err(|_| invalid(&line))?;
+        let sums = sums
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| u32::from_str_radix(s, 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid(&line))?;
+        map.insert(PathBuf::from(path), ChunkSums { chunk_size, sums });
+    }
+    Ok(map)
+}
+
+/// Writes the lines of a `_checksums` entry, the paths are separated by `/`
+pub(crate) fn write<W: Write>(map: &ChecksumMap, mut w: W) -> io::Result<()> {
+    for (path, sums) in map {
+        let path: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+        let hex: Vec<_> = sums.sums.iter().map(|s| format!("{:08x}", s)).collect();
+        writeln!(
+            w,
+            "{}={}:{}",
+            path.join("/"),
+            sums.chunk_size,
+            hex.join(",")
+        )?;
+    }
+    Ok(())
+}
+
+/// Computes the checksums of the data passed to `update`
+pub(crate) struct Checksummer {
+    chunk_size: u32,
+    sums: Vec<u32>,
+    hasher: crc32fast::Hasher,
+    filled: u64,
+}
+
+impl Checksummer {
+    pub fn new(chunk_size: u32) -> Self {
+        Checksummer {
+            chunk_size: chunk_size.max(1),
+            sums: vec![],
+            hasher: crc32fast::Hasher::new(),
+            filled: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut buf: &[u8]) {
+        let chunk_size = u64::from(self.chunk_size);
+        while !buf.is_empty() {
+            let n = std::cmp::min(buf.len() as u64, chunk_size - self.filled) as usize;
+            self.hasher.update(&buf[..n]);
+            self.filled += n as u64;
+            buf = &buf[n..];
+            if self.filled == chunk_size {
+                let hasher = std::mem::replace(&mut self.hasher, crc32fast::Hasher::new());
+                self.sums.push(hasher.finalize());
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> ChunkSums {
+        if self.filled > 0 {
+            self.sums.push(self.hasher.finalize());
+        }
+        ChunkSums {
+            chunk_size: self.chunk_size,
+            sums: self.sums,
+        }
+    }
+}
+
+/// Passes every read to a `Checksummer` if there's one
+pub(crate) struct ChecksumReader<'a, R> {
+    pub inner: R,
+    pub sums: Option<&'a mut Checksummer>,
+}
+
+impl<R: Read> Read for ChecksumReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(sums) = self.sums.as_deref_mut() {
+            sums.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Passes every write to a `Checksummer` if there's one
+pub(crate) struct ChecksumWriter<'a, W> {
+    pub inner: W,
+    pub sums: Option<&'a mut Checksummer>,
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(sums) = self.sums.as_deref_mut() {
+            sums.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns `true` if `path` is the `_checksums` entry in the root directory
+#[cfg(feature = "fs")]
+pub(crate) fn is_checksums(path: &Path) -> bool {
+    path == Path::new(CHECKSUMS)
+}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_sums() {
+        let data: Vec<u8> = (0..10u8).collect();
+        let mut sums = Checksummer::new(4);
+        sums.update(&data[..3]);
+        sums.update(&data[3..]);
+        let sums = sums.finish();
+        let expected: Vec<_> = data
+            .chunks(4)
+            .map(|c| {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(c);
+                hasher.finalize()
+            })
+            .collect();
+        assert_eq!(sums.sums, expected);
+
+        let mut map = ChecksumMap::new();
+        map.insert(PathBuf::from("a/b.lua"), sums.clone());
+        let mut buf = vec![];
+        write(&map, &mut buf).unwrap();
+        assert!(buf.starts_with(b"a/b.lua=4:"));
+        assert_eq!(parse(&buf[..]).unwrap(), map);
+
+        let mut other = sums.clone();
+        other.sums[2] ^= 1;
+        assert_eq!(sums.first_mismatch(&other), Some(2));
+        assert_eq!(sums.first_mismatch(&sums), None);
+    }
+}
+// }}}
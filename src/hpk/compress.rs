@@ -1,8 +1,14 @@
 use std::io;
 use std::io::prelude::*;
 use std::io::Cursor;
+use std::marker::PhantomData;
 
+#[cfg(feature = "zstd")]
 use zstd::stream::Decoder as ZstdDecoder;
+#[cfg(feature = "zstd")]
+use zstd::stream::Encoder as ZstdEncoder;
+
+use crate::Compression;
 
 pub trait Decoder {
     fn decode_chunk<R: Read + ?Sized, W: Write + ?Sized>(r: &mut R, w: &mut W) -> io::Result<u64>;
@@ -10,22 +16,132 @@ pub trait Decoder {
 
 pub trait Encoder {
     fn encode_chunk<R: Read, W: Write>(r: &mut R, w: &mut W) -> io::Result<u64>;
+
+    /// Encodes the chunk with the given compression level
+    ///
+    /// Encoders without compression levels ignore the level.
+    ///
+    fn encode_chunk_with_level<R: Read, W: Write>(
+        r: &mut R,
+        w: &mut W,
+        _level: u32,
+    ) -> io::Result<u64> {
+        Self::encode_chunk(r, w)
+    }
+}
+
+/// A chunk codec of the compression registry, see `codecs`
+///
+/// The codec of a compressed entry is found by the identifier at the start of its
+/// compression header.
+///
+pub trait Codec: Send + Sync {
+    fn compression(&self) -> Compression;
+
+    fn decode(&self, r: &mut dyn Read, w: &mut dyn Write) -> io::Result<u64>;
+
+    /// Encodes the chunk, `None` uses the default level of the encoder
+    fn encode(&self, r: &mut dyn Read, w: &mut dyn Write, level: Option<u32>) -> io::Result<u64>;
+}
+
+/// A codec of a decoder and an encoder type
+struct Registered<D, E> {
+    compression: Compression,
+    _types: PhantomData<fn() -> (D, E)>,
+}
+
+impl<D, E> Registered<D, E> {
+    const fn new(compression: Compression) -> Self {
+        Registered {
+            compression,
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<D: Decoder, E: Encoder> Codec for Registered<D, E> {
+    fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    fn decode(&self, r: &mut dyn Read, w: &mut dyn Write) -> io::Result<u64> {
+        D::decode_chunk(r, w)
+    }
+
+    fn encode(
+        &self,
+        mut r: &mut dyn Read,
+        mut w: &mut dyn Write,
+        level: Option<u32>,
+    ) -> io::Result<u64> {
+        match level {
+            Some(level) => E::encode_chunk_with_level(&mut r, &mut w, level),
+            None => E::encode_chunk(&mut r, &mut w),
+        }
+    }
+}
+
+static CODECS: [&dyn Codec; 3] = [
+    &Registered::<Zlib, Zlib>::new(Compression::Zlib),
+    &Registered::<Lz4, Lz4Block>::new(Compression::Lz4),
+    &Registered::<Zstd, Zstd>::new(Compression::Zstd),
+];
+
+/// Returns the known chunk codecs, `Compression::None` stores the data as it is
+pub fn codecs() -> &'static [&'static dyn Codec] {
+    &CODECS
+}
+
+/// Returns the codec of the compression, `None` for `Compression::None`
+pub fn codec(compression: Compression) -> Option<&'static dyn Codec> {
+    codecs()
+        .iter()
+        .copied()
+        .find(|c| c.compression() == compression)
+}
+
+/// Returns the codec of the 4-byte identifier at the start of an entry
+pub fn detect(identifier: &[u8]) -> Option<&'static dyn Codec> {
+    codecs()
+        .iter()
+        .copied()
+        .find(|c| c.compression().identifier() == identifier)
 }
 
+/// The magic number of the LZ4 frame format (little endian)
+const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+
 pub enum Zlib {}
 pub enum Zstd {}
+/// Decodes LZ4 chunks stored in the block format (Surviving Mars) or in the frame format
+pub enum Lz4 {}
 pub enum Lz4Block {}
-#[allow(dead_code)]
 #[cfg(feature = "lz4frame")]
 pub enum Lz4Frame {}
 
+impl Decoder for Lz4 {
+    fn decode_chunk<R: Read + ?Sized, W: Write + ?Sized>(r: &mut R, w: &mut W) -> io::Result<u64> {
+        let mut buf = vec![];
+        r.read_to_end(&mut buf)?;
+        if buf.starts_with(&LZ4_FRAME_MAGIC) {
+            #[cfg(feature = "lz4frame")]
+            return Lz4Frame::decode_chunk(&mut Cursor::new(buf), w);
+            #[cfg(not(feature = "lz4frame"))]
+            return Err(io::Error::other(
+                "LZ4 frame format requires the `lz4frame` feature",
+            ));
+        }
+        Lz4Block::decode_chunk(&mut Cursor::new(buf), w)
+    }
+}
+
 impl Decoder for Lz4Block {
     fn decode_chunk<R: Read + ?Sized, W: Write + ?Sized>(r: &mut R, w: &mut W) -> io::Result<u64> {
         let mut buf = vec![];
         r.read_to_end(&mut buf)?;
         match lz4_compress::decompress(&buf) {
             Ok(buf) => io::copy(&mut Cursor::new(&buf), w),
-            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+            Err(e) => Err(io::Error::other(e)),
         }
     }
 }
@@ -70,7 +186,17 @@ impl Decoder for Zlib {
 
 impl Encoder for Zlib {
     fn encode_chunk<R: Read, W: Write>(r: &mut R, w: &mut W) -> io::Result<u64> {
-        let mut enc = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::best());
+        Self::encode_chunk_with_level(r, w, flate2::Compression::best().level())
+    }
+
+    /// Levels range from 0 (no compression) to 9 (best compression)
+    fn encode_chunk_with_level<R: Read, W: Write>(
+        r: &mut R,
+        w: &mut W,
+        level: u32,
+    ) -> io::Result<u64> {
+        let level = flate2::Compression::new(level.min(9));
+        let mut enc = flate2::write::ZlibEncoder::new(vec![], level);
         io::copy(r, &mut enc)?;
         match enc.finish() {
             Ok(buf) => {
@@ -82,6 +208,7 @@ impl Encoder for Zlib {
     }
 }
 
+#[cfg(feature = "zstd")]
 impl Decoder for Zstd {
     fn decode_chunk<R: Read + ?Sized, W: Write + ?Sized>(r: &mut R, w: &mut W) -> io::Result<u64> {
         let mut dec = ZstdDecoder::new(r)?;
@@ -89,10 +216,73 @@ impl Decoder for Zstd {
     }
 }
 
+#[cfg(feature = "zstd")]
+impl Encoder for Zstd {
+    fn encode_chunk<R: Read, W: Write>(r: &mut R, w: &mut W) -> io::Result<u64> {
+        Self::encode_chunk_with_level(r, w, zstd::DEFAULT_COMPRESSION_LEVEL as u32)
+    }
+
+    /// Levels range from 1 to 22
+    fn encode_chunk_with_level<R: Read, W: Write>(
+        r: &mut R,
+        w: &mut W,
+        level: u32,
+    ) -> io::Result<u64> {
+        let level = level.clamp(1, 22) as i32;
+        let mut enc = ZstdEncoder::new(vec![], level)?;
+        io::copy(r, &mut enc)?;
+        let mut buf = Cursor::new(enc.finish()?);
+        io::copy(&mut buf, w)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+impl Decoder for Zstd {
+    fn decode_chunk<R: Read + ?Sized, W: Write + ?Sized>(_: &mut R, _: &mut W) -> io::Result<u64> {
+        Err(zstd_unsupported())
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+impl Encoder for Zstd {
+    fn encode_chunk<R: Read, W: Write>(_: &mut R, _: &mut W) -> io::Result<u64> {
+        Err(zstd_unsupported())
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn zstd_unsupported() -> io::Error {
+    io::Error::other("ZSTD requires the `zstd` feature")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn registry() {
+        let input = "Hello World, Hello World, Hello World".repeat(10);
+        for codec in codecs() {
+            let compression = codec.compression();
+            assert_eq!(
+                detect(compression.identifier()).unwrap().compression(),
+                compression
+            );
+            if cfg!(not(feature = "zstd")) && compression == Compression::Zstd {
+                continue;
+            }
+            let mut buf = vec![];
+            let mut output = vec![];
+            codec
+                .encode(&mut input.as_bytes(), &mut buf, Some(1))
+                .unwrap();
+            codec.decode(&mut &buf[..], &mut output).unwrap();
+            assert_eq!(input.as_bytes(), &output[..]);
+        }
+        assert!(codec(Compression::None).is_none());
+        assert!(detect(b"LZMA").is_none());
+    }
+
     #[test]
     fn zlib() {
         let input = "Hello World".as_bytes();
@@ -103,6 +293,29 @@ mod tests {
         assert_eq!(input, &output[..]);
     }
 
+    #[test]
+    fn zlib_levels() {
+        let input = "Hello World, Hello World, Hello World".repeat(100);
+        for level in &[0, 1, 6, 9, 42] {
+            let mut buf = vec![];
+            let mut output = vec![];
+            Zlib::encode_chunk_with_level(&mut Cursor::new(&input), &mut buf, *level).unwrap();
+            Zlib::decode_chunk(&mut Cursor::new(buf), &mut output).unwrap();
+            assert_eq!(input.as_bytes(), &output[..]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd() {
+        let input = "Hello World".as_bytes();
+        let mut buf = vec![];
+        let mut output = vec![];
+        Zstd::encode_chunk(&mut Cursor::new(input), &mut buf).unwrap();
+        Zstd::decode_chunk(&mut Cursor::new(buf), &mut output).unwrap();
+        assert_eq!(input, &output[..]);
+    }
+
     #[test]
     fn lz4_block() {
         let input = "Hello World".as_bytes();
@@ -113,6 +326,28 @@ mod tests {
         assert_eq!(input, &output[..]);
     }
 
+    #[test]
+    fn lz4_detect_block() {
+        let input = "Hello World".as_bytes();
+        let mut buf = vec![];
+        let mut output = vec![];
+        Lz4Block::encode_chunk(&mut Cursor::new(input), &mut buf).unwrap();
+        Lz4::decode_chunk(&mut Cursor::new(buf), &mut output).unwrap();
+        assert_eq!(input, &output[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "lz4frame")]
+    fn lz4_detect_frame() {
+        let input = "Hello World".as_bytes();
+        let mut buf = vec![];
+        let mut output = vec![];
+        Lz4Frame::encode_chunk(&mut Cursor::new(input), &mut buf).unwrap();
+        assert!(buf.starts_with(&LZ4_FRAME_MAGIC));
+        Lz4::decode_chunk(&mut Cursor::new(buf), &mut output).unwrap();
+        assert_eq!(input, &output[..]);
+    }
+
     #[test]
     #[cfg(feature = "lz4frame")]
     fn lz4_frame() {
@@ -0,0 +1,353 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Cursor, SeekFrom};
+
+use crate::compress::{codec, detect, Codec};
+use crate::format::COMPRESSION_HEADER_LENGTH;
+use crate::pool::Task;
+use crate::{CancelToken, Compression, CompressionHeader, HpkError, HpkResult};
+
+/// Probes the compression identifier at the current position of `r`
+///
+/// Entries shorter than the 4-byte identifier are stored uncompressed, other read
+/// errors are returned.
+///
+pub fn get_compression<T: Read + Seek>(r: &mut T) -> HpkResult<Compression> {
+    let pos = r.stream_position()?;
+    let compression = match Compression::read_from(r) {
+        Ok(c) => c,
+        Err(HpkError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => Compression::None,
+        Err(e) => return Err(e),
+    };
+    r.seek(SeekFrom::Start(pos))?;
+
+    Ok(compression)
+}
+
+/// Compresses the data with the encoder used
+///
+/// if no data is written at all the hpk compression header is written without any chunks
+/// it's the same behaviour as in a DLC file for Tropico 4
+///
+/// With `Compression::None` the data is copied as it is.
+///
+pub fn compress(options: &CompressOptions, r: &mut dyn Read, w: &mut dyn Write) -> HpkResult<u64> {
+    if !options.compressor.is_compressed() {
+        return Ok(io::copy(r, w)?);
+    }
+
+    let mut queue = EncodeQueue::new(options);
+    queue.push((), options.compressor, r)?;
+    let ((), file) = queue.pop().expect("the file was queued");
+    file.write_to(w)
+}
+
+/// Estimates the stored size of a file with `size` bytes which is compressed from `r`
+///
+/// Only the first chunk is read and encoded, the other chunks are assumed to compress
+/// at the same ratio. The estimate includes the compression header.
+///
+#[cfg(feature = "fs")]
+pub(crate) fn estimate_compressed(
+    options: &CompressOptions,
+    r: &mut dyn Read,
+    size: u64,
+) -> HpkResult<u64> {
+    let mut chunk = vec![];
+    let n = r
+        .take(u64::from(options.chunk_size))
+        .read_to_end(&mut chunk)?;
+    if n == 0 {
+        return Ok(COMPRESSION_HEADER_LENGTH);
+    }
+    let codec = codec(options.compressor).expect("compressed entries have a codec");
+    let encoded = encode(codec, options.level, &chunk)?.len();
+    let chunks = size.div_ceil(u64::from(options.chunk_size.max(1)));
+    let data = (u128::from(size) * encoded as u128).div_ceil(n as u128);
+    let data = u64::try_from(data).unwrap_or(u64::MAX);
+    Ok(COMPRESSION_HEADER_LENGTH + 4 * chunks + data)
+}
+
+/// Compresses the archive which `write` stores in `spool` as a whole into `w`
+///
+/// The archive is written through a buffer, the spool is rewound before it's
+/// compressed.
+///
+pub(crate) fn compress_spooled<S, F>(
+    options: &CompressOptions,
+    spool: S,
+    w: &mut dyn Write,
+    write: F,
+) -> HpkResult<()>
+where
+    S: Read + Write + Seek,
+    F: FnOnce(&mut BufWriter<S>) -> HpkResult<()>,
+{
+    let spooled = write_spooled(spool, write)?;
+    compress(options, &mut BufReader::new(spooled), w)?;
+    Ok(())
+}
+
+/// Writes into `spool` through a buffer with `write` and rewinds it for reading
+pub(crate) fn write_spooled<S, F>(spool: S, write: F) -> HpkResult<S>
+where
+    S: Write + Seek,
+    F: FnOnce(&mut BufWriter<S>) -> HpkResult<()>,
+{
+    let mut spooled = BufWriter::new(spool);
+    write(&mut spooled)?;
+    let mut spooled = spooled.into_inner().map_err(|e| e.into_error())?;
+    spooled.seek(SeekFrom::Start(0))?;
+    Ok(spooled)
+}
+
+/// Encodes the chunks of consecutive files on the shared worker pool
+///
+/// Up to `threads` chunks of the options are encoded at the same time, also chunks of
+/// different files, so a run of small files is compressed in parallel like the chunks
+/// of a large file. `pop` returns the files in the order they were pushed.
+///
+pub(crate) struct EncodeQueue<'o, T> {
+    options: &'o CompressOptions,
+    files: VecDeque<(T, EncodedFile)>,
+    /// Number of chunks which are still encoded
+    running: usize,
+}
+
+impl<'o, T> EncodeQueue<'o, T> {
+    pub(crate) fn new(options: &'o CompressOptions) -> Self {
+        EncodeQueue {
+            options,
+            files: VecDeque::new(),
+            running: 0,
+        }
+    }
+
+    /// Reads `r` in chunks and queues them for encoding with `compressor`
+    ///
+    /// Returns the tag of the file which can be updated until the file is popped.
+    ///
+    pub(crate) fn push(
+        &mut self,
+        tag: T,
+        compressor: Compression,
+        r: &mut dyn Read,
+    ) -> HpkResult<&mut T> {
+        let mut file = EncodedFile {
+            compressor,
+            chunk_size: self.options.chunk_size,
+            inflated_length: 0,
+            chunks: vec![],
+            waited: 0,
+        };
+        if let Err(e) = self.read_chunks(&mut file, r) {
+            // the dropped chunks are still encoded but nobody waits for them
+            self.running -= file.running();
+            return Err(e);
+        }
+        self.files.push_back((tag, file));
+        Ok(&mut self.files.back_mut().expect("the file was queued").0)
+    }
+
+    fn read_chunks(&mut self, file: &mut EncodedFile, r: &mut dyn Read) -> HpkResult<()> {
+        let codec = codec(file.compressor).expect("compressed entries have a codec");
+        let level = self.options.level;
+        let threads = self.options.threads.max(1);
+        loop {
+            CancelToken::check(self.options.cancel.as_ref())?;
+
+            let mut chunk = vec![];
+            let n = r.take(u64::from(file.chunk_size)).read_to_end(&mut chunk)?;
+            if n == 0 {
+                // no data left.
+                return Ok(());
+            }
+            // the compression header can't describe more than 4 GiB of data
+            file.inflated_length = file
+                .inflated_length
+                .checked_add(n as u32)
+                .ok_or(HpkError::OffsetOverflow)?;
+
+            if self.running >= threads {
+                self.wait_oldest(file);
+            }
+            let task = Task::spawn(threads, move || encode(codec, level, &chunk));
+            if task.is_running() {
+                self.running += 1;
+            }
+            file.chunks.push(task);
+        }
+    }
+
+    /// Waits for the chunk which was queued first of the chunks still encoded
+    fn wait_oldest(&mut self, current: &mut EncodedFile) {
+        let files = self.files.iter_mut().map(|(_, file)| file);
+        for file in files.chain(Some(current)) {
+            if file.wait_next() {
+                self.running -= 1;
+                return;
+            }
+        }
+    }
+
+    /// Returns the first file once `threads` chunks are queued, an empty file counts
+    /// as one chunk
+    #[cfg(feature = "fs")]
+    pub(crate) fn pop_full(&mut self) -> Option<(T, EncodedFile)> {
+        let queued: usize = self.files.iter().map(|(_, f)| f.chunks.len().max(1)).sum();
+        if queued >= self.options.threads.max(1) {
+            self.pop()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of queued files
+    #[cfg(feature = "fs")]
+    pub(crate) fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Waits for the chunks of the first file and returns it
+    pub(crate) fn pop(&mut self) -> Option<(T, EncodedFile)> {
+        let (tag, mut file) = self.files.pop_front()?;
+        while file.wait_next() {
+            self.running -= 1;
+        }
+        Some((tag, file))
+    }
+}
+
+/// The chunks of a file which are encoded by an `EncodeQueue`
+pub(crate) struct EncodedFile {
+    compressor: Compression,
+    chunk_size: u32,
+    inflated_length: u32,
+    chunks: Vec<Task<io::Result<Vec<u8>>>>,
+    /// Number of leading chunks which are encoded
+    waited: usize,
+}
+
+impl EncodedFile {
+    /// Waits for the first chunk which is still encoded, `false` if there is none
+    fn wait_next(&mut self) -> bool {
+        while let Some(task) = self.chunks.get_mut(self.waited) {
+            self.waited += 1;
+            if task.is_running() {
+                task.wait();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn running(&self) -> usize {
+        self.chunks[self.waited..]
+            .iter()
+            .filter(|task| task.is_running())
+            .count()
+    }
+
+    /// Writes the compression header and the encoded chunks
+    pub(crate) fn write_to(self, w: &mut dyn Write) -> HpkResult<u64> {
+        let chunks = self
+            .chunks
+            .into_iter()
+            .map(Task::join)
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut offsets = Vec::with_capacity(chunks.len());
+        let mut position = 0;
+        for chunk in &chunks {
+            offsets.push(u32::try_from(position).map_err(|_| HpkError::OffsetOverflow)?);
+            position += chunk.len();
+        }
+
+        let header_size = CompressionHeader::write(
+            self.compressor,
+            self.chunk_size,
+            self.inflated_length,
+            &offsets,
+            w,
+        )?;
+        for chunk in &chunks {
+            w.write_all(chunk)?;
+        }
+        Ok(header_size + position as u64)
+    }
+}
+
+/// Encodes a chunk, the game stores the raw chunk if the compression doesn't help
+fn encode(codec: &dyn Codec, level: Option<u32>, chunk: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    codec.encode(&mut Cursor::new(chunk), &mut out, level)?;
+    // a chunk with the full length is never decoded
+    if out.len() >= chunk.len() {
+        return Ok(chunk.to_vec());
+    }
+    Ok(out)
+}
+
+/// Chunk size, encoder and level of `compress`
+pub struct CompressOptions {
+    pub(crate) chunk_size: u32,
+    pub(crate) compressor: Compression,
+    pub(crate) level: Option<u32>,
+    pub(crate) threads: usize,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32768,
+            compressor: Compression::Zlib,
+            level: None,
+            threads: 1,
+            cancel: None,
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn read_from<T: Read + ?Sized>(r: &mut T) -> HpkResult<Self> {
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf)?;
+        Ok(detect(&buf).map_or(Compression::None, |c| c.compression()))
+    }
+}
+
+impl CompressionHeader {
+    pub fn read_from<T: Read + ?Sized>(length: u64, r: &mut T) -> HpkResult<CompressionHeader> {
+        let header_length = COMPRESSION_HEADER_LENGTH as usize;
+        let mut data = vec![0; header_length + 4];
+        r.read_exact(&mut data[..header_length])?;
+        match r.read_exact(&mut data[header_length..]) {
+            Ok(_) => {
+                let mut first = [0; 4];
+                first.copy_from_slice(&data[header_length..]);
+                let table = CompressionHeader::table_length(u32::from_le_bytes(first), length)?;
+                // grows with the data which is actually read
+                r.take(table - 4).read_to_end(&mut data)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => data.truncate(header_length),
+            Err(e) => return Err(HpkError::Io(e)),
+        }
+        Ok(CompressionHeader::parse(&data, length)?)
+    }
+
+    pub(crate) fn write(
+        compressor: Compression,
+        chunk_size: u32,
+        inflated_length: u32,
+        offsets: &[u32],
+        out: &mut dyn Write,
+    ) -> HpkResult<u64> {
+        let mut buf = vec![];
+        let size =
+            CompressionHeader::encode(compressor, inflated_length, chunk_size, offsets, &mut buf)?;
+        out.write_all(&buf)?;
+        Ok(size)
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, Cursor};
+use std::path::{Component, Path, PathBuf};
+
+use crate::checksum;
+use crate::create::write_file;
+use crate::extract::filedate_to_unix;
+use crate::sync::parse_filedates;
+use crate::{CreateOptions, DirEntry, HpkArchive, HpkError, HpkResult, HpkWriter};
+
+const FILEDATES: &str = "_filedates";
+/// Seconds between the Unix epoch and 1980-01-01, the earliest ZIP timestamp
+const ZIP_EPOCH: i64 = 315_532_800;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Hpk,
+    Zip,
+    Tar,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> HpkResult<Format> {
+        let ext = path.extension().and_then(|e| e.to_str());
+        match ext.map(str::to_ascii_lowercase).as_deref() {
+            Some("hpk") => Ok(Format::Hpk),
+            Some("zip") => Ok(Format::Zip),
+            Some("tar") => Ok(Format::Tar),
+            _ => Err(invalid_input(format!(
+                "unknown archive format: {}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// Converts the archive `src` into the archive `dest`
+///
+/// The formats are detected by the file extensions `.hpk`, `.zip` and `.tar`, one of
+/// the archives has to be a hpk archive. The entries are streamed one by one without
+/// extracting them first.
+///
+/// The modification times of a hpk archive are read from its `_filedates` entry, the
+/// `_filedates` and `_checksums` entries themselves aren't converted. The times of
+/// a ZIP or TAR archive are stored in a `_filedates` entry if `options` has a
+/// filedates format. The file content is converted as it's stored, see
+/// `CreateOptions::cripple_lua_files` and `ExtractOptions::fix_lua_files`.
+///
+pub fn convert<P, Q>(options: &CreateOptions, src: P, dest: Q) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    match (Format::from_path(src)?, Format::from_path(dest)?) {
+        (Format::Hpk, Format::Zip) => {
+            let archive = HpkArchive::open(src)?;
+            write_file(dest, options.atomic, |out| hpk_to_zip(&archive, out))
+        }
+        (Format::Hpk, Format::Tar) => {
+            let archive = HpkArchive::open(src)?;
+            write_file(dest, options.atomic, |out| hpk_to_tar(&archive, out))
+        }
+        (Format::Zip, Format::Hpk) => {
+            let r = BufReader::new(File::open(src)?);
+            let mut zip = zip::ZipArchive::new(r).map_err(io::Error::from)?;
+            write_file(dest, options.atomic, |out| {
+                zip_to_hpk(options, &mut zip, out)
+            })
+        }
+        (Format::Tar, Format::Hpk) => {
+            let r = BufReader::new(File::open(src)?);
+            write_file(dest, options.atomic, |out| {
+                tar_to_hpk(options, tar::Archive::new(r), out)
+            })
+        }
+        _ => Err(invalid_input(format!(
+            "unsupported conversion: {} to {}",
+            src.display(),
+            dest.display()
+        ))),
+    }
+}
+
+// {{{ hpk to zip/tar
+/// Returns the entries to convert with their modification times in Unix seconds
+fn hpk_entries(archive: &HpkArchive) -> HpkResult<Vec<(&DirEntry, Option<i64>)>> {
+    let is_special = |e: &DirEntry| {
+        e.depth() == 1
+            && !e.is_dir()
+            && (e.path() == Path::new(FILEDATES) || checksum::is_checksums(e.path()))
+    };
+    let filedates = match archive.entry(FILEDATES) {
+        Some(e) if e.depth() == 1 && !e.is_dir() => parse_filedates(&archive.read_file(FILEDATES)?),
+        _ => HashMap::new(),
+    };
+    Ok(archive
+        .entries()
+        .iter()
+        .filter(|e| e.depth() > 0 && !is_special(e))
+        .map(|e| (e, filedates.get(e.path()).map(|&v| filedate_to_unix(v))))
+        .collect())
+}
+
+/// The entry name with `/` separators used by ZIP and TAR
+fn entry_name(path: &Path) -> String {
+    let names: Vec<_> = path.iter().map(|n| n.to_string_lossy()).collect();
+    names.join("/")
+}
+
+fn hpk_to_zip<W: Write + Seek>(archive: &HpkArchive, w: W) -> HpkResult<()> {
+    let mut zip = zip::ZipWriter::new(w);
+    for (entry, mtime) in hpk_entries(archive)? {
+        let options = zip::write::FileOptions::default()
+            .last_modified_time(mtime.map(zip_datetime).unwrap_or_default());
+        let name = entry_name(entry.path());
+        if entry.is_dir() {
+            zip.add_directory(name, options).map_err(io::Error::from)?;
+        } else {
+            let size = archive.metadata(entry)?.size;
+            let options = options
+                .compression_method(zip::CompressionMethod::Deflated)
+                .large_file(size > u64::from(u32::MAX));
+            zip.start_file(name, options).map_err(io::Error::from)?;
+            io::copy(&mut archive.open_entry(entry)?, &mut zip)?;
+        }
+    }
+    zip.finish().map_err(io::Error::from)?;
+    Ok(())
+}
+
+fn hpk_to_tar<W: Write>(archive: &HpkArchive, w: W) -> HpkResult<()> {
+    let mut tar = tar::Builder::new(w);
+    for (entry, mtime) in hpk_entries(archive)? {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(mtime.map_or(0, |t| u64::try_from(t).unwrap_or(0)));
+        let name = entry_name(entry.path());
+        if entry.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, name, io::empty())?;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(archive.metadata(entry)?.size);
+            tar.append_data(&mut header, name, archive.open_entry(entry)?)?;
+        }
+    }
+    tar.into_inner()?;
+    Ok(())
+}
+// }}}
+
+// {{{ zip/tar to hpk
+/// Collects the entries of a ZIP or TAR archive into a new hpk archive
+struct HpkBuilder<'a, W: Write + Seek> {
+    options: &'a CreateOptions,
+    writer: HpkWriter<'a, W>,
+    filedates: Vec<u8>,
+}
+
+impl<'a, W: Write + Seek> HpkBuilder<'a, W> {
+    fn new(options: &'a CreateOptions, w: W) -> HpkResult<Self> {
+        Ok(HpkBuilder {
+            options,
+            writer: HpkWriter::new(options, w)?,
+            filedates: vec![],
+        })
+    }
+
+    fn add<R: Read>(&mut self, path: &Path, is_dir: bool, mtime: i64, r: R) -> HpkResult<()> {
+        let value = self.options.filedate_from_unix(mtime);
+        if value.is_some() && path == Path::new(FILEDATES) {
+            // replaced by the new `_filedates` entry
+            return Ok(());
+        }
+        if is_dir {
+            self.writer.add_dir(path)?;
+        } else {
+            self.writer.add_file(path, r)?;
+        }
+        if let Some(value) = value {
+            writeln!(self.filedates, "{}={}", path.display(), value)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> HpkResult<()> {
+        if self.options.with_filedates() {
+            let filedates = Cursor::new(self.filedates);
+            self.writer.add_uncompressed(FILEDATES, filedates)?;
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+fn zip_to_hpk<R, W>(options: &CreateOptions, zip: &mut zip::ZipArchive<R>, w: W) -> HpkResult<()>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut builder = HpkBuilder::new(options, w)?;
+    for i in 0..zip.len() {
+        let file = zip.by_index(i).map_err(io::Error::from)?;
+        let path = file
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or(HpkError::InvalidDirEntryName)?;
+        let mtime = unix_time(file.last_modified());
+        builder.add(&path, file.is_dir(), mtime, file)?;
+    }
+    builder.finish()
+}
+
+fn tar_to_hpk<R: Read, W: Write + Seek>(
+    options: &CreateOptions,
+    mut tar: tar::Archive<R>,
+    w: W,
+) -> HpkResult<()> {
+    let mut builder = HpkBuilder::new(options, w)?;
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let is_dir = match entry.header().entry_type() {
+            tar::EntryType::Directory => true,
+            tar::EntryType::Regular | tar::EntryType::Continuous => false,
+            // links and special files can't be stored
+            _ => continue,
+        };
+        let path = match enclosed_tar_path(&entry.path()?)? {
+            Some(path) => path,
+            // the root `./` of `tar -C dir -cf x.tar .`
+            None => continue,
+        };
+        let mtime = entry.header().mtime()?;
+        let mtime = i64::try_from(mtime).unwrap_or(i64::MAX);
+        builder.add(&path, is_dir, mtime, entry)?;
+    }
+    builder.finish()
+}
+
+/// Returns the path of a TAR entry without its `.` components, `None` for the root
+///
+/// Absolute paths and `..` are rejected like `enclosed_name` does for a ZIP entry.
+///
+fn enclosed_tar_path(path: &Path) -> HpkResult<Option<PathBuf>> {
+    let mut enclosed = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => enclosed.push(name),
+            Component::CurDir => {}
+            _ => return Err(HpkError::InvalidDirEntryName),
+        }
+    }
+    Ok(Some(enclosed).filter(|p| !p.as_os_str().is_empty()))
+}
+// }}}
+
+// {{{ ZIP timestamps
+/// Converts seconds since the Unix epoch into a ZIP timestamp, clamped to its range
+fn zip_datetime(secs: i64) -> zip::DateTime {
+    let secs = secs.max(ZIP_EPOCH);
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    let year = u16::try_from(year).unwrap_or(u16::MAX);
+    let (hour, minute, second) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    zip::DateTime::from_date_and_time(
+        year,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Converts a ZIP timestamp into seconds since the Unix epoch
+fn unix_time(dt: zip::DateTime) -> i64 {
+    let days = days_from_civil(
+        i64::from(dt.year()),
+        i64::from(dt.month()),
+        i64::from(dt.day()),
+    );
+    days * 86_400
+        + i64::from(dt.hour()) * 3600
+        + i64::from(dt.minute()) * 60
+        + i64::from(dt.second())
+}
+
+/// Days since the Unix epoch of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The date of the days since the Unix epoch, inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+// }}}
+
+fn invalid_input(msg: String) -> HpkError {
+    HpkError::Io(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
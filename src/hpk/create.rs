@@ -0,0 +1,1170 @@
+use std::ffi::OsStr;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::prelude::*;
+#[cfg(feature = "fs")]
+use std::io::{BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "fs")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glob::Pattern;
+
+#[cfg(feature = "fs")]
+use crate::compression::{compress_spooled, estimate_compressed, EncodeQueue};
+#[cfg(feature = "fs")]
+use crate::extract::{SEC_TO_UNIX_EPOCH, WINDOWS_TICKS};
+use crate::format::Layout;
+#[cfg(feature = "fs")]
+use crate::read::spool;
+#[cfg(feature = "fs")]
+use crate::vfs::Source;
+#[cfg(feature = "fs")]
+use crate::writer::QueuedFile;
+#[cfg(feature = "fs")]
+use crate::{get_compression, ignore};
+use crate::{CancelToken, CompressOptions, Compression, Progress, Transform};
+#[cfg(feature = "fs")]
+use crate::{HpkArchive, HpkError, HpkResult, HpkWriter, VolumeWriter};
+
+// struct CreateOptions {{{
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FileDateFormat {
+    Default,
+    Short,
+}
+
+/// How `create` handles symbolic links in the input directory
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Packs the targets of the links, a link to one of its parent directories
+    /// fails with a walkdir loop error
+    Follow,
+    /// Leaves the links out of the archive
+    #[default]
+    Skip,
+    /// Fails with `HpkError::Symlink`
+    Error,
+}
+
+/// The order in which `create` packs the entries of a directory
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortOrder {
+    /// Compares the raw bytes of the names like the game engines do
+    #[default]
+    Bytes,
+    /// Compares the lowercased names, equal names are ordered by their bytes
+    ///
+    /// Names which aren't valid UTF-8 are compared lossy.
+    ///
+    CaseInsensitive,
+}
+
+impl SortOrder {
+    pub fn compare(self, a: &OsStr, b: &OsStr) -> std::cmp::Ordering {
+        match self {
+            SortOrder::Bytes => a.cmp(b),
+            SortOrder::CaseInsensitive => {
+                let lower = |s: &OsStr| s.to_string_lossy().to_lowercase();
+                lower(a).cmp(&lower(b)).then_with(|| a.cmp(b))
+            }
+        }
+    }
+}
+
+/// The order in which `create` stores the content of the files, see
+/// `CreateOptions::set_data_order`
+///
+/// The directories keep the order of their entries, only the data region is
+/// reordered. Files which are loaded together end up next to each other and the
+/// sequential reads of the game hit fewer seeks.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DataOrder {
+    /// The order of the directory tree
+    #[default]
+    Tree,
+    /// Groups the files by their extensions, the listed extensions come first in the
+    /// given order and the other extensions follow sorted by name
+    Extensions(Vec<String>),
+    /// The files of an access profile come first in the order of the profile, the
+    /// other files follow in the order of the tree
+    Profile(Vec<PathBuf>),
+}
+
+impl DataOrder {
+    /// Reads an access profile, one path per line with `/` or `\` as separators
+    ///
+    /// Empty lines and lines starting with `#` are skipped, e.g. the files which were
+    /// opened by the game in the order they were opened.
+    ///
+    #[cfg(feature = "fs")]
+    pub fn read_profile<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        let content = std::fs::read_to_string(file)?;
+        let paths = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.split(['/', '\\'])
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .collect();
+        Ok(DataOrder::Profile(paths))
+    }
+}
+
+pub struct CreateOptions {
+    pub(crate) compress: bool,
+    pub(crate) compress_options: CompressOptions,
+    pub(crate) cripple_lua_files: bool,
+    pub(crate) extensions: Vec<String>,
+    pub(crate) compress_filter: Option<CompressFilter>,
+    pub(crate) filedates_fmt: Option<FileDateFormat>,
+    pub(crate) wide_offsets: bool,
+    pub(crate) big_endian: bool,
+    pub(crate) fragments_per_file: u32,
+    pub(crate) data_offset: Option<u32>,
+    pub(crate) alignment: Option<u64>,
+    pub(crate) deterministic: bool,
+    pub(crate) checksums: bool,
+    pub(crate) dedup: bool,
+    pub(crate) excludes: Vec<Pattern>,
+    pub(crate) default_excludes: bool,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) sort_order: SortOrder,
+    pub(crate) sort_fn: Option<Arc<SortFn>>,
+    pub(crate) data_order: DataOrder,
+    pub(crate) data_order_fn: Option<Arc<DataOrderFn>>,
+    pub(crate) atomic: bool,
+    pub(crate) volume_size: Option<u64>,
+    pub(crate) delta_min_size: Option<u64>,
+    #[cfg(feature = "sha256")]
+    pub(crate) sha256_manifest: bool,
+    #[cfg(feature = "sign")]
+    pub(crate) signing_key: Option<[u8; 32]>,
+    pub(crate) progress: Option<Box<dyn Progress>>,
+    pub(crate) transform: Option<Arc<dyn Transform>>,
+}
+
+type CompressFilter = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+type SortFn = dyn Fn(&OsStr, &OsStr) -> std::cmp::Ordering + Send + Sync;
+type DataOrderFn = dyn Fn(&Path, &Path) -> std::cmp::Ordering + Send + Sync;
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            compress: false,
+            compress_options: Default::default(),
+            cripple_lua_files: false,
+            extensions: vec![
+                "lst".into(),
+                "lua".into(),
+                "xml".into(),
+                "tga".into(),
+                "dds".into(),
+                "xtex".into(),
+                "bin".into(),
+                "csv".into(),
+            ],
+            compress_filter: None,
+            filedates_fmt: None,
+            wide_offsets: false,
+            big_endian: false,
+            fragments_per_file: 1,
+            data_offset: None,
+            alignment: None,
+            deterministic: false,
+            checksums: false,
+            dedup: false,
+            excludes: vec![],
+            default_excludes: true,
+            symlinks: SymlinkPolicy::default(),
+            sort_order: SortOrder::default(),
+            atomic: true,
+            volume_size: None,
+            delta_min_size: None,
+            #[cfg(feature = "sha256")]
+            sha256_manifest: false,
+            #[cfg(feature = "sign")]
+            signing_key: None,
+            sort_fn: None,
+            data_order: DataOrder::default(),
+            data_order_fn: None,
+            progress: None,
+            transform: None,
+        }
+    }
+}
+
+impl CreateOptions {
+    pub fn new() -> Self {
+        CreateOptions::default()
+    }
+
+    pub fn compress(&mut self) {
+        self.compress = true;
+    }
+
+    pub fn use_lz4(&mut self) {
+        self.compress_options.compressor = Compression::Lz4;
+    }
+
+    pub fn use_zstd(&mut self) {
+        self.compress_options.compressor = Compression::Zstd;
+    }
+
+    /// Sets the encoder for the chunks, `Compression::None` stores all files uncompressed
+    pub fn with_compressor(&mut self, compressor: Compression) {
+        self.compress_options.compressor = compressor;
+    }
+
+    pub fn cripple_lua_files(&mut self) {
+        self.cripple_lua_files = true;
+    }
+
+    pub fn with_chunk_size(&mut self, chunk_size: u32) {
+        self.compress_options.chunk_size = chunk_size;
+    }
+
+    /// Sets the compression level of the encoder
+    ///
+    /// ZLIB accepts levels from 0 to 9 (default: 9), ZSTD from 1 to 22 (default: 3).
+    /// The level is ignored by the LZ4 encoder.
+    ///
+    pub fn with_compression_level(&mut self, level: u32) {
+        self.compress_options.level = Some(level);
+    }
+
+    /// Sets the number of chunks which are compressed at the same time
+    ///
+    /// The chunks are compressed by a worker pool which is shared by all archives,
+    /// `create` also compresses the chunks of consecutive small files at the same time.
+    /// The chunks are still written in order, the output doesn't depend on the
+    /// number of threads.
+    ///
+    pub fn with_threads(&mut self, threads: usize) {
+        self.compress_options.threads = threads;
+    }
+
+    pub fn with_extensions(&mut self, ext: Vec<String>) {
+        self.extensions = ext;
+    }
+
+    /// Skips the files and directories matching one of the glob patterns
+    ///
+    /// A pattern without a `/` matches the file name, other patterns match the path
+    /// relative to the input directory. The patterns of a `.hpkignore` file in the
+    /// input directory are added to them.
+    ///
+    pub fn set_excludes(&mut self, patterns: &[String]) {
+        self.excludes = patterns
+            .iter()
+            .filter_map(|s| Pattern::new(s).ok())
+            .collect();
+    }
+
+    /// Packs the files of version control systems, editors and file managers like
+    /// `.git`, `Thumbs.db` or `*.swp` which are skipped by default
+    ///
+    pub fn no_default_excludes(&mut self) {
+        self.default_excludes = false;
+    }
+
+    /// Sets how symbolic links in the input directory are handled, they're skipped
+    /// by default
+    ///
+    pub fn set_symlink_policy(&mut self, policy: SymlinkPolicy) {
+        self.symlinks = policy;
+    }
+
+    /// Sets the order of the entries in a directory, the names are compared
+    /// byte-wise by default
+    ///
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        self.sort_order = order;
+        self.sort_fn = None;
+    }
+
+    /// Orders the entries of a directory with `compare` instead of a `SortOrder`
+    ///
+    /// The function is called with the file names, e.g. for a locale-aware collation.
+    ///
+    pub fn sort_by<F>(&mut self, compare: F)
+    where
+        F: Fn(&OsStr, &OsStr) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.sort_fn = Some(Arc::new(compare));
+    }
+
+    /// Sets the order in which the content of the files is stored, the order of the
+    /// directory tree by default
+    ///
+    pub fn set_data_order(&mut self, order: DataOrder) {
+        self.data_order = order;
+        self.data_order_fn = None;
+    }
+
+    /// Stores the content of the files in the order of `compare` instead of a
+    /// `DataOrder`
+    ///
+    /// The function is called with the paths of the files relative to the input
+    /// directory, files which compare equal keep the order of the tree.
+    ///
+    pub fn order_data_by<F>(&mut self, compare: F)
+    where
+        F: Fn(&Path, &Path) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.data_order_fn = Some(Arc::new(compare));
+    }
+
+    /// Sorts the files in the order of the tree into the data order, the sort is stable
+    #[cfg(feature = "fs")]
+    pub(crate) fn sort_data(&self, files: &mut [&Path]) {
+        if let Some(compare) = &self.data_order_fn {
+            files.sort_by(|a, b| compare(a, b));
+            return;
+        }
+        match &self.data_order {
+            DataOrder::Tree => {}
+            DataOrder::Extensions(extensions) => files.sort_by_cached_key(|path| {
+                let ext = path.extension().unwrap_or_default().to_string_lossy();
+                match extensions.iter().position(|e| ext.eq_ignore_ascii_case(e)) {
+                    Some(pos) => (pos, String::new()),
+                    None => (extensions.len(), ext.to_lowercase()),
+                }
+            }),
+            DataOrder::Profile(paths) => {
+                let mut positions = std::collections::HashMap::new();
+                for (pos, path) in paths.iter().enumerate() {
+                    positions.entry(path.as_path()).or_insert(pos);
+                }
+                files.sort_by_key(|path| positions.get(path).copied().unwrap_or(usize::MAX));
+            }
+        }
+    }
+
+    /// Decides with `filter` which files are compressed instead of the list of extensions
+    ///
+    /// The filter is called with the path of the file relative to the input directory.
+    ///
+    pub fn compress_if<F>(&mut self, filter: F)
+    where
+        F: Fn(&Path) -> bool + Send + Sync + 'static,
+    {
+        self.compress_filter = Some(Box::new(filter));
+    }
+
+    pub(crate) fn should_compress(&self, path: &Path) -> bool {
+        if !self.compress_options.compressor.is_compressed() {
+            return false;
+        }
+        if let Some(filter) = &self.compress_filter {
+            return filter(path);
+        }
+        let ext = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .map_or("".to_string(), |s| s.to_ascii_lowercase());
+        self.extensions.contains(&ext)
+    }
+
+    pub(crate) fn cripples_lua(&self, path: &Path) -> bool {
+        self.cripple_lua_files
+            && path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("lua"))
+    }
+
+    /// Stops the creation as soon as the token is cancelled
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.compress_options.cancel = Some(token);
+    }
+
+    /// Creates byte-identical archives from identical input
+    ///
+    /// Entries are always sorted by name and the encoders use fixed parameters, this
+    /// additionally stores the Unix epoch in `_filedates` instead of the modification
+    /// times.
+    ///
+    pub fn deterministic(&mut self) {
+        self.deterministic = true;
+    }
+
+    /// Stores CRC32 checksums of the decompressed content in a `_checksums` file
+    ///
+    /// A checksum covers a block of the chunk size, the extraction verifies the
+    /// files with them.
+    ///
+    pub fn with_checksums(&mut self) {
+        self.checksums = true;
+    }
+
+    /// Stores the changed files of a patch with at least `min_size` bytes as binary
+    /// deltas against their old content, see `hpk::patch`
+    ///
+    /// A delta is only stored if it's smaller than the file. The game engines can't
+    /// load the deltas, `hpk::apply_patch` reconstructs the files.
+    ///
+    pub fn store_deltas(&mut self, min_size: u64) {
+        self.delta_min_size = Some(min_size);
+    }
+
+    /// Stores the content of identical files only once
+    ///
+    /// `write_hpk` reads every file an additional time to find the duplicates, their
+    /// entries point at the data of the first packed file.
+    ///
+    pub fn deduplicate(&mut self) {
+        self.dedup = true;
+    }
+
+    /// Sets whether `create` and `repack` write into a temporary `<file>.tmp` which is
+    /// renamed to the archive once it's complete
+    ///
+    /// Enabled by default, a failed or interrupted run leaves an existing archive
+    /// untouched instead of a truncated one.
+    ///
+    pub fn set_atomic(&mut self, atomic: bool) {
+        self.atomic = atomic;
+    }
+
+    /// Scrambles the directory listings and the stored content with `transform`
+    ///
+    /// The archive is read with the same transform, see `HpkIter::transform`.
+    ///
+    pub fn with_transform<T: Transform + 'static>(&mut self, transform: T) {
+        self.transform = Some(Arc::new(transform));
+    }
+
+    pub fn with_default_filedates_format(&mut self) {
+        self.filedates_fmt = Some(FileDateFormat::Default);
+    }
+
+    pub fn with_short_filedates_format(&mut self) {
+        self.filedates_fmt = Some(FileDateFormat::Short);
+    }
+
+    /// Stores offsets and lengths as 64-bit values for archives larger than 4 GiB
+    ///
+    /// Without it creating such an archive fails with `HpkError::OffsetOverflow`.
+    ///
+    pub fn with_wide_offsets(&mut self) {
+        self.wide_offsets = true;
+    }
+
+    /// Stores the header, the fragment table and the directory entries in big-endian
+    /// byte order like some console releases
+    ///
+    /// The content of the files, including the compression headers, isn't affected.
+    ///
+    pub fn with_big_endian(&mut self) {
+        self.big_endian = true;
+    }
+
+    /// Splits the content of every file into `count` fragments for game versions
+    /// which expect several fragments per file
+    ///
+    /// The fragments of a file are stored one after the other, the directory
+    /// listings are stored in their first fragment. The default is 1.
+    ///
+    pub fn with_fragments_per_file(&mut self, count: u32) {
+        self.fragments_per_file = count.max(1);
+    }
+
+    /// Starts the file contents at `offset` instead of directly behind the header
+    ///
+    /// For game versions with an extended header, the space between the header and
+    /// `offset` is filled with zeros. The offset can't be smaller than the header, 36
+    /// bytes or 52 bytes with `with_wide_offsets`.
+    ///
+    pub fn with_data_offset(&mut self, offset: u32) {
+        self.data_offset = Some(offset);
+    }
+
+    /// Starts the content of every uncompressed file at a multiple of `alignment`
+    ///
+    /// The gaps in the data region are filled with zeros. Engines which map the archive
+    /// into memory can use textures like DDS files in place, the alignment must be a
+    /// power of two, e.g. 4096 for the page size.
+    ///
+    pub fn with_alignment(&mut self, alignment: u64) {
+        self.alignment = Some(alignment);
+    }
+
+    /// Splits the archive of `create` into volumes of at most `size` bytes
+    ///
+    /// The volumes are named `archive_000.hpk`, `archive_001.hpk`, ... after the
+    /// archive file and are read with `HpkArchive::open_volumes`. The volumes are
+    /// written directly, not atomically. See `VolumeWriter` for `write_hpk`.
+    ///
+    pub fn split_volumes(&mut self, size: u64) {
+        self.volume_size = Some(size);
+    }
+
+    /// Writes the SHA-256 manifest `archive.hpk.sha256` after `create` and `pack`
+    /// for the mirrors of the archive, see `hpk::write_sha256_manifest`
+    #[cfg(feature = "sha256")]
+    pub fn with_sha256_manifest(&mut self) {
+        self.sha256_manifest = true;
+    }
+
+    /// Signs the archive with the Ed25519 `secret_key`, the signature is stored as
+    /// `_signature` entry in the root directory
+    ///
+    /// The signature covers the data, the directory tree and the fragment table and is
+    /// checked with `HpkArchive::verify_signature` and the key of
+    /// `hpk::signing_public_key`. Signatures are randomized, signed archives aren't
+    /// reproducible. An archive opened by `HpkWriter::open` can't be signed again.
+    ///
+    #[cfg(feature = "sign")]
+    pub fn sign_with(&mut self, secret_key: [u8; 32]) {
+        self.signing_key = Some(secret_key);
+    }
+
+    pub(crate) fn layout(&self) -> Layout {
+        Layout {
+            wide: self.wide_offsets,
+            big_endian: self.big_endian,
+        }
+    }
+
+    /// Reports every packed file and the read bytes of the input files to `progress`
+    pub fn set_progress<P: Progress + 'static>(&mut self, progress: P) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Applies the symlink policy to `entry`, the input directory itself is always
+    /// followed
+    ///
+    #[cfg(feature = "fs")]
+    pub(crate) fn skip_symlink(&self, entry: &walkdir::DirEntry) -> HpkResult<bool> {
+        if entry.depth() == 0 || !entry.path_is_symlink() {
+            return Ok(false);
+        }
+        match self.symlinks {
+            SymlinkPolicy::Follow => Ok(false),
+            SymlinkPolicy::Skip => Ok(true),
+            SymlinkPolicy::Error => Err(HpkError::Symlink(entry.path().to_path_buf())),
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn sorter(
+        &self,
+    ) -> impl FnMut(&walkdir::DirEntry, &walkdir::DirEntry) -> std::cmp::Ordering + Send + Sync
+    {
+        let order = self.sort_order;
+        let compare = self.sort_fn.clone();
+        move |a, b| match &compare {
+            Some(compare) => compare(a.file_name(), b.file_name()),
+            None => order.compare(a.file_name(), b.file_name()),
+        }
+    }
+
+    /// Compares two names of a directory like `sorter`
+    #[cfg(feature = "fs")]
+    pub(crate) fn compare_names(&self, a: &OsStr, b: &OsStr) -> std::cmp::Ordering {
+        match &self.sort_fn {
+            Some(compare) => compare(a, b),
+            None => self.sort_order.compare(a, b),
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn excludes(&self, dir: &Path) -> HpkResult<ignore::Excludes> {
+        ignore::Excludes::new(&self.excludes, self.default_excludes, dir)
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn with_filedates(&self) -> bool {
+        self.filedates_fmt.is_some()
+    }
+
+    /// Calculates the file time for the _filedates file
+    ///
+    /// The actually values for Tropico 3 and Grand Ages: Rome are stored
+    /// as Windows file times (default format) and for Tropico 4 and Omerta
+    /// the values are the Windows file times divided by 2000 (short format).
+    ///
+    /// Tropico 5 and Victor Vran don't seem to use it anymore.
+    ///
+    #[cfg(feature = "fs")]
+    pub(crate) fn filedates_value_for_path<P: AsRef<Path>>(&self, path: P) -> HpkResult<i64> {
+        let fmt = self.filedates_fmt.unwrap_or(FileDateFormat::Default);
+        self.filedate_in_format(path, fmt)
+    }
+
+    /// Converts seconds since the Unix epoch into a value of the _filedates file, `None`
+    /// without a filedates format
+    #[cfg(feature = "fs")]
+    pub(crate) fn filedate_from_unix(&self, secs: i64) -> Option<i64> {
+        let secs = if self.deterministic { 0 } else { secs };
+        let filetime = secs
+            .saturating_add(SEC_TO_UNIX_EPOCH)
+            .saturating_mul(WINDOWS_TICKS);
+        match self.filedates_fmt? {
+            FileDateFormat::Short => Some(filetime / 2000),
+            FileDateFormat::Default => Some(filetime),
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn filedate_in_format<P: AsRef<Path>>(
+        &self,
+        path: P,
+        fmt: FileDateFormat,
+    ) -> HpkResult<i64> {
+        if self.deterministic {
+            let filetime = SEC_TO_UNIX_EPOCH * WINDOWS_TICKS;
+            return match fmt {
+                FileDateFormat::Short => Ok(filetime / 2000),
+                FileDateFormat::Default => Ok(filetime),
+            };
+        }
+        let ft = filetime::FileTime::from_last_modification_time(&path.as_ref().metadata()?);
+        let filetime = ft.seconds();
+
+        // Convert the platform dependent file time to Windows file time
+        #[cfg(unix)]
+        let filetime = (filetime + SEC_TO_UNIX_EPOCH) * WINDOWS_TICKS;
+
+        match fmt {
+            FileDateFormat::Short => Ok(filetime / 2000),
+            FileDateFormat::Default => Ok(filetime),
+        }
+    }
+}
+// }}}
+
+#[cfg(feature = "fs")]
+pub fn create<P>(options: &CreateOptions, dir: P, file: P) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+{
+    let file = file.as_ref();
+    if let Some(size) = options.volume_size {
+        let mut out = BufWriter::new(VolumeWriter::create(file, size)?);
+        write_hpk(options, dir, &mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.finish()?;
+    } else {
+        write_file(file, options.atomic, |out| write_hpk(options, dir, out))?;
+    }
+    #[cfg(feature = "sha256")]
+    if options.sha256_manifest {
+        crate::write_sha256_manifest(file)?;
+    }
+    Ok(())
+}
+
+/// Writes the entries of the archive `src` into the new archive `dst`
+///
+/// The entries are decompressed and packed again with the settings of `options`,
+/// e.g. to switch from ZLIB to LZ4 or to store the files uncompressed.
+///
+#[cfg(feature = "fs")]
+pub fn repack<P, Q>(options: &CreateOptions, src: P, dst: Q) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let archive = HpkArchive::open(src)?;
+    write_file(dst.as_ref(), options.atomic, |out| {
+        archive.repack_into(options, out)
+    })
+}
+
+/// Creates the archive `file` with `op`
+///
+/// With `atomic` the archive is written into `<file>.tmp` which replaces `file` once
+/// it's complete and synced to disk.
+///
+#[cfg(feature = "fs")]
+pub(crate) fn write_file<F>(file: &Path, atomic: bool, op: F) -> HpkResult<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> HpkResult<()>,
+{
+    let tmp = if atomic {
+        let mut name = file.as_os_str().to_os_string();
+        name.push(".tmp");
+        PathBuf::from(name)
+    } else {
+        file.to_path_buf()
+    };
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    let result = op(&mut out).and_then(|()| {
+        let out = out.into_inner().map_err(|e| e.into_error())?;
+        if atomic {
+            out.sync_all()?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) if atomic => Ok(std::fs::rename(&tmp, file)?),
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // an archive without header and fragment table is useless
+            if atomic || matches!(e, HpkError::Cancelled(_)) {
+                let _ = std::fs::remove_file(&tmp);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Adds or replaces files inside of the existing archive `file`
+///
+/// `files` yields pairs of the path inside of the archive and the path of the file
+/// to store. Only the new content, the directory tree and the fragment table are
+/// written, see `HpkWriter::open`.
+///
+#[cfg(feature = "fs")]
+pub fn update<P, I, N, S>(options: &CreateOptions, file: P, files: I) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = (N, S)>,
+    N: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    let f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(file)?;
+    let mut writer = HpkWriter::open(options, f)?;
+    for (name, src) in files {
+        let r = BufReader::new(File::open(src)?);
+        writer.replace_file(name, r)?;
+    }
+    let mut f = writer.finish()?;
+    let end = f.stream_position()?;
+    f.set_len(end)?;
+    Ok(())
+}
+
+/// Removes files and directories from the existing archive `file`
+///
+/// Only the directory tree and the fragment table are written again, the removed
+/// content stays in the archive unless `compact` is set. Compacting copies the
+/// remaining entries into a new archive which replaces `file`.
+///
+#[cfg(feature = "fs")]
+pub fn remove<P, I, N>(file: P, paths: I, compact: bool) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = N>,
+    N: AsRef<Path>,
+{
+    let file = file.as_ref();
+    let options = CreateOptions::new();
+    let f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(file)?;
+    let mut writer = HpkWriter::open(&options, f)?;
+    for path in paths {
+        writer.remove(path)?;
+    }
+    let mut f = writer.finish()?;
+    let end = f.stream_position()?;
+    f.set_len(end)?;
+    drop(f);
+
+    if compact {
+        defrag(file)?;
+    }
+    Ok(())
+}
+
+/// Rewrites the archive `file` with every file stored in a single fragment
+///
+/// The stored content is copied as is and the unreferenced data is left out, see
+/// `HpkArchive::compact_into`. A compressed archive is compressed again with the
+/// same encoder.
+///
+#[cfg(feature = "fs")]
+pub fn defrag<P: AsRef<Path>>(file: P) -> HpkResult<()> {
+    let file = file.as_ref();
+    let archive = HpkArchive::open(file)?;
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmpfile = tempfile::Builder::new().prefix("hpk").tempfile_in(dir)?;
+    let mut out = BufWriter::new(tmpfile.as_file_mut());
+    if archive.is_compressed() {
+        let compressor = get_compression(&mut File::open(file)?)?;
+        let options = CompressOptions {
+            compressor,
+            ..Default::default()
+        };
+        compress_spooled(&options, spool()?, &mut out, |spooled| {
+            archive.compact_into(spooled)
+        })?;
+    } else {
+        archive.compact_into(&mut out)?;
+    }
+    out.flush()?;
+    drop(out);
+    drop(archive);
+    tmpfile.persist(file).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// An entry reported by `dry_run`
+#[derive(Debug)]
+#[cfg(feature = "fs")]
+pub struct DryRunEntry {
+    /// Path of the entry inside of the archive
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// The content would be stored compressed
+    pub compress: bool,
+    /// Size of the uncompressed content
+    pub size: u64,
+    /// Estimated size of the content in the archive, the size of a compressed file
+    /// is estimated from its first chunk
+    pub stored: u64,
+}
+
+/// Walks `dir` like `create` and reports the entries which would be packed
+///
+/// Nothing is written, only the first chunk of the files which would be compressed is
+/// read for their estimated stored size. The `_filedates` file isn't reported.
+///
+#[cfg(feature = "fs")]
+pub fn dry_run<P: AsRef<Path>>(options: &CreateOptions, dir: P) -> HpkResult<Vec<DryRunEntry>> {
+    let dir = dir.as_ref();
+    let excludes = options.excludes(dir)?;
+    let walkdir = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .follow_links(options.symlinks == SymlinkPolicy::Follow)
+        .sort_by(options.sorter())
+        .into_iter()
+        .filter_entry(|e| !excludes.is_excluded(e.path().strip_prefix(dir).unwrap()));
+
+    let mut entries = vec![];
+    for entry in walkdir {
+        let entry = entry?;
+        if options.skip_symlink(&entry)? {
+            continue;
+        }
+        let path = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+        let is_dir = entry.file_type().is_dir();
+        let (compress, size) = if is_dir {
+            (false, 0)
+        } else {
+            (options.should_compress(&path), entry.metadata()?.len())
+        };
+        let stored = if compress {
+            let mut r = File::open(entry.path())?;
+            estimate_compressed(&options.compress_options, &mut r, size)?
+        } else {
+            size
+        };
+        entries.push(DryRunEntry {
+            path,
+            is_dir,
+            compress,
+            size,
+            stored,
+        });
+    }
+    Ok(entries)
+}
+
+/// Writes the hpk archive of `dir` into `w`
+///
+/// The current position of the writer is treated as the start of the archive, all
+/// offsets stored in the header and the fragment table are relative to it. This makes
+/// it possible to embed an archive into a larger container file.
+///
+/// The writer is positioned at the end of the archive when the function returns.
+///
+#[cfg(feature = "fs")]
+pub fn write_hpk<P, W>(options: &CreateOptions, dir: P, w: &mut W) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    W: Write + Seek,
+{
+    if options.compress {
+        compress_spooled(&options.compress_options, spool()?, w, |spooled| {
+            write_archive(options, dir.as_ref(), spooled)
+        })
+    } else {
+        write_archive(options, dir.as_ref(), w)
+    }
+}
+
+/// Writes the hpk archive of the tree `source` into `w`
+///
+/// Works like `write_hpk` for a `vfs::Source` instead of a directory. The entries are
+/// sorted, excluded and deduplicated with the settings of `options`, a `.hpkignore`
+/// file in the root of the source adds exclude patterns. The `_filedates` are the
+/// modification times of the source.
+///
+/// ```
+/// # fn main() -> Result<(), hpk::HpkError> {
+/// let mut source = hpk::vfs::MemorySource::new();
+/// source.add_file("scripts/main.lua", "return 1");
+/// source.add_file("maps/a.bin", "map");
+/// let mut packed = std::io::Cursor::new(vec![]);
+/// hpk::write_source(&hpk::CreateOptions::new(), &source, &mut packed)?;
+///
+/// // repack the archive without its lua scripts
+/// let archive = hpk::HpkArchive::from_reader(packed)?;
+/// let mut options = hpk::CreateOptions::new();
+/// options.set_excludes(&["*.lua".to_string()]);
+/// let mut out = std::io::Cursor::new(vec![]);
+/// hpk::write_source(&options, &archive, &mut out)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[cfg(feature = "fs")]
+pub fn write_source<S, W>(options: &CreateOptions, source: &S, w: &mut W) -> HpkResult<()>
+where
+    S: Source + ?Sized,
+    W: Write + Seek,
+{
+    if options.compress {
+        compress_spooled(&options.compress_options, spool()?, w, |spooled| {
+            write_source_archive(options, source, spooled)
+        })
+    } else {
+        write_source_archive(options, source, w)
+    }
+}
+
+#[cfg(feature = "fs")]
+fn write_source_archive<S, W>(options: &CreateOptions, source: &S, w: &mut W) -> HpkResult<()>
+where
+    S: Source + ?Sized,
+    W: Write + Seek,
+{
+    let hpkignore = Path::new(ignore::HPKIGNORE);
+    let content = match source.metadata(hpkignore) {
+        Ok(md) if !md.is_dir => {
+            let mut content = String::new();
+            source.open(hpkignore)?.read_to_string(&mut content)?;
+            Some(content)
+        }
+        _ => None,
+    };
+    let excludes = ignore::Excludes::with_ignore(
+        &options.excludes,
+        options.default_excludes,
+        content.as_deref(),
+    );
+    let mut entries = vec![];
+    walk_source(options, source, &excludes, Path::new(""), &mut entries)?;
+    write_tree(options, &entries, |p| source.open(p), w)
+}
+
+/// Collects the entries below `dir` in the order of `write_hpk`, the entries of a
+/// directory come before the directory itself
+#[cfg(feature = "fs")]
+fn walk_source<S: Source + ?Sized>(
+    options: &CreateOptions,
+    source: &S,
+    excludes: &ignore::Excludes,
+    dir: &Path,
+    entries: &mut Vec<InputEntry>,
+) -> HpkResult<()> {
+    let mut names = source.read_dir(dir)?;
+    names.sort_by(|a, b| options.compare_names(a, b));
+    for name in names {
+        let path = dir.join(name);
+        if excludes.is_excluded(&path) {
+            continue;
+        }
+        let md = source.metadata(&path)?;
+        if md.is_dir {
+            walk_source(options, source, excludes, &path, entries)?;
+        }
+        let secs = md.modified.map_or(0, unix_secs);
+        entries.push(InputEntry {
+            path,
+            is_dir: md.is_dir,
+            filedate: options.filedate_from_unix(secs),
+        });
+    }
+    Ok(())
+}
+
+/// Seconds of `time` since the Unix epoch
+#[cfg(feature = "fs")]
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+#[cfg(feature = "fs")]
+fn write_archive<W>(options: &CreateOptions, dir: &Path, w: &mut W) -> HpkResult<()>
+where
+    W: Write + Seek,
+{
+    use walkdir::WalkDir;
+
+    let excludes = options.excludes(dir)?;
+    // `filter_entry` can't prune directories which are yielded after their contents
+    let included = |e: &walkdir::Result<walkdir::DirEntry>| match e {
+        Ok(e) => !excludes.is_excluded_tree(e.path().strip_prefix(dir).unwrap()),
+        Err(_) => true,
+    };
+    let follow_links = options.symlinks == SymlinkPolicy::Follow;
+    let walkdir = WalkDir::new(dir)
+        .contents_first(true)
+        .follow_links(follow_links)
+        .sort_by(options.sorter())
+        .into_iter()
+        .filter(included);
+
+    let mut entries = vec![];
+    for entry in walkdir {
+        let entry = entry?;
+        if entry.depth() == 0 || options.skip_symlink(&entry)? {
+            continue;
+        }
+        let is_dir = entry.file_type().is_dir();
+        if !is_dir && !entry.file_type().is_file() {
+            continue;
+        }
+        let filedate = if options.with_filedates() {
+            Some(options.filedates_value_for_path(entry.path())?)
+        } else {
+            None
+        };
+        entries.push(InputEntry {
+            path: entry.path().strip_prefix(dir).unwrap().to_path_buf(),
+            is_dir,
+            filedate,
+        });
+    }
+    write_tree(options, &entries, |p| Ok(File::open(dir.join(p))?), w)
+}
+
+/// A file or a directory of the input tree
+#[cfg(feature = "fs")]
+struct InputEntry {
+    /// Path relative to the input directory
+    path: PathBuf,
+    is_dir: bool,
+    /// Value of the `_filedates` entry
+    filedate: Option<i64>,
+}
+
+/// Writes the archive of `entries` in the order of the tree, `open` opens the files
+///
+/// The files keep the order of the tree in their directories, their content is stored
+/// in the data order of `options`.
+///
+#[cfg(feature = "fs")]
+fn write_tree<W, F, R>(
+    options: &CreateOptions,
+    entries: &[InputEntry],
+    open: F,
+    w: &mut W,
+) -> HpkResult<()>
+where
+    W: Write + Seek,
+    F: Fn(&Path) -> HpkResult<R>,
+    R: Read,
+{
+    use crate::dedup::ContentIndex;
+
+    let mut writer = HpkWriter::new(options, w)?;
+    let mut filedates = vec![];
+    for entry in entries {
+        if let Some(val) = entry.filedate {
+            writeln!(filedates, "{}={}", entry.path.display(), val)?;
+        }
+        if entry.is_dir {
+            writer.add_dir(&entry.path)?;
+        } else {
+            writer.reserve_file(&entry.path)?;
+        }
+    }
+
+    let mut files: Vec<_> = entries
+        .iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| e.path.as_path())
+        .collect();
+    options.sort_data(&mut files);
+
+    let progress = options.progress.as_deref();
+    let cancel = options.compress_options.cancel.as_ref();
+    let mut completed = vec![];
+    let mut contents = ContentIndex::default();
+    // compressed files wait in the queue until it's full, the other files are stored
+    // after the queued ones to keep the data order
+    let mut queue = EncodeQueue::new(&options.compress_options);
+    for &path in &files {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            return Err(HpkError::Cancelled(completed));
+        }
+        if let Some(progress) = progress {
+            progress.on_entry(path, completed.len() + queue.len(), files.len());
+        }
+        let duplicate = if options.dedup {
+            contents.find_or_insert(options, path, &open)?
+        } else {
+            None
+        };
+        let result = match duplicate {
+            Some(target) => store_queued(&mut writer, &mut queue, &mut completed, false)
+                .and_then(|_| writer.replace_link(path, &target))
+                .map(|_| completed.push(path.to_path_buf())),
+            None if options.should_compress(path) => writer
+                .queue_file(&mut queue, path, open(path)?)
+                .and_then(|_| store_queued(&mut writer, &mut queue, &mut completed, true)),
+            None => store_queued(&mut writer, &mut queue, &mut completed, false)
+                .and_then(|_| writer.replace_file(path, open(path)?))
+                .map(|_| completed.push(path.to_path_buf())),
+        };
+        match result {
+            Ok(()) => {}
+            Err(HpkError::Cancelled(_)) => return Err(HpkError::Cancelled(completed)),
+            Err(e) => return Err(e),
+        }
+    }
+    match store_queued(&mut writer, &mut queue, &mut completed, false) {
+        Ok(()) => {}
+        Err(HpkError::Cancelled(_)) => return Err(HpkError::Cancelled(completed)),
+        Err(e) => return Err(e),
+    }
+    if options.with_filedates() {
+        // _filedates is stored at the end of the root directory
+        writer.add_uncompressed("_filedates", Cursor::new(&filedates))?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Stores the files returned by `queue`, all of them unless only the files of a
+/// `full` queue are stored
+#[cfg(feature = "fs")]
+fn store_queued<W: Write + Seek>(
+    writer: &mut HpkWriter<'_, W>,
+    queue: &mut EncodeQueue<'_, QueuedFile>,
+    completed: &mut Vec<PathBuf>,
+    full: bool,
+) -> HpkResult<()> {
+    loop {
+        let next = if full { queue.pop_full() } else { queue.pop() };
+        let (file, encoded) = match next {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        let path = file.path.clone();
+        writer.replace_encoded(file, encoded)?;
+        completed.push(path);
+    }
+}
+
+// vim: fdm=marker
@@ -0,0 +1,143 @@
+use std::io::prelude::*;
+
+use crate::{get_compression, CompressionHeader, DirEntry, Fragment, HpkArchive, HpkResult};
+
+/// Writes a detailed report of the raw structures of an archive
+///
+/// The report contains all header fields including the unknown values, the fragment
+/// tables, the directory tree with the fragment indices and the compression header
+/// of every file. Damaged entries are reported instead of stopping the dump.
+///
+pub struct ArchiveDebug<'a> {
+    archive: &'a HpkArchive,
+}
+
+impl<'a> ArchiveDebug<'a> {
+    pub fn new(archive: &'a HpkArchive) -> Self {
+        ArchiveDebug { archive }
+    }
+
+    pub fn dump(&self, w: &mut dyn Write) -> HpkResult<()> {
+        self.dump_header(w)?;
+        self.dump_fragments(w)?;
+        self.dump_tree(w)?;
+        Ok(())
+    }
+
+    fn dump_header(&self, w: &mut dyn Write) -> HpkResult<()> {
+        let hdr = self.archive.header();
+        writeln!(w, "header:")?;
+        writeln!(
+            w,
+            "  identifier: {}",
+            String::from_utf8_lossy(&hdr._identifier)
+        )?;
+        writeln!(w, "  data_offset: 0x{:X}", hdr.data_offset)?;
+        writeln!(w, "  fragments_per_file: {}", hdr.fragments_per_file)?;
+        writeln!(w, "  _unknown2: 0x{:X}", hdr._unknown2)?;
+        writeln!(
+            w,
+            "  fragments_residual_offset: 0x{:X}",
+            hdr.fragments_residual_offset
+        )?;
+        writeln!(
+            w,
+            "  fragments_residual_count: {}",
+            hdr.fragments_residual_count
+        )?;
+        writeln!(w, "  _unknown5: 0x{:X}", hdr._unknown5)?;
+        writeln!(
+            w,
+            "  fragmented_filesystem_offset: 0x{:X}",
+            hdr.fragmented_filesystem_offset
+        )?;
+        writeln!(
+            w,
+            "  fragmented_filesystem_length: {}",
+            hdr.fragmented_filesystem_length
+        )?;
+        writeln!(w, "  wide offsets: {}", hdr.is_wide())?;
+        writeln!(w, "  big-endian: {}", hdr.is_big_endian())?;
+        writeln!(w, "  compressed archive: {}", self.archive.is_compressed())?;
+        writeln!(w, "  filesystem entries: {}", hdr.filesystem_entries())?;
+        Ok(())
+    }
+
+    fn dump_fragments(&self, w: &mut dyn Write) -> HpkResult<()> {
+        fn fragment(w: &mut dyn Write, prefix: &str, f: &Fragment) -> HpkResult<()> {
+            writeln!(w, "{}0x{:<8X} len: {}", prefix, f.offset, f.length)?;
+            Ok(())
+        }
+
+        writeln!(w, "fragments:")?;
+        for (index, fragments) in self.archive.fragments().iter().enumerate() {
+            for (i, f) in fragments.iter().enumerate() {
+                if i == 0 {
+                    fragment(w, &format!("  {:>5}: ", index + 1), f)?;
+                } else {
+                    fragment(w, "         ", f)?;
+                }
+            }
+        }
+        if !self.archive.residuals().is_empty() {
+            writeln!(w, "residual fragments:")?;
+            for f in self.archive.residuals() {
+                fragment(w, "         ", f)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_tree(&self, w: &mut dyn Write) -> HpkResult<()> {
+        writeln!(w, "tree:")?;
+        for entry in self.archive.entries() {
+            let indent = "  ".repeat(entry.depth() + 1);
+            let name = if entry.depth() == 0 {
+                "/".into()
+            } else {
+                entry.file_name().to_string_lossy()
+            };
+            writeln!(
+                w,
+                "{}{} {} index={}",
+                indent,
+                if entry.is_dir() { "dir: " } else { "file:" },
+                name,
+                entry.index() + 1
+            )?;
+            if !entry.is_dir() {
+                if let Err(e) = self.dump_compression(w, entry, &indent) {
+                    writeln!(w, "{}  error: {}", indent, e)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_compression(&self, w: &mut dyn Write, entry: &DirEntry, indent: &str) -> HpkResult<()> {
+        let mut r = self.archive.reader(entry);
+        let compression = get_compression(&mut r)?;
+        if !compression.is_compressed() {
+            writeln!(w, "{}  stored: {} bytes", indent, r.len())?;
+            return Ok(());
+        }
+        let hdr = CompressionHeader::read_from(r.len(), &mut r)?;
+        writeln!(
+            w,
+            "{}  compressed: {} inflated_length={} chunk_size={} chunks={}",
+            indent,
+            hdr.compressor,
+            hdr.inflated_length,
+            hdr.chunk_size,
+            hdr.chunks.len()
+        )?;
+        for chunk in &hdr.chunks {
+            writeln!(
+                w,
+                "{}    chunk: 0x{:<8X} len: {}",
+                indent, chunk.offset, chunk.length
+            )?;
+        }
+        Ok(())
+    }
+}
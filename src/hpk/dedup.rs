@@ -0,0 +1,96 @@
+use std::cmp;
+#[cfg(feature = "fs")]
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "fs")]
+use crate::{CreateOptions, HpkResult};
+
+/// The length and the CRC32 of the content and whether the file is compressed and
+/// its lua header crippled
+#[cfg(feature = "fs")]
+type ContentKey = (u64, u32, bool, bool);
+
+/// The packed files by their content, see `CreateOptions::deduplicate`
+///
+/// Files with the same key are compared byte by byte, a collision of the checksums
+/// never links different contents.
+///
+#[derive(Default)]
+#[cfg(feature = "fs")]
+pub(crate) struct ContentIndex {
+    files: HashMap<ContentKey, Vec<PathBuf>>,
+}
+
+#[cfg(feature = "fs")]
+impl ContentIndex {
+    /// Returns a packed file with the same content as `path` or adds `path` to the index
+    ///
+    /// The paths are relative to the input directory, `open` opens the files.
+    ///
+    pub fn find_or_insert<F, R>(
+        &mut self,
+        options: &CreateOptions,
+        path: &Path,
+        open: F,
+    ) -> HpkResult<Option<PathBuf>>
+    where
+        F: Fn(&Path) -> HpkResult<R>,
+        R: Read,
+    {
+        let (len, crc) = checksum(open(path)?)?;
+        let key = (
+            len,
+            crc,
+            options.should_compress(path),
+            options.cripples_lua(path),
+        );
+        let files = self.files.entry(key).or_default();
+        for other in files.iter() {
+            let (a, b) = (open(path)?, open(other)?);
+            if same_content(a, b)? {
+                return Ok(Some(other.clone()));
+            }
+        }
+        files.push(path.to_path_buf());
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "fs")]
+fn checksum<R: Read>(mut r: R) -> io::Result<(u64, u32)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            return Ok((len, hasher.finalize()));
+        }
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+}
+
+/// Compares the content of two readers
+pub(crate) fn same_content<A: Read, B: Read>(a: A, b: B) -> io::Result<bool> {
+    let mut a = BufReader::new(a);
+    let mut b = BufReader::new(b);
+    loop {
+        let buf_a = a.fill_buf()?;
+        let buf_b = b.fill_buf()?;
+        if buf_a.is_empty() || buf_b.is_empty() {
+            return Ok(buf_a.is_empty() && buf_b.is_empty());
+        }
+        let n = cmp::min(buf_a.len(), buf_b.len());
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
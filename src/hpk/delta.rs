@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Name of the entry in the root directory of a patch which lists the delta entries
+pub(crate) const DELTAS: &str = "_deltas";
+
+const MAGIC: &[u8; 4] = b"HPKD";
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+/// Smallest block of the old content which is matched
+const MIN_BLOCK: usize = 64;
+/// Limits the index of the old content to about a million blocks
+const MAX_BLOCKS: usize = 1 << 20;
+
+/// Encodes `new` as a binary delta against `old`
+///
+/// The old content is indexed in blocks by a rolling hash, the matching blocks of the
+/// new content are extended in both directions and stored as copies of the old
+/// content, everything else is inserted literally.
+///
+/// ```text
+/// "HPKD" varint(new length) crc32(old) crc32(new)
+/// 0 varint(offset) varint(length)    copy from the old content
+/// 1 varint(length) bytes             insert
+/// ```
+///
+pub(crate) fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    write_varint(&mut out, new.len() as u64);
+    out.extend_from_slice(&crc32fast::hash(old).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(new).to_le_bytes());
+
+    let block = MIN_BLOCK.max(old.len() / MAX_BLOCKS);
+    let mut literal = 0;
+    if old.len() >= block && new.len() >= block {
+        let mut index = HashMap::new();
+        for offset in (0..=old.len() - block).step_by(block) {
+            index
+                .entry(hash(&old[offset..offset + block]))
+                .or_insert(offset);
+        }
+        // the factor of the byte which leaves the window
+        let leading = (1..block).fold(1u32, |f, _| f.wrapping_mul(PRIME));
+
+        let mut pos = 0;
+        let mut h = hash(&new[..block]);
+        while pos + block <= new.len() {
+            let found = index
+                .get(&h)
+                .copied()
+                .filter(|&o| old[o..o + block] == new[pos..pos + block]);
+            if let Some(offset) = found {
+                let (mut start, mut offset) = (pos, offset);
+                while start > literal && offset > 0 && new[start - 1] == old[offset - 1] {
+                    start -= 1;
+                    offset -= 1;
+                }
+                let mut len = pos + block - start;
+                while start + len < new.len()
+                    && offset + len < old.len()
+                    && new[start + len] == old[offset + len]
+                {
+                    len += 1;
+                }
+                write_insert(&mut out, &new[literal..start]);
+                out.push(COPY);
+                write_varint(&mut out, offset as u64);
+                write_varint(&mut out, len as u64);
+                pos = start + len;
+                literal = pos;
+                if pos + block <= new.len() {
+                    h = hash(&new[pos..pos + block]);
+                }
+                continue;
+            }
+            if pos + block < new.len() {
+                h = h
+                    .wrapping_sub(u32::from(new[pos]).wrapping_mul(leading))
+                    .wrapping_mul(PRIME)
+                    .wrapping_add(u32::from(new[pos + block]));
+            }
+            pos += 1;
+        }
+    }
+    write_insert(&mut out, &new[literal..]);
+    out
+}
+
+/// Reconstructs the new content from `old` and the `delta` of `encode`
+///
+/// Fails with `InvalidData` if `old` isn't the content the delta was created against
+/// or the delta is corrupt.
+///
+pub(crate) fn apply(old: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut r = delta
+        .strip_prefix(&MAGIC[..])
+        .ok_or_else(|| invalid("not a delta"))?;
+    let length = read_varint(&mut r)?;
+    let mut crcs = [0; 8];
+    r.read_exact(&mut crcs)?;
+    let (crc_old, crc_new) = crcs.split_at(4);
+    if crc_old != crc32fast::hash(old).to_le_bytes() {
+        return Err(invalid("the old content of the delta differs"));
+    }
+
+    let mut new = Vec::with_capacity(length.min(1 << 30) as usize);
+    let mut tag = [0; 1];
+    while r.read(&mut tag)? == 1 {
+        // a corrupt delta can't grow the content beyond its declared length
+        let left = length - new.len() as u64;
+        match tag[0] {
+            COPY => {
+                let offset = read_varint(&mut r)?;
+                let len = read_varint(&mut r)?;
+                let copy = offset
+                    .checked_add(len)
+                    .filter(|&end| end <= old.len() as u64)
+                    .map(|end| &old[offset as usize..end as usize])
+                    .ok_or_else(|| invalid("delta copies beyond the old content"))?;
+                if len > left {
+                    return Err(invalid("delta exceeds its length"));
+                }
+                new.extend_from_slice(copy);
+            }
+            INSERT => {
+                let len = read_varint(&mut r)?;
+                if len > r.len() as u64 {
+                    return Err(invalid("truncated delta"));
+                }
+                if len > left {
+                    return Err(invalid("delta exceeds its length"));
+                }
+                let (insert, rest) = r.split_at(len as usize);
+                new.extend_from_slice(insert);
+                r = rest;
+            }
+            _ => return Err(invalid("invalid delta operation")),
+        }
+    }
+    if new.len() as u64 != length || crc_new != crc32fast::hash(&new).to_le_bytes() {
+        return Err(invalid("the reconstructed content of the delta differs"));
+    }
+    Ok(new)
+}
+
+/// Parses the paths of a `_deltas` entry, one path per line separated by `/`
+pub(crate) fn parse<R: Read>(r: R) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for line in io::BufReader::new(r).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            paths.push(line.split('/').collect());
+        }
+    }
+    Ok(paths)
+}
+
+/// Writes the lines of a `_deltas` entry
+pub(crate) fn write<W: Write>(paths: &[&Path], mut w: W) -> io::Result<()> {
+    for path in paths {
+        let path: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+        writeln!(w, "{}", path.join("/"))?;
+    }
+    Ok(())
+}
+
+const PRIME: u32 = 0x0100_0193;
+
+fn hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |h, &b| {
+        h.wrapping_mul(PRIME).wrapping_add(u32::from(b))
+    })
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    if !data.is_empty() {
+        out.push(INSERT);
+        write_varint(out, data.len() as u64);
+        out.extend_from_slice(data);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(r: &mut &[u8]) -> io::Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0; 1];
+        r.read_exact(&mut byte)?;
+        n |= u64::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "invalid varint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize, mut x: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let old = noise(100_000, 1);
+        let mut new = old.clone();
+        new[50_000..50_100].copy_from_slice(&noise(100, 2));
+        new.splice(10_000..10_000, noise(500, 3));
+        new.truncate(90_000);
+        new.extend_from_slice(&old[..1000]);
+
+        let delta = encode(&old, &new);
+        assert!(delta.len() < 1000, "{} bytes", delta.len());
+        assert_eq!(apply(&old, &delta).unwrap(), new);
+
+        for (old, new) in &[(&b""[..], &b"abc"[..]), (&b"abc"[..], &b""[..])] {
+            assert_eq!(apply(old, &encode(old, new)).unwrap(), *new);
+        }
+    }
+
+    #[test]
+    fn rejects_other_content() {
+        let old = noise(10_000, 1);
+        let new = noise(10_000, 2);
+        let delta = encode(&old, &new);
+        assert_eq!(apply(&old, &delta).unwrap(), new);
+        let err = apply(&new, &delta).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(apply(&old, &delta[..delta.len() - 1]).is_err());
+        assert!(apply(&old, b"ZLIB").is_err());
+    }
+
+    #[test]
+    fn rejects_growing_delta() {
+        let old = noise(10_000, 1);
+        let mut delta = MAGIC.to_vec();
+        write_varint(&mut delta, 15_000);
+        delta.extend_from_slice(&crc32fast::hash(&old).to_le_bytes());
+        delta.extend_from_slice(&[0; 4]);
+        for _ in 0..2 {
+            delta.push(COPY);
+            write_varint(&mut delta, 0);
+            write_varint(&mut delta, old.len() as u64);
+        }
+        let err = apply(&old, &delta).unwrap_err();
+        assert_eq!(err.to_string(), "delta exceeds its length");
+
+        delta.truncate(delta.len() - 4);
+        write_insert(&mut delta, &old);
+        let err = apply(&old, &delta).unwrap_err();
+        assert_eq!(err.to_string(), "delta exceeds its length");
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io::prelude::*;
+#[cfg(feature = "fs")]
+use std::io::{BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "fs")]
+use crate::compression::{compress_spooled, write_spooled};
+#[cfg(feature = "fs")]
+use crate::create::{write_file, write_hpk};
+use crate::dedup;
+#[cfg(feature = "fs")]
+use crate::delta;
+#[cfg(feature = "fs")]
+use crate::extract::is_safe_path;
+#[cfg(feature = "fs")]
+use crate::read::spool;
+#[cfg(feature = "fs")]
+use crate::{Compression, CreateOptions, HpkError, HpkWriter};
+use crate::{DirEntry, HpkArchive, HpkResult};
+
+/// How an entry differs between two archives
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffKind {
+    /// The entry exists only in the second archive
+    Added,
+    /// The entry exists only in the first archive
+    Removed,
+    /// The decompressed content differs or a file was replaced by a directory
+    Modified,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiffEntry {
+    pub path: PathBuf,
+    pub kind: DiffKind,
+    /// The entry is a directory in the archive it's found in
+    pub is_dir: bool,
+}
+
+/// Compares the directory trees and the file contents of the archives `a` and `b`
+///
+/// The entries are sorted by path. Files are compared by their decompressed content,
+/// a file which is only stored with another compression isn't reported.
+///
+#[cfg(feature = "fs")]
+pub fn diff<P, Q>(a: P, b: Q) -> HpkResult<Vec<DiffEntry>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let a = HpkArchive::open(a)?;
+    let b = HpkArchive::open(b)?;
+    diff_archives(&a, &b)
+}
+
+/// Creates the patch archive `file` with the entries of `new` which were added or
+/// modified compared to `old`
+///
+/// `old` and `new` are archives or directories. The game engines load the patch
+/// on top of the old archive, see `OverlayArchive`. Removed entries can't be removed
+/// by an overlay, they are returned with the other differences and aren't part of the
+/// patch.
+///
+/// With `CreateOptions::store_deltas` large files are stored as binary deltas against
+/// their old content, such a patch is applied with `apply_patch`.
+///
+#[cfg(feature = "fs")]
+pub fn patch<P, Q, R>(options: &CreateOptions, old: P, new: Q, file: R) -> HpkResult<Vec<DiffEntry>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let old = open_source(old.as_ref())?;
+    let new = open_source(new.as_ref())?;
+    let mut changes = vec![];
+    write_file(file.as_ref(), options.atomic, |out| {
+        changes = new.patch_into(&old, options, out)?;
+        Ok(())
+    })?;
+    Ok(changes)
+}
+
+/// Opens the archive `path` or packs the directory `path` uncompressed into a
+/// temporary archive
+#[cfg(feature = "fs")]
+fn open_source(path: &Path) -> HpkResult<HpkArchive> {
+    if !path.is_dir() {
+        return HpkArchive::open(path);
+    }
+    let mut options = CreateOptions::new();
+    options.with_compressor(Compression::None);
+    let tmpfile = write_spooled(spool()?, |tmp| write_hpk(&options, path, tmp))?;
+    HpkArchive::from_reader(tmpfile)
+}
+
+#[cfg(feature = "fs")]
+pub(crate) fn write_patch<W: Write + Seek>(
+    old: &HpkArchive,
+    new: &HpkArchive,
+    options: &CreateOptions,
+    w: &mut W,
+) -> HpkResult<Vec<DiffEntry>> {
+    if options.compress {
+        let mut changes = vec![];
+        compress_spooled(&options.compress_options, spool()?, w, |tmp| {
+            changes = write_changes(old, new, options, tmp)?;
+            Ok(())
+        })?;
+        Ok(changes)
+    } else {
+        write_changes(old, new, options, w)
+    }
+}
+
+#[cfg(feature = "fs")]
+fn write_changes<W: Write + Seek>(
+    old: &HpkArchive,
+    new: &HpkArchive,
+    options: &CreateOptions,
+    w: &mut W,
+) -> HpkResult<Vec<DiffEntry>> {
+    let changes = diff_archives(old, new)?;
+    let mut writer = HpkWriter::new(options, w)?;
+    let mut deltas = vec![];
+    for change in changes.iter().filter(|c| c.kind != DiffKind::Removed) {
+        let entry = new
+            .entry(&change.path)
+            .expect("changed entries exist in the new archive");
+        if entry.is_dir() {
+            writer.add_dir(entry.path())?;
+            continue;
+        }
+        let base = match (change.kind, old.entry(entry.path())) {
+            (DiffKind::Modified, Some(base)) if !base.is_dir() => Some(base),
+            _ => None,
+        };
+        if let (Some(min_size), Some(base)) = (options.delta_min_size, base) {
+            if new.metadata(entry)?.size >= min_size {
+                let content = new.read_file(entry.path())?;
+                let delta = delta::encode(&old.read_file(base.path())?, &content);
+                if delta.len() < content.len() {
+                    let compressor = options.compress_options.compressor;
+                    writer.add_file_with(entry.path(), Cursor::new(delta), compressor)?;
+                    deltas.push(entry.path());
+                    continue;
+                }
+            }
+        }
+        writer.add_file(entry.path(), new.open_entry(entry)?)?;
+    }
+    if !deltas.is_empty() {
+        let mut buf = vec![];
+        delta::write(&deltas, &mut buf)?;
+        writer.add_uncompressed(delta::DELTAS, Cursor::new(buf))?;
+    }
+    writer.finish()?;
+    Ok(changes)
+}
+
+/// Extracts the entries of the patch archive `patch` into `dest` and reconstructs the
+/// files which are stored as deltas against the archive or directory `old`
+///
+/// The result is the same as extracting a patch which was created without deltas.
+///
+#[cfg(feature = "fs")]
+pub fn apply_patch<P, Q, R>(old: P, patch: Q, dest: R) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let old = open_source(old.as_ref())?;
+    let patch = HpkArchive::open(patch)?;
+    patch.apply_patch_to(&old, dest)
+}
+
+#[cfg(feature = "fs")]
+pub(crate) fn extract_patch(patch: &HpkArchive, old: &HpkArchive, dest: &Path) -> HpkResult<()> {
+    let deltas = patch.deltas()?;
+    let mut rejected = vec![];
+    fs::create_dir_all(dest)?;
+    for entry in patch.entries().iter().filter(|e| e.depth() > 0) {
+        let path = entry.path();
+        if !is_safe_path(path) {
+            // the entries of a rejected directory are rejected as well
+            rejected.push(path.to_path_buf());
+            continue;
+        }
+        if entry.is_dir() {
+            fs::create_dir_all(dest.join(path))?;
+        } else if entry.depth() == 1 && path == Path::new(delta::DELTAS) {
+            continue;
+        } else if deltas.iter().any(|p| p == path) {
+            let content = delta::apply(&old.read_file(path)?, &patch.read_file(path)?)?;
+            fs::write(dest.join(path), content)?;
+        } else {
+            let mut out = BufWriter::new(fs::File::create(dest.join(path))?);
+            patch.copy_file(path, &mut out)?;
+            out.flush()?;
+        }
+    }
+    if !rejected.is_empty() {
+        return Err(HpkError::UnsafePaths(rejected));
+    }
+    Ok(())
+}
+
+/// Returns the content of the file `path` of the patch archive `patch` and
+/// reconstructs it if it's stored as a delta against `old`
+#[cfg(feature = "fs")]
+pub(crate) fn read_patched(
+    patch: &HpkArchive,
+    old: &HpkArchive,
+    path: &Path,
+) -> HpkResult<Vec<u8>> {
+    let content = patch.read_file(path)?;
+    if patch.deltas()?.iter().any(|p| p == path) {
+        return Ok(delta::apply(&old.read_file(path)?, &content)?);
+    }
+    Ok(content)
+}
+
+pub(crate) fn diff_archives(a: &HpkArchive, b: &HpkArchive) -> HpkResult<Vec<DiffEntry>> {
+    fn tree(archive: &HpkArchive) -> BTreeMap<&Path, &DirEntry> {
+        archive
+            .entries()
+            .iter()
+            .filter(|e| e.depth() > 0)
+            .map(|e| (e.path(), e))
+            .collect()
+    }
+    let tree_a = tree(a);
+    let tree_b = tree(b);
+
+    let mut changes = vec![];
+    for (path, entry) in &tree_a {
+        let kind = match tree_b.get(path) {
+            None => DiffKind::Removed,
+            Some(other) if entry.is_dir() != other.is_dir() => DiffKind::Modified,
+            Some(other) if entry.is_dir() || same_content(a, entry, b, other)? => continue,
+            Some(_) => DiffKind::Modified,
+        };
+        changes.push(DiffEntry {
+            path: path.to_path_buf(),
+            kind,
+            is_dir: entry.is_dir(),
+        });
+    }
+    for (path, entry) in &tree_b {
+        if !tree_a.contains_key(path) {
+            changes.push(DiffEntry {
+                path: path.to_path_buf(),
+                kind: DiffKind::Added,
+                is_dir: entry.is_dir(),
+            });
+        }
+    }
+    changes.sort_by(|x, y| x.path.cmp(&y.path));
+    Ok(changes)
+}
+
+fn same_content(a: &HpkArchive, ea: &DirEntry, b: &HpkArchive, eb: &DirEntry) -> HpkResult<bool> {
+    if a.metadata(ea)?.size != b.metadata(eb)?.size {
+        return Ok(false);
+    }
+    Ok(dedup::same_content(a.open_entry(ea)?, b.open_entry(eb)?)?)
+}
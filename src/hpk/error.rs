@@ -0,0 +1,145 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::format::{FormatError, NameError};
+
+pub(crate) type HpkResult<T> = Result<T, HpkError>;
+
+/// Alias for `HpkError`
+pub type Error = HpkError;
+
+/// Errors of reading and writing archives
+#[derive(Debug)]
+pub enum HpkError {
+    InvalidHeader,
+    InvalidDirEntryName,
+    /// The name of a new entry can't be stored or extracted, contains the path of the
+    /// entry. See `format::validate_name`.
+    InvalidName(PathBuf, NameError),
+    InvalidFragmentIndex,
+    /// The fragment table or the residual table reaches beyond the end of the file
+    TruncatedFragmentTable,
+    /// The chunk offsets of a compressed file are out of order or out of bounds
+    InvalidCompressionHeader,
+    EntryNotFound(PathBuf),
+    /// A new entry of `HpkWriter` has the path of an existing entry
+    DuplicateEntry(PathBuf),
+    /// A parent directory of a new entry of `HpkWriter` is a file, contains the path
+    /// of the file
+    NotADirectory(PathBuf),
+    /// The entries were not extracted because their paths would leave the destination
+    /// directory, e.g. with `..` or an absolute path. All other entries were extracted.
+    UnsafePaths(Vec<PathBuf>),
+    /// The operation was stopped by a `CancelToken`. Contains the paths of the
+    /// entries which were completely processed before.
+    Cancelled(Vec<PathBuf>),
+    /// An offset or length doesn't fit into the 32-bit fields of the archive.
+    /// See `CreateOptions::with_wide_offsets`.
+    OffsetOverflow,
+    /// The decompressed content doesn't match the checksums of the `_checksums` entry
+    ChecksumMismatch(PathBuf),
+    /// The archive has no `_signature` entry, see `HpkArchive::verify_signature`
+    Unsigned,
+    /// The `_signature` entry doesn't match the content of the archive and the public key
+    InvalidSignature,
+    /// The directory fragment with the 0-based index reaches beyond the end of the archive
+    FragmentOutOfBounds(usize),
+    /// The decompressed files are larger than the limit passed to `HpkArchive::read_all`
+    SizeLimitExceeded(u64),
+    /// The input directory contains a symbolic link, see `SymlinkPolicy::Error`
+    Symlink(PathBuf),
+    Io(io::Error),
+    #[cfg(feature = "fs")]
+    WalkDir(walkdir::Error),
+    #[cfg(feature = "watch")]
+    Watch(notify::Error),
+}
+
+impl std::fmt::Display for HpkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HpkError::InvalidHeader => write!(f, "invalid hpk header signature"),
+            HpkError::InvalidDirEntryName => write!(f, "invalid directory entry name"),
+            HpkError::InvalidName(path, e) => write!(f, "{}: {}", path.display(), e),
+            HpkError::InvalidFragmentIndex => write!(f, "invalid fragment index"),
+            HpkError::TruncatedFragmentTable => write!(f, "truncated fragment table"),
+            HpkError::InvalidCompressionHeader => write!(f, "invalid compression header"),
+            HpkError::EntryNotFound(path) => write!(f, "entry not found: {}", path.display()),
+            HpkError::DuplicateEntry(path) => write!(f, "duplicate entry: {}", path.display()),
+            HpkError::NotADirectory(path) => write!(f, "not a directory: {}", path.display()),
+            HpkError::UnsafePaths(paths) => {
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "unsafe entry paths rejected: {}", paths.join(", "))
+            }
+            HpkError::Cancelled(_) => write!(f, "operation cancelled"),
+            HpkError::OffsetOverflow => write!(f, "offset doesn't fit into 32 bits"),
+            HpkError::ChecksumMismatch(path) => {
+                write!(f, "checksum mismatch: {}", path.display())
+            }
+            HpkError::Unsigned => write!(f, "the archive isn't signed"),
+            HpkError::InvalidSignature => write!(f, "invalid archive signature"),
+            HpkError::FragmentOutOfBounds(index) => {
+                write!(
+                    f,
+                    "fragment {} reaches beyond the end of the archive",
+                    index
+                )
+            }
+            HpkError::SizeLimitExceeded(limit) => {
+                write!(f, "decompressed size exceeds the limit of {} bytes", limit)
+            }
+            HpkError::Symlink(path) => write!(f, "symbolic link: {}", path.display()),
+            HpkError::Io(e) => write!(f, "{}", e),
+            #[cfg(feature = "fs")]
+            HpkError::WalkDir(e) => write!(f, "{}", e),
+            #[cfg(feature = "watch")]
+            HpkError::Watch(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HpkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HpkError::Io(e) => Some(e),
+            #[cfg(feature = "fs")]
+            HpkError::WalkDir(e) => Some(e),
+            #[cfg(feature = "watch")]
+            HpkError::Watch(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HpkError {
+    fn from(err: io::Error) -> HpkError {
+        HpkError::Io(err)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl From<walkdir::Error> for HpkError {
+    fn from(err: walkdir::Error) -> HpkError {
+        HpkError::WalkDir(err)
+    }
+}
+
+#[cfg(feature = "watch")]
+impl From<notify::Error> for HpkError {
+    fn from(err: notify::Error) -> HpkError {
+        HpkError::Watch(err)
+    }
+}
+
+impl From<FormatError> for HpkError {
+    fn from(err: FormatError) -> HpkError {
+        match err {
+            FormatError::InvalidHeader => HpkError::InvalidHeader,
+            FormatError::InvalidFragmentIndex => HpkError::InvalidFragmentIndex,
+            FormatError::InvalidCompressionHeader => HpkError::InvalidCompressionHeader,
+            FormatError::OffsetOverflow => HpkError::OffsetOverflow,
+            FormatError::UnexpectedEnd => HpkError::Io(io::ErrorKind::UnexpectedEof.into()),
+            FormatError::InvalidName(e) => HpkError::InvalidName(PathBuf::new(), e),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use glob::Pattern;
+
+use crate::compression::write_spooled;
+use crate::read::spool;
+use crate::{audit, walk};
+use crate::{AuditFormat, CancelToken, FragmentedReader};
+use crate::{HpkArchive, HpkResult, NameDecoding, Progress, Transform};
+
+/// The Windows epoch starts 1601-01-01T00:00:00Z. It's SEC_TO_UNIX_EPOCH seconds
+/// before the Unix epoch 1970-01-01T00:00:00Z.
+///
+pub(crate) const SEC_TO_UNIX_EPOCH: i64 = 11_644_473_600;
+pub(crate) const WINDOWS_TICKS: i64 = 10_000_000;
+
+// struct ExtractOptions {{{
+/// How the extraction creates file entries which share their content with an
+/// already extracted entry, like the duplicates packed by `CreateOptions::deduplicate`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Decompresses every entry on its own
+    #[default]
+    Extract,
+    /// Creates a hard link to the extracted file, falls back to a copy if the file
+    /// system doesn't support hard links
+    HardLink,
+    /// Copies the extracted file, file systems like Btrfs, XFS or APFS share the data
+    /// of the copy (reflink)
+    Copy,
+}
+
+/// How the extraction handles a file entry whose destination path already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExistingPolicy {
+    /// Replaces the existing file
+    #[default]
+    Overwrite,
+    /// Keeps the existing file and doesn't extract the entry
+    Skip,
+    /// Keeps the existing file and extracts the entry next to it, `a.lua` becomes
+    /// `a.1.lua` or the first free number after it
+    Rename,
+}
+
+#[derive(Default)]
+pub struct ExtractOptions {
+    pub(crate) paths: Vec<Pattern>,
+    pub(crate) excludes: Vec<Pattern>,
+    pub(crate) skip_filedates: bool,
+    pub(crate) skip_checksums: bool,
+    pub(crate) fix_lua_files: bool,
+    pub(crate) sparse: bool,
+    pub(crate) duplicates: DuplicatePolicy,
+    pub(crate) existing: ExistingPolicy,
+    pub(crate) state: Option<PathBuf>,
+    pub(crate) verbose: bool,
+    pub(crate) threads: usize,
+    pub(crate) names: NameDecoding,
+    pub(crate) audit: Option<RefCell<audit::AuditLog>>,
+    pub(crate) audit_digests: bool,
+    pub(crate) cancel: Option<CancelToken>,
+    pub(crate) progress: Option<Box<dyn Progress>>,
+    pub(crate) transform: Option<Arc<dyn Transform>>,
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn skip_filedates(&mut self) {
+        self.skip_filedates = true;
+    }
+
+    /// Extracts a `_checksums` file like any other file instead of verifying the
+    /// extracted files with it
+    ///
+    pub fn skip_checksums(&mut self) {
+        self.skip_checksums = true;
+    }
+
+    pub fn fix_lua_files(&mut self) {
+        self.fix_lua_files = true;
+    }
+
+    /// Writes the extracted files as sparse files
+    ///
+    /// Blocks of zeros are skipped instead of written, file systems supporting sparse
+    /// files don't allocate space for them. Saves disk space for huge, mostly empty
+    /// map data.
+    ///
+    pub fn sparse_files(&mut self) {
+        self.sparse = true;
+    }
+
+    /// Sets how entries which share their content with an extracted entry are created
+    ///
+    /// A hard link shares the file with all its names, modifying one of them changes
+    /// the others as well.
+    ///
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicates = policy;
+    }
+
+    /// Sets how file entries whose destination already exists are extracted
+    ///
+    /// A file continued with `resume_state` is always written at its extracted length.
+    ///
+    pub fn set_existing_policy(&mut self, policy: ExistingPolicy) {
+        self.existing = policy;
+    }
+
+    /// Records the extracted files in the state file `path`
+    ///
+    /// An interrupted extraction with the same state file skips the files which are
+    /// already extracted. A file whose extraction was cancelled by the `CancelToken`
+    /// is continued at its extracted length. The state file is removed once all
+    /// entries are extracted.
+    ///
+    pub fn resume_state<P: AsRef<Path>>(&mut self, path: P) {
+        self.state = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Sets how `extract` and `extract_reader` decode the names of the entries
+    pub fn set_name_decoding(&mut self, names: NameDecoding) {
+        self.names = names;
+    }
+
+    /// Decodes a scrambled archive with `transform` in `extract` and `extract_reader`
+    pub fn with_transform<T: Transform + 'static>(&mut self, transform: T) {
+        self.transform = Some(Arc::new(transform));
+    }
+
+    /// Sets the number of threads used to decompress the chunks of a file
+    ///
+    /// Only files with several chunks benefit from more threads.
+    ///
+    pub fn with_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn set_paths(&mut self, paths: &[String]) {
+        self.paths = paths.iter().filter_map(|s| Pattern::new(s).ok()).collect();
+    }
+
+    /// Skips the entries matching one of the glob patterns, even if they match `set_paths`
+    pub fn set_excludes(&mut self, patterns: &[String]) {
+        self.excludes = patterns
+            .iter()
+            .filter_map(|s| Pattern::new(s).ok())
+            .collect();
+    }
+
+    /// Writes a record for every processed file entry and a final summary to `w`
+    ///
+    /// Every record is flushed as soon as the entry is completed.
+    ///
+    pub fn audit_manifest<W: Write + Send + 'static>(&mut self, w: W) {
+        let format = match self.audit.take() {
+            Some(log) => log.into_inner().format(),
+            None => AuditFormat::default(),
+        };
+        self.audit = Some(RefCell::new(audit::AuditLog::new(Box::new(w), format)));
+    }
+
+    pub fn set_audit_format(&mut self, format: AuditFormat) {
+        if let Some(log) = &self.audit {
+            log.borrow_mut().set_format(format);
+        }
+    }
+
+    /// Adds the SHA-256 digest of every written file to the records of `audit_manifest`
+    #[cfg(feature = "sha256")]
+    pub fn audit_digests(&mut self) {
+        self.audit_digests = true;
+    }
+
+    /// Stops the extraction as soon as the token is cancelled
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    /// Reports every extracted file entry and the written bytes to `progress`
+    pub fn set_progress<P: Progress + 'static>(&mut self, progress: P) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        if self.excludes.iter().any(|pat| pat.matches_path(path)) {
+            return false;
+        }
+        if self.paths.is_empty() {
+            return true;
+        }
+        for pat in &self.paths {
+            if pat.matches_path(path) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns a hasher for the digest of an audit record, it's disabled without
+    /// `audit_digests`
+    ///
+    pub(crate) fn audit_hasher(&self) -> audit::AuditHasher {
+        audit::AuditHasher::new(self.audit.is_some() && self.audit_digests)
+    }
+
+    pub(crate) fn audit(
+        &self,
+        rec: &audit::AuditRecord<'_>,
+        summary: &mut audit::AuditSummary,
+    ) -> HpkResult<()> {
+        if let Some(log) = &self.audit {
+            log.borrow_mut().record(rec, summary)?;
+        }
+        Ok(())
+    }
+}
+// }}}
+
+pub fn extract<P>(options: &ExtractOptions, file: P, dest: P) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+{
+    let walk = walk::walk(file)?
+        .name_decoding(options.names)
+        .shared_transform(options.transform.clone());
+    HpkArchive::from_walk_lenient(walk).extract_with(options, dest)
+}
+
+/// Extracts an archive which is read from `r` without seeking, e.g. from stdin
+///
+/// The directory tree and the fragment table are stored at the end of an archive,
+/// the input is spooled into a temporary file before anything is extracted.
+///
+pub fn extract_reader<R, P>(options: &ExtractOptions, mut r: R, dest: P) -> HpkResult<()>
+where
+    R: Read,
+    P: AsRef<Path>,
+{
+    let tmpfile = write_spooled(spool()?, |tmp| {
+        io::copy(&mut r, tmp)?;
+        Ok(())
+    })?;
+    let walk = walk::walk_reader(tmpfile)?
+        .name_decoding(options.names)
+        .shared_transform(options.transform.clone());
+    HpkArchive::from_walk_lenient(walk).extract_with(options, dest)
+}
+
+/// Returns `true` if `path` stays inside of the directory it's joined to
+pub(crate) fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Converts a value of a `_filedates` file in either format into seconds since the
+/// Unix epoch
+pub(crate) fn filedate_to_unix(val: i64) -> i64 {
+    // This catches the different file time formats.
+    // Multiplication overflows for the Windows file time
+    let val = match val.checked_mul(2000) {
+        Some(val) => val,
+        None => val,
+    };
+    (val / WINDOWS_TICKS) - SEC_TO_UNIX_EPOCH
+}
+
+pub(crate) fn process_filedates<P, R>(dest: P, r: &mut FragmentedReader<R>) -> HpkResult<()>
+where
+    P: AsRef<Path>,
+    R: Read + Seek,
+{
+    // macro: is_valid {{{
+    macro_rules! is_valid {
+        ($e:expr) => {{
+            #[cfg(unix)]
+            let valid = $e.exists();
+            #[cfg(windows)]
+            let valid = $e.exists() && $e.metadata()?.is_file();
+            valid
+        }};
+    }
+    // }}}
+
+    let br = io::BufReader::new(r);
+    for line in br.lines() {
+        let line = line?;
+        let entry: Vec<_> = line.rsplitn(2, '=').collect();
+        if entry.len() < 2 || !is_safe_path(Path::new(entry[1])) {
+            continue;
+        }
+        if let Ok(val) = entry[0].parse::<i64>() {
+            let ft = filetime::FileTime::from_unix_time(filedate_to_unix(val), 0);
+
+            let path = dest.as_ref().join(entry[1]);
+            if is_valid!(path) {
+                filetime::set_file_times(path, ft, ft)?;
+            } else {
+                // Remove the first component of the path and try again because
+                // Grand Ages: Rome adds the basename of the original hpk file to the path
+                let mut comps = Path::new(entry[1]).components();
+                comps.next();
+
+                let path = dest.as_ref().join(comps.as_path());
+                if is_valid!(path) {
+                    filetime::set_file_times(path, ft, ft)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// vim: fdm=marker
@@ -0,0 +1,219 @@
+//! C interface for tools which link against the library
+//!
+//! Build the shared library with `cargo build --lib --release --features ffi`, the
+//! declarations are in `include/hpk.h`.
+//!
+//! The functions return `NULL` or a negative value on failure, the message of the
+//! last error of the calling thread is returned by `hpk_last_error`. Paths are
+//! UTF-8 strings, the entry paths use `/` as separator.
+//!
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::{Component, Path};
+use std::ptr;
+
+use crate::{CreateOptions, DirEntry, HpkArchive, HpkError, HpkResult};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(e: &HpkError) {
+    let msg = CString::new(e.to_string().replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = msg);
+}
+
+fn ok_or_error<T>(result: HpkResult<T>) -> Option<T> {
+    result.map_err(|e| set_error(&e)).ok()
+}
+
+unsafe fn to_path<'a>(s: *const c_char) -> HpkResult<&'a Path> {
+    if s.is_null() {
+        return Err(HpkError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "path is NULL",
+        )));
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(Path::new(s)),
+        Err(e) => Err(HpkError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            e,
+        ))),
+    }
+}
+
+/// The file and directory entries of the archive without the root directory
+fn entries(archive: &HpkArchive) -> &[DirEntry] {
+    match archive.entries() {
+        [root, entries @ ..] if root.depth() == 0 => entries,
+        entries => entries,
+    }
+}
+
+/// Copies `data` into `buf` if it fits and returns the length of `data`
+unsafe fn copy_out(data: &[u8], buf: *mut u8, len: usize) -> i64 {
+    if !buf.is_null() && data.len() <= len {
+        ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+    }
+    data.len() as i64
+}
+
+/// Returns the message of the last error of the calling thread or `NULL`
+///
+/// The string is valid until the next call of a `hpk_` function on the thread.
+///
+#[no_mangle]
+pub extern "C" fn hpk_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Opens the archive at `path`, returns `NULL` on failure
+///
+/// # Safety
+///
+/// `path` must be a valid nul-terminated string. The archive must be released
+/// with `hpk_close`.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_open(path: *const c_char) -> *mut HpkArchive {
+    match ok_or_error(to_path(path).and_then(HpkArchive::open)) {
+        Some(archive) => Box::into_raw(Box::new(archive)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Releases an archive returned by `hpk_open`
+///
+/// # Safety
+///
+/// `archive` must be `NULL` or a pointer returned by `hpk_open` which wasn't
+/// closed before.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_close(archive: *mut HpkArchive) {
+    if !archive.is_null() {
+        drop(Box::from_raw(archive));
+    }
+}
+
+/// Returns the number of file and directory entries of the archive
+///
+/// # Safety
+///
+/// `archive` must be a pointer returned by `hpk_open`.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_entry_count(archive: *const HpkArchive) -> usize {
+    match archive.as_ref() {
+        Some(archive) => entries(archive).len(),
+        None => 0,
+    }
+}
+
+/// Copies the path of the entry at `index` into `buf`
+///
+/// Returns the length of the path without a nul terminator, the path is only
+/// copied if `len` is large enough. Returns -1 if `index` is out of range.
+///
+/// # Safety
+///
+/// `archive` must be a pointer returned by `hpk_open` and `buf` must be `NULL` or
+/// valid for writes of `len` bytes.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_entry_path(
+    archive: *const HpkArchive,
+    index: usize,
+    buf: *mut c_char,
+    len: usize,
+) -> i64 {
+    let entry = match archive.as_ref().and_then(|a| entries(a).get(index)) {
+        Some(entry) => entry,
+        None => return -1,
+    };
+    let path = entry
+        .path()
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    copy_out(path.as_bytes(), buf.cast(), len)
+}
+
+/// Returns 1 if the entry at `index` is a directory, 0 for a file and -1 if
+/// `index` is out of range
+///
+/// # Safety
+///
+/// `archive` must be a pointer returned by `hpk_open`.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_entry_is_dir(archive: *const HpkArchive, index: usize) -> c_int {
+    match archive.as_ref().and_then(|a| entries(a).get(index)) {
+        Some(entry) => c_int::from(entry.is_dir()),
+        None => -1,
+    }
+}
+
+/// Reads the decompressed content of the file at `path` into `buf`
+///
+/// Returns the length of the content, the content is only read and copied if `len` is
+/// large enough. Returns -1 on failure.
+///
+/// # Safety
+///
+/// `archive` must be a pointer returned by `hpk_open`, `path` a valid nul-terminated
+/// string and `buf` must be `NULL` or valid for writes of `len` bytes.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_read_entry(
+    archive: *const HpkArchive,
+    path: *const c_char,
+    buf: *mut u8,
+    len: usize,
+) -> i64 {
+    let archive = match archive.as_ref() {
+        Some(archive) => archive,
+        None => return -1,
+    };
+    let read = |path: &Path| {
+        let entry = archive
+            .entry(path)
+            .ok_or_else(|| HpkError::EntryNotFound(path.to_path_buf()))?;
+        // the size is in the compression header, a too small buffer isn't filled
+        let size = archive.metadata(entry)?.size;
+        if buf.is_null() || size > len as u64 {
+            return Ok(size as i64);
+        }
+        Ok(copy_out(&archive.read_file(path)?, buf, len))
+    };
+    ok_or_error(to_path(path).and_then(read)).unwrap_or(-1)
+}
+
+/// Creates the archive `file` from the directory `dir` with the default options
+///
+/// Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `dir` and `file` must be valid nul-terminated strings.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hpk_create_from_dir(dir: *const c_char, file: *const c_char) -> c_int {
+    unsafe fn create(dir: *const c_char, file: *const c_char) -> HpkResult<()> {
+        crate::create(&CreateOptions::new(), to_path(dir)?, to_path(file)?)
+    }
+
+    match ok_or_error(create(dir, file)) {
+        Some(()) => 0,
+        None => -1,
+    }
+}
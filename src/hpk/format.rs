@@ -0,0 +1,799 @@
+//! Definitions of the hpk format which only depend on `core` and `alloc`
+//!
+//! The types are parsed from byte slices and encoded into byte vectors. The rest of
+//! the crate reads and writes them through `std::io`, the `hpk-format` crate builds
+//! this module for `no_std` targets.
+//!
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+pub const HPK_SIG: [u8; 4] = *b"BPUL";
+/// Signature of the big-endian variant used by some console releases
+pub const HPK_SIG_BE: [u8; 4] = *b"LUPB";
+pub const HEADER_LENGTH: u8 = 36;
+/// Header length of the variant with 64-bit offsets
+pub const WIDE_HEADER_LENGTH: u8 = 52;
+
+/// Size of the compression identifier, the inflated length and the chunk size
+pub const COMPRESSION_HEADER_LENGTH: u64 = 12;
+
+/// Errors of parsing and encoding the format structures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FormatError {
+    InvalidHeader,
+    InvalidFragmentIndex,
+    InvalidCompressionHeader,
+    /// An offset or length doesn't fit into the 32-bit fields of the archive
+    OffsetOverflow,
+    /// The data ends before the value is complete
+    UnexpectedEnd,
+    /// A name can't be stored as a directory entry
+    InvalidName(NameError),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::InvalidHeader => write!(f, "invalid hpk header signature"),
+            FormatError::InvalidFragmentIndex => write!(f, "invalid fragment index"),
+            FormatError::InvalidCompressionHeader => write!(f, "invalid compression header"),
+            FormatError::OffsetOverflow => write!(f, "offset doesn't fit into 32 bits"),
+            FormatError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            FormatError::InvalidName(e) => write!(f, "invalid name: {}", e),
+        }
+    }
+}
+
+/// The longest name the 16-bit length field of a directory entry allows
+pub const MAX_NAME_LENGTH: usize = u16::MAX as usize;
+
+/// Why a name can't be stored as a directory entry, see `validate_name`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NameError {
+    Empty,
+    /// The length of the name is greater than `MAX_NAME_LENGTH`
+    TooLong(usize),
+    /// The name contains `/` or `\`
+    Separator,
+    /// A control character or one of `<>:"|?*` which Windows doesn't allow
+    ForbiddenCharacter(u8),
+    /// `.`, `..` or a device name of Windows like `CON` or `LPT1.txt`
+    Reserved,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "empty name"),
+            NameError::TooLong(n) => write!(f, "name of {} bytes is too long", n),
+            NameError::Separator => write!(f, "name contains a path separator"),
+            NameError::ForbiddenCharacter(c) => {
+                write!(
+                    f,
+                    "name contains the forbidden character {:?}",
+                    char::from(*c)
+                )
+            }
+            NameError::Reserved => write!(f, "reserved name"),
+        }
+    }
+}
+
+/// Checks that `name` can be stored as a directory entry and extracted on Windows
+pub fn validate_name(name: &[u8]) -> Result<(), NameError> {
+    const DEVICES: [&[u8]; 4] = [b"CON", b"PRN", b"AUX", b"NUL"];
+
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong(name.len()));
+    }
+    if let Some(&c) = name
+        .iter()
+        .find(|&&c| c < 0x20 || b"/\\<>:\"|?*".contains(&c))
+    {
+        return Err(match c {
+            b'/' | b'\\' => NameError::Separator,
+            c => NameError::ForbiddenCharacter(c),
+        });
+    }
+    if name == b"." || name == b".." {
+        return Err(NameError::Reserved);
+    }
+    // the device names are reserved with any extension
+    let stem = name.split(|&c| c == b'.').next().unwrap_or(name);
+    let stem = match stem.iter().rposition(|&c| c != b' ') {
+        Some(end) => &stem[..=end],
+        None => stem,
+    };
+    let is_device = match stem.len() {
+        3 => DEVICES.iter().any(|d| stem.eq_ignore_ascii_case(d)),
+        4 => {
+            (stem[..3].eq_ignore_ascii_case(b"COM") || stem[..3].eq_ignore_ascii_case(b"LPT"))
+                && (b'1'..=b'9').contains(&stem[3])
+        }
+        _ => false,
+    };
+    if is_device {
+        return Err(NameError::Reserved);
+    }
+    Ok(())
+}
+
+/// Splits off the first `N` bytes of `data`
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], FormatError> {
+    if data.len() < N {
+        return Err(FormatError::UnexpectedEnd);
+    }
+    let (head, tail) = data.split_at(N);
+    *data = tail;
+    let mut buf = [0; N];
+    buf.copy_from_slice(head);
+    Ok(buf)
+}
+
+// struct Layout {{{
+/// Width of the offsets and byte order of the header, the fragment tables and the
+/// directory entries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub wide: bool,
+    pub big_endian: bool,
+}
+
+impl Layout {
+    pub fn header_length(self) -> u8 {
+        if self.wide {
+            WIDE_HEADER_LENGTH
+        } else {
+            HEADER_LENGTH
+        }
+    }
+
+    /// Size of a fragment entry: offset and length
+    pub fn fragment_size(self) -> u64 {
+        if self.wide {
+            16
+        } else {
+            8
+        }
+    }
+
+    pub fn parse_u16(self, data: &mut &[u8]) -> Result<u16, FormatError> {
+        let buf = take(data)?;
+        if self.big_endian {
+            Ok(u16::from_be_bytes(buf))
+        } else {
+            Ok(u16::from_le_bytes(buf))
+        }
+    }
+
+    pub fn parse_u32(self, data: &mut &[u8]) -> Result<u32, FormatError> {
+        let buf = take(data)?;
+        if self.big_endian {
+            Ok(u32::from_be_bytes(buf))
+        } else {
+            Ok(u32::from_le_bytes(buf))
+        }
+    }
+
+    pub fn parse_offset(self, data: &mut &[u8]) -> Result<u64, FormatError> {
+        match (self.wide, self.big_endian) {
+            (true, true) => Ok(u64::from_be_bytes(take(data)?)),
+            (true, false) => Ok(u64::from_le_bytes(take(data)?)),
+            (false, _) => Ok(u64::from(self.parse_u32(data)?)),
+        }
+    }
+
+    pub fn encode_u16(self, out: &mut Vec<u8>, value: u16) {
+        if self.big_endian {
+            out.extend_from_slice(&value.to_be_bytes());
+        } else {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn encode_u32(self, out: &mut Vec<u8>, value: u32) {
+        if self.big_endian {
+            out.extend_from_slice(&value.to_be_bytes());
+        } else {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Encodes the value as u64 or as u32 if it fits
+    pub fn encode_offset(self, out: &mut Vec<u8>, value: u64) -> Result<(), FormatError> {
+        match (self.wide, self.big_endian) {
+            (true, true) => out.extend_from_slice(&value.to_be_bytes()),
+            (true, false) => out.extend_from_slice(&value.to_le_bytes()),
+            (false, _) => {
+                let value = u32::try_from(value).map_err(|_| FormatError::OffsetOverflow)?;
+                self.encode_u32(out, value);
+            }
+        }
+        Ok(())
+    }
+}
+// }}}
+
+// struct Header {{{
+/// The archive header at the start of the file
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Header {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) _identifier: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(skip))]
+    wide: bool,
+    pub data_offset: u32,
+    pub fragments_per_file: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) _unknown2: u32,
+    pub fragments_residual_offset: u64,
+    pub fragments_residual_count: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) _unknown5: u32,
+    pub fragmented_filesystem_offset: u64,
+    pub fragmented_filesystem_length: u64,
+}
+
+impl Header {
+    pub fn new(
+        data_offset: u32,
+        fragmented_filesystem_offset: u64,
+        fragmented_filesystem_length: u64,
+        fragments_per_file: u32,
+        layout: Layout,
+    ) -> Header {
+        Header {
+            _identifier: if layout.big_endian {
+                HPK_SIG_BE
+            } else {
+                HPK_SIG
+            },
+            wide: layout.wide,
+            data_offset,
+            fragments_per_file,
+            _unknown2: 0xFF,
+            fragments_residual_offset: 0,
+            fragments_residual_count: 0,
+            _unknown5: 1,
+            fragmented_filesystem_offset,
+            fragmented_filesystem_length,
+        }
+    }
+
+    /// Returns the layout of the header at the start of `data`, `archive_len` is the
+    /// length of the whole archive
+    ///
+    /// The header doesn't mark the variant with 64-bit offsets. It's parsed with both
+    /// layouts and the 32-bit layout wins if its fragment table lies between the header
+    /// and the end of the archive, otherwise the 64-bit layout if its table does. `data`
+    /// holds the first `WIDE_HEADER_LENGTH` bytes or the whole archive if it's shorter.
+    ///
+    pub fn parse_layout(data: &[u8], archive_len: u64) -> Result<Layout, FormatError> {
+        let big_endian = match take(&mut &data[..])? {
+            HPK_SIG => false,
+            HPK_SIG_BE => true,
+            _ => return Err(FormatError::InvalidHeader),
+        };
+        let narrow = Layout {
+            wide: false,
+            big_endian,
+        };
+        let wide = Layout {
+            wide: true,
+            big_endian,
+        };
+        let table_fits = |layout: Layout| match Header::parse_with(data, layout) {
+            Ok(hdr) => {
+                let header_length = u64::from(layout.header_length());
+                // the data of a 64-bit archive can't overlap its header
+                (!layout.wide || u64::from(hdr.data_offset) >= header_length)
+                    && hdr.fragmented_filesystem_offset >= header_length
+                    && hdr
+                        .fragmented_filesystem_offset
+                        .checked_add(hdr.fragmented_filesystem_length)
+                        .is_some_and(|end| end <= archive_len)
+            }
+            Err(_) => false,
+        };
+        if !table_fits(narrow) && table_fits(wide) {
+            return Ok(wide);
+        }
+        Ok(narrow)
+    }
+
+    /// Parses the header at the start of `data`, see `parse_layout`
+    pub fn parse(data: &[u8], archive_len: u64) -> Result<Header, FormatError> {
+        let layout = Header::parse_layout(data, archive_len)?;
+        Header::parse_with(data, layout)
+    }
+
+    fn parse_with(data: &[u8], layout: Layout) -> Result<Header, FormatError> {
+        let mut data = data;
+        let identifier = take(&mut data)?;
+        let data_offset = layout.parse_u32(&mut data)?;
+        let fragments_per_file = layout.parse_u32(&mut data)?;
+        if fragments_per_file == 0 {
+            return Err(FormatError::InvalidHeader);
+        }
+        Ok(Header {
+            _identifier: identifier,
+            wide: layout.wide,
+            data_offset,
+            fragments_per_file,
+            _unknown2: layout.parse_u32(&mut data)?,
+            fragments_residual_offset: layout.parse_offset(&mut data)?,
+            fragments_residual_count: layout.parse_offset(&mut data)?,
+            _unknown5: layout.parse_u32(&mut data)?,
+            fragmented_filesystem_offset: layout.parse_offset(&mut data)?,
+            fragmented_filesystem_length: layout.parse_offset(&mut data)?,
+        })
+    }
+
+    /// Returns `true` if the offsets and lengths of the archive are stored as 64-bit values
+    pub fn is_wide(&self) -> bool {
+        self.wide
+    }
+
+    /// Returns `true` if the header, the fragment tables and the directory entries are
+    /// stored in big-endian byte order
+    pub fn is_big_endian(&self) -> bool {
+        self._identifier == HPK_SIG_BE
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout {
+            wide: self.is_wide(),
+            big_endian: self.is_big_endian(),
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), FormatError> {
+        let layout = self.layout();
+        out.extend_from_slice(&self._identifier);
+        layout.encode_u32(out, self.data_offset);
+        layout.encode_u32(out, self.fragments_per_file);
+        layout.encode_u32(out, self._unknown2);
+        layout.encode_offset(out, self.fragments_residual_offset)?;
+        layout.encode_offset(out, self.fragments_residual_count)?;
+        layout.encode_u32(out, self._unknown5);
+        layout.encode_offset(out, self.fragmented_filesystem_offset)?;
+        layout.encode_offset(out, self.fragmented_filesystem_length)?;
+        Ok(())
+    }
+
+    pub fn filesystem_entries(&self) -> usize {
+        let size = self.layout().fragment_size() * u64::from(self.fragments_per_file);
+        (self.fragmented_filesystem_length / size) as usize
+    }
+}
+// }}}
+
+// struct Fragment {{{
+/// A contiguous part of an entry, the offset is relative to the start of the archive
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Fragment {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl Fragment {
+    pub fn new(offset: u64, length: u64) -> Fragment {
+        Fragment { offset, length }
+    }
+
+    pub fn parse(data: &mut &[u8], layout: Layout) -> Result<Fragment, FormatError> {
+        let offset = layout.parse_offset(data)?;
+        let length = layout.parse_offset(data)?;
+        Ok(Fragment { offset, length })
+    }
+
+    /// Parses `n` consecutive fragments
+    pub fn parse_n(n: usize, data: &[u8], layout: Layout) -> Result<Vec<Fragment>, FormatError> {
+        let mut data = data;
+        // the capacity is bounded by the data instead of trusting `n`
+        let fits = data.len() / layout.fragment_size() as usize;
+        let mut fragments = Vec::with_capacity(n.min(fits));
+        for _ in 0..n {
+            fragments.push(Fragment::parse(&mut data, layout)?);
+        }
+        Ok(fragments)
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>, layout: Layout) -> Result<(), FormatError> {
+        layout.encode_offset(out, self.offset)?;
+        layout.encode_offset(out, self.length)?;
+        Ok(())
+    }
+}
+// }}}
+
+// struct RawDirEntry {{{
+/// A directory entry as stored in the directory fragments
+///
+/// `index` is the 0-based index of the entry's fragments, `name` isn't decoded.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawDirEntry<'a> {
+    pub index: usize,
+    pub is_dir: bool,
+    pub name: &'a [u8],
+}
+
+impl<'a> RawDirEntry<'a> {
+    pub fn parse(data: &mut &'a [u8], layout: Layout) -> Result<RawDirEntry<'a>, FormatError> {
+        let index = layout
+            .parse_u32(data)?
+            .checked_sub(1)
+            .ok_or(FormatError::InvalidFragmentIndex)?;
+        let is_dir = layout.parse_u32(data)? != 0;
+        let name_length = usize::from(layout.parse_u16(data)?);
+        if data.len() < name_length {
+            return Err(FormatError::UnexpectedEnd);
+        }
+        let (name, tail) = data.split_at(name_length);
+        *data = tail;
+        Ok(RawDirEntry {
+            index: index as usize,
+            is_dir,
+            name,
+        })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>, layout: Layout) -> Result<(), FormatError> {
+        let index = u32::try_from(self.index)
+            .ok()
+            .and_then(|i| i.checked_add(1))
+            .ok_or(FormatError::InvalidFragmentIndex)?;
+        let name_length = u16::try_from(self.name.len())
+            .map_err(|_| FormatError::InvalidName(NameError::TooLong(self.name.len())))?;
+        layout.encode_u32(out, index);
+        layout.encode_u32(out, u32::from(self.is_dir));
+        layout.encode_u16(out, name_length);
+        out.extend_from_slice(self.name);
+        Ok(())
+    }
+}
+
+/// Iterator over the entries of a directory fragment, see `dir_entries`
+pub struct DirEntries<'a> {
+    data: &'a [u8],
+    layout: Layout,
+}
+
+/// Parses the entries of a directory fragment
+pub fn dir_entries(data: &[u8], layout: Layout) -> DirEntries<'_> {
+    DirEntries { data, layout }
+}
+
+impl<'a> Iterator for DirEntries<'a> {
+    type Item = Result<RawDirEntry<'a>, FormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let entry = RawDirEntry::parse(&mut self.data, self.layout);
+        if entry.is_err() {
+            // stop after the first error
+            self.data = &[];
+        }
+        Some(entry)
+    }
+}
+// }}}
+
+// enum Compression {{{
+/// The chunk codec of an entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Compression {
+    Zlib,
+    Lz4,
+    Zstd,
+    None,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match *self {
+            Compression::Zlib => write!(f, "ZLIB"),
+            Compression::Lz4 => write!(f, "LZ4"),
+            Compression::Zstd => write!(f, "ZSTD"),
+            Compression::None => write!(f, "None"),
+        }
+    }
+}
+
+impl Compression {
+    pub fn is_compressed(&self) -> bool {
+        !core::matches!(*self, Compression::None)
+    }
+
+    /// Returns the compression of the 4-byte identifier at the start of an entry
+    pub fn from_identifier(id: [u8; 4]) -> Compression {
+        match &id {
+            b"ZLIB" => Compression::Zlib,
+            b"LZ4 " => Compression::Lz4,
+            b"ZSTD" => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Returns the identifier, which is empty for uncompressed entries
+    pub fn identifier(&self) -> &'static [u8] {
+        match *self {
+            Compression::Zlib => b"ZLIB",
+            Compression::Lz4 => b"LZ4 ",
+            Compression::Zstd => b"ZSTD",
+            Compression::None => b"",
+        }
+    }
+}
+// }}}
+
+// struct CompressionHeader {{{
+/// The header of a compressed entry with the chunk offset table
+pub struct CompressionHeader {
+    pub compressor: Compression,
+    pub inflated_length: u32,
+    pub chunk_size: u32,
+    pub chunks: Vec<Chunk>,
+}
+
+/// A compressed chunk, the offset is relative to the start of the entry
+#[derive(Copy, Clone)]
+pub struct Chunk {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl CompressionHeader {
+    /// Validates the first chunk offset of an entry with `length` bytes and returns
+    /// the size of the chunk offset table
+    pub fn table_length(first: u32, length: u64) -> Result<u64, FormatError> {
+        let first = u64::from(first);
+        // the offset table is part of the entry, this caps the number of chunks
+        if first < 16 || first % 4 != 0 || first > length {
+            return Err(FormatError::InvalidCompressionHeader);
+        }
+        Ok(first - COMPRESSION_HEADER_LENGTH)
+    }
+
+    /// Parses the header at the start of `data` of an entry with `length` bytes
+    ///
+    /// `data` contains at least the 12 header bytes and the chunk offset table.
+    /// Without any byte after the header the entry has no chunks.
+    ///
+    pub fn parse(data: &[u8], length: u64) -> Result<CompressionHeader, FormatError> {
+        let mut data = data;
+        let compressor = Compression::from_identifier(take(&mut data)?);
+        let inflated_length = u32::from_le_bytes(take(&mut data)?);
+        let chunk_size = u32::from_le_bytes(take(&mut data)?);
+        let chunks = if data.is_empty() {
+            Vec::new()
+        } else {
+            let first = u32::from_le_bytes(take(&mut data)?);
+            let count = CompressionHeader::table_length(first, length)? / 4;
+            // the capacity is bounded by the data instead of trusting `first`
+            let fits = data.len() / 4 + 1;
+            let mut offsets = Vec::with_capacity(fits.min(count as usize));
+            offsets.push(u64::from(first));
+            for _ in 1..count {
+                offsets.push(u64::from(u32::from_le_bytes(take(&mut data)?)));
+            }
+            let mut ends = offsets.iter().skip(1).chain(core::iter::once(&length));
+            offsets
+                .iter()
+                .map(|&offset| {
+                    let end = *ends.next().expect("one end per offset");
+                    match end.checked_sub(offset) {
+                        Some(length) => Ok(Chunk { offset, length }),
+                        None => Err(FormatError::InvalidCompressionHeader),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        Ok(CompressionHeader {
+            compressor,
+            inflated_length,
+            chunk_size,
+            chunks,
+        })
+    }
+
+    /// Encodes the header and the chunk offset table, `offsets` are relative to the
+    /// end of the table. Returns the size of the encoded header.
+    pub fn encode(
+        compressor: Compression,
+        inflated_length: u32,
+        chunk_size: u32,
+        offsets: &[u32],
+        out: &mut Vec<u8>,
+    ) -> Result<u64, FormatError> {
+        let offsets_size = u32::try_from(offsets.len())
+            .ok()
+            .and_then(|n| n.checked_mul(4))
+            .ok_or(FormatError::OffsetOverflow)?;
+        let header_size = COMPRESSION_HEADER_LENGTH as u32 + offsets_size;
+
+        out.extend_from_slice(compressor.identifier());
+        out.extend_from_slice(&inflated_length.to_le_bytes());
+        out.extend_from_slice(&chunk_size.to_le_bytes());
+        for offset in offsets {
+            let offset = offset
+                .checked_add(header_size)
+                .ok_or(FormatError::OffsetOverflow)?;
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        Ok(u64::from(header_size))
+    }
+}
+// }}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn names() {
+        for name in [
+            &b"a.lua"[..],
+            b"CONSOLE",
+            b"com0",
+            b"lpt10.txt",
+            b"..a",
+            b" nul",
+        ] {
+            assert_eq!(validate_name(name), Ok(()), "{:?}", name);
+        }
+        assert_eq!(validate_name(b""), Err(NameError::Empty));
+        let long = vec![b'a'; MAX_NAME_LENGTH + 1];
+        assert_eq!(validate_name(&long), Err(NameError::TooLong(65536)));
+        assert_eq!(validate_name(&long[1..]), Ok(()));
+        assert_eq!(validate_name(b"a/b"), Err(NameError::Separator));
+        assert_eq!(validate_name(b"a\\b"), Err(NameError::Separator));
+        assert_eq!(
+            validate_name(b"a:b"),
+            Err(NameError::ForbiddenCharacter(b':'))
+        );
+        assert_eq!(
+            validate_name(b"a\tb"),
+            Err(NameError::ForbiddenCharacter(b'\t'))
+        );
+        for name in [
+            &b"."[..],
+            b"..",
+            b"con",
+            b"Aux.lua",
+            b"NUL .txt",
+            b"COM1",
+            b"lpt9.x",
+        ] {
+            assert_eq!(validate_name(name), Err(NameError::Reserved), "{:?}", name);
+        }
+
+        let dent = RawDirEntry {
+            index: 0,
+            is_dir: false,
+            name: &long,
+        };
+        assert_eq!(
+            dent.encode(
+                &mut vec![],
+                Layout {
+                    wide: false,
+                    big_endian: false
+                }
+            ),
+            Err(FormatError::InvalidName(NameError::TooLong(65536)))
+        );
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        for &(wide, big_endian) in &[(false, false), (true, false), (false, true), (true, true)] {
+            let layout = Layout { wide, big_endian };
+            let length = u32::from(layout.header_length());
+            let header = Header::new(length, 100, 16, 1, layout);
+            let mut data = vec![];
+            header.encode(&mut data).unwrap();
+            assert_eq!(data.len(), usize::from(layout.header_length()));
+
+            assert_eq!(Header::parse_layout(&data, 116), Ok(layout));
+            let parsed = Header::parse(&data, 116).unwrap();
+            assert_eq!(parsed.layout(), layout);
+            assert_eq!(parsed.fragmented_filesystem_offset, 100);
+            assert_eq!(parsed.filesystem_entries(), if wide { 1 } else { 2 });
+        }
+        // a 32-bit header with the data offset of a 64-bit header
+        let layout = Layout {
+            wide: false,
+            big_endian: false,
+        };
+        let mut data = vec![];
+        Header::new(52, 100, 16, 1, layout)
+            .encode(&mut data)
+            .unwrap();
+        assert_eq!(Header::parse_layout(&data, 116), Ok(layout));
+        assert_eq!(Header::parse(&data, 116).unwrap().data_offset, 52);
+
+        assert_eq!(
+            Header::parse(b"ZIPX0000", 8).err(),
+            Some(FormatError::InvalidHeader)
+        );
+        assert_eq!(
+            Header::parse(&HPK_SIG, 4).err(),
+            Some(FormatError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn parse_dir_entries() {
+        let layout = Layout {
+            wide: false,
+            big_endian: true,
+        };
+        let mut data = vec![];
+        for entry in &[
+            RawDirEntry {
+                index: 1,
+                is_dir: true,
+                name: b"dir",
+            },
+            RawDirEntry {
+                index: 2,
+                is_dir: false,
+                name: b"file.lua",
+            },
+        ] {
+            entry.encode(&mut data, layout).unwrap();
+        }
+
+        let entries = dir_entries(&data, layout)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].index, 2);
+        assert_eq!(entries[1].name, b"file.lua");
+
+        let truncated = dir_entries(&data[..data.len() - 1], layout).collect::<Vec<_>>();
+        assert_eq!(truncated.last(), Some(&Err(FormatError::UnexpectedEnd)));
+        let zero = [0; 10];
+        assert_eq!(
+            dir_entries(&zero, layout).next(),
+            Some(Err(FormatError::InvalidFragmentIndex))
+        );
+    }
+
+    #[test]
+    fn compression_header() {
+        let mut data = vec![];
+        let size = CompressionHeader::encode(Compression::Zlib, 100, 32, &[0, 10], &mut data);
+        assert_eq!(size, Ok(20));
+        let hdr = CompressionHeader::parse(&data, 40).unwrap();
+        assert_eq!(hdr.compressor, Compression::Zlib);
+        assert_eq!(hdr.inflated_length, 100);
+        let chunks: Vec<_> = hdr.chunks.iter().map(|c| (c.offset, c.length)).collect();
+        assert_eq!(chunks, [(20, 10), (30, 10)]);
+
+        let hdr = CompressionHeader::parse(&data[..12], 12).unwrap();
+        assert!(hdr.chunks.is_empty());
+        assert_eq!(
+            CompressionHeader::parse(&data, 18).err(),
+            Some(FormatError::InvalidCompressionHeader)
+        );
+    }
+}
+
+// vim: fdm=marker
@@ -0,0 +1,127 @@
+use std::cmp;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::format::Layout;
+use crate::read::read_exact_vec;
+use crate::{Fragment, Header, HpkError, HpkResult};
+
+impl Fragment {
+    pub(crate) fn read_from<T: Read>(mut r: T, layout: Layout) -> HpkResult<Fragment> {
+        let mut buf = [0; 16];
+        let buf = &mut buf[..layout.fragment_size() as usize];
+        r.read_exact(buf)?;
+        Ok(Fragment::parse(&mut &buf[..], layout)?)
+    }
+
+    /// Reads the residual fragment table described by the header
+    ///
+    /// `offset` is the start of the archive inside of `r`.
+    ///
+    pub(crate) fn read_residual_from<T: Read + Seek>(
+        hdr: &Header,
+        mut r: T,
+        offset: u64,
+    ) -> HpkResult<Vec<Fragment>> {
+        let count = hdr.fragments_residual_count;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let length = count
+            .checked_mul(hdr.layout().fragment_size())
+            .ok_or(HpkError::TruncatedFragmentTable)?;
+        let end = r.seek(SeekFrom::End(0))?;
+        let table_end = offset
+            .checked_add(hdr.fragments_residual_offset)
+            .and_then(|start| start.checked_add(length));
+        match table_end {
+            Some(n) if n <= end => {}
+            _ => return Err(HpkError::TruncatedFragmentTable),
+        }
+        let start = offset + hdr.fragments_residual_offset;
+        let mut data = vec![0; length as usize];
+        r.seek(SeekFrom::Start(start))?;
+        r.read_exact(&mut data)?;
+
+        Ok(Fragment::parse_n(count as usize, &data, hdr.layout())?)
+    }
+
+    pub(crate) fn write(&self, w: &mut dyn Write, layout: Layout) -> HpkResult<()> {
+        let mut buf = Vec::with_capacity(16);
+        self.encode(&mut buf, layout)?;
+        w.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+/// The fragment table of an archive grouped by entry
+///
+/// Every entry has `fragments_per_file` fragments, the index of an entry is the
+/// 0-based fragment index of `DirEntry::index`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct FragmentTable {
+    entries: Vec<Vec<Fragment>>,
+    truncated: bool,
+}
+
+impl FragmentTable {
+    /// Reads the fragment table described by `header` from the archive `r`
+    ///
+    /// The length of the table in the header is checked against the length of `r`.
+    /// A table which reaches beyond the end of `r` is read up to the last complete
+    /// entry and marked as truncated instead of failing.
+    ///
+    pub fn read<R: Read + Seek>(header: &Header, mut r: R) -> HpkResult<FragmentTable> {
+        if header.fragments_per_file == 0 {
+            return Err(HpkError::InvalidHeader);
+        }
+        let layout = header.layout();
+        let per_file = header.fragments_per_file as usize;
+        let entry_size = layout.fragment_size() * u64::from(header.fragments_per_file);
+
+        let start = header.fragmented_filesystem_offset;
+        let available = r.seek(SeekFrom::End(0))?.saturating_sub(start);
+        let length = cmp::min(header.fragmented_filesystem_length, available);
+        let count = length / entry_size;
+
+        r.seek(SeekFrom::Start(start))?;
+        let data = read_exact_vec(&mut r, count * entry_size)?;
+        let entries = data
+            .chunks_exact(entry_size as usize)
+            .map(|data| Fragment::parse_n(per_file, data, layout))
+            .collect::<Result<_, _>>()?;
+
+        Ok(FragmentTable {
+            entries,
+            truncated: length < header.fragmented_filesystem_length,
+        })
+    }
+
+    /// Whether the table in the header reaches beyond the end of the file
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Number of complete entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The fragments of the entry `index`
+    pub fn get(&self, index: usize) -> Option<&[Fragment]> {
+        self.entries.get(index).map(Vec::as_slice)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[Fragment]> {
+        self.entries.iter().map(Vec::as_slice)
+    }
+
+    pub fn into_inner(self) -> Vec<Vec<Fragment>> {
+        self.entries
+    }
+}
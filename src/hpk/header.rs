@@ -0,0 +1,31 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::format::WIDE_HEADER_LENGTH;
+use crate::{Header, HpkResult};
+
+impl Header {
+    /// Reads the header at the current position of `r`, the archive ends at the end
+    /// of `r`
+    pub(crate) fn read_from<T: Read + Seek>(mut r: T) -> HpkResult<Self> {
+        let start = r.stream_position()?;
+        let end = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(start))?;
+        let mut data = Vec::with_capacity(usize::from(WIDE_HEADER_LENGTH));
+        r.by_ref()
+            .take(u64::from(WIDE_HEADER_LENGTH))
+            .read_to_end(&mut data)?;
+        let hdr = Header::parse(&data, end.saturating_sub(start))?;
+        r.seek(SeekFrom::Start(
+            start + u64::from(hdr.layout().header_length()),
+        ))?;
+        Ok(hdr)
+    }
+
+    pub(crate) fn write(&self, w: &mut dyn Write) -> HpkResult<()> {
+        let mut buf = Vec::with_capacity(usize::from(WIDE_HEADER_LENGTH));
+        self.encode(&mut buf)?;
+        w.write_all(&buf)?;
+        Ok(())
+    }
+}
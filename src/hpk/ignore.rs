@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use glob::Pattern;
+
+use crate::HpkResult;
+
+/// Name of the file in the input directory which lists additional exclude patterns
+pub(crate) const HPKIGNORE: &str = ".hpkignore";
+
+/// Files of version control systems, file managers and editors
+const DEFAULT_EXCLUDES: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+    "*.swp",
+    "*~",
+    HPKIGNORE,
+];
+
+/// The glob patterns of the files and directories which aren't packed
+///
+/// A pattern without a `/` matches the file name, other patterns match the path
+/// relative to the input directory.
+///
+pub(crate) struct Excludes {
+    patterns: Vec<Pattern>,
+}
+
+impl Excludes {
+    /// Combines `patterns`, the default patterns and the patterns of a `.hpkignore`
+    /// file in `dir`
+    ///
+    pub fn new(patterns: &[Pattern], defaults: bool, dir: &Path) -> HpkResult<Excludes> {
+        match fs::read_to_string(dir.join(HPKIGNORE)) {
+            Ok(content) => Ok(Self::with_ignore(patterns, defaults, Some(&content))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::with_ignore(patterns, defaults, None))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Combines `patterns`, the default patterns and the `.hpkignore` lines `ignore`
+    pub fn with_ignore(patterns: &[Pattern], defaults: bool, ignore: Option<&str>) -> Excludes {
+        let mut patterns = patterns.to_vec();
+        if defaults {
+            patterns.extend(DEFAULT_EXCLUDES.iter().filter_map(|s| Pattern::new(s).ok()));
+        }
+        if let Some(content) = ignore {
+            patterns.extend(parse(content));
+        }
+        Excludes { patterns }
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(name) => Path::new(name),
+            None => return false,
+        };
+        self.patterns.iter().any(|pat| {
+            if pat.as_str().contains('/') {
+                pat.matches_path(path)
+            } else {
+                pat.matches_path(name)
+            }
+        })
+    }
+
+    /// Returns `true` if `path` or one of its parent directories is excluded
+    pub fn is_excluded_tree(&self, path: &Path) -> bool {
+        path.ancestors().any(|p| self.is_excluded(p))
+    }
+}
+
+/// Parses the lines of a `.hpkignore` file, empty lines and `#` comments are skipped
+fn parse(content: &str) -> impl Iterator<Item = Pattern> + '_ {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_start_matches('/').trim_end_matches('/'))
+        .filter_map(|line| Pattern::new(line).ok())
+}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_patterns() {
+        let patterns: Vec<_> = parse("# comment\n\n/maps/*.bak\nbuild/\n*.tmp").collect();
+        let excludes = Excludes {
+            patterns: patterns.clone(),
+        };
+        assert_eq!(patterns.len(), 3);
+        assert!(excludes.is_excluded(Path::new("maps/old.bak")));
+        assert!(!excludes.is_excluded(Path::new("other/old.bak")));
+        assert!(excludes.is_excluded(Path::new("scripts/build")));
+        assert!(excludes.is_excluded(Path::new("a/b/c.tmp")));
+        assert!(!excludes.is_excluded(Path::new("a/b/c.lua")));
+        assert!(!excludes.is_excluded(Path::new("scripts/build/d.lua")));
+        assert!(excludes.is_excluded_tree(Path::new("scripts/build/d.lua")));
+    }
+}
+// }}}
@@ -0,0 +1,60 @@
+use std::fmt::Write as _;
+use std::io::prelude::*;
+
+use crate::{DirEntry, HpkArchive, HpkResult};
+
+/// Writes one JSON object per entry, see `HpkArchive::print_json`
+pub(crate) fn print_json<'a, I>(
+    archive: &HpkArchive,
+    w: &mut dyn Write,
+    entries: I,
+) -> HpkResult<()>
+where
+    I: IntoIterator<Item = &'a DirEntry>,
+{
+    let fragments = archive.fragments();
+    for entry in entries {
+        let path = entry.path().to_string_lossy();
+        write!(w, r#"{{"path":"{}""#, escape_json(&path))?;
+        if entry.is_dir() {
+            write!(w, r#","type":"dir""#)?;
+        } else {
+            let md = archive.metadata(entry)?;
+            write!(
+                w,
+                r#","type":"file","codec":"{}","size":{},"stored":{},"chunks":{}"#,
+                md.compression, md.size, md.compressed_size, md.chunks,
+            )?;
+        }
+        write!(w, r#","fragments":["#)?;
+        let list = fragments.get(entry.index()).map_or(&[][..], Vec::as_slice);
+        for (i, fragment) in list.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(
+                w,
+                r#"{}{{"offset":{},"length":{}}}"#,
+                sep, fragment.offset, fragment.length
+            )?;
+        }
+        writeln!(w, "]}}")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
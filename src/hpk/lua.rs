@@ -1,6 +1,7 @@
 use std::io;
 use std::io::prelude::*;
 
+#[cfg(feature = "fs")]
 #[rustfmt::skip]
 static LUA_VALID_HEADER_32: [u8; 29] = [
     0x1B, 0x4C, 0x75, 0x61, 0x53, 0x00,
@@ -9,6 +10,7 @@ static LUA_VALID_HEADER_32: [u8; 29] = [
     0x78, 0x56, 0x00, 0x00,
     0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x77, 0x40,
 ];
+#[cfg(feature = "fs")]
 #[rustfmt::skip]
 static LUA_VALID_HEADER_64: [u8; 33] = [
     0x1B, 0x4C, 0x75, 0x61, 0x53, 0x00,
@@ -111,6 +113,7 @@ mod parser {
 
 pub type LuaHeaderRewriteReader<R> =
     LuaHeaderRewriter<R, fn(&mut R, &mut [u8]) -> io::Result<usize>>;
+#[cfg(feature = "fs")]
 pub type LuaHeaderRewriteWriter<W> = LuaHeaderRewriter<W, fn(&mut W, &[u8]) -> io::Result<usize>>;
 
 pub struct LuaHeaderRewriter<T, F> {
@@ -172,6 +175,7 @@ where
     LuaHeaderRewriter::new(r, read_with_invalid_header)
 }
 
+#[cfg(feature = "fs")]
 pub fn fix_header<W>(w: W) -> LuaHeaderRewriteWriter<W>
 where
     W: Write,
@@ -185,17 +189,16 @@ fn read_with_invalid_header<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<us
         Ok(0) => Ok(0),
         Ok(n) => {
             let mut tmp = &tmp[0..n];
-            match parser::check_valid_header(&tmp) {
+            match parser::check_valid_header(tmp) {
                 Ok((remaining, bits)) => {
                     let mut w = io::Cursor::new(buf);
-                    let mut n = 0;
                     let hdr = match bits {
                         parser::Bits::Int32 => &LUA_INVALID_HEADER_32[..],
                         parser::Bits::Int64 => &LUA_INVALID_HEADER_64[..],
                     };
-                    n += w.write(hdr)?;
-                    n += w.write(remaining)?;
-                    Ok(n)
+                    w.write_all(hdr)?;
+                    w.write_all(remaining)?;
+                    Ok(hdr.len() + remaining.len())
                 }
                 Err(_) => tmp.read(buf),
             }
@@ -204,17 +207,18 @@ fn read_with_invalid_header<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<us
     }
 }
 
+#[cfg(feature = "fs")]
 fn write_with_valid_header<W: Write>(w: &mut W, buf: &[u8]) -> io::Result<usize> {
     match parser::check_invalid_header(buf) {
         Ok((remaining, bits)) => {
-            let mut n = 0;
             let hdr = match bits {
                 parser::Bits::Int32 => &LUA_VALID_HEADER_32[..],
                 parser::Bits::Int64 => &LUA_VALID_HEADER_64[..],
             };
-            n += w.write(hdr)? - 2; // ignore the two additional bytes
-            n += w.write(remaining)?;
-            Ok(n)
+            w.write_all(hdr)?;
+            w.write_all(remaining)?;
+            // the two additional bytes of the valid header aren't part of `buf`
+            Ok(hdr.len() - 2 + remaining.len())
         }
         Err(_) => w.write(buf),
     }
@@ -226,6 +230,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "fs")]
     fn check_valid_header_parser() {
         assert_eq!(
             parser::check_valid_header(&LUA_VALID_HEADER_32),
@@ -250,11 +255,12 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "fs")]
     fn header_rewrite() {
         let mut input = io::Cursor::new(vec![]);
         let mut buf = io::Cursor::new(vec![]);
-        input.write(&LUA_VALID_HEADER_64).unwrap();
-        input.write(&[0xCA, 0xFE, 0xCA, 0xFE]).unwrap();
+        input.write_all(&LUA_VALID_HEADER_64).unwrap();
+        input.write_all(&[0xCA, 0xFE, 0xCA, 0xFE]).unwrap();
         input.set_position(0);
 
         {
@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{EntryMetadata, Fragment, Header, HpkArchive, HpkResult};
+
+/// The complete layout of an archive
+///
+/// With the `serde` feature the manifest can be serialized, e.g. to JSON for diffing
+/// two archives.
+///
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ArchiveManifest {
+    /// The whole archive is compressed
+    pub compressed: bool,
+    pub header: Header,
+    pub fragments: Vec<Vec<Fragment>>,
+    pub residuals: Vec<Fragment>,
+    /// All entries of the directory tree, see `HpkArchive::entries`
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub depth: usize,
+    /// Index into the fragment table
+    pub index: usize,
+    /// Sizes and compression, only set for files
+    pub metadata: Option<EntryMetadata>,
+}
+
+impl ArchiveManifest {
+    pub(crate) fn new(archive: &HpkArchive) -> HpkResult<Self> {
+        let mut entries = Vec::with_capacity(archive.entries().len());
+        for entry in archive.entries() {
+            let metadata = if entry.is_dir() {
+                None
+            } else {
+                Some(archive.metadata(entry)?)
+            };
+            entries.push(ManifestEntry {
+                path: entry.path().to_path_buf(),
+                is_dir: entry.is_dir(),
+                depth: entry.depth(),
+                index: entry.index(),
+                metadata,
+            });
+        }
+        Ok(ArchiveManifest {
+            compressed: archive.is_compressed(),
+            header: archive.header().clone(),
+            fragments: archive.fragments().to_vec(),
+            residuals: archive.residuals().to_vec(),
+            entries,
+        })
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    Request, FUSE_ROOT_ID,
+};
+
+use crate::{HpkArchive, HpkError, HpkResult};
+
+/// How long the kernel caches attributes and lookups, the archive doesn't change
+const TTL: Duration = Duration::from_secs(60);
+const BLOCK_SIZE: u32 = 512;
+
+/// Mounts `archive` read-only at `mountpoint` and serves it until it's unmounted
+///
+/// The inode of an entry is its position in `HpkArchive::entries` plus one, the
+/// root of the archive is the root of the mount. File contents are decompressed on
+/// demand, a read only decodes the chunks it covers. All entries carry the
+/// modification time of the archive file.
+///
+/// Blocks the calling thread, the mount is released with `fusermount -u`.
+///
+pub fn mount<P: AsRef<Path>>(archive: HpkArchive, mountpoint: P) -> HpkResult<()> {
+    let name = archive
+        .path()
+        .file_name()
+        .map_or_else(|| "hpk".into(), |name| name.to_string_lossy().into_owned());
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(name),
+        MountOption::Subtype("hpk".into()),
+    ];
+    fuser::mount2(HpkFs::new(archive), mountpoint, &options)?;
+    Ok(())
+}
+
+struct HpkFs {
+    archive: HpkArchive,
+    /// Positions of the children of every directory entry
+    children: Vec<Vec<usize>>,
+    /// Position of the parent of every entry, the root is its own parent
+    parents: Vec<usize>,
+    mtime: SystemTime,
+}
+
+impl HpkFs {
+    fn new(archive: HpkArchive) -> HpkFs {
+        let entries = archive.entries();
+        let positions = entries
+            .iter()
+            .enumerate()
+            .map(|(pos, e)| (e.path(), pos))
+            .collect::<HashMap<_, _>>();
+        let mut children = vec![vec![]; entries.len()];
+        let mut parents = vec![0; entries.len()];
+        for (pos, entry) in entries.iter().enumerate().skip(1) {
+            let parent = entry.path().parent().and_then(|p| positions.get(p));
+            if let Some(&parent) = parent {
+                children[parent].push(pos);
+                parents[pos] = parent;
+            }
+        }
+        let mtime = fs::metadata(archive.path())
+            .and_then(|md| md.modified())
+            .unwrap_or(UNIX_EPOCH);
+        HpkFs {
+            archive,
+            children,
+            parents,
+            mtime,
+        }
+    }
+
+    fn position(&self, ino: u64) -> Option<usize> {
+        let pos = usize::try_from(ino.checked_sub(FUSE_ROOT_ID)?).ok()?;
+        (pos < self.archive.entries().len()).then_some(pos)
+    }
+
+    fn lookup_child(&self, parent: usize, name: &OsStr) -> Option<usize> {
+        let entries = self.archive.entries();
+        self.children
+            .get(parent)?
+            .iter()
+            .copied()
+            .find(|&pos| entries[pos].file_name() == name)
+    }
+
+    fn attr(&self, pos: usize) -> HpkResult<FileAttr> {
+        let entry = &self.archive.entries()[pos];
+        let (kind, perm, size) = if entry.is_dir() {
+            (FileType::Directory, 0o555, 0)
+        } else {
+            (
+                FileType::RegularFile,
+                0o444,
+                self.archive.metadata(entry)?.size,
+            )
+        };
+        Ok(FileAttr {
+            ino: pos as u64 + FUSE_ROOT_ID,
+            size,
+            blocks: size.div_ceil(u64::from(BLOCK_SIZE)),
+            atime: self.mtime,
+            mtime: self.mtime,
+            ctime: self.mtime,
+            crtime: self.mtime,
+            kind,
+            perm,
+            nlink: if entry.is_dir() { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        })
+    }
+
+    fn read_at(&self, pos: usize, offset: u64, size: u32) -> HpkResult<Vec<u8>> {
+        let mut r = self.archive.open_entry(&self.archive.entries()[pos])?;
+        r.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity(size as usize);
+        r.take(u64::from(size)).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl Filesystem for HpkFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let child = self
+            .position(parent)
+            .and_then(|parent| self.lookup_child(parent, name));
+        match child.map(|pos| self.attr(pos)) {
+            Some(Ok(attr)) => reply.entry(&TTL, &attr, 0),
+            Some(Err(e)) => reply.error(errno(&e)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.position(ino).map(|pos| self.attr(pos)) {
+            Some(Ok(attr)) => reply.attr(&TTL, &attr),
+            Some(Err(e)) => reply.error(errno(&e)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let pos = match self.position(ino) {
+            Some(pos) if !self.archive.entries()[pos].is_dir() => pos,
+            Some(_) => return reply.error(libc::EISDIR),
+            None => return reply.error(libc::ENOENT),
+        };
+        let offset = match u64::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => return reply.error(libc::EINVAL),
+        };
+        match self.read_at(pos, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let pos = match self.position(ino) {
+            Some(pos) if self.archive.entries()[pos].is_dir() => pos,
+            Some(_) => return reply.error(libc::ENOTDIR),
+            None => return reply.error(libc::ENOENT),
+        };
+        let entries = self.archive.entries();
+        let dots = [
+            (pos, FileType::Directory, OsStr::new(".")),
+            (self.parents[pos], FileType::Directory, OsStr::new("..")),
+        ];
+        let children = self.children[pos].iter().map(|&child| {
+            let entry = &entries[child];
+            let kind = if entry.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            (child, kind, entry.file_name())
+        });
+        // the offset passed back is the index of the next entry
+        let listing = IntoIterator::into_iter(dots).chain(children).enumerate();
+        for (i, (child, kind, name)) in listing.skip(offset.max(0) as usize) {
+            if reply.add(child as u64 + FUSE_ROOT_ID, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn errno(e: &HpkError) -> i32 {
+    match e {
+        HpkError::EntryNotFound(_) => libc::ENOENT,
+        HpkError::Io(e) => e.raw_os_error().unwrap_or(match e.kind() {
+            io::ErrorKind::InvalidInput => libc::EINVAL,
+            _ => libc::EIO,
+        }),
+        _ => libc::EIO,
+    }
+}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateOptions, HpkWriter};
+
+    fn hpk_fs() -> HpkFs {
+        let mut options = CreateOptions::new();
+        options.with_chunk_size(4);
+        let mut w = HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+        w.add_file("a/x.txt", &b"0123456789"[..]).unwrap();
+        w.add_file("b.txt", &b"b"[..]).unwrap();
+        let data = w.finish().unwrap().into_inner();
+        HpkFs::new(HpkArchive::from_reader(io::Cursor::new(data)).unwrap())
+    }
+
+    #[test]
+    fn lookup_and_attr() {
+        let fs = hpk_fs();
+        let root = fs.position(FUSE_ROOT_ID).unwrap();
+        assert_eq!(root, 0);
+        assert!(fs.lookup_child(root, OsStr::new("missing")).is_none());
+
+        let a = fs.lookup_child(root, OsStr::new("a")).unwrap();
+        let attr = fs.attr(a).unwrap();
+        assert_eq!(attr.kind, FileType::Directory);
+        assert_eq!(fs.parents[a], root);
+
+        let x = fs.lookup_child(a, OsStr::new("x.txt")).unwrap();
+        let attr = fs.attr(x).unwrap();
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.size, 10);
+        assert_eq!(fs.position(attr.ino), Some(x));
+        assert!(fs.position(0).is_none());
+        assert!(fs.position(100).is_none());
+    }
+
+    #[test]
+    fn read_at() {
+        let fs = hpk_fs();
+        let a = fs.lookup_child(0, OsStr::new("a")).unwrap();
+        let x = fs.lookup_child(a, OsStr::new("x.txt")).unwrap();
+        assert_eq!(fs.read_at(x, 0, 3).unwrap(), b"012");
+        assert_eq!(fs.read_at(x, 5, 100).unwrap(), b"56789");
+        assert_eq!(fs.read_at(x, 20, 4).unwrap(), b"");
+        assert!(fs.read_at(a, 0, 4).is_err());
+    }
+}
+// }}}
+
+// vim: fdm=marker
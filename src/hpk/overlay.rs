@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{EntryMetadata, PathLookup};
+use crate::read::{EntryReader, FragmentedReader, SourceReader};
+use crate::{DirEntry, HpkArchive, HpkError, HpkResult};
+
+/// Several hpk archives merged into one directory tree
+///
+/// Later archives override the entries of earlier archives with the same path like
+/// the games do when they load their archives. A file replacing a directory hides
+/// the entries of that directory and vice versa.
+///
+pub struct OverlayArchive {
+    archives: Vec<HpkArchive>,
+    entries: Vec<DirEntry>,
+    /// The sorted lookup keys of the paths of `entries`
+    keys: Vec<PathBuf>,
+    /// The index of the archive which provides the entry with the same index
+    sources: Vec<usize>,
+    lookup: PathLookup,
+}
+
+impl OverlayArchive {
+    pub fn new(archives: Vec<HpkArchive>) -> Self {
+        OverlayArchive::with_path_lookup(archives, PathLookup::Exact)
+    }
+
+    /// Merges the archives and compares their paths with `lookup`
+    ///
+    /// With `PathLookup::CaseInsensitive` the entry `Data/Script.lua` of a later
+    /// archive overrides `data/script.lua` like in the games on Windows.
+    ///
+    pub fn with_path_lookup(archives: Vec<HpkArchive>, lookup: PathLookup) -> Self {
+        let mut tree: BTreeMap<PathBuf, (usize, &DirEntry)> = BTreeMap::new();
+        for (source, archive) in archives.iter().enumerate() {
+            for entry in archive.entries() {
+                let key = lookup.key(entry.path()).into_owned();
+                if let Some((_, existing)) = tree.get(&key) {
+                    if existing.is_dir() != entry.is_dir() {
+                        tree.retain(|p, _| !p.starts_with(&key));
+                    }
+                }
+                tree.insert(key, (source, entry));
+            }
+        }
+        // the paths are sorted, so the directories come before their entries
+        let mut keys = Vec::with_capacity(tree.len());
+        let mut sources = Vec::with_capacity(tree.len());
+        let mut entries = Vec::with_capacity(tree.len());
+        for (key, (source, entry)) in tree {
+            keys.push(key);
+            sources.push(source);
+            entries.push(entry.clone());
+        }
+
+        OverlayArchive {
+            archives,
+            entries,
+            keys,
+            sources,
+            lookup,
+        }
+    }
+
+    pub fn archives(&self) -> &[HpkArchive] {
+        &self.archives
+    }
+
+    pub fn into_archives(self) -> Vec<HpkArchive> {
+        self.archives
+    }
+
+    /// Returns all entries of the merged tree, starting with the root directory
+    pub fn entries(&self) -> &[DirEntry] {
+        &self.entries
+    }
+
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&DirEntry> {
+        self.position(path.as_ref()).map(|i| &self.entries[i])
+    }
+
+    /// Returns the archive which provides the entry at `path`
+    pub fn source<P: AsRef<Path>>(&self, path: P) -> Option<&HpkArchive> {
+        self.position(path.as_ref())
+            .map(|i| &self.archives[self.sources[i]])
+    }
+
+    /// Returns the entries of the directory at `path`, an empty path is the root
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<&DirEntry>> {
+        let path = path.as_ref();
+        let key = match self.position(path) {
+            Some(i) if self.entries[i].is_dir() => &self.keys[i],
+            _ => return Err(HpkError::EntryNotFound(path.to_path_buf())),
+        };
+        Ok(self
+            .entries
+            .iter()
+            .zip(&self.keys)
+            .filter(|(e, k)| e.depth() > 0 && k.parent() == Some(key))
+            .map(|(e, _)| e)
+            .collect())
+    }
+
+    /// Reads the decompressed content of the file at `path`
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<u8>> {
+        let path = path.as_ref();
+        match self.position(path) {
+            // the path of the entry in its archive, `path` may differ in case
+            Some(i) => self.archives[self.sources[i]].read_file(self.entries[i].path()),
+            None => Err(HpkError::EntryNotFound(path.to_path_buf())),
+        }
+    }
+
+    /// Reads the compression header of the file `entry`
+    pub fn metadata(&self, entry: &DirEntry) -> HpkResult<EntryMetadata> {
+        let (archive, entry) = self.resolve(entry)?;
+        archive.metadata(entry)
+    }
+
+    /// Opens a reader which decompresses the content of the file `entry` while reading
+    pub fn open_entry(
+        &self,
+        entry: &DirEntry,
+    ) -> HpkResult<EntryReader<FragmentedReader<SourceReader<'_>>>> {
+        let (archive, entry) = self.resolve(entry)?;
+        archive.open_entry(entry)
+    }
+
+    fn position(&self, path: &Path) -> Option<usize> {
+        let key = self.lookup.key(path);
+        self.keys.binary_search_by(|k| k.as_path().cmp(&key)).ok()
+    }
+
+    /// Looks up the archive and its own entry for the path of `entry`
+    fn resolve(&self, entry: &DirEntry) -> HpkResult<(&HpkArchive, &DirEntry)> {
+        let path = entry.path();
+        self.position(path)
+            .map(|i| (&self.archives[self.sources[i]], &self.entries[i]))
+            .ok_or_else(|| HpkError::EntryNotFound(path.to_path_buf()))
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::compression::compress_spooled;
+use crate::create::write_file;
+use crate::read::spool;
+use crate::{Compression, CreateOptions, HpkError, HpkResult, HpkWriter};
+
+/// Archive paths mapped to source files and directories, see `pack`
+///
+/// The sources can be scattered over the file system, e.g. the outputs of a build.
+/// The manifest is read from TOML or JSON:
+///
+/// ```toml
+/// [[entries]]
+/// path = "scripts/main.lua"
+/// source = "build/lua/main.lua"
+///
+/// [[entries]]
+/// path = "maps"
+/// source = "assets/maps"
+///
+/// [[entries]]
+/// path = "video/intro.bik"
+/// source = "/mnt/media/intro.bik"
+/// compression = "none"
+/// ```
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PackManifest {
+    /// The entries in the order they are added to the archive
+    pub entries: Vec<PackEntry>,
+    /// Directory of the relative source paths
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackEntry {
+    /// Path of the entry inside of the archive
+    pub path: PathBuf,
+    /// A file or a directory whose tree is added below `path`
+    pub source: PathBuf,
+    /// Overrides the encoder and the compressed extensions of the options,
+    /// one of `none`, `zlib`, `lz4` and `zstd`
+    #[serde(default, deserialize_with = "compression")]
+    pub compression: Option<Compression>,
+}
+
+impl PackManifest {
+    /// Reads the manifest file `path`, the relative source paths are resolved
+    /// against the directory of the manifest
+    ///
+    /// Files with the extension `.json` are parsed as JSON, all other files as TOML.
+    ///
+    pub fn read<P: AsRef<Path>>(path: P) -> HpkResult<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let mut manifest = if is_json {
+            Self::from_json(&content)?
+        } else {
+            Self::from_toml(&content)?
+        };
+        manifest.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    pub fn from_toml(s: &str) -> HpkResult<Self> {
+        toml::from_str(s).map_err(|e| invalid_manifest(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> HpkResult<Self> {
+        serde_json::from_str(s).map_err(|e| invalid_manifest(e.to_string()))
+    }
+}
+
+fn compression<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Compression>, D::Error> {
+    use serde::de::Error;
+
+    let name = String::deserialize(d)?;
+    let compression = match name.to_ascii_lowercase().as_str() {
+        "none" => Compression::None,
+        "zlib" => Compression::Zlib,
+        "lz4" => Compression::Lz4,
+        "zstd" => Compression::Zstd,
+        _ => return Err(D::Error::custom(format!("unknown compression: {}", name))),
+    };
+    Ok(Some(compression))
+}
+
+fn invalid_manifest(msg: String) -> HpkError {
+    let msg = format!("invalid manifest: {}", msg);
+    HpkError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Creates the archive `file` from the entries of `manifest`
+pub fn pack<P: AsRef<Path>>(
+    options: &CreateOptions,
+    manifest: &PackManifest,
+    file: P,
+) -> HpkResult<()> {
+    write_file(file.as_ref(), options.atomic, |out| {
+        write_pack(options, manifest, out)
+    })?;
+    #[cfg(feature = "sha256")]
+    if options.sha256_manifest {
+        crate::write_sha256_manifest(file)?;
+    }
+    Ok(())
+}
+
+/// Writes the archive of the entries of `manifest` into `w`
+///
+/// The entries are added in the order of the manifest, the files of a source directory
+/// in the sort order of `options`. A path which is added twice is an error.
+///
+/// The writer is positioned at the end of the archive when the function returns.
+///
+pub fn write_pack<W>(options: &CreateOptions, manifest: &PackManifest, w: &mut W) -> HpkResult<()>
+where
+    W: Write + Seek,
+{
+    if options.compress {
+        compress_spooled(&options.compress_options, spool()?, w, |tmp| {
+            write_entries(options, manifest, tmp)
+        })
+    } else {
+        write_entries(options, manifest, w)
+    }
+}
+
+fn write_entries<W: Write + Seek>(
+    options: &CreateOptions,
+    manifest: &PackManifest,
+    w: &mut W,
+) -> HpkResult<()> {
+    let mut writer = HpkWriter::new(options, w)?;
+    for entry in &manifest.entries {
+        let source = manifest.base.join(&entry.source);
+        if !fs::metadata(&source)?.is_dir() {
+            add_file(&mut writer, entry, &entry.path, &source)?;
+            continue;
+        }
+        writer.add_dir(&entry.path)?;
+        let walkdir = walkdir::WalkDir::new(&source)
+            .min_depth(1)
+            .sort_by(options.sorter());
+        for dent in walkdir {
+            let dent = dent?;
+            let path = entry.path.join(dent.path().strip_prefix(&source).unwrap());
+            if dent.file_type().is_dir() {
+                writer.add_dir(&path)?;
+            } else {
+                add_file(&mut writer, entry, &path, dent.path())?;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn add_file<W: Write + Seek>(
+    writer: &mut HpkWriter<'_, W>,
+    entry: &PackEntry,
+    path: &Path,
+    source: &Path,
+) -> HpkResult<()> {
+    let file = BufReader::new(File::open(source)?);
+    match entry.compression {
+        Some(compression) => writer.add_file_with(path, file, compression),
+        None => writer.add_file(path, file),
+    }
+}
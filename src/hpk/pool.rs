@@ -0,0 +1,95 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+
+/// Returns the worker threads which encode and decode the chunks of all archives
+///
+/// The pool is started on first use with one thread per core and lives until the
+/// process exits. The callers bound their own parallelism with their `threads`
+/// option, the pool bounds the parallelism of the whole process.
+///
+pub(crate) fn pool() -> &'static rayon::ThreadPool {
+    static POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .thread_name(|i| format!("hpk-worker-{}", i))
+            .build()
+            .expect("failed to start the worker threads")
+    })
+}
+
+/// A job which runs on the pool or which already ran on the calling thread
+pub(crate) enum Task<T> {
+    Done(T),
+    Running(mpsc::Receiver<thread::Result<T>>),
+}
+
+impl<T: Send + 'static> Task<T> {
+    /// Runs `f` on the pool, or right away on the calling thread with a single thread
+    pub(crate) fn spawn<F>(threads: usize, f: F) -> Task<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        if threads <= 1 {
+            return Task::Done(f());
+        }
+        let (tx, rx) = mpsc::sync_channel(1);
+        pool().spawn(move || {
+            // a panic is passed to the waiting thread instead of aborting the process
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        Task::Running(rx)
+    }
+}
+
+impl<T> Task<T> {
+    pub(crate) fn is_running(&self) -> bool {
+        matches!(self, Task::Running(_))
+    }
+
+    /// Blocks until the job is done, the result stays in the task
+    pub(crate) fn wait(&mut self) {
+        if let Task::Running(rx) = self {
+            match rx.recv().expect("worker thread dropped its job") {
+                Ok(value) => *self = Task::Done(value),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+    }
+
+    /// Blocks until the job is done and returns its result
+    pub(crate) fn join(mut self) -> T {
+        self.wait();
+        match self {
+            Task::Done(value) => value,
+            Task::Running(_) => unreachable!(),
+        }
+    }
+}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks() {
+        let tasks: Vec<_> = (0..16u64).map(|i| Task::spawn(4, move || i * i)).collect();
+        let results: Vec<_> = tasks.into_iter().map(Task::join).collect();
+        assert_eq!(results, (0..16).map(|i| i * i).collect::<Vec<_>>());
+
+        let mut task = Task::spawn(1, || thread::current().id());
+        assert!(!task.is_running());
+        task.wait();
+        assert_eq!(task.join(), thread::current().id());
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    fn panicking_task() {
+        Task::spawn(2, || panic!("boom")).join()
+    }
+}
+// }}}
+
+// vim: fdm=marker
@@ -0,0 +1,57 @@
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Receives progress reports while an archive is created or extracted
+///
+/// All methods have empty default implementations.
+///
+pub trait Progress: Send + Sync {
+    /// Called before the file entry `index` of `total` file entries is processed
+    fn on_entry(&self, _path: &Path, _index: usize, _total: usize) {}
+
+    /// Called with the number of uncompressed bytes processed since the last call
+    fn on_bytes(&self, _written: u64) {}
+}
+
+/// Reports every read to `Progress::on_bytes`
+pub(crate) struct ProgressReader<'a, R> {
+    pub inner: R,
+    pub progress: Option<&'a dyn Progress>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(progress) = self.progress {
+            if n > 0 {
+                progress.on_bytes(n as u64);
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Reports every write to `Progress::on_bytes`
+#[cfg(feature = "fs")]
+pub(crate) struct ProgressWriter<'a, W> {
+    pub inner: W,
+    pub progress: Option<&'a dyn Progress>,
+}
+
+#[cfg(feature = "fs")]
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(progress) = self.progress {
+            if n > 0 {
+                progress.on_bytes(n as u64);
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,68 +1,120 @@
 use std::cmp;
+#[cfg(feature = "fs")]
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::io::SeekFrom;
+use std::io::{Cursor, IoSliceMut, SeekFrom};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use super::Fragment;
+use rayon::prelude::*;
+
+use super::HpkResult;
+use super::{compress, get_compression, Chunk, Compression, CompressionHeader, Fragment};
+use super::{CancelToken, Transform};
+use crate::pool::pool;
 
 struct FragmentState {
     offset: u64,
     length: u64,
     end_pos: u64,
-    limit: u64,
 }
 
+/// Size of the buffer allocated by the first `BufRead::fill_buf` call
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reads the content of a file entry from its fragments
+///
+/// The reader implements `BufRead`, its buffer is allocated by the first `fill_buf`
+/// call. Plain `read` calls with an empty buffer go straight to the inner reader and
+/// fill the whole buffer across the fragment boundaries. `inner` only seeks between
+/// fragments which aren't adjacent.
+///
 pub struct FragmentedReader<T> {
     inner: T,
     length: u64,
+    /// Position of the next byte read from `inner`, behind the buffered bytes
     pos: u64,
     fragments: Vec<FragmentState>,
+    /// Index of the fragment which contains `pos`
+    current: usize,
+    /// Whether `inner` has to seek to `pos` before the next read
+    seek_pending: bool,
+    /// Decodes the read bytes, the offset is the start of the archive inside of `inner`
+    transform: Option<(Arc<dyn Transform>, u64)>,
+    buf: Vec<u8>,
+    filled: usize,
+    consumed: usize,
 }
 
 impl<T: Read + Seek> FragmentedReader<T> {
     pub(crate) fn new(inner: T, fragments: &[Fragment]) -> Self {
         let states: Vec<_> = fragments
             .iter()
-            .map(|f| FragmentState {
-                offset: f.offset,
-                length: f.length,
-                end_pos: 0,
-                limit: f.length,
-            })
-            .scan(0, |state, mut f| {
-                *state += f.length;
-                f.end_pos = *state;
-                Some(f)
+            .scan(0, |state: &mut u64, f| {
+                *state = state.saturating_add(f.length);
+                Some(FragmentState {
+                    offset: f.offset,
+                    length: f.length,
+                    end_pos: *state,
+                })
             })
             .collect();
 
-        let length = fragments.iter().map(|f| f.length).sum();
+        let length = states.last().map_or(0, |f| f.end_pos);
 
         Self {
             inner,
             length,
             pos: 0,
             fragments: states,
+            current: 0,
+            seek_pending: true,
+            transform: None,
+            buf: vec![],
+            filled: 0,
+            consumed: 0,
         }
     }
 
+    /// Decodes the read bytes with `transform`, `start` is the start of the archive
+    pub(crate) fn with_transform(
+        mut self,
+        transform: Option<Arc<dyn Transform>>,
+        start: u64,
+    ) -> Self {
+        self.transform = transform.map(|t| (t, start));
+        self
+    }
+
+    /// Position of the next byte returned by `read`
+    fn position(&self) -> u64 {
+        self.pos - (self.filled - self.consumed) as u64
+    }
+
     fn set_position(&mut self, pos: u64) -> io::Result<()> {
-        if self.pos == pos {
+        // stay inside of the buffer if possible
+        let buffer_start = self.pos - self.filled as u64;
+        if self.filled > 0 && buffer_start <= pos && pos <= self.pos {
+            self.consumed = (pos - buffer_start) as usize;
             return Ok(());
         }
+        self.filled = 0;
+        self.consumed = 0;
 
-        let mut limit = pos;
-        for f in &mut self.fragments {
-            let n = cmp::min(f.length, limit);
-            f.limit = f.length - n;
-            limit -= n;
-
-            // read will seek when limit == length
-            if f.limit > 0 && f.limit != f.length {
-                self.inner.seek(SeekFrom::Start(f.offset + n))?;
-            }
+        if self.pos == pos {
+            return Ok(());
         }
+
+        // the end positions are sorted, find the first fragment which ends behind `pos`
+        self.current = self.fragments.partition_point(|f| f.end_pos <= pos);
         self.pos = pos;
+        self.seek_pending = true;
+        if let Some(f) = self.fragments.get(self.current) {
+            let start = f.end_pos.saturating_sub(f.length);
+            let offset = f.offset.saturating_add(pos - start);
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.seek_pending = false;
+        }
         Ok(())
     }
 
@@ -79,29 +131,116 @@ impl<T: Read + Seek> FragmentedReader<T> {
     fn into_inner(self) -> T {
         self.inner
     }
+
+    /// Reads from the fragments of `inner`, bypassing the buffer
+    fn read_fragments(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // move the cursor over the exhausted and empty fragments
+        while let Some(f) = self.fragments.get(self.current) {
+            if f.end_pos > self.pos {
+                break;
+            }
+            // `inner` is already at the start of a following adjacent fragment
+            let end = f.offset.saturating_add(f.length);
+            let adjacent = f.length > 0
+                && self
+                    .fragments
+                    .get(self.current + 1)
+                    .is_some_and(|next| next.offset == end);
+            self.seek_pending |= !adjacent;
+            self.current += 1;
+        }
+
+        let f = match self.fragments.get(self.current) {
+            Some(f) => f,
+            None => return Ok(0),
+        };
+        let start = f.end_pos.saturating_sub(f.length);
+        let offset = f.offset.saturating_add(self.pos - start);
+        if self.seek_pending {
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.seek_pending = false;
+        }
+
+        let max = cmp::min(buf.len() as u64, f.end_pos - self.pos) as usize;
+        let n = self.inner.read(&mut buf[..max])?;
+        if let Some((transform, start)) = &self.transform {
+            transform.decode(offset.saturating_sub(*start), &mut buf[..n]);
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    /// Fills `buf` from as many fragments as needed
+    ///
+    /// The read stops early at the end of the file or when `inner` returns less
+    /// than the rest of the current fragment.
+    ///
+    fn read_spanning(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            let n = match self.read_fragments(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                // the error is returned again by the next read
+                Err(_) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+            total += n;
+            let in_fragment = self
+                .fragments
+                .get(self.current)
+                .is_some_and(|f| f.end_pos > self.pos);
+            if in_fragment {
+                break;
+            }
+        }
+        Ok(total)
+    }
 }
 
 impl<T: Read + Seek> Read for FragmentedReader<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let current = self
-            .fragments
-            .iter()
-            .rposition(|f| f.end_pos <= self.pos)
-            .map_or(0, |i| i + 1);
+        if self.consumed < self.filled {
+            let n = cmp::min(buf.len(), self.filled - self.consumed);
+            buf[..n].copy_from_slice(&self.buf[self.consumed..self.consumed + n]);
+            self.consumed += n;
+            return Ok(n);
+        }
+        self.read_spanning(buf)
+    }
 
-        if let Some(f) = self.fragments.get_mut(current) {
-            // Nothing has been read yet? seek to fragment start
-            if f.limit == f.length {
-                self.inner.seek(SeekFrom::Start(f.offset))?;
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs {
+            let n = match self.read(buf) {
+                Ok(n) => n,
+                Err(_) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+            total += n;
+            if n < buf.len() {
+                break;
             }
+        }
+        Ok(total)
+    }
+}
 
-            let max = cmp::min(buf.len() as u64, f.limit) as usize;
-            let n = self.inner.read(&mut buf[..max])?;
-            self.pos += n as u64;
-            f.limit -= n as u64;
-            return Ok(n);
+impl<T: Read + Seek> BufRead for FragmentedReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.filled {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.resize(BUFFER_SIZE, 0);
+            let result = self.read_spanning(&mut buf);
+            self.buf = buf;
+            self.filled = result?;
+            self.consumed = 0;
         }
-        Ok(0)
+        Ok(&self.buf[self.consumed..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed = cmp::min(self.consumed + amt, self.filled);
     }
 }
 
@@ -113,7 +252,7 @@ impl<T: Read + Seek> Seek for FragmentedReader<T> {
                 return Ok(n);
             }
             SeekFrom::End(n) => (self.length, n),
-            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::Current(n) => (self.position(), n),
         };
 
         let new_pos = if offset >= 0 {
@@ -134,6 +273,538 @@ impl<T: Read + Seek> Seek for FragmentedReader<T> {
     }
 }
 
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Positioned reads, the backend of `SourceReader`
+///
+/// Reading at an absolute position doesn't move a cursor which is shared by the
+/// readers, so a fragment read is a single `pread` instead of a seek and a read.
+///
+pub(crate) trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<usize>;
+
+    /// Length of the data, the base of `SeekFrom::End`
+    fn size(&self) -> io::Result<u64>;
+}
+
+#[cfg(feature = "fs")]
+impl ReadAt for File {
+    fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::read_at(self, buf, pos);
+        #[cfg(windows)]
+        return std::os::windows::fs::FileExt::seek_read(self, buf, pos);
+        // the portable fallback moves the cursor of the file
+        #[cfg(not(any(unix, windows)))]
+        {
+            let mut f = self;
+            f.seek(SeekFrom::Start(pos))?;
+            f.read(buf)
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ReadAt for memmap2::Mmap {
+    fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+        let start = cmp::min(pos, self.len() as u64) as usize;
+        let n = cmp::min(buf.len(), self.len() - start);
+        buf[..n].copy_from_slice(&self[start..start + n]);
+        Ok(n)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+/// Readers without positioned reads seek and read while holding the lock
+impl ReadAt for Mutex<Box<dyn ReadSeek + Send>> {
+    fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+        let mut r = lock(self)?;
+        r.seek(SeekFrom::Start(pos))?;
+        r.read(buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        lock(self)?.seek(SeekFrom::End(0))
+    }
+}
+
+/// Reads the archive data from the file, from a memory map of the file or from a
+/// reader shared by all entries
+///
+/// Every reader keeps its own position and reads the file with positioned reads, the
+/// readers of one archive can be used from several threads at the same time.
+///
+pub struct SourceReader<'a> {
+    source: &'a (dyn ReadAt + Sync),
+    pos: u64,
+}
+
+impl<'a> SourceReader<'a> {
+    #[cfg(feature = "fs")]
+    pub(crate) fn file(f: &'a File) -> Self {
+        SourceReader { source: f, pos: 0 }
+    }
+
+    pub(crate) fn shared(r: &'a Mutex<Box<dyn ReadSeek + Send>>) -> Self {
+        SourceReader { source: r, pos: 0 }
+    }
+
+    #[cfg(feature = "mmap")]
+    pub(crate) fn map(map: &'a memmap2::Mmap) -> Self {
+        SourceReader {
+            source: map,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for SourceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.source.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SourceReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.source.size()?, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        self.pos = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Reads exactly `length` bytes without trusting `length` for the allocation
+///
+/// The lengths come from the archive, the buffer only grows with the data which is
+/// actually read.
+///
+pub(crate) fn read_exact_vec<R: Read + ?Sized>(r: &mut R, length: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(cmp::min(length, 64 * 1024) as usize);
+    r.take(length).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn lock<T: ?Sized>(m: &Mutex<Box<T>>) -> io::Result<MutexGuard<'_, Box<T>>> {
+    m.lock()
+        .map_err(|_| io::Error::other("reader poisoned by a panic"))
+}
+
+/// Reads the decompressed content of a file entry
+///
+/// Compressed files are decoded chunk by chunk while reading. Seeking only decodes
+/// the chunk containing the new position.
+///
+pub struct EntryReader<R> {
+    inner: R,
+    compression: Compression,
+    chunks: Vec<Chunk>,
+    next: usize,
+    chunk_size: u64,
+    length: u64,
+    pos: u64,
+    buf: Cursor<Vec<u8>>,
+}
+
+impl<R: Read + Seek> EntryReader<R> {
+    pub(crate) fn new(mut inner: R, length: u64) -> HpkResult<Self> {
+        let compression = get_compression(&mut inner)?;
+        check_supported(compression)?;
+        let (chunks, chunk_size, length) = if compression.is_compressed() {
+            let hdr = CompressionHeader::read_from(length, &mut inner)?;
+            let length = u64::from(hdr.inflated_length);
+            (hdr.chunks, u64::from(hdr.chunk_size), length)
+        } else {
+            (vec![], 0, length)
+        };
+        Ok(Self {
+            inner,
+            compression,
+            chunks,
+            next: 0,
+            chunk_size,
+            length,
+            pos: 0,
+            buf: Cursor::new(vec![]),
+        })
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the decompressed length of the entry
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let chunk = match self.chunks.get(self.next) {
+            Some(chunk) => *chunk,
+            None => return Ok(false),
+        };
+        let raw_len = chunk_len(self.chunk_size, self.length, self.next);
+        self.next += 1;
+        let data = read_exact_vec(&mut self.inner, chunk.length)?;
+
+        let out = self.buf.get_mut();
+        resize_buffer(out, raw_len as usize);
+        decode_chunk(self.compression, &data, out)?;
+        self.buf.set_position(0);
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Read for EntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.compression.is_compressed() {
+            return self.inner.read(buf);
+        }
+        loop {
+            let n = self.buf.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            if !self.next_chunk()? {
+                if self.pos < self.length {
+                    check_inflated_length(self.pos, self.length)?;
+                }
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for EntryReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if !self.compression.is_compressed() {
+            return self.inner.seek(pos);
+        }
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.length.checked_add_signed(n),
+        };
+        let target = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        if self.chunk_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "compression header has a chunk size of 0",
+            ));
+        }
+
+        let index = target / self.chunk_size;
+        if index < self.chunks.len() as u64 {
+            let index = index as usize;
+            // decode the chunk unless it's the current one
+            if self.next != index + 1 {
+                self.next = index;
+                self.inner
+                    .seek(SeekFrom::Start(self.chunks[index].offset))?;
+                self.next_chunk()?;
+            }
+            self.buf.set_position(target % self.chunk_size);
+        } else {
+            self.next = self.chunks.len();
+            self.buf.get_mut().clear();
+            self.buf.set_position(0);
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+/// Decodes the chunks of a compressed file while reading
+///
+/// Unlike `EntryReader` the chunks are only read in order, which allows decoding
+/// batches of chunks in parallel with `with_threads`. Uncompressed files are
+/// passed through.
+///
+pub struct ChunkedDecoder<R> {
+    inner: R,
+    compression: Compression,
+    chunks: Vec<Chunk>,
+    next: usize,
+    chunk_size: u64,
+    length: u64,
+    threads: usize,
+    /// The decoded chunks of the current batch, one after another
+    buf: Cursor<Vec<u8>>,
+}
+
+impl<R: Read + Seek> ChunkedDecoder<R> {
+    /// Reads the compression header of the file with the stored `length`
+    pub fn new(mut inner: R, length: u64) -> HpkResult<Self> {
+        let compression = get_compression(&mut inner)?;
+        check_supported(compression)?;
+        let (chunks, chunk_size, length) = if compression.is_compressed() {
+            let hdr = CompressionHeader::read_from(length, &mut inner)?;
+            let length = u64::from(hdr.inflated_length);
+            (hdr.chunks, u64::from(hdr.chunk_size), length)
+        } else {
+            (vec![], 0, length)
+        };
+        Ok(Self {
+            inner,
+            compression,
+            chunks,
+            next: 0,
+            chunk_size,
+            length,
+            threads: 1,
+            buf: Cursor::new(vec![]),
+        })
+    }
+
+    /// Decodes up to `threads` chunks at once on the worker pool shared with the
+    /// compression, see `CreateOptions::with_threads`
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    fn next_batch(&mut self) -> io::Result<bool> {
+        let end = cmp::min(self.next + self.threads, self.chunks.len());
+        if self.next >= end {
+            return Ok(false);
+        }
+        let mut batch = Vec::with_capacity(end - self.next);
+        let mut filled = 0;
+        for (index, chunk) in self.chunks.iter().enumerate().take(end).skip(self.next) {
+            let data = read_exact_vec(&mut self.inner, chunk.length)?;
+            let raw_len = chunk_len(self.chunk_size, self.length, index) as usize;
+            batch.push((data, raw_len));
+            filled += raw_len;
+        }
+        self.next = end;
+
+        // every chunk is decoded into its own slice of the buffer, which is read once
+        // all of them are decoded
+        self.buf.set_position(filled as u64);
+        let out = self.buf.get_mut();
+        resize_buffer(out, filled);
+        let mut slices = Vec::with_capacity(batch.len());
+        let mut rest = &mut out[..];
+        for (data, raw_len) in batch {
+            let (slice, tail) = rest.split_at_mut(raw_len);
+            slices.push((data, slice));
+            rest = tail;
+        }
+
+        let compression = self.compression;
+        if slices.len() == 1 {
+            let (data, out) = slices.pop().expect("one chunk");
+            decode_chunk(compression, &data, out)?;
+        } else {
+            pool().install(|| {
+                slices
+                    .into_par_iter()
+                    .try_for_each(|(data, out)| decode_chunk(compression, &data, out))
+            })?;
+        }
+        self.buf.set_position(0);
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Read for ChunkedDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.compression.is_compressed() {
+            return self.inner.read(buf);
+        }
+        loop {
+            let n = self.buf.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            if !self.next_batch()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Rejects a compression which can't be decoded with the enabled features
+#[cfg_attr(feature = "zstd", allow(unused_variables))]
+fn check_supported(compression: Compression) -> HpkResult<()> {
+    #[cfg(not(feature = "zstd"))]
+    if compression == Compression::Zstd {
+        return Err(compress::zstd_unsupported().into());
+    }
+    Ok(())
+}
+
+/// Returns the decompressed length of the chunk at `index`
+pub(crate) fn chunk_len(chunk_size: u64, length: u64, index: usize) -> u64 {
+    let start = (index as u64).saturating_mul(chunk_size);
+    cmp::min(chunk_size, length.saturating_sub(start))
+}
+
+/// Sets the length of the chunk buffer to `len`
+///
+/// Grown memory comes zeroed from the allocator, its pages aren't touched before a
+/// chunk is decoded into them. A header with a bogus chunk size doesn't commit the
+/// memory up front.
+///
+fn resize_buffer(buf: &mut Vec<u8>, len: usize) {
+    if buf.capacity() < len {
+        *buf = vec![0; len];
+    } else {
+        buf.resize(len, 0);
+    }
+}
+
+/// Fails a decoder which writes more than the decompressed length of the chunk
+struct ChunkWriter<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.pos + buf.len();
+        if end > self.out.len() {
+            return Err(invalid_chunk("chunk exceeds its decompressed length"));
+        }
+        self.out[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes `data` into `out` which has the decompressed length of the chunk
+///
+/// A chunk with its decompressed length is stored uncompressed like the game does.
+/// A chunk which fails to decode or which doesn't decode to exactly the length of
+/// `out` fails with an `InvalidData` error.
+///
+fn decode_chunk(compression: Compression, data: &[u8], out: &mut [u8]) -> io::Result<()> {
+    if data.len() == out.len() {
+        out.copy_from_slice(data);
+        return Ok(());
+    }
+    let mut r = Cursor::new(data);
+    let mut w = ChunkWriter { out, pos: 0 };
+    let codec = compress::codec(compression).expect("compressed entries have a codec");
+    codec
+        .decode(&mut r, &mut w)
+        .map_err(|e| invalid_chunk(&format!("chunk fails to decode: {}", e)))?;
+    if w.pos < w.out.len() {
+        return Err(invalid_chunk(
+            "chunk is shorter than its decompressed length",
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if a compressed entry ends before its decompressed length from the header
+fn check_inflated_length(written: u64, length: u64) -> io::Result<()> {
+    if written != length {
+        let msg = format!("decompressed {} bytes instead of {}", written, length);
+        return Err(invalid_chunk(&msg));
+    }
+    Ok(())
+}
+
+fn invalid_chunk(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A temporary file for the spooled data, an in-memory buffer without the `fs`
+/// feature
+///
+#[cfg(feature = "fs")]
+pub(crate) type Spool = File;
+#[cfg(not(feature = "fs"))]
+pub(crate) type Spool = Cursor<Vec<u8>>;
+
+pub(crate) fn spool() -> io::Result<Spool> {
+    #[cfg(feature = "fs")]
+    return tempfile::tempfile();
+    #[cfg(not(feature = "fs"))]
+    return Ok(Cursor::new(vec![]));
+}
+
+/// Copies the content of the entry to `w`, compressed entries are decompressed
+pub fn copy<R, W>(r: &mut FragmentedReader<R>, w: &mut W) -> HpkResult<u64>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    copy_cancellable(r, w, None, 1)
+}
+
+/// Copies the decompressed content, the chunks are decoded by up to `threads` threads
+pub(crate) fn copy_cancellable<R, W>(
+    r: &mut FragmentedReader<R>,
+    w: &mut W,
+    cancel: Option<&CancelToken>,
+    threads: usize,
+) -> HpkResult<u64>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let len = r.len();
+    let mut decoder = ChunkedDecoder::new(r, len)?.with_threads(threads);
+    let mut buf = vec![0; 64 * 1024];
+    let mut written = 0;
+    loop {
+        CancelToken::check(cancel)?;
+        let n = decoder.read(&mut buf)?;
+        if n == 0 {
+            if decoder.compression.is_compressed() {
+                check_inflated_length(written, decoder.length)?;
+            }
+            return Ok(written);
+        }
+        w.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+}
+
 // Tests {{{
 #[cfg(test)]
 mod tests {
@@ -200,6 +871,7 @@ mod tests {
     // }}}
 
     // trait PrintState {{{
+    #[allow(dead_code)]
     trait PrintState {
         fn print_state(&mut self);
     }
@@ -207,18 +879,16 @@ mod tests {
     impl<T: Read + Seek> PrintState for FragmentedReader<T> {
         fn print_state(&mut self) {
             println!("pos: {}", self.pos);
-            println!("inner pos: {:?}", self.inner.seek(SeekFrom::Current(0)));
+            println!("inner pos: {:?}", self.inner.stream_position());
             print!("positions: ");
             for pos in &self.fragments {
                 print!("{} ", pos.end_pos);
             }
             println!();
+            println!("current fragment: {}", self.current);
             println!("fragment states:");
             for (i, s) in self.fragments.iter().enumerate() {
-                println!(
-                    "{}: off: {} len: {} limit: {}",
-                    i, s.offset, s.length, s.limit
-                );
+                println!("{}: off: {} len: {}", i, s.offset, s.length);
             }
         }
     }
@@ -226,7 +896,7 @@ mod tests {
 
     #[test]
     fn fragmented_reader_read() {
-        let sample = vec![
+        let sample = [
             (10, 12, 0x11),
             (32, 20, 0x22),
             (60, 35, 0x33),
@@ -238,17 +908,11 @@ mod tests {
 
         let mut buf = vec![0; r.len() as usize];
 
-        let n = r.read(&mut buf).unwrap();
-        assert_eq!(n, 12);
-        let mut start = n;
-        let n = r.read(&mut buf[start..]).unwrap();
-        assert_eq!(n, 20);
-        start += n;
-        let n = r.read(&mut buf[start..]).unwrap();
-        assert_eq!(n, 35);
-        start += n;
-        let n = r.read(&mut buf[start..]).unwrap();
-        assert_eq!(n, 22);
+        // reads span the fragment boundaries
+        let n = r.read(&mut buf[..15]).unwrap();
+        assert_eq!(n, 15);
+        let n = r.read(&mut buf[15..]).unwrap();
+        assert_eq!(n, 74);
 
         // EOF of fragmented file reached
         let n = r.read(&mut buf).unwrap();
@@ -270,7 +934,7 @@ mod tests {
 
     #[test]
     fn fragmented_reader_read_exact() {
-        let sample = vec![
+        let sample = [
             (10, 12, 0x11),
             (32, 20, 0x22),
             (60, 35, 0x33),
@@ -304,7 +968,7 @@ mod tests {
 
     #[test]
     fn fragmented_reader_seek() {
-        let sample = vec![
+        let sample = [
             (10, 12, 0x11),
             (32, 20, 0x22),
             (60, 35, 0x33),
@@ -342,6 +1006,219 @@ mod tests {
         assert_eq!(n, 20);
         assert_eq!(buf, [0x22; 20]);
     }
+
+    #[test]
+    fn fragmented_reader_empty_fragments() {
+        let sample = [
+            (0, 0, 0x00),
+            (10, 12, 0x11),
+            (30, 0, 0x00),
+            (32, 20, 0x22),
+            (60, 0, 0x00),
+        ];
+        let mut r = create_fragmented_reader!(128, 0xFF, sample);
+        assert_eq!(r.len(), 32);
+
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf[..12], [0x11; 12]);
+        assert_eq!(&buf[12..], [0x22; 20]);
+
+        r.seek(SeekFrom::Start(12)).unwrap();
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x22; 4]);
+
+        let mut r = create_fragmented_reader!(16, 0xFF, [(4, 0, 0x00)]);
+        assert!(r.is_empty());
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn fragmented_reader_many_fragments() {
+        // 3000 fragments of 1 to 4 bytes in reverse order with gaps between them
+        let mut data = vec![0xFF; 3000 * 5];
+        let mut expected = vec![];
+        let mut fragments = vec![];
+        for i in (0..3000u64).rev() {
+            let offset = i * 5;
+            let length = 1 + i % 4;
+            for j in 0..length {
+                data[(offset + j) as usize] = (i + j) as u8;
+            }
+            fragments.push(Fragment::new(offset, length));
+        }
+        for f in &fragments {
+            expected.extend_from_slice(&data[f.offset as usize..(f.offset + f.length) as usize]);
+        }
+
+        let mut r = FragmentedReader::new(Cursor::new(data), &fragments);
+        assert_eq!(r.len(), expected.len() as u64);
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        let mut pos = 0;
+        let mut buf = [0; 7];
+        for _ in 0..500 {
+            pos = (pos + 4099) % (expected.len() - buf.len());
+            r.seek(SeekFrom::Start(pos as u64)).unwrap();
+            r.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, expected[pos..pos + buf.len()]);
+        }
+    }
+
+    #[test]
+    fn fragmented_reader_read_vectored() {
+        let sample = [(10, 12, 0x11), (32, 20, 0x22), (60, 35, 0x33)];
+        let mut r = create_fragmented_reader!(128, 0xFF, sample);
+
+        let (mut a, mut b, mut c) = ([0; 10], [0; 30], [0; 40]);
+        let mut bufs = [
+            IoSliceMut::new(&mut a),
+            IoSliceMut::new(&mut b),
+            IoSliceMut::new(&mut c),
+        ];
+        assert_eq!(r.read_vectored(&mut bufs).unwrap(), 67);
+        assert_eq!(a, [0x11; 10]);
+        assert_eq!(b[..2], [0x11; 2]);
+        assert_eq!(b[2..22], [0x22; 20]);
+        assert_eq!(b[22..], [0x33; 8]);
+        assert_eq!(c[..27], [0x33; 27]);
+    }
+
+    /// Counts the seeks of the inner reader
+    struct CountSeeks(Cursor<Vec<u8>>, usize);
+
+    impl Read for CountSeeks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for CountSeeks {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.1 += 1;
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn fragmented_reader_adjacent_fragments() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let fragments = create_fragments!([(0, 10), (10, 0), (10, 20), (30, 10), (50, 10)]);
+        let mut r = FragmentedReader::new(CountSeeks(Cursor::new(data), 0), &fragments);
+
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf[..40], (0..40).collect::<Vec<_>>()[..]);
+        assert_eq!(buf[40..], (50..60).collect::<Vec<_>>()[..]);
+        // the start, behind the empty fragment and the gap
+        assert_eq!(r.into_inner().1, 3);
+    }
+
+    #[test]
+    fn fragmented_reader_buf_read() {
+        let sample = [(10, 12, b'a'), (32, 20, b'\n'), (60, 35, b'b')];
+        let mut r = create_fragmented_reader!(128, 0xFF, sample);
+
+        // the buffer spans the fragments
+        assert_eq!(r.fill_buf().unwrap().len(), 67);
+        assert_eq!(r.fill_buf().unwrap()[..12], [b'a'; 12]);
+        r.consume(4);
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [b'a'; 4]);
+        assert_eq!(r.stream_position().unwrap(), 8);
+
+        // seeking inside of the buffer keeps it
+        r.seek(SeekFrom::Current(-6)).unwrap();
+        assert_eq!(r.fill_buf().unwrap()[..10], [b'a'; 10]);
+        r.seek(SeekFrom::Start(11)).unwrap();
+        assert_eq!(r.fill_buf().unwrap()[..2], [b'a', b'\n']);
+        r.consume(1);
+
+        let mut lines = r.lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "");
+        assert_eq!(lines.nth(18).unwrap().unwrap(), "");
+        assert_eq!(lines.next().unwrap().unwrap(), "b".repeat(35));
+        assert!(lines.next().is_none());
+
+        let mut r = create_fragmented_reader!(128, 0xFF, sample);
+        r.fill_buf().unwrap();
+        r.seek(SeekFrom::End(-2)).unwrap();
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"bb");
+    }
+
+    #[test]
+    fn source_readers_keep_their_position() {
+        let shared: Mutex<Box<dyn ReadSeek + Send>> =
+            Mutex::new(Box::new(Cursor::new((0..100u8).collect::<Vec<_>>())));
+        #[cfg(feature = "fs")]
+        let file = {
+            let mut f = tempfile::tempfile().unwrap();
+            f.write_all(&(0..100u8).collect::<Vec<_>>()).unwrap();
+            f
+        };
+        let sources = vec![
+            (SourceReader::shared(&shared), SourceReader::shared(&shared)),
+            #[cfg(feature = "fs")]
+            (SourceReader::file(&file), SourceReader::file(&file)),
+        ];
+
+        for (mut a, mut b) in sources {
+            let mut buf = [0; 3];
+            a.seek(SeekFrom::Start(10)).unwrap();
+            b.seek(SeekFrom::End(-3)).unwrap();
+            a.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [10, 11, 12]);
+            b.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [97, 98, 99]);
+            assert_eq!(b.read(&mut buf).unwrap(), 0);
+            a.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [13, 14, 15]);
+            assert_eq!(a.seek(SeekFrom::Current(-16)).unwrap(), 0);
+            assert!(a.seek(SeekFrom::Current(-1)).is_err());
+        }
+    }
+
+    #[test]
+    fn chunked_decoder() {
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let options = crate::CompressOptions {
+            chunk_size: 512,
+            ..Default::default()
+        };
+        let mut compressed = vec![];
+        crate::compress(&options, &mut &content[..], &mut compressed).unwrap();
+        let length = compressed.len() as u64;
+
+        for threads in [1, 3, 16] {
+            let r = Cursor::new(&compressed);
+            let mut decoder = ChunkedDecoder::new(r, length)
+                .unwrap()
+                .with_threads(threads);
+            assert_eq!(decoder.compression(), Compression::Zlib);
+            let mut buf = vec![];
+            let mut small = [0; 100];
+            loop {
+                let n = decoder.read(&mut small).unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&small[..n]);
+            }
+            assert_eq!(buf, content);
+        }
+
+        let mut decoder = ChunkedDecoder::new(Cursor::new(&content), 5000).unwrap();
+        assert_eq!(decoder.compression(), Compression::None);
+        let mut buf = vec![];
+        decoder.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+    }
 }
 // }}}
 
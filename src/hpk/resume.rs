@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// The progress of an extraction, see `ExtractOptions::resume_state`
+///
+/// Every line of the state file is a record of a processed entry. A `done` record
+/// marks an extracted file, a `partial` record the length of a file whose extraction
+/// was cancelled.
+///
+/// ```text
+/// done\tfolder/a.lua
+/// partial\t1048576\tmaps/terrain.bin
+/// ```
+///
+pub(crate) struct ExtractState {
+    path: PathBuf,
+    file: File,
+    /// The extracted length of the entries, `None` for completed entries
+    entries: HashMap<String, Option<u64>>,
+}
+
+impl ExtractState {
+    /// Reads the records of a previous extraction if the state file exists
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                let mut fields = line.splitn(3, '\t');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some("done"), Some(name), None) => {
+                        entries.insert(name.to_string(), None);
+                    }
+                    (Some("partial"), Some(len), Some(name)) => {
+                        if let Ok(len) = len.parse() {
+                            entries.insert(name.to_string(), Some(len));
+                        }
+                    }
+                    // ignore a torn record of an aborted write
+                    _ => {}
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ExtractState {
+            path: path.to_path_buf(),
+            file,
+            entries,
+        })
+    }
+
+    pub fn is_done(&self, entry: &Path) -> bool {
+        matches!(self.entries.get(&*entry.to_string_lossy()), Some(None))
+    }
+
+    /// Returns the length of the partially extracted file
+    pub fn offset(&self, entry: &Path) -> u64 {
+        match self.entries.get(&*entry.to_string_lossy()) {
+            Some(Some(len)) => *len,
+            _ => 0,
+        }
+    }
+
+    pub fn done(&mut self, entry: &Path) -> io::Result<()> {
+        self.record(entry, "done\t")
+    }
+
+    pub fn partial(&mut self, entry: &Path, len: u64) -> io::Result<()> {
+        self.record(entry, &format!("partial\t{}\t", len))
+    }
+
+    /// Removes the state file after the extraction is completed
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+
+    fn record(&mut self, entry: &Path, prefix: &str) -> io::Result<()> {
+        let name = entry.to_string_lossy();
+        // such an entry is extracted again
+        if name.contains('\n') {
+            return Ok(());
+        }
+        self.file
+            .write_all(format!("{}{}\n", prefix, name).as_bytes())
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::{volume_path, CorruptEntry, Corruption, HpkArchive, HpkResult, VolumeReader};
+
+/// Returns the path of the SHA-256 manifest of the archive `file`,
+/// `archive.hpk` becomes `archive.hpk.sha256`
+pub fn sha256_manifest_path<P: AsRef<Path>>(file: P) -> PathBuf {
+    let file = file.as_ref();
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".sha256");
+    file.with_file_name(name)
+}
+
+/// Writes the SHA-256 manifest of the archive `file` next to it, see
+/// `CreateOptions::with_sha256_manifest`
+///
+/// The first line holds the digest of the archive file, the other lines the digests
+/// of the decompressed files in the order of the directory tree:
+///
+/// ```text
+/// 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae  mod.hpk
+/// fcde2b2edba56bf408601fb721fe9b5c338d10ee429ea04fae5511b68fbf8fb9  scripts/main.lua
+/// ```
+///
+/// The first line can be checked with `sha256sum -c`. The volumes of a split archive
+/// are hashed as one file. Returns the path of the manifest.
+///
+pub fn write_sha256_manifest<P: AsRef<Path>>(file: P) -> HpkResult<PathBuf> {
+    let file = file.as_ref();
+    let (r, archive) = open(file)?;
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+
+    let mut out = format!("{}  {}\n", hex(&digest(r)?), name);
+    for entry in archive.entries().iter().filter(|e| !e.is_dir()) {
+        let digest = digest(archive.open_entry(entry)?)?;
+        out.push_str(&format!("{}  {}\n", hex(&digest), slashed(entry.path())));
+    }
+    let manifest = sha256_manifest_path(file);
+    fs::write(&manifest, out)?;
+    Ok(manifest)
+}
+
+/// Checks the archive `file` against the SHA-256 `manifest` of `write_sha256_manifest`
+///
+/// Returns the archive file and the entries whose digests differ, the entries of the
+/// manifest which are missing in the archive and the files of the archive which
+/// aren't listed in the manifest.
+///
+pub fn verify_sha256_manifest<P, Q>(file: P, manifest: Q) -> HpkResult<Vec<CorruptEntry>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let file = file.as_ref();
+    let content = fs::read_to_string(manifest)?;
+    let mut lines = content.lines().filter(|l| !l.is_empty()).map(parse_line);
+    let (expected, _) = lines.next().unwrap_or_else(|| Err(invalid("empty")))?;
+
+    let (r, archive) = open(file)?;
+    let mut corrupt = vec![];
+    if digest(r)? != expected {
+        corrupt.push(CorruptEntry {
+            path: PathBuf::from(file.file_name().unwrap_or_default()),
+            corruption: Corruption::Sha256Mismatch,
+        });
+    }
+
+    let mut listed = HashSet::new();
+    for line in lines {
+        let (expected, path) = line?;
+        let path = PathBuf::from(path);
+        let corruption = match archive.entry(&path).filter(|e| !e.is_dir()) {
+            None => Some(Corruption::NotInArchive),
+            Some(entry) => match archive.open_entry(entry).map(digest) {
+                Ok(Ok(digest)) if digest == expected => None,
+                Ok(Ok(_)) => Some(Corruption::Sha256Mismatch),
+                Ok(Err(e)) | Err(e) => Some(Corruption::Unreadable(e)),
+            },
+        };
+        if let Some(corruption) = corruption {
+            corrupt.push(CorruptEntry {
+                path: path.clone(),
+                corruption,
+            });
+        }
+        listed.insert(path);
+    }
+    for entry in archive.entries().iter().filter(|e| !e.is_dir()) {
+        if !listed.contains(entry.path()) {
+            corrupt.push(CorruptEntry {
+                path: entry.path().to_path_buf(),
+                corruption: Corruption::NotInManifest,
+            });
+        }
+    }
+    Ok(corrupt)
+}
+
+/// Opens the archive file or its volumes for the digest of the whole file
+fn open(file: &Path) -> HpkResult<(Box<dyn Read>, HpkArchive)> {
+    if !file.exists() && volume_path(file, 0).exists() {
+        let r = VolumeReader::open(file)?;
+        return Ok((Box::new(r), HpkArchive::open_volumes(file)?));
+    }
+    let r = BufReader::new(File::open(file)?);
+    Ok((Box::new(r), HpkArchive::open(file)?))
+}
+
+fn digest<R: Read>(mut r: R) -> HpkResult<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut r, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Parses a line `hex digest` two spaces `path`
+fn parse_line(line: &str) -> HpkResult<([u8; 32], &str)> {
+    let (digest, path) = line.split_once("  ").ok_or_else(|| invalid(line))?;
+    let mut bytes = [0; 32];
+    if digest.len() != 64 || !digest.is_ascii() {
+        return Err(invalid(line));
+    }
+    for (byte, i) in bytes.iter_mut().zip((0..64).step_by(2)) {
+        *byte = u8::from_str_radix(&digest[i..i + 2], 16).map_err(|_| invalid(line))?;
+    }
+    Ok((bytes, path))
+}
+
+fn invalid(line: &str) -> crate::HpkError {
+    let msg = format!("invalid sha256 manifest line: {}", line);
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn slashed(path: &Path) -> String {
+    let names: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+    names.join("/")
+}
@@ -0,0 +1,74 @@
+use std::io::prelude::*;
+
+use ed25519_compact::{KeyPair, Noise, PublicKey, Seed, Signature, SigningState};
+
+use crate::{HpkArchive, HpkError, HpkResult};
+
+/// Name of the entry in the root directory which holds the signature of the archive
+pub(crate) const SIGNATURE: &str = "_signature";
+
+/// Length of the content of the `_signature` entry
+pub(crate) const SIGNATURE_LENGTH: u64 = Signature::BYTES as u64;
+
+/// Returns the Ed25519 public key of the secret key passed to `CreateOptions::sign_with`
+pub fn signing_public_key(secret_key: &[u8; 32]) -> [u8; 32] {
+    *KeyPair::from_seed(Seed::new(*secret_key)).pk
+}
+
+/// Starts the signature of the bytes written by `HpkWriter`
+pub(crate) fn signer(secret_key: &[u8; 32]) -> SigningState {
+    let keys = KeyPair::from_seed(Seed::new(*secret_key));
+    keys.sk.sign_incremental(Noise::generate())
+}
+
+/// Checks the `_signature` entry of `archive` against `public_key`
+///
+/// The signed message is the archive from the end of the header to the end of the
+/// fragment table without the content of the `_signature` entry, followed by the
+/// header. The data, the directory tree and the fragment table are covered, a changed
+/// byte fails the verification.
+///
+pub(crate) fn verify_signature(archive: &HpkArchive, public_key: &[u8; 32]) -> HpkResult<()> {
+    let entry = match archive.entry(SIGNATURE) {
+        Some(e) if e.depth() == 1 && !e.is_dir() => e,
+        _ => return Err(HpkError::Unsigned),
+    };
+    let signature = archive.read_file(SIGNATURE)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| HpkError::InvalidSignature)?;
+    let mut state = PublicKey::new(*public_key)
+        .verify_incremental(&signature)
+        .map_err(|_| HpkError::InvalidSignature)?;
+
+    let fragments = &archive.fragments()[entry.index()];
+    let skip_start = fragments.first().map_or(0, |f| f.offset);
+    let skip_end = skip_start + SIGNATURE_LENGTH;
+
+    let header = archive.header();
+    let header_length = u64::from(header.layout().header_length());
+    let end = header
+        .fragmented_filesystem_offset
+        .checked_add(header.fragmented_filesystem_length)
+        .filter(|&end| end >= skip_end && skip_start >= header_length)
+        .ok_or(HpkError::InvalidSignature)?;
+    if end > archive.archive_len()? {
+        return Err(HpkError::TruncatedFragmentTable);
+    }
+
+    let ranges = [
+        (header_length, skip_start),
+        (skip_end, end),
+        (0, header_length),
+    ];
+    let mut buf = vec![0; 64 * 1024];
+    for &(start, end) in &ranges {
+        let mut r = archive.raw_reader(start, end - start);
+        loop {
+            let n = r.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            state.absorb(&buf[..n]);
+        }
+    }
+    state.verify().map_err(|_| HpkError::InvalidSignature)
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+/// Size of the blocks which are checked for zeros
+const BLOCK_SIZE: u64 = 4096;
+
+/// Seeks over blocks of zeros instead of writing them if `sparse` is set
+///
+/// The skipped blocks become holes on file systems supporting sparse files. The
+/// zeros at the start of a block are held back until the block is complete, a
+/// skipped block at the end is completed by `flush` with a single zero byte.
+///
+pub(crate) struct SparseWriter<W> {
+    inner: W,
+    sparse: bool,
+    pos: u64,
+    /// Zeros of the current block which aren't written yet
+    zeros: u64,
+    /// The position of `inner` is behind a skipped block
+    hole: bool,
+    /// Bytes which are already written by a previous extraction
+    skip: u64,
+}
+
+impl<W: Write + Seek> SparseWriter<W> {
+    pub fn new(inner: W, sparse: bool) -> Self {
+        SparseWriter {
+            inner,
+            sparse,
+            pos: 0,
+            zeros: 0,
+            hole: false,
+            skip: 0,
+        }
+    }
+
+    /// Discards the first `len` bytes, `inner` is positioned behind them
+    pub fn resume_at(mut self, len: u64) -> Self {
+        self.skip = len;
+        self
+    }
+
+    /// Writes the held back zeros of the current block
+    fn fill(&mut self) -> io::Result<()> {
+        if self.hole {
+            self.inner.seek(SeekFrom::Start(self.pos - self.zeros))?;
+            self.hole = false;
+        }
+        if self.zeros > 0 {
+            self.inner
+                .write_all(&[0; BLOCK_SIZE as usize][..self.zeros as usize])?;
+            self.zeros = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for SparseWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.skip > 0 {
+            let n = (buf.len() as u64).min(self.skip);
+            self.skip -= n;
+            self.pos += n;
+            return Ok(n as usize);
+        }
+        if !self.sparse {
+            return self.inner.write(buf);
+        }
+        // stop at the next block boundary
+        let boundary = BLOCK_SIZE - self.pos % BLOCK_SIZE;
+        let buf = &buf[..buf.len().min(boundary as usize)];
+        if self.pos % BLOCK_SIZE == self.zeros && buf.iter().all(|&b| b == 0) {
+            self.pos += buf.len() as u64;
+            self.zeros += buf.len() as u64;
+            if self.zeros == BLOCK_SIZE {
+                self.zeros = 0;
+                self.hole = true;
+            }
+            return Ok(buf.len());
+        }
+        self.fill()?;
+        let n = self.inner.write(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.zeros > 0 {
+            self.fill()?;
+        } else if self.hole {
+            self.inner.seek(SeekFrom::Start(self.pos - 1))?;
+            self.inner.write_all(&[0])?;
+            self.hole = false;
+        }
+        self.inner.flush()
+    }
+}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::io::{Cursor, SeekFrom};
+
+    use super::{SparseWriter, BLOCK_SIZE};
+
+    /// Counts the bytes which are actually written
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        written: usize,
+    }
+
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = self.inner.write(buf)?;
+            self.written += n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Counting {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn sparse_writer() {
+        let block = BLOCK_SIZE as usize;
+        let mut data = vec![0u8; block * 8];
+        data[..10].copy_from_slice(b"ABCDEFGHIJ");
+        // a zero run which doesn't start at a block boundary
+        data[block * 3 + 5] = 1;
+
+        // a trailing hole is completed with a single byte
+        for &(trailing, written) in &[(0, block * 2 + 1), (100, block * 2 + 100)] {
+            let mut data = data.clone();
+            data.resize(data.len() + trailing, 0);
+            let inner = Counting {
+                inner: Cursor::new(vec![]),
+                written: 0,
+            };
+            let mut w = SparseWriter::new(inner, true);
+            // odd sized writes like those of a `BufWriter`
+            for chunk in data.chunks(3000) {
+                w.write_all(chunk).unwrap();
+            }
+            w.flush().unwrap();
+            assert_eq!(w.inner.inner.get_ref(), &data);
+            assert_eq!(w.inner.written, written);
+        }
+    }
+}
+// }}}
+
+// vim: fdm=marker
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{Compression, HpkArchive, HpkResult};
+
+/// Number of entries listed in `ArchiveStats::largest`
+const LARGEST_ENTRIES: usize = 10;
+
+/// Totals of an archive for deciding what to recompress
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ArchiveStats {
+    pub files: usize,
+    /// Number of directories without the root directory
+    pub dirs: usize,
+    /// Stored size of all files inside of the archive
+    pub compressed_size: u64,
+    /// Decompressed size of all files
+    pub size: u64,
+    /// Totals per file extension, sorted by the extension
+    pub extensions: Vec<ExtensionStats>,
+    /// The files with the largest decompressed size, largest first
+    pub largest: Vec<EntryStats>,
+    /// Bytes of the data section which aren't used by any entry
+    ///
+    /// This includes the space of the residual fragments.
+    ///
+    pub padding: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ExtensionStats {
+    /// The lowercase extension without a dot, empty for files without one
+    pub extension: String,
+    pub files: usize,
+    pub compressed_size: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EntryStats {
+    pub path: PathBuf,
+    pub compression: Compression,
+    pub compressed_size: u64,
+    pub size: u64,
+}
+
+impl ArchiveStats {
+    /// Reads the compression headers of all files of `archive`
+    pub fn compute(archive: &HpkArchive) -> HpkResult<Self> {
+        let mut stats = ArchiveStats::default();
+        let mut extensions = BTreeMap::new();
+        let mut entries = vec![];
+
+        for entry in archive.entries().iter().filter(|e| e.depth() > 0) {
+            if entry.is_dir() {
+                stats.dirs += 1;
+                continue;
+            }
+            let md = archive.metadata(entry)?;
+            stats.files += 1;
+            stats.compressed_size = stats.compressed_size.saturating_add(md.compressed_size);
+            stats.size = stats.size.saturating_add(md.size);
+
+            let extension = entry
+                .path()
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let ext = extensions
+                .entry(extension.clone())
+                .or_insert_with(|| ExtensionStats {
+                    extension,
+                    ..Default::default()
+                });
+            ext.files += 1;
+            ext.compressed_size = ext.compressed_size.saturating_add(md.compressed_size);
+            ext.size = ext.size.saturating_add(md.size);
+
+            entries.push(EntryStats {
+                path: entry.path().to_path_buf(),
+                compression: md.compression,
+                compressed_size: md.compressed_size,
+                size: md.size,
+            });
+        }
+        entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        entries.truncate(LARGEST_ENTRIES);
+
+        stats.extensions = extensions.into_values().collect();
+        stats.largest = entries;
+        stats.padding = padding(archive);
+        Ok(stats)
+    }
+
+    /// Compressed size divided by the decompressed size, 1.0 for empty archives
+    pub fn ratio(&self) -> f64 {
+        ratio(self.compressed_size, self.size)
+    }
+}
+
+impl ExtensionStats {
+    /// Compressed size divided by the decompressed size
+    pub fn ratio(&self) -> f64 {
+        ratio(self.compressed_size, self.size)
+    }
+}
+
+fn ratio(compressed_size: u64, size: u64) -> f64 {
+    if size == 0 {
+        return 1.0;
+    }
+    compressed_size as f64 / size as f64
+}
+
+/// Sums the gaps between the fragments of the entries
+fn padding(archive: &HpkArchive) -> u64 {
+    let mut fragments = archive
+        .fragments()
+        .iter()
+        .flatten()
+        .filter(|f| f.length > 0)
+        .collect::<Vec<_>>();
+    fragments.sort_by_key(|f| f.offset);
+
+    let mut padding = 0;
+    let mut end = u64::from(archive.header().data_offset);
+    for f in fragments {
+        padding = u64::saturating_add(padding, f.offset.saturating_sub(end));
+        end = end.max(f.offset.saturating_add(f.length));
+    }
+    padding
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::checksum;
+use crate::create::FileDateFormat;
+use crate::dedup::same_content;
+use crate::{lua, CreateOptions, DirEntry, HpkArchive, HpkResult, HpkWriter};
+
+const FILEDATES: &str = "_filedates";
+
+/// The changes applied by `sync_dir`
+#[derive(Clone, Debug, Default)]
+pub struct SyncSummary {
+    /// Files which are new in the directory
+    pub added: Vec<PathBuf>,
+    /// Files whose content differs from the stored content
+    pub changed: Vec<PathBuf>,
+    /// Files and directories which aren't in the directory anymore
+    pub removed: Vec<PathBuf>,
+    /// Number of files whose stored content is kept
+    pub unchanged: usize,
+}
+
+impl SyncSummary {
+    /// Returns `true` if the archive already matched the directory
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Updates the archive `file` to match the directory `dir`
+///
+/// Only new and changed files are compressed and written, the stored content of the
+/// unchanged files is kept as it is. A file is unchanged if its modification time
+/// in the `_filedates` entry of the archive and its size are the same, otherwise the
+/// decompressed content is compared with the file. The entries which aren't in `dir`
+/// anymore are removed and an existing `_filedates` entry is written again.
+///
+/// The replaced content stays unreferenced in the archive like with `update`,
+/// `defrag` removes it.
+///
+pub fn sync_dir<D, P>(options: &CreateOptions, dir: D, file: P) -> HpkResult<SyncSummary>
+where
+    D: AsRef<Path>,
+    P: AsRef<Path>,
+{
+    let (dir, file) = (dir.as_ref(), file.as_ref());
+    let archive = HpkArchive::open(file)?;
+    let entries: HashMap<_, _> = archive
+        .entries()
+        .iter()
+        .filter(|e| e.depth() > 0)
+        .map(|e| (e.path(), e))
+        .collect();
+
+    let old_filedates = match entries.get(Path::new(FILEDATES)) {
+        Some(e) if e.depth() == 1 && !e.is_dir() => Some(archive.read_file(FILEDATES)?),
+        _ => None,
+    };
+    let stored_dates = old_filedates
+        .as_deref()
+        .map(parse_filedates)
+        .unwrap_or_default();
+    let fmt = options.filedates_fmt.or_else(|| {
+        // the short format stores the Windows file time divided by 2000
+        let value = *stored_dates.values().next()?;
+        if value < 10_000_000_000_000_000 {
+            Some(FileDateFormat::Short)
+        } else {
+            Some(FileDateFormat::Default)
+        }
+    });
+    let fmt = fmt.filter(|_| options.with_filedates() || old_filedates.is_some());
+
+    let excludes = options.excludes(dir)?;
+    let included = |e: &walkdir::Result<walkdir::DirEntry>| match e {
+        Ok(e) => !excludes.is_excluded_tree(e.path().strip_prefix(dir).unwrap()),
+        Err(_) => true,
+    };
+    let walkdir = WalkDir::new(dir)
+        .contents_first(true)
+        .follow_links(options.symlinks == crate::SymlinkPolicy::Follow)
+        .sort_by(options.sorter())
+        .into_iter()
+        .filter(included);
+
+    let mut summary = SyncSummary::default();
+    // the paths of the directory and whether they're directories
+    let mut present = HashMap::new();
+    let mut dirs = vec![];
+    let mut filedates = vec![];
+    for entry in walkdir {
+        let entry = entry?;
+        if entry.depth() == 0 || options.skip_symlink(&entry)? {
+            continue;
+        }
+        let path = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+        if let Some(fmt) = fmt {
+            let value = options.filedate_in_format(entry.path(), fmt)?;
+            writeln!(filedates, "{}={}", path.display(), value)?;
+        }
+        let is_dir = entry.file_type().is_dir();
+        if is_dir {
+            dirs.push(path.clone());
+        } else if entry.file_type().is_file() {
+            let stored = entries.get(path.as_path()).filter(|e| !e.is_dir());
+            match stored {
+                None => summary.added.push(path.clone()),
+                Some(stored) => {
+                    let modified = match (fmt, stored_dates.get(&path)) {
+                        (Some(fmt), Some(&value)) if !options.deterministic => {
+                            value != options.filedate_in_format(entry.path(), fmt)?
+                                || archive.metadata(stored)?.size != entry.metadata()?.len()
+                        }
+                        _ => true,
+                    };
+                    if modified && !is_unchanged(options, &archive, stored, entry.path(), &path)? {
+                        summary.changed.push(path.clone());
+                    } else {
+                        summary.unchanged += 1;
+                    }
+                }
+            }
+        } else {
+            continue;
+        }
+        present.insert(path, is_dir);
+    }
+
+    // an entry whose type changed is removed and added again
+    let mut removed_dirs: Vec<&Path> = vec![];
+    for entry in archive.entries().iter().filter(|e| e.depth() > 0) {
+        let path = entry.path();
+        if is_special(entry) || removed_dirs.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        if present.get(path) != Some(&entry.is_dir()) {
+            if entry.is_dir() {
+                removed_dirs.push(path);
+            }
+            summary.removed.push(path.to_path_buf());
+        }
+    }
+    let filedates_changed = fmt.is_some() && old_filedates.as_deref() != Some(&filedates[..]);
+    if summary.is_empty() && !filedates_changed {
+        return Ok(summary);
+    }
+    drop(entries);
+    drop(archive);
+
+    let f = OpenOptions::new().read(true).write(true).open(file)?;
+    let mut writer = HpkWriter::open(options, f)?;
+    for path in &summary.removed {
+        writer.remove(path)?;
+    }
+    for path in &dirs {
+        writer.add_dir(path)?;
+    }
+    for path in summary.added.iter().chain(&summary.changed) {
+        let r = BufReader::new(File::open(dir.join(path))?);
+        writer.replace_file(path, r)?;
+    }
+    if filedates_changed {
+        writer.replace_uncompressed(FILEDATES, Cursor::new(filedates))?;
+    }
+    let mut f = writer.finish()?;
+    let end = f.stream_position()?;
+    f.set_len(end)?;
+    Ok(summary)
+}
+
+/// Compares the decompressed content of `stored` with the file `src`
+fn is_unchanged(
+    options: &CreateOptions,
+    archive: &HpkArchive,
+    stored: &DirEntry,
+    src: &Path,
+    path: &Path,
+) -> HpkResult<bool> {
+    let content = archive.open_entry(stored)?;
+    let f = File::open(src)?;
+    let same = if options.cripples_lua(path) {
+        same_content(content, lua::cripple_header(f))?
+    } else {
+        same_content(content, f)?
+    };
+    Ok(same)
+}
+
+/// The `_filedates` and `_checksums` entries are maintained by `sync_dir` itself
+fn is_special(entry: &DirEntry) -> bool {
+    entry.depth() == 1
+        && !entry.is_dir()
+        && (entry.path() == Path::new(FILEDATES) || checksum::is_checksums(entry.path()))
+}
+
+/// Parses the lines `path=value` of a `_filedates` entry
+pub(crate) fn parse_filedates(data: &[u8]) -> HashMap<PathBuf, i64> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| {
+            let (path, value) = line.rsplit_once('=')?;
+            Some((PathBuf::from(path), value.parse().ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,242 @@
+//! Randomized directory trees and round trip checks for the archive writers
+//!
+//! `TreeGenerator` fills a directory with a reproducible random tree of nested
+//! directories and files. `verify_round_trip` packs a directory with `write_hpk` and
+//! compares the walked archive byte for byte with the files on disk.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), hpk::HpkError> {
+//! use hpk::testkit::{verify_round_trip, TreeGenerator};
+//!
+//! let dir = std::path::Path::new("tree");
+//! TreeGenerator::new(42).generate(dir)?;
+//!
+//! let mut options = hpk::CreateOptions::new();
+//! options.compress();
+//! verify_round_trip(&options, dir)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::{copy, walk_reader, write_hpk, CreateOptions, HpkError, HpkResult};
+
+// struct Rng {{{
+/// xorshift64* generator, good enough for test data and free of dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15 | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in `0..=max`
+    fn upto(&mut self, max: usize) -> usize {
+        (self.next_u64() % (max as u64 + 1)) as usize
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let n = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&n[..chunk.len()]);
+        }
+    }
+}
+// }}}
+
+// struct TreeGenerator {{{
+/// Generates reproducible random directory trees
+///
+/// The same seed and limits always produce the same tree. The files are a mix of
+/// empty files, random bytes which don't compress and repetitive text which does.
+///
+pub struct TreeGenerator {
+    rng: Rng,
+    max_depth: usize,
+    max_entries: usize,
+    max_file_size: usize,
+}
+
+impl TreeGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            max_depth: 3,
+            max_entries: 6,
+            max_file_size: 64 * 1024,
+        }
+    }
+
+    /// Maximum nesting of the generated directories, defaults to 3
+    pub fn max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Maximum number of entries per directory, defaults to 6
+    pub fn max_entries(&mut self, count: usize) {
+        self.max_entries = count;
+    }
+
+    /// Maximum size of the generated files, defaults to 64 KiB
+    pub fn max_file_size(&mut self, size: usize) {
+        self.max_file_size = size;
+    }
+
+    /// Creates the random tree inside of `dir` and returns the paths of the new
+    /// files and directories relative to `dir`
+    ///
+    /// `dir` is created if it doesn't exist.
+    ///
+    pub fn generate<P: AsRef<Path>>(&mut self, dir: P) -> HpkResult<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut paths = vec![];
+        self.generate_dir(dir, Path::new(""), 0, &mut paths)?;
+        Ok(paths)
+    }
+
+    fn generate_dir(
+        &mut self,
+        root: &Path,
+        dir: &Path,
+        depth: usize,
+        paths: &mut Vec<PathBuf>,
+    ) -> HpkResult<()> {
+        let count = self.rng.upto(self.max_entries);
+        let mut names = BTreeSet::new();
+        for _ in 0..count {
+            let is_dir = depth < self.max_depth && self.rng.upto(3) == 0;
+            let name = self.name(is_dir);
+            if !names.insert(name.clone()) {
+                continue;
+            }
+            let path = dir.join(name);
+            if is_dir {
+                fs::create_dir(root.join(&path))?;
+                paths.push(path.clone());
+                self.generate_dir(root, &path, depth + 1, paths)?;
+            } else {
+                fs::write(root.join(&path), self.content())?;
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&mut self, is_dir: bool) -> String {
+        const EXTENSIONS: [&str; 5] = ["lst", "xml", "bin", "txt", "dat"];
+        let len = 1 + self.rng.upto(11);
+        let mut name: String = (0..len)
+            .map(|_| (b'a' + self.rng.upto(25) as u8) as char)
+            .collect();
+        if !is_dir {
+            name.push('.');
+            name.push_str(EXTENSIONS[self.rng.upto(EXTENSIONS.len() - 1)]);
+        }
+        name
+    }
+
+    fn content(&mut self) -> Vec<u8> {
+        let size = self.rng.upto(self.max_file_size);
+        match self.rng.upto(3) {
+            0 => vec![],
+            1 => {
+                let mut buf = vec![0; size];
+                self.rng.fill(&mut buf);
+                buf
+            }
+            _ => {
+                let line = format!("line {} of a compressible file\n", self.rng.next_u64());
+                line.bytes().cycle().take(size).collect()
+            }
+        }
+    }
+}
+// }}}
+
+/// Packs `dir` with `write_hpk` and checks that walking the archive returns the same
+/// directories and files with the same content
+///
+/// The comparison is byte for byte, options which change the content like
+/// `cripple_lua_files` or add entries like the filedates file are reported as a
+/// mismatch with an `InvalidData` error.
+///
+pub fn verify_round_trip<P: AsRef<Path>>(options: &CreateOptions, dir: P) -> HpkResult<()> {
+    let dir = dir.as_ref();
+    let mut out = Cursor::new(vec![]);
+    write_hpk(options, dir, &mut out)?;
+    out.set_position(0);
+
+    let mut expected = BTreeMap::new();
+    read_tree(dir, Path::new(""), &mut expected)?;
+
+    let mismatch = |path: &Path, what: &str| {
+        let msg = format!("{}: {}", path.display(), what);
+        HpkError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+    };
+
+    let mut walk = walk_reader(out)?;
+    let mut seen = BTreeSet::new();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.depth() == 0 {
+            continue;
+        }
+        match expected.get(path) {
+            None => return Err(mismatch(path, "unexpected entry in the archive")),
+            Some(None) if !entry.is_dir() => return Err(mismatch(path, "expected a directory")),
+            Some(Some(_)) if entry.is_dir() => return Err(mismatch(path, "expected a file")),
+            Some(None) => {}
+            Some(Some(content)) => {
+                let mut buf = vec![];
+                walk.read_file(&entry, |mut r| copy(&mut r, &mut buf).map(|_| ()))?;
+                if &buf != content {
+                    return Err(mismatch(path, "content differs"));
+                }
+            }
+        }
+        seen.insert(path.to_path_buf());
+    }
+    if let Some(path) = expected.keys().find(|p| !seen.contains(*p)) {
+        return Err(mismatch(path, "missing in the archive"));
+    }
+    Ok(())
+}
+
+/// Collects the directories (`None`) and files of `root` keyed by their relative path
+fn read_tree(
+    root: &Path,
+    dir: &Path,
+    tree: &mut BTreeMap<PathBuf, Option<Vec<u8>>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            tree.insert(path.clone(), None);
+            read_tree(root, &path, tree)?;
+        } else {
+            let mut content = vec![];
+            fs::File::open(entry.path())?.read_to_end(&mut content)?;
+            tree.insert(path, Some(content));
+        }
+    }
+    Ok(())
+}
+
+// vim: fdm=marker
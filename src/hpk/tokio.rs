@@ -0,0 +1,171 @@
+//! Async access to archives for applications running on a tokio runtime
+//!
+//! Opening an archive, decoding the entries and extracting run on the blocking
+//! thread pool of the runtime, so they don't stall its worker threads.
+//!
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use ::tokio::io::{AsyncRead, ReadBuf};
+use ::tokio::sync::mpsc;
+use ::tokio::task;
+
+use crate::{DirEntry, EntryMetadata, ExtractOptions, HpkError, HpkResult};
+
+/// Number of decoded buffers an `EntryStream` holds before the decoding waits
+const STREAM_CAPACITY: usize = 4;
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// An opened hpk archive which can be shared between tasks
+///
+/// Cloning is cheap, the clones share the opened archive.
+///
+#[derive(Clone)]
+pub struct HpkArchive {
+    inner: Arc<crate::HpkArchive>,
+}
+
+impl HpkArchive {
+    pub async fn open<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        let file = file.as_ref().to_path_buf();
+        let archive = blocking(move || crate::HpkArchive::open(file)).await?;
+        Ok(HpkArchive::from(archive))
+    }
+
+    /// Returns the synchronous archive for everything which doesn't read entries
+    pub fn archive(&self) -> &crate::HpkArchive {
+        &self.inner
+    }
+
+    pub fn entries(&self) -> &[DirEntry] {
+        self.inner.entries()
+    }
+
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&DirEntry> {
+        self.inner.entry(path)
+    }
+
+    pub async fn metadata(&self, entry: &DirEntry) -> HpkResult<EntryMetadata> {
+        let (archive, entry) = (self.inner.clone(), entry.clone());
+        blocking(move || archive.metadata(&entry)).await
+    }
+
+    /// Reads the decompressed content of the file at `path`
+    pub async fn read_file<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<u8>> {
+        let (archive, path) = (self.inner.clone(), path.as_ref().to_path_buf());
+        blocking(move || archive.read_file(path)).await
+    }
+
+    /// Streams the decompressed content of a file entry
+    ///
+    /// The entry is decoded on the blocking thread pool while the stream is read.
+    /// Errors while decoding are returned by the reads of the stream. Must be called
+    /// from within a tokio runtime.
+    ///
+    pub fn open_entry(&self, entry: &DirEntry) -> HpkResult<EntryStream> {
+        if entry.is_dir() {
+            return Err(HpkError::EntryNotFound(entry.path().to_path_buf()));
+        }
+        let (archive, entry) = (self.inner.clone(), entry.clone());
+        let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+        task::spawn_blocking(move || {
+            let result = archive
+                .open_entry(&entry)
+                .map_err(into_io)
+                .and_then(|mut r| {
+                    let mut buf = vec![0; STREAM_BUFFER_SIZE];
+                    loop {
+                        let n = r.read(&mut buf)?;
+                        // stop decoding if the stream was dropped
+                        if n == 0 || tx.blocking_send(Ok(buf[..n].to_vec())).is_err() {
+                            return Ok(());
+                        }
+                    }
+                });
+            if let Err(e) = result {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        Ok(EntryStream {
+            rx,
+            buf: vec![],
+            pos: 0,
+        })
+    }
+
+    /// Extracts the archive like `hpk::extract` into `dest`
+    pub async fn extract_with<P: AsRef<Path>>(
+        &self,
+        options: ExtractOptions,
+        dest: P,
+    ) -> HpkResult<()> {
+        let (archive, dest) = (self.inner.clone(), dest.as_ref().to_path_buf());
+        blocking(move || archive.extract_with(&options, dest)).await
+    }
+
+    pub async fn extract_to<P: AsRef<Path>>(&self, dest: P) -> HpkResult<()> {
+        self.extract_with(ExtractOptions::new(), dest).await
+    }
+}
+
+impl From<crate::HpkArchive> for HpkArchive {
+    fn from(archive: crate::HpkArchive) -> Self {
+        HpkArchive {
+            inner: Arc::new(archive),
+        }
+    }
+}
+
+/// The decompressed content of a file entry, see `HpkArchive::open_entry`
+pub struct EntryStream {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for EntryStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pos == self.buf.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Ok(buf))) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = out.remaining().min(self.buf.len() - self.pos);
+        let pos = self.pos;
+        out.put_slice(&self.buf[pos..pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn blocking<F, T>(f: F) -> HpkResult<T>
+where
+    F: FnOnce() -> HpkResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => Err(HpkError::Io(io::Error::other(e))),
+    }
+}
+
+fn into_io(e: HpkError) -> io::Error {
+    match e {
+        HpkError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
@@ -0,0 +1,73 @@
+use std::io;
+use std::io::prelude::*;
+
+/// Scrambles the stored data of an archive
+///
+/// Some releases obfuscate the data region of their archives, i.e. the stored content
+/// of the files and the directory listings. The header and the fragment tables aren't
+/// transformed. `offset` is the position of the bytes relative to the start of the
+/// archive, see `HpkIter::transform` and `CreateOptions::with_transform`.
+///
+pub trait Transform: Send + Sync {
+    /// Restores the stored bytes `data` which were read at `offset`
+    fn decode(&self, offset: u64, data: &mut [u8]);
+
+    /// Scrambles the bytes `data` before they are written at `offset`
+    ///
+    /// Calls `decode` by default which fits a symmetric transform like XOR.
+    ///
+    fn encode(&self, offset: u64, data: &mut [u8]) {
+        self.decode(offset, data);
+    }
+}
+
+/// XORs the data with a repeated key which starts at the beginning of the archive
+#[derive(Clone, Debug)]
+pub struct XorKey {
+    key: Vec<u8>,
+}
+
+impl XorKey {
+    /// An empty key leaves the data unchanged
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> Self {
+        XorKey { key: key.into() }
+    }
+}
+
+impl Transform for XorKey {
+    fn decode(&self, offset: u64, data: &mut [u8]) {
+        if self.key.is_empty() {
+            return;
+        }
+        let start = (offset % self.key.len() as u64) as usize;
+        let key = self.key.iter().cycle().skip(start);
+        for (b, k) in data.iter_mut().zip(key) {
+            *b ^= k;
+        }
+    }
+}
+
+/// Encodes the written bytes, `pos` is the position relative to the start of the archive
+pub(crate) struct TransformWriter<'a, W> {
+    pub inner: W,
+    pub transform: Option<&'a dyn Transform>,
+    pub pos: u64,
+}
+
+impl<W: Write> Write for TransformWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let transform = match self.transform {
+            Some(transform) => transform,
+            None => return self.inner.write(buf),
+        };
+        let mut data = buf.to_vec();
+        transform.encode(self.pos, &mut data);
+        self.inner.write_all(&data)?;
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::prelude::*;
+
+use crate::{DirEntry, HpkArchive, HpkResult};
+
+/// Columns of `HpkArchive::print_tree`
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    sizes: bool,
+    compression: bool,
+}
+
+impl TreeOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prints the uncompressed size of the files in front of the tree
+    pub fn show_sizes(&mut self) {
+        self.sizes = true;
+    }
+
+    /// Prints the compression of the files in front of the tree
+    pub fn show_compression(&mut self) {
+        self.compression = true;
+    }
+}
+
+/// Returns for every entry whether it's the last entry of its parent directory
+///
+/// The entries are in walk order, the following entries with a greater depth are the
+/// children of an entry.
+///
+fn last_siblings(entries: &[DirEntry]) -> Vec<bool> {
+    let mut last = vec![false; entries.len()];
+    // whether a later sibling was seen for each depth
+    let mut seen: Vec<bool> = vec![];
+    for (i, entry) in entries.iter().enumerate().rev() {
+        let depth = entry.depth();
+        seen.resize(depth + 1, false);
+        last[i] = !seen[depth];
+        seen[depth] = true;
+    }
+    last
+}
+
+pub(crate) fn print_tree(
+    archive: &HpkArchive,
+    w: &mut dyn Write,
+    options: &TreeOptions,
+) -> HpkResult<()> {
+    let entries = archive.entries();
+    let last = last_siblings(entries);
+    // whether the ancestor at each depth is the last entry of its directory
+    let mut ancestors: Vec<bool> = vec![];
+    let (mut dirs, mut files) = (0, 0);
+
+    for (entry, &is_last) in entries.iter().zip(&last) {
+        let md = if entry.is_dir() {
+            None
+        } else {
+            Some(archive.metadata(entry)?)
+        };
+        if options.sizes {
+            match &md {
+                Some(md) => write!(w, "{:>10} ", md.size)?,
+                None => write!(w, "{:>10} ", "")?,
+            }
+        }
+        if options.compression {
+            match &md {
+                Some(md) => write!(w, "{:<5} ", md.compression.to_string())?,
+                None => write!(w, "{:<5} ", "")?,
+            }
+        }
+
+        let depth = entry.depth();
+        if depth == 0 {
+            writeln!(w, ".")?;
+            continue;
+        }
+        ancestors.truncate(depth - 1);
+        for &ancestor_is_last in &ancestors {
+            w.write_all(if ancestor_is_last { "    " } else { "│   " }.as_bytes())?;
+        }
+        let branch = if is_last { "└── " } else { "├── " };
+        writeln!(w, "{}{}", branch, entry.file_name().to_string_lossy())?;
+        ancestors.push(is_last);
+
+        if entry.is_dir() {
+            dirs += 1;
+        } else {
+            files += 1;
+        }
+    }
+    writeln!(w, "\n{} directories, {} files", dirs, files)?;
+    Ok(())
+}
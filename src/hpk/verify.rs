@@ -0,0 +1,150 @@
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::checksum::{self, ChecksumWriter, Checksummer, ChunkSums};
+use crate::read::{ChunkedDecoder, FragmentedReader};
+use crate::{copy, get_compression};
+use crate::{CompressionHeader, DirEntry, Fragment, HpkArchive, HpkError, HpkResult};
+
+/// What's wrong with a corrupt entry
+#[derive(Debug)]
+pub enum Corruption {
+    /// The fragment reaches beyond the end of the archive
+    OutOfBounds(Fragment),
+    /// The compression header couldn't be parsed or the content couldn't be read
+    Unreadable(HpkError),
+    /// The decompressed content doesn't have the length of the compression header
+    LengthMismatch { expected: u64, actual: u64 },
+    /// The checksum of the decompressed block `chunk` doesn't match the `_checksums` entry
+    ChecksumMismatch { chunk: usize },
+    /// The SHA-256 digest doesn't match the manifest, see `verify_sha256_manifest`
+    Sha256Mismatch,
+    /// The file of the SHA-256 manifest is missing in the archive
+    NotInArchive,
+    /// The file of the archive isn't listed in the SHA-256 manifest
+    NotInManifest,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::OutOfBounds(fragment) => write!(
+                f,
+                "fragment 0x{:X} len: {} is out of bounds",
+                fragment.offset, fragment.length
+            ),
+            Corruption::Unreadable(e) => write!(f, "unreadable: {}", e),
+            Corruption::LengthMismatch { expected, actual } => write!(
+                f,
+                "decompressed to {} bytes, expected {} bytes",
+                actual, expected
+            ),
+            Corruption::ChecksumMismatch { chunk } => {
+                write!(f, "checksum mismatch in block {}", chunk)
+            }
+            Corruption::Sha256Mismatch => write!(f, "sha256 mismatch"),
+            Corruption::NotInArchive => write!(f, "missing in the archive"),
+            Corruption::NotInManifest => write!(f, "not listed in the manifest"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CorruptEntry {
+    pub path: PathBuf,
+    pub corruption: Corruption,
+}
+
+/// Opens the archive and checks every entry, see `HpkArchive::verify`
+///
+/// An error is returned if the header or the directory tree can't be read at all.
+///
+#[cfg(feature = "fs")]
+pub fn verify<P: AsRef<Path>>(file: P) -> HpkResult<Vec<CorruptEntry>> {
+    HpkArchive::open(file)?.verify()
+}
+
+pub(crate) fn verify_archive(archive: &HpkArchive) -> HpkResult<Vec<CorruptEntry>> {
+    let len = archive.archive_len()?;
+    let mut corrupt = vec![];
+    let checksums = match archive.checksums() {
+        Ok(checksums) => checksums,
+        Err(e) => {
+            corrupt.push(CorruptEntry {
+                path: PathBuf::from(checksum::CHECKSUMS),
+                corruption: Corruption::Unreadable(e),
+            });
+            None
+        }
+    };
+
+    for entry in archive.entries() {
+        let out_of_bounds = archive.fragments()[entry.index()]
+            .iter()
+            .find(|f| !matches!(f.offset.checked_add(f.length), Some(end) if end <= len));
+        if let Some(fragment) = out_of_bounds {
+            corrupt.push(CorruptEntry {
+                path: entry.path().to_path_buf(),
+                corruption: Corruption::OutOfBounds(fragment.clone()),
+            });
+            continue;
+        }
+        if entry.is_dir() {
+            continue;
+        }
+        let expected = checksums.as_ref().and_then(|c| c.get(entry.path()));
+        if let Err(corruption) = verify_content(archive, entry, expected) {
+            corrupt.push(CorruptEntry {
+                path: entry.path().to_path_buf(),
+                corruption,
+            });
+        }
+    }
+    Ok(corrupt)
+}
+
+fn verify_content(
+    archive: &HpkArchive,
+    entry: &DirEntry,
+    sums: Option<&ChunkSums>,
+) -> Result<(), Corruption> {
+    match inflated_lengths(&mut archive.reader(entry)) {
+        Ok(Some((expected, actual))) if expected != actual => {
+            return Err(Corruption::LengthMismatch { expected, actual })
+        }
+        Ok(_) => {}
+        Err(e) => return Err(Corruption::Unreadable(e)),
+    }
+    let expected = match sums {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let mut actual = Checksummer::new(expected.chunk_size);
+    let mut w = ChecksumWriter {
+        inner: io::sink(),
+        sums: Some(&mut actual),
+    };
+    copy(&mut archive.reader(entry), &mut w).map_err(Corruption::Unreadable)?;
+    match expected.first_mismatch(&actual.finish()) {
+        Some(chunk) => Err(Corruption::ChecksumMismatch { chunk }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the declared and the actual decompressed length of a compressed file
+fn inflated_lengths<R: Read + Seek>(r: &mut FragmentedReader<R>) -> HpkResult<Option<(u64, u64)>> {
+    if !get_compression(r)?.is_compressed() {
+        return Ok(None);
+    }
+    let hdr = CompressionHeader::read_from(r.len(), r)?;
+    r.seek(SeekFrom::Start(0))?;
+    // the chunks are checked while decoding, the total length is compared here
+    let length = r.len();
+    let actual = io::copy(&mut ChunkedDecoder::new(r, length)?, &mut io::sink())?;
+    Ok(Some((u64::from(hdr.inflated_length), actual)))
+}
@@ -0,0 +1,228 @@
+//! File trees which are packed by `hpk::write_source`
+//!
+//! A `Source` lists directories, opens files and reports their metadata by paths
+//! relative to its root, the empty path is the root itself. `FsSource` reads a
+//! directory on the disk, `MemorySource` holds a tree in memory for hermetic tests and
+//! `HpkArchive` is a source as well, packing it transforms one archive into another.
+//!
+//! ```
+//! # fn main() -> Result<(), hpk::HpkError> {
+//! let mut source = hpk::vfs::MemorySource::new();
+//! source.add_file("scripts/main.lua", "return 1");
+//! source.add_dir("maps");
+//!
+//! let mut out = std::io::Cursor::new(vec![]);
+//! hpk::write_source(&hpk::CreateOptions::new(), &source, &mut out)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{HpkArchive, HpkError, HpkResult, SymlinkPolicy};
+
+/// The metadata of an entry of a `Source`
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// Length of the file content
+    pub len: u64,
+    /// Time of the last modification stored in `_filedates`, `None` is the Unix epoch
+    pub modified: Option<SystemTime>,
+}
+
+/// A tree of directories and files, see the module documentation
+pub trait Source {
+    /// Returns the names of the entries of the directory `path`
+    fn read_dir(&self, path: &Path) -> HpkResult<Vec<OsString>>;
+
+    /// Opens the file `path` for reading
+    fn open(&self, path: &Path) -> HpkResult<Box<dyn Read + '_>>;
+
+    fn metadata(&self, path: &Path) -> HpkResult<Metadata>;
+}
+
+// struct FsSource {{{
+/// A directory on the disk
+///
+/// The symbolic links below the root are handled like in `hpk::create`, a link to
+/// one of its parent directories fails with an `Other` I/O error.
+///
+pub struct FsSource {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+}
+
+impl FsSource {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        FsSource {
+            root: root.as_ref().to_path_buf(),
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+
+    pub fn set_symlink_policy(&mut self, policy: SymlinkPolicy) {
+        self.symlinks = policy;
+    }
+}
+
+impl Source for FsSource {
+    fn read_dir(&self, path: &Path) -> HpkResult<Vec<OsString>> {
+        let dir = self.root.join(path);
+        let mut names = vec![];
+        for dent in fs::read_dir(&dir)? {
+            let dent = dent?;
+            if dent.file_type()?.is_symlink() {
+                match self.symlinks {
+                    SymlinkPolicy::Skip => continue,
+                    SymlinkPolicy::Error => return Err(HpkError::Symlink(dent.path())),
+                    SymlinkPolicy::Follow => {
+                        let target = fs::canonicalize(dent.path())?;
+                        if fs::canonicalize(&dir)?.starts_with(&target) {
+                            let msg = format!("file system loop: {}", dent.path().display());
+                            return Err(HpkError::Io(io::Error::other(msg)));
+                        }
+                    }
+                }
+            }
+            names.push(dent.file_name());
+        }
+        Ok(names)
+    }
+
+    fn open(&self, path: &Path) -> HpkResult<Box<dyn Read + '_>> {
+        let file = File::open(self.root.join(path))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn metadata(&self, path: &Path) -> HpkResult<Metadata> {
+        let md = fs::metadata(self.root.join(path))?;
+        Ok(Metadata {
+            is_dir: md.is_dir(),
+            len: md.len(),
+            modified: md.modified().ok(),
+        })
+    }
+}
+// }}}
+
+// struct MemorySource {{{
+/// A tree of directories and files in memory
+#[derive(Clone, Debug, Default)]
+pub struct MemorySource {
+    /// The directories map to `None`, the root isn't stored
+    entries: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the directory `path` and its missing parent directories
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) {
+        for dir in path.as_ref().ancestors() {
+            if !dir.as_os_str().is_empty() {
+                self.entries.entry(dir.to_path_buf()).or_insert(None);
+            }
+        }
+    }
+
+    /// Adds or replaces the file `path` and adds its missing parent directories
+    pub fn add_file<P: AsRef<Path>, C: Into<Vec<u8>>>(&mut self, path: P, content: C) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries
+            .insert(path.to_path_buf(), Some(content.into()));
+    }
+
+    fn get(&self, path: &Path) -> HpkResult<Option<&[u8]>> {
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        match self.entries.get(path) {
+            Some(content) => Ok(content.as_deref()),
+            None => Err(HpkError::EntryNotFound(path.to_path_buf())),
+        }
+    }
+}
+
+impl Source for MemorySource {
+    fn read_dir(&self, path: &Path) -> HpkResult<Vec<OsString>> {
+        if self.get(path)?.is_some() {
+            return Err(HpkError::EntryNotFound(path.to_path_buf()));
+        }
+        Ok(self
+            .entries
+            .keys()
+            .filter(|p| p.parent() == Some(path))
+            .filter_map(|p| p.file_name().map(|n| n.to_os_string()))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> HpkResult<Box<dyn Read + '_>> {
+        match self.get(path)? {
+            Some(content) => Ok(Box::new(content)),
+            None => Err(HpkError::EntryNotFound(path.to_path_buf())),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> HpkResult<Metadata> {
+        let content = self.get(path)?;
+        Ok(Metadata {
+            is_dir: content.is_none(),
+            len: content.map_or(0, |c| c.len() as u64),
+            modified: None,
+        })
+    }
+}
+// }}}
+
+/// The entries of the archive, the files are decompressed while reading
+///
+/// The reserved files like `_filedates` are regular files of the source, exclude them
+/// with `CreateOptions::set_excludes` when the new archive stores its own.
+///
+impl Source for HpkArchive {
+    fn read_dir(&self, path: &Path) -> HpkResult<Vec<OsString>> {
+        let entries = HpkArchive::read_dir(self, path)?;
+        Ok(entries
+            .iter()
+            .filter_map(|e| e.path().file_name().map(|n| n.to_os_string()))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> HpkResult<Box<dyn Read + '_>> {
+        let entry = self
+            .entry(path)
+            .ok_or_else(|| HpkError::EntryNotFound(path.to_path_buf()))?;
+        Ok(Box::new(self.open_entry(entry)?))
+    }
+
+    fn metadata(&self, path: &Path) -> HpkResult<Metadata> {
+        let entry = self
+            .entry(path)
+            .ok_or_else(|| HpkError::EntryNotFound(path.to_path_buf()))?;
+        let len = if entry.is_dir() {
+            0
+        } else {
+            HpkArchive::metadata(self, entry)?.size
+        };
+        Ok(Metadata {
+            is_dir: entry.is_dir(),
+            len,
+            modified: None,
+        })
+    }
+}
+
+// vim: fdm=marker
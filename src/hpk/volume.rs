@@ -0,0 +1,238 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use crate::{HpkError, HpkResult};
+
+/// Returns the path of the volume `index` of the archive `file`,
+/// `archive.hpk` becomes `archive_000.hpk`, `archive_001.hpk`, ...
+pub fn volume_path<P: AsRef<Path>>(file: P, index: usize) -> PathBuf {
+    let file = file.as_ref();
+    let mut name = file.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_{:03}", index));
+    if let Some(ext) = file.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    file.with_file_name(name)
+}
+
+// struct VolumeWriter {{{
+/// Writes an archive into volumes of a fixed size, see `CreateOptions::split_volumes`
+///
+/// The archive is written as one stream of bytes which is cut into the volumes
+/// `archive_000.hpk`, `archive_001.hpk`, ... All volumes except the last one have
+/// exactly the volume size. `VolumeReader` chains them back together.
+///
+/// ```no_run
+/// # fn main() -> Result<(), hpk::HpkError> {
+/// let options = hpk::CreateOptions::new();
+/// // 2 GiB volumes for FAT32 flash drives
+/// let mut w = hpk::VolumeWriter::create("archive.hpk", 2 << 30)?;
+/// hpk::write_hpk(&options, "dir", &mut w)?;
+/// let volumes = w.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct VolumeWriter {
+    path: PathBuf,
+    size: u64,
+    volumes: Vec<File>,
+    pos: u64,
+    len: u64,
+}
+
+impl VolumeWriter {
+    /// Starts the volumes of the archive `file` which are at most `size` bytes long
+    pub fn create<P: AsRef<Path>>(file: P, size: u64) -> HpkResult<Self> {
+        if size == 0 {
+            return Err(HpkError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid volume size: 0",
+            )));
+        }
+        let mut w = VolumeWriter {
+            path: file.as_ref().to_path_buf(),
+            size,
+            volumes: vec![],
+            pos: 0,
+            len: 0,
+        };
+        w.open_volume(0)?;
+        Ok(w)
+    }
+
+    /// Flushes the volumes to the disk and removes the left over volumes of an older
+    /// and larger archive with the same name
+    ///
+    /// Returns the paths of the written volumes.
+    ///
+    pub fn finish(self) -> HpkResult<Vec<PathBuf>> {
+        for f in &self.volumes {
+            f.sync_all()?;
+        }
+        let mut index = self.volumes.len();
+        loop {
+            match fs::remove_file(volume_path(&self.path, index)) {
+                Ok(()) => index += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok((0..self.volumes.len())
+            .map(|i| volume_path(&self.path, i))
+            .collect())
+    }
+
+    /// Creates the volumes up to `index`, the earlier volumes are padded to the
+    /// volume size so that the offsets of the later volumes stay valid
+    fn open_volume(&mut self, index: usize) -> io::Result<&mut File> {
+        while self.volumes.len() <= index {
+            for f in &self.volumes {
+                if f.metadata()?.len() < self.size {
+                    f.set_len(self.size)?;
+                }
+            }
+            let path = volume_path(&self.path, self.volumes.len());
+            let f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            self.volumes.push(f);
+        }
+        Ok(&mut self.volumes[index])
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let index = (self.pos / self.size) as usize;
+        let offset = self.pos % self.size;
+        let n = (buf.len() as u64).min(self.size - offset) as usize;
+        let f = self.open_volume(index)?;
+        f.seek(SeekFrom::Start(offset))?;
+        let n = f.write(&buf[..n])?;
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.volumes.iter_mut().try_for_each(File::flush)
+    }
+}
+
+impl Seek for VolumeWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(self.pos, self.len, pos)?;
+        Ok(self.pos)
+    }
+}
+// }}}
+
+// struct VolumeReader {{{
+/// Reads the volumes written by `VolumeWriter` as one archive
+///
+/// ```no_run
+/// # fn main() -> Result<(), hpk::HpkError> {
+/// let r = hpk::VolumeReader::open("archive.hpk")?;
+/// let archive = hpk::HpkArchive::from_reader(r)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct VolumeReader {
+    volumes: Vec<File>,
+    /// Start offset of every volume and the total length as last element
+    starts: Vec<u64>,
+    pos: u64,
+}
+
+impl VolumeReader {
+    /// Opens the volumes `archive_000.hpk`, `archive_001.hpk`, ... of the archive
+    /// `file` until the next volume doesn't exist
+    pub fn open<P: AsRef<Path>>(file: P) -> HpkResult<Self> {
+        let file = file.as_ref();
+        let mut volumes = vec![];
+        let mut starts = vec![0];
+        loop {
+            let f = match File::open(volume_path(file, volumes.len())) {
+                Ok(f) => f,
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !volumes.is_empty() => break,
+                Err(e) => return Err(e.into()),
+            };
+            let end = starts[volumes.len()] + f.metadata()?.len();
+            starts.push(end);
+            volumes.push(f);
+        }
+        Ok(VolumeReader {
+            volumes,
+            starts,
+            pos: 0,
+        })
+    }
+
+    /// Number of the chained volumes
+    pub fn volumes(&self) -> usize {
+        self.volumes.len()
+    }
+
+    /// Total length of the volumes
+    pub fn len(&self) -> u64 {
+        self.starts[self.volumes.len()]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // the last volume which starts at or before the position
+        let index = self.starts.partition_point(|&start| start <= self.pos) - 1;
+        if index >= self.volumes.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        let offset = self.pos - self.starts[index];
+        let remaining = self.starts[index + 1] - self.pos;
+        let n = (buf.len() as u64).min(remaining) as usize;
+        let f = &mut self.volumes[index];
+        f.seek(SeekFrom::Start(offset))?;
+        let n = f.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(self.pos, self.len(), pos)?;
+        Ok(self.pos)
+    }
+}
+// }}}
+
+fn seek_position(current: u64, len: u64, pos: SeekFrom) -> io::Result<u64> {
+    let pos = match pos {
+        SeekFrom::Start(n) => Some(n),
+        SeekFrom::End(n) => len.checked_add_signed(n),
+        SeekFrom::Current(n) => current.checked_add_signed(n),
+    };
+    pos.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+// vim: fdm=marker
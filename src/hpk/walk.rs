@@ -1,13 +1,20 @@
+#[cfg(feature = "mmap")]
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+#[cfg(feature = "fs")]
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::Cursor;
 use std::io::SeekFrom;
+use std::iter::FusedIterator;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
 
-use crate::read::FragmentedReader;
+use crate::format;
+use crate::format::RawDirEntry;
+use crate::read::{spool, FragmentedReader, ReadSeek, SourceReader, Spool};
 use crate::{copy, get_compression};
-use crate::{DirEntry, Fragment, Header, HpkResult};
+use crate::{Fragment, Header, HpkError, HpkResult, Transform};
 
 macro_rules! itry {
     ($e:expr) => {
@@ -18,73 +25,147 @@ macro_rules! itry {
     };
 }
 
+/// Walks the directory tree of the hpk archive `file`
+///
+/// The returned iterator owns the opened file and yields the entries depth-first,
+/// starting with the root directory.
+///
+#[cfg(feature = "fs")]
 pub fn walk<P: AsRef<Path>>(file: P) -> HpkResult<HpkIter> {
+    walk_at(file, 0)
+}
+
+/// Walks an hpk archive which starts at `offset` inside of `file`
+///
+/// The offsets of the header and the fragment table are treated as relative to the
+/// start of the archive.
+///
+#[cfg(feature = "fs")]
+pub fn walk_at<P: AsRef<Path>>(file: P, offset: u64) -> HpkResult<HpkIter> {
     let file = file.as_ref().to_path_buf();
-    let (mut f, _tempdir) = {
-        let mut f = File::open(&file)?;
-
-        if get_compression(&mut f)?.is_compressed() {
-            let tempdir = tempfile::Builder::new().prefix("hpk").tempdir()?;
-            let tmpfile = tempdir.path().join(
-                file.file_name()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("temp.hpk"),
-            );
-
-            let fragment = Fragment::new(0, f.metadata()?.len());
-            let mut r = FragmentedReader::new(&f, &[fragment]);
-            let mut out = File::create(&tmpfile)?;
+    let f = File::open(&file)?;
+    walk_source(file, Source::File(f), offset)
+}
+
+/// Walks an hpk archive which is read from `r`
+///
+/// `HpkIter::path` returns an empty path for such an archive.
+///
+pub fn walk_reader<R>(r: R) -> HpkResult<HpkIter>
+where
+    R: Read + Seek + Send + 'static,
+{
+    walk_source(PathBuf::new(), Source::Reader(Mutex::new(Box::new(r))), 0)
+}
+
+/// The archive data is read from a file or from any other reader
+enum Source {
+    #[cfg(feature = "fs")]
+    File(File),
+    Reader(Mutex<Box<dyn ReadSeek + Send>>),
+}
+
+impl Source {
+    fn reader(&self) -> SourceReader<'_> {
+        match self {
+            #[cfg(feature = "fs")]
+            Source::File(f) => SourceReader::file(f),
+            Source::Reader(r) => SourceReader::shared(r),
+        }
+    }
+}
+
+impl From<Spool> for Source {
+    fn from(spool: Spool) -> Self {
+        #[cfg(feature = "fs")]
+        return Source::File(spool);
+        #[cfg(not(feature = "fs"))]
+        return Source::Reader(Mutex::new(Box::new(spool)));
+    }
+}
+
+fn walk_source(file: PathBuf, source: Source, offset: u64) -> HpkResult<HpkIter> {
+    let (source, offset, compressed) = {
+        let mut r = source.reader();
+        r.seek(SeekFrom::Start(offset))?;
+
+        if get_compression(&mut r)?.is_compressed() {
+            let length = r.seek(SeekFrom::End(0))?.saturating_sub(offset);
+            let fragment = Fragment::new(offset, length);
+            let mut r = FragmentedReader::new(r, &[fragment]);
+            let mut out = spool()?;
             copy(&mut r, &mut out)?;
 
-            (File::open(tmpfile)?, Some(tempdir))
+            (Source::from(out), 0, true)
         } else {
-            (f, None)
+            (source, offset, false)
         }
     };
 
+    let mut f = source.reader();
+    f.seek(SeekFrom::Start(offset))?;
     let hdr = Header::read_from(&mut f)?;
-    let mut fragments_data = Cursor::new(vec![0; hdr.fragmented_filesystem_length as usize]);
-
-    f.seek(SeekFrom::Start(hdr.fragmented_filesystem_offset))?;
-    f.read_exact(fragments_data.get_mut().as_mut_slice())?;
-
-    let mut fragments = Vec::with_capacity(hdr.filesystem_entries());
-    for _ in 0..hdr.filesystem_entries() {
-        fragments.push(Fragment::read_nth_from(
-            hdr.fragments_per_file as usize,
-            &mut fragments_data,
-        )?);
+    let end = f.seek(SeekFrom::End(0))?;
+    let table_start = offset
+        .checked_add(hdr.fragmented_filesystem_offset)
+        .ok_or(HpkError::TruncatedFragmentTable)?;
+    match table_start.checked_add(hdr.fragmented_filesystem_length) {
+        Some(n) if n <= end => {}
+        _ => return Err(HpkError::TruncatedFragmentTable),
     }
+    // the table is decoded entry by entry while walking, see `HpkIter::entry_fragments`
+    let mut table = vec![0; hdr.fragmented_filesystem_length as usize];
+    f.seek(SeekFrom::Start(table_start))?;
+    f.read_exact(&mut table)?;
 
-    let mut residual_data = Cursor::new(vec![0; (hdr.fragments_residual_count * 8) as usize]);
-
-    f.seek(SeekFrom::Start(hdr.fragments_residual_offset))?;
-    f.read_exact(residual_data.get_mut().as_mut_slice())?;
-
-    let residual_count = hdr.fragments_residual_count;
-    let residuals = Fragment::read_nth_from(residual_count as usize, &mut residual_data)?;
+    let residuals = Fragment::read_residual_from(&hdr, &mut f, offset)?;
+    let visited = vec![false; hdr.filesystem_entries()];
 
     Ok(HpkIter {
         file,
-        f,
-        compressed: _tempdir.is_some(),
+        f: source,
+        offset,
+        compressed,
         header: hdr,
         start: Some(DirEntry::new_root()),
-        fragments,
+        table,
+        fragments: OnceLock::new(),
         residuals,
         stack_list: vec![],
+        pending: None,
+        last_dir: false,
+        max_depth: usize::MAX,
+        names: NameDecoding::default(),
+        names_only: false,
+        transform: None,
+        visited,
     })
 }
 
 pub struct HpkIter {
     file: PathBuf,
-    f: File,
+    f: Source,
+    offset: u64,
     compressed: bool,
     header: Header,
     start: Option<DirEntry>,
-    pub fragments: Vec<Vec<Fragment>>,
+    /// The raw fragment table
+    table: Vec<u8>,
+    /// The decoded fragment table, see `fragments`
+    fragments: OnceLock<Vec<Vec<Fragment>>>,
     pub residuals: Vec<Fragment>,
     stack_list: Vec<DirList>,
+    /// The directory returned last, its entries are read by the next call of `next`
+    pending: Option<DirEntry>,
+    last_dir: bool,
+    max_depth: usize,
+    names: NameDecoding,
+    /// The entries don't get the path of their parent directory
+    names_only: bool,
+    /// Decodes the directory listings and the file content
+    transform: Option<Arc<dyn Transform>>,
+    /// The directory fragments which were read, a directory can't be listed twice
+    visited: Vec<bool>,
 }
 
 struct DirList {
@@ -100,6 +181,9 @@ impl Iterator for HpkIter {
                 return Some(result);
             }
         }
+        if let Some(dent) = self.pending.take() {
+            itry!(self.push(&dent));
+        }
         while !self.stack_list.is_empty() {
             match self.stack_list.last_mut().expect("bug?").next() {
                 None => self.pop(),
@@ -115,7 +199,77 @@ impl Iterator for HpkIter {
     }
 }
 
+impl FusedIterator for HpkIter {}
+
 impl HpkIter {
+    /// Doesn't descend into directories deeper than `depth`, the root has the depth 0
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Sets how the names of the entries are decoded, invalid UTF-8 is an error by default
+    pub fn name_decoding(mut self, names: NameDecoding) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Yields the entries with their names instead of their full paths
+    ///
+    /// Joining every name with the path of its parent allocates and copies the parent
+    /// for each entry, which dominates the time of listing huge archives. The parent
+    /// of an entry follows from the order and the depth of the entries instead.
+    ///
+    /// `HpkArchive::from_walk` expects full paths, don't use it with such a walk.
+    ///
+    pub fn names_only(mut self) -> Self {
+        self.names_only = true;
+        self
+    }
+
+    /// Decodes the stored data of a scrambled archive with `transform`
+    ///
+    /// The directory listings and the content of the files are decoded while reading,
+    /// e.g. with `XorKey` once the key of the archive is known.
+    ///
+    pub fn transform<T: Transform + 'static>(self, transform: T) -> Self {
+        self.shared_transform(Some(Arc::new(transform)))
+    }
+
+    pub(crate) fn shared_transform(mut self, transform: Option<Arc<dyn Transform>>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Yields only the entries for which `predicate` returns `true`
+    ///
+    /// The entries of a rejected directory are skipped without reading its
+    /// directory fragment.
+    ///
+    pub fn filter_entry<P>(self, predicate: P) -> FilterEntry<P>
+    where
+        P: FnMut(&DirEntry) -> bool,
+    {
+        FilterEntry {
+            it: self,
+            predicate,
+        }
+    }
+
+    /// Skips the entries of the directory which was returned last
+    ///
+    /// If the last entry was a file, the remaining entries of its parent directory
+    /// are skipped.
+    ///
+    pub fn skip_dir(&mut self) {
+        if self.last_dir {
+            self.last_dir = false;
+            self.pending = None;
+        } else {
+            self.stack_list.pop();
+        }
+    }
+
     pub fn path(&self) -> &Path {
         &self.file
     }
@@ -128,41 +282,202 @@ impl HpkIter {
         &self.header
     }
 
+    /// Number of entries in the fragment table including the root directory
+    ///
+    /// The count is known without reading the directory tree, unreferenced fragments
+    /// of removed entries are included.
+    ///
+    pub fn entry_count(&self) -> usize {
+        self.header.filesystem_entries()
+    }
+
+    /// The fragments of all entries indexed by `DirEntry::index`
+    ///
+    /// The walk itself only decodes the fragments of the visited entries, the whole
+    /// table is decoded by the first call.
+    ///
+    pub fn fragments(&self) -> &[Vec<Fragment>] {
+        self.fragments.get_or_init(|| {
+            (0..self.entry_count())
+                .filter_map(|index| self.entry_fragments(index))
+                .collect()
+        })
+    }
+
+    /// Decodes the fragments of the entry `index`, `None` if it's out of bounds
+    fn entry_fragments(&self, index: usize) -> Option<Vec<Fragment>> {
+        if let Some(fragments) = self.fragments.get() {
+            return fragments.get(index).cloned();
+        }
+        let layout = self.header.layout();
+        let count = self.header.fragments_per_file as usize;
+        let size = layout.fragment_size() as usize * count;
+        let start = index.checked_mul(size)?;
+        let data = self.table.get(start..start.checked_add(size)?)?;
+        Fragment::parse_n(count, data, layout).ok()
+    }
+
+    /// Returns the entries of the directory at `path`, an empty path is the root
+    ///
+    /// Only the directory fragments along `path` are read, the rest of the tree isn't
+    /// parsed. This allows to list the directories of a huge archive on demand.
+    ///
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> HpkResult<Vec<DirEntry>> {
+        let path = path.as_ref();
+        let not_found = || HpkError::EntryNotFound(path.to_path_buf());
+        let mut dir = DirEntry::new_root();
+        for name in path.iter() {
+            dir = self
+                .dir_entries(&dir)?
+                .into_iter()
+                .find(|e| e.file_name() == name)
+                .filter(DirEntry::is_dir)
+                .ok_or_else(not_found)?;
+        }
+        self.dir_entries(&dir)
+    }
+
     pub fn read_file<F>(&self, entry: &DirEntry, op: F) -> HpkResult<()>
     where
-        F: FnOnce(FragmentedReader<&File>) -> HpkResult<()>,
+        F: FnOnce(FragmentedReader<SourceReader<'_>>) -> HpkResult<()>,
     {
         if !entry.is_dir() {
-            let fragments = &self.fragments[entry.index()];
-            let fragments: Vec<_> = fragments.to_vec();
-            let r = FragmentedReader::new(&self.f, &fragments);
-            op(r)?;
+            op(self.fragment_reader(entry))?;
         }
         Ok(())
     }
 
+    pub(crate) fn fragment_reader(&self, entry: &DirEntry) -> FragmentedReader<SourceReader<'_>> {
+        self.fragment_reader_from(self.f.reader(), entry)
+    }
+
+    /// Creates a reader for the fragments of `entry` on top of another view of the file
+    pub(crate) fn fragment_reader_from<T>(&self, inner: T, entry: &DirEntry) -> FragmentedReader<T>
+    where
+        T: Read + Seek,
+    {
+        let fragments: Vec<_> = self
+            .entry_fragments(entry.index())
+            .unwrap_or_default()
+            .iter()
+            .map(|f| Fragment::new(self.offset.saturating_add(f.offset), f.length))
+            .collect();
+        FragmentedReader::new(inner, &fragments).with_transform(self.transform.clone(), self.offset)
+    }
+
+    /// Creates a reader for the stored bytes of `fragment`, a scrambled archive is decoded
+    pub(crate) fn stored_reader(&self, fragment: &Fragment) -> FragmentedReader<SourceReader<'_>> {
+        let fragment = Fragment::new(self.offset.saturating_add(fragment.offset), fragment.length);
+        FragmentedReader::new(self.f.reader(), &[fragment])
+            .with_transform(self.transform.clone(), self.offset)
+    }
+
+    /// Creates a reader for the stored bytes at `offset` of the archive
+    #[cfg(feature = "sign")]
+    pub(crate) fn raw_reader(
+        &self,
+        offset: u64,
+        length: u64,
+    ) -> FragmentedReader<SourceReader<'_>> {
+        let fragment = Fragment::new(self.offset.saturating_add(offset), length);
+        FragmentedReader::new(self.f.reader(), &[fragment])
+    }
+
+    /// The range of the file holding `length` bytes at `offset` of the stored content
+    /// of `entry`, `None` if the bytes span several fragments
+    #[cfg(feature = "mmap")]
+    pub(crate) fn source_range(
+        &self,
+        entry: &DirEntry,
+        offset: u64,
+        length: u64,
+    ) -> Option<std::ops::Range<usize>> {
+        // the stored bytes of a scrambled archive aren't the content
+        if self.transform.is_some() {
+            return None;
+        }
+        let mut pos = offset;
+        for f in self.entry_fragments(entry.index())? {
+            if pos < f.length {
+                if pos.checked_add(length)? > f.length {
+                    return None;
+                }
+                let start = self.offset.checked_add(f.offset)?.checked_add(pos)?;
+                let start = usize::try_from(start).ok()?;
+                return Some(start..start.checked_add(usize::try_from(length).ok()?)?);
+            }
+            pos -= f.length;
+        }
+        None
+    }
+
+    pub(crate) fn transform_ref(&self) -> Option<&Arc<dyn Transform>> {
+        self.transform.as_ref()
+    }
+
+    /// Length of the archive data starting at its offset
+    pub(crate) fn archive_len(&self) -> HpkResult<u64> {
+        let end = self.f.reader().seek(SeekFrom::End(0))?;
+        Ok(end.saturating_sub(self.offset))
+    }
+
+    /// The opened archive file or the decompressed temporary file
+    #[cfg(feature = "mmap")]
+    pub(crate) fn file(&self) -> Option<&File> {
+        match &self.f {
+            Source::File(f) => Some(f),
+            Source::Reader(_) => None,
+        }
+    }
+
     fn handle_entry(&mut self, dent: DirEntry) -> Option<HpkResult<DirEntry>> {
-        if dent.is_dir() {
-            itry!(self.push(&dent));
+        if dent.index() >= self.entry_count() {
+            return Some(Err(HpkError::InvalidFragmentIndex));
+        }
+        self.last_dir = dent.is_dir();
+        if dent.is_dir() && dent.depth() < self.max_depth {
+            self.pending = Some(DirEntry::new_dir(dent.path(), dent.index(), dent.depth()));
         }
         Some(Ok(dent))
     }
 
     fn push(&mut self, dent: &DirEntry) -> HpkResult<()> {
-        let fragment = &self.fragments[dent.index()][0];
-        let mut dir_entries = Cursor::new(vec![0; fragment.length as usize]);
+        // a directory which contains one of its parents would be walked forever
+        if std::mem::replace(&mut self.visited[dent.index()], true) {
+            return Err(HpkError::InvalidFragmentIndex);
+        }
+        let entries = self.dir_entries(dent)?;
+        self.stack_list.push(DirList { entries });
+        Ok(())
+    }
 
-        self.f.seek(SeekFrom::Start(fragment.offset))?;
-        self.f
-            .read_exact(&mut dir_entries.get_mut().as_mut_slice())?;
+    /// Reads the entries of the directory fragment of `dent`
+    fn dir_entries(&self, dent: &DirEntry) -> HpkResult<Vec<DirEntry>> {
+        let fragment = self
+            .entry_fragments(dent.index())
+            .and_then(|f| f.into_iter().next())
+            .ok_or(HpkError::InvalidFragmentIndex)?;
+        match fragment.offset.checked_add(fragment.length) {
+            Some(end) if end <= self.archive_len()? => {}
+            _ => return Err(HpkError::FragmentOutOfBounds(dent.index())),
+        }
+        let mut data = vec![0; fragment.length as usize];
 
-        let mut list = vec![];
-        while dir_entries.position() < fragment.length {
-            let entry = DirEntry::read_from(dent.path(), dent.depth + 1, &mut dir_entries)?;
-            list.push(entry);
+        let mut f = self.f.reader();
+        f.seek(SeekFrom::Start(self.offset + fragment.offset))?;
+        f.read_exact(&mut data)?;
+        if let Some(transform) = &self.transform {
+            transform.decode(fragment.offset, &mut data);
         }
-        self.stack_list.push(DirList { entries: list });
-        Ok(())
+
+        let parent = if self.names_only {
+            Path::new("")
+        } else {
+            dent.path()
+        };
+        format::dir_entries(&data, self.header.layout())
+            .map(|raw| DirEntry::from_raw(parent, dent.depth + 1, raw?, self.names))
+            .collect()
     }
 
     fn pop(&mut self) {
@@ -170,6 +485,47 @@ impl HpkIter {
     }
 }
 
+/// An iterator which prunes the walk with a predicate, see `HpkIter::filter_entry`
+pub struct FilterEntry<P> {
+    it: HpkIter,
+    predicate: P,
+}
+
+impl<P> Iterator for FilterEntry<P>
+where
+    P: FnMut(&DirEntry) -> bool,
+{
+    type Item = HpkResult<DirEntry>;
+
+    fn next(&mut self) -> Option<HpkResult<DirEntry>> {
+        loop {
+            let dent = itry!(self.it.next()?);
+            if (self.predicate)(&dent) {
+                return Some(Ok(dent));
+            }
+            if dent.is_dir() {
+                self.it.skip_dir();
+            }
+        }
+    }
+}
+
+impl<P> FusedIterator for FilterEntry<P> where P: FnMut(&DirEntry) -> bool {}
+
+impl<P> FilterEntry<P> {
+    /// See `HpkIter::skip_dir`
+    pub fn skip_dir(&mut self) {
+        self.it.skip_dir();
+    }
+
+    pub fn read_file<F>(&self, entry: &DirEntry, op: F) -> HpkResult<()>
+    where
+        F: FnOnce(FragmentedReader<SourceReader<'_>>) -> HpkResult<()>,
+    {
+        self.it.read_file(entry, op)
+    }
+}
+
 impl Iterator for DirList {
     type Item = HpkResult<DirEntry>;
 
@@ -181,3 +537,173 @@ impl Iterator for DirList {
         }
     }
 }
+
+// enum NameDecoding {{{
+/// How the names of the directory entries are decoded
+///
+/// Some archives contain names in legacy encodings like CP-1251 or Latin-1 instead
+/// of UTF-8.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NameDecoding {
+    /// Invalid UTF-8 fails with `HpkError::InvalidDirEntryName`
+    #[default]
+    Strict,
+    /// Invalid UTF-8 sequences are replaced with U+FFFD
+    Lossy,
+    /// The bytes are used as they are, only supported on Unix and `Lossy` on
+    /// other platforms
+    Raw,
+}
+
+impl NameDecoding {
+    pub(crate) fn decode(self, buf: Vec<u8>) -> HpkResult<OsString> {
+        match self {
+            NameDecoding::Strict => String::from_utf8(buf)
+                .map(OsString::from)
+                .map_err(|_| HpkError::InvalidDirEntryName),
+            NameDecoding::Lossy => Ok(String::from_utf8_lossy(&buf).into_owned().into()),
+            #[cfg(unix)]
+            NameDecoding::Raw => Ok(std::os::unix::ffi::OsStringExt::from_vec(buf)),
+            #[cfg(not(unix))]
+            NameDecoding::Raw => NameDecoding::Lossy.decode(buf),
+        }
+    }
+}
+// }}}
+
+// enum FileType {{{
+#[derive(Clone, Copy)]
+enum FileType {
+    Dir(usize),
+    File(usize),
+}
+// }}}
+
+// struct DirEntry {{{
+/// An entry of the directory tree, the root has an empty path
+#[derive(Clone)]
+pub struct DirEntry {
+    path: PathBuf,
+    ft: FileType,
+    depth: usize,
+}
+
+impl DirEntry {
+    /// Path of the entry relative to the root of the archive
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        self.path
+            .file_name()
+            .unwrap_or_else(|| self.path.as_os_str())
+    }
+
+    /// 0-based index of the entry's fragments, see `HpkIter::fragments`
+    pub fn index(&self) -> usize {
+        match self.ft {
+            FileType::Dir(idx) => idx,
+            FileType::File(idx) => idx,
+        }
+    }
+
+    /// Number of parent directories, 0 for the root
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_dir(&self) -> bool {
+        std::matches!(self.ft, FileType::Dir(_))
+    }
+
+    pub(crate) fn new_root() -> Self {
+        DirEntry {
+            path: PathBuf::new(),
+            ft: FileType::Dir(0),
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn new_dir<P: AsRef<Path>>(path: P, index: usize, depth: usize) -> Self {
+        DirEntry {
+            path: path.as_ref().to_path_buf(),
+            ft: FileType::Dir(index),
+            depth,
+        }
+    }
+
+    pub(crate) fn from_raw(
+        parent: &Path,
+        depth: usize,
+        raw: RawDirEntry<'_>,
+        names: NameDecoding,
+    ) -> HpkResult<DirEntry> {
+        let ft = if raw.is_dir {
+            FileType::Dir(raw.index)
+        } else {
+            FileType::File(raw.index)
+        };
+        let name = names.decode(raw.name.to_vec())?;
+        // the decoded name is reused without copying for the entries of the root
+        let path = if parent.as_os_str().is_empty() {
+            PathBuf::from(name)
+        } else {
+            parent.join(name)
+        };
+
+        Ok(DirEntry { path, ft, depth })
+    }
+}
+// }}}
+
+// Tests {{{
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::{CompressOptions, CreateOptions, HpkArchive, HpkWriter};
+
+    #[test]
+    fn walk_compressed_reader() {
+        let options = CreateOptions::new();
+        let mut w = HpkWriter::new(&options, Cursor::new(vec![])).unwrap();
+        w.add_file("folder/a.lst", &b"Hello World"[..]).unwrap();
+        let data = w.finish().unwrap().into_inner();
+        let mut compressed = vec![];
+        crate::compress(&CompressOptions::default(), &mut &data[..], &mut compressed).unwrap();
+
+        let walk = walk_reader(Cursor::new(compressed)).unwrap();
+        assert!(walk.is_compressed());
+        let archive = HpkArchive::from_walk(walk).unwrap();
+        assert_eq!(archive.read_file("folder/a.lst").unwrap(), b"Hello World");
+    }
+
+    #[test]
+    fn walk_decodes_fragments_lazily() {
+        let options = CreateOptions::new();
+        let mut w = HpkWriter::new(&options, Cursor::new(vec![])).unwrap();
+        w.add_file("folder/a.lst", &b"Hello World"[..]).unwrap();
+        w.add_file("b.txt", &b"b"[..]).unwrap();
+        let data = w.finish().unwrap().into_inner();
+
+        let mut walk = walk_reader(Cursor::new(data)).unwrap();
+        let entries = walk.by_ref().collect::<HpkResult<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(walk.fragments.get().is_none());
+
+        let a = entries
+            .iter()
+            .find(|e| e.path().ends_with("a.lst"))
+            .unwrap();
+        assert_eq!(walk.fragments().len(), walk.entry_count());
+        let length = walk.entry_fragments(a.index()).unwrap()[0].length;
+        assert_eq!(walk.fragments()[a.index()][0].length, length);
+        assert!(walk.entry_fragments(walk.entry_count()).is_none());
+    }
+}
+// }}}
+
+// vim: fdm=marker
@@ -0,0 +1,74 @@
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::{create, sync_dir, CancelToken, CreateOptions, HpkError, HpkResult, SyncSummary};
+
+/// Time without further changes before the archive is updated
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+/// Interval of checking the `CancelToken` while waiting for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Updates the archive `file` whenever the files in `dir` change
+///
+/// The archive is created if it doesn't exist, otherwise it's synchronized with the
+/// directory before watching, see `sync_dir`. A burst of changes like saving several
+/// files results in a single update. `on_sync` is called with the result of every
+/// update, an empty summary reports the creation. A failed update doesn't stop the
+/// watching.
+///
+/// Runs until the `CancelToken` of `options` is cancelled, see
+/// `CreateOptions::set_cancel_token`. The archive must not be inside of `dir`.
+///
+pub fn watch<D, P, F>(options: &CreateOptions, dir: D, file: P, mut on_sync: F) -> HpkResult<()>
+where
+    D: AsRef<Path>,
+    P: AsRef<Path>,
+    F: FnMut(HpkResult<SyncSummary>),
+{
+    let (dir, file) = (dir.as_ref(), file.as_ref());
+    let cancel = options.compress_options.cancel.as_ref();
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if parent.canonicalize()?.starts_with(dir.canonicalize()?) {
+        return Err(HpkError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the archive is inside of the watched directory",
+        )));
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+
+    if file.exists() {
+        on_sync(sync_dir(options, dir, file));
+    } else {
+        on_sync(create(options, dir, file).map(|()| SyncSummary::default()));
+    }
+
+    let mut changed: Option<Instant> = None;
+    while !cancel.is_some_and(CancelToken::is_cancelled) {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            // reading the files doesn't change them
+            Ok(Ok(event)) if !matches!(event.kind, EventKind::Access(_)) => {
+                changed = Some(Instant::now());
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => on_sync(Err(e.into())),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(HpkError::Io(io::Error::other("the watcher stopped")));
+            }
+        }
+        if changed.is_some_and(|t| t.elapsed() >= SETTLE_TIME) {
+            changed = None;
+            on_sync(sync_dir(options, dir, file));
+        }
+    }
+    Ok(())
+}
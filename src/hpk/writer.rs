@@ -0,0 +1,801 @@
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+use std::path::{Component, Path};
+
+#[cfg(feature = "fs")]
+use crate::checksum::ChunkSums;
+use crate::checksum::{self, ChecksumMap, ChecksumReader, Checksummer};
+#[cfg(feature = "fs")]
+use crate::compression::{EncodeQueue, EncodedFile};
+use crate::format::{self, Layout, RawDirEntry};
+use crate::progress::ProgressReader;
+use crate::read::{read_exact_vec, EntryReader, FragmentedReader};
+#[cfg(feature = "sign")]
+use crate::sign::{self, SIGNATURE, SIGNATURE_LENGTH};
+use crate::transform::{Transform, TransformWriter};
+use crate::{compress, lua};
+use crate::{CompressOptions, Compression, CreateOptions, DirEntry, Fragment, Header};
+use crate::{HpkError, HpkResult, NameDecoding};
+
+/// An entry of the directory tree with its fragment index, new directories get an
+/// index when the tree is written
+enum Node {
+    File(usize),
+    Dir(Option<usize>, Vec<(String, Node)>),
+}
+
+/// A file of `HpkWriter::queue_file` which waits in an `EncodeQueue`
+#[cfg(feature = "fs")]
+pub(crate) struct QueuedFile {
+    pub(crate) path: PathBuf,
+    sums: Option<ChunkSums>,
+}
+
+/// Builds an hpk archive from files which are added one by one
+///
+/// The content of a file is written immediately, the directory tree, the fragment
+/// table and the header are written by `finish`. Entries of a directory are stored
+/// in the order they are added.
+///
+/// `CreateOptions::compress` is ignored, the finished archive can be compressed
+/// with `hpk::compress`.
+///
+pub struct HpkWriter<'a, W: Write + Seek> {
+    options: &'a CreateOptions,
+    w: Output<W>,
+    start: u64,
+    layout: Layout,
+    data_offset: u32,
+    fragments: Vec<Fragment>,
+    root: Vec<(String, Node)>,
+    /// Fragment indices of the directories written by `finish`
+    dirs: Vec<usize>,
+    /// Checksums of the added files, written as `_checksums` by `finish`
+    checksums: Option<ChecksumMap>,
+}
+
+impl<'a, W: Write + Seek> HpkWriter<'a, W> {
+    /// Starts a new archive at the current position of `w`
+    pub fn new(options: &'a CreateOptions, mut w: W) -> HpkResult<Self> {
+        let start = w.stream_position()?;
+        let layout = options.layout();
+        let header_length = u32::from(layout.header_length());
+        let data_offset = options.data_offset.unwrap_or(header_length);
+        if data_offset < header_length {
+            return Err(HpkError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid data offset: {}", data_offset),
+            )));
+        }
+        check_alignment(options)?;
+        w.seek(SeekFrom::Start(start + u64::from(header_length)))?;
+        let mut w = Output {
+            inner: w,
+            #[cfg(feature = "sign")]
+            signer: options.signing_key.as_ref().map(sign::signer),
+        };
+        io::copy(
+            &mut io::repeat(0).take(u64::from(data_offset - header_length)),
+            &mut w,
+        )?;
+        Ok(HpkWriter {
+            options,
+            w,
+            start,
+            layout,
+            data_offset,
+            // the root directory is always the first fragment
+            fragments: vec![Fragment::new(0, 0)],
+            root: vec![],
+            dirs: vec![],
+            checksums: if options.checksums {
+                Some(ChecksumMap::new())
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Adds the directory `path` and all its missing parent directories
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> HpkResult<()> {
+        let names = split_new_path(path.as_ref())?;
+        dir_entries(&mut self.root, &names)?;
+        Ok(())
+    }
+
+    /// Adds the file `path` with the content of `r`
+    ///
+    /// The content is compressed and the lua header is crippled like in `hpk::create`.
+    ///
+    pub fn add_file<P: AsRef<Path>, R: Read>(&mut self, path: P, r: R) -> HpkResult<()> {
+        let path = path.as_ref();
+        let compressor = self.compressor(path);
+        self.add_entry(path, r, compressor, false)
+    }
+
+    /// Adds the file `path` with the content of `r` compressed with `compression`
+    /// instead of the encoder and the extensions of the options
+    ///
+    /// `Compression::None` stores the content uncompressed.
+    ///
+    pub fn add_file_with<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        r: R,
+        compression: Compression,
+    ) -> HpkResult<()> {
+        let compressor = Some(compression).filter(Compression::is_compressed);
+        self.add_entry(path.as_ref(), r, compressor, false)
+    }
+
+    /// Adds the file `path` or replaces the content of an existing file
+    ///
+    /// A replaced file keeps its fragment index, the old content is left unreferenced.
+    ///
+    pub fn replace_file<P: AsRef<Path>, R: Read>(&mut self, path: P, r: R) -> HpkResult<()> {
+        let path = path.as_ref();
+        let compressor = self.compressor(path);
+        self.add_entry(path, r, compressor, true)
+    }
+
+    /// Adds the file `path` which shares the stored content of the file `target`
+    ///
+    /// The new entry gets its own fragment index pointing at the same data, replacing
+    /// one of the files later doesn't change the other one.
+    ///
+    pub fn add_link<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        path: P,
+        target: Q,
+    ) -> HpkResult<()> {
+        let (path, target) = (path.as_ref(), target.as_ref());
+        let index = find_file(&self.root, target)
+            .ok_or_else(|| HpkError::EntryNotFound(target.to_path_buf()))?;
+        let fragment = self.fragments[index - 1].clone();
+        self.insert_file(path, fragment)?;
+        self.link_checksums(path, target);
+        Ok(())
+    }
+
+    /// Adds the file `path` which stores `bytes` as they are
+    ///
+    /// The bytes aren't compressed, checksummed or aligned, a compressed content read by
+    /// `HpkArchive::read_fragment_raw` stays compressed. Only the transform of the
+    /// options is applied. Returns the fragment of the stored bytes.
+    ///
+    pub fn write_fragment_raw<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        bytes: &[u8],
+    ) -> HpkResult<Fragment> {
+        let path = path.as_ref();
+        self.insert_file(path, Fragment::new(0, 0))?;
+        let position = self.w.stream_position()? - self.start;
+        let mut w = TransformWriter {
+            inner: &mut self.w,
+            transform: self.options.transform.as_deref(),
+            pos: position,
+        };
+        w.write_all(bytes)?;
+        let fragment = Fragment::new(position, bytes.len() as u64);
+        let last = self.fragments.len() - 1;
+        self.fragments[last] = fragment.clone();
+        Ok(fragment)
+    }
+
+    /// Adds the file `path` without content, the entry keeps its position in the
+    /// directory when `replace_file` or `replace_link` store the content later
+    #[cfg(feature = "fs")]
+    pub(crate) fn reserve_file(&mut self, path: &Path) -> HpkResult<()> {
+        self.insert_file(path, Fragment::new(0, 0))
+    }
+
+    /// Points the existing file `path` at the stored content of the file `target`
+    #[cfg(feature = "fs")]
+    pub(crate) fn replace_link(&mut self, path: &Path, target: &Path) -> HpkResult<()> {
+        let index = find_file(&self.root, target)
+            .ok_or_else(|| HpkError::EntryNotFound(target.to_path_buf()))?;
+        let existing = find_file(&self.root, path)
+            .ok_or_else(|| HpkError::EntryNotFound(path.to_path_buf()))?;
+        self.fragments[existing - 1] = self.fragments[index - 1].clone();
+        self.link_checksums(path, target);
+        Ok(())
+    }
+
+    fn link_checksums(&mut self, path: &Path, target: &Path) {
+        if let Some(checksums) = &mut self.checksums {
+            if let Some(sums) = checksums.get(target).cloned() {
+                checksums.insert(path.to_path_buf(), sums);
+            }
+        }
+    }
+
+    /// Adds the new file `path` with the stored content at `fragment`
+    fn insert_file(&mut self, path: &Path, fragment: Fragment) -> HpkResult<()> {
+        let mut names = split_new_path(path)?;
+        let name = names.pop().ok_or(HpkError::InvalidDirEntryName)?;
+        let entries = dir_entries(&mut self.root, &names)?;
+        if entries.iter().any(|(n, _)| n == name) {
+            return Err(HpkError::DuplicateEntry(path.to_path_buf()));
+        }
+        self.fragments.push(fragment);
+        entries.push((name.to_string(), Node::File(self.fragments.len())));
+        Ok(())
+    }
+
+    /// Removes the file or the directory `path` with all its entries
+    ///
+    /// The content stays in the archive until it's compacted, see
+    /// `HpkArchive::compact_into`.
+    ///
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> HpkResult<()> {
+        let path = path.as_ref();
+        let not_found = || HpkError::EntryNotFound(path.to_path_buf());
+        let mut names = split_path(path)?;
+        let name = names.pop().ok_or_else(not_found)?;
+        let mut entries = &mut self.root;
+        for parent in names {
+            entries = match entries.iter_mut().find(|(n, _)| n == parent) {
+                Some((_, Node::Dir(_, entries))) => entries,
+                _ => return Err(not_found()),
+            };
+        }
+        let pos = entries
+            .iter()
+            .position(|(n, _)| n == name)
+            .ok_or_else(not_found)?;
+        entries.remove(pos);
+        Ok(())
+    }
+
+    pub(crate) fn add_uncompressed<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        r: R,
+    ) -> HpkResult<()> {
+        self.add_entry(path.as_ref(), r, None, false)
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn replace_uncompressed<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        r: R,
+    ) -> HpkResult<()> {
+        self.add_entry(path.as_ref(), r, None, true)
+    }
+
+    /// Returns the encoder of the options if the file `path` should be compressed
+    fn compressor(&self, path: &Path) -> Option<Compression> {
+        if self.options.should_compress(path) {
+            Some(self.options.compress_options.compressor)
+        } else {
+            None
+        }
+    }
+
+    fn add_entry<R: Read>(
+        &mut self,
+        path: &Path,
+        r: R,
+        compressor: Option<Compression>,
+        replace: bool,
+    ) -> HpkResult<()> {
+        let options = self.options;
+        let mut r = ProgressReader {
+            inner: r,
+            progress: options.progress.as_deref(),
+        };
+        let mut sums = self
+            .checksums
+            .as_ref()
+            .map(|_| Checksummer::new(options.compress_options.chunk_size));
+        self.put_entry(path, replace, compressor.is_none(), |mut w| {
+            if options.cripples_lua(path) {
+                let r = lua::cripple_header(&mut r);
+                write_content(options, r, &mut w, compressor, sums.as_mut())
+            } else {
+                write_content(options, &mut r, &mut w, compressor, sums.as_mut())
+            }
+        })?;
+        if let (Some(checksums), Some(sums)) = (&mut self.checksums, sums) {
+            checksums.insert(path.to_path_buf(), sums.finish());
+        }
+        Ok(())
+    }
+
+    /// Reads the reserved file `path` from `r` and queues it for compression, the
+    /// file is stored by `replace_encoded` when `queue` returns it
+    #[cfg(feature = "fs")]
+    pub(crate) fn queue_file<R: Read>(
+        &self,
+        queue: &mut EncodeQueue<'_, QueuedFile>,
+        path: &Path,
+        r: R,
+    ) -> HpkResult<()> {
+        let options = self.options;
+        let compressor = options.compress_options.compressor;
+        let mut r = ProgressReader {
+            inner: r,
+            progress: options.progress.as_deref(),
+        };
+        let mut sums = self
+            .checksums
+            .as_ref()
+            .map(|_| Checksummer::new(options.compress_options.chunk_size));
+        let file = QueuedFile {
+            path: path.to_path_buf(),
+            sums: None,
+        };
+        let file = if options.cripples_lua(path) {
+            let r = lua::cripple_header(&mut r);
+            let mut r = ChecksumReader {
+                inner: r,
+                sums: sums.as_mut(),
+            };
+            queue.push(file, compressor, &mut r)?
+        } else {
+            let mut r = ChecksumReader {
+                inner: r,
+                sums: sums.as_mut(),
+            };
+            queue.push(file, compressor, &mut r)?
+        };
+        file.sums = sums.map(Checksummer::finish);
+        Ok(())
+    }
+
+    /// Stores a file of `queue_file` with its encoded chunks
+    #[cfg(feature = "fs")]
+    pub(crate) fn replace_encoded(
+        &mut self,
+        file: QueuedFile,
+        encoded: EncodedFile,
+    ) -> HpkResult<()> {
+        self.put_entry(&file.path, true, false, |w| encoded.write_to(w))?;
+        if let (Some(checksums), Some(sums)) = (&mut self.checksums, file.sums) {
+            checksums.insert(file.path, sums);
+        }
+        Ok(())
+    }
+
+    /// Adds the file `path` with the content stored by `write`, the content is padded to
+    /// the alignment of the options if `align` is set
+    fn put_entry<F>(&mut self, path: &Path, replace: bool, align: bool, write: F) -> HpkResult<()>
+    where
+        F: FnOnce(&mut dyn Write) -> HpkResult<u64>,
+    {
+        let mut names = split_new_path(path)?;
+        let name = names.pop().ok_or(HpkError::InvalidDirEntryName)?;
+        let entries = dir_entries(&mut self.root, &names)?;
+        let existing = match entries.iter().find(|(n, _)| n == name) {
+            None => None,
+            Some((_, Node::File(index))) if replace => Some(*index),
+            Some(_) => return Err(HpkError::DuplicateEntry(path.to_path_buf())),
+        };
+
+        let options = self.options;
+        let mut position = self.w.stream_position()? - self.start;
+        if let (true, Some(alignment)) = (align, options.alignment) {
+            let padding = (alignment - position % alignment) % alignment;
+            io::copy(&mut io::repeat(0).take(padding), &mut self.w)?;
+            position += padding;
+        }
+        let mut w = TransformWriter {
+            inner: &mut self.w,
+            transform: options.transform.as_deref(),
+            pos: position,
+        };
+        let n = write(&mut w)?;
+
+        let fragment = Fragment::new(position, n);
+        match existing {
+            Some(index) => self.fragments[index - 1] = fragment,
+            None => {
+                self.fragments.push(fragment);
+                entries.push((name.to_string(), Node::File(self.fragments.len())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the directory tree, the fragment table and the header
+    ///
+    /// Returns the inner writer positioned at the end of the archive.
+    ///
+    pub fn finish(mut self) -> HpkResult<W> {
+        if let Some(mut checksums) = self.checksums.take() {
+            checksums.retain(|path, _| find_file(&self.root, path).is_some());
+            let mut buf = vec![];
+            checksum::write(&checksums, &mut buf)?;
+            self.add_entry(Path::new(checksum::CHECKSUMS), Cursor::new(buf), None, true)?;
+        }
+        #[cfg(feature = "sign")]
+        let signature = match self.w.signer {
+            Some(_) => Some(self.add_signature()?),
+            None => None,
+        };
+        let root = std::mem::take(&mut self.root);
+        self.fragments[0] = self.write_dir(root)?;
+        self.dirs.push(1);
+
+        let mut is_dir = vec![false; self.fragments.len()];
+        for &index in &self.dirs {
+            is_dir[index - 1] = true;
+        }
+        let layout = self.layout;
+        let per_file = self.options.fragments_per_file;
+        let fragmented_filesystem_offset = self.w.stream_position()? - self.start;
+        let fragmented_filesystem_length =
+            self.fragments.len() as u64 * layout.fragment_size() * u64::from(per_file);
+        for (fragment, &is_dir) in self.fragments.iter().zip(&is_dir) {
+            for fragment in split_fragment(fragment, per_file, is_dir) {
+                fragment.write(&mut self.w, layout)?;
+            }
+        }
+        let end = self.w.stream_position()?;
+
+        self.w.seek(SeekFrom::Start(self.start))?;
+        let header = Header::new(
+            self.data_offset,
+            fragmented_filesystem_offset,
+            fragmented_filesystem_length,
+            per_file,
+            layout,
+        );
+        header.write(&mut self.w)?;
+        #[cfg(feature = "sign")]
+        if let (Some(position), Some(signer)) = (signature, self.w.signer.take()) {
+            let mut buf = signer.sign().to_vec();
+            if let Some(transform) = &self.options.transform {
+                transform.encode(position, &mut buf);
+            }
+            self.w.seek(SeekFrom::Start(self.start + position))?;
+            self.w.write_all(&buf)?;
+        }
+        self.w.seek(SeekFrom::Start(end))?;
+
+        Ok(self.w.inner)
+    }
+
+    /// Adds the `_signature` entry, its content is left out of the signature and
+    /// written by `finish` after the header
+    #[cfg(feature = "sign")]
+    fn add_signature(&mut self) -> HpkResult<u64> {
+        if self.root.iter().any(|(n, _)| n == SIGNATURE) {
+            return Err(HpkError::DuplicateEntry(SIGNATURE.into()));
+        }
+        let position = self.w.stream_position()? - self.start;
+        io::copy(&mut io::repeat(0).take(SIGNATURE_LENGTH), &mut self.w.inner)?;
+        self.fragments
+            .push(Fragment::new(position, SIGNATURE_LENGTH));
+        let index = self.fragments.len();
+        self.root.push((SIGNATURE.to_string(), Node::File(index)));
+        Ok(position)
+    }
+
+    /// Writes the subdirectories first and then the entry list of the directory itself
+    fn write_dir(&mut self, entries: Vec<(String, Node)>) -> HpkResult<Fragment> {
+        let mut buf = vec![];
+        for (name, node) in entries {
+            let (index, is_dir) = match node {
+                Node::File(index) => (index, false),
+                Node::Dir(index, entries) => {
+                    let fragment = self.write_dir(entries)?;
+                    let index = match index {
+                        Some(index) => {
+                            self.fragments[index - 1] = fragment;
+                            index
+                        }
+                        None => {
+                            self.fragments.push(fragment);
+                            self.fragments.len()
+                        }
+                    };
+                    self.dirs.push(index);
+                    (index, true)
+                }
+            };
+            let dent = RawDirEntry {
+                index: index - 1,
+                is_dir,
+                name: name.as_bytes(),
+            };
+            dent.encode(&mut buf, self.layout)?;
+        }
+        let position = self.w.stream_position()? - self.start;
+        if let Some(transform) = &self.options.transform {
+            transform.encode(position, &mut buf);
+        }
+        self.w.write_all(&buf)?;
+        Ok(Fragment::new(position, buf.len() as u64))
+    }
+}
+
+impl<'a, W: Read + Write + Seek> HpkWriter<'a, W> {
+    /// Opens the archive at the current position of `w` to add or replace files
+    ///
+    /// Existing entries keep their content and fragment index. New content is written
+    /// over the old fragment table, `finish` writes the directory tree and the fragment
+    /// table again. Residual fragments are dropped and a `_filedates` entry isn't
+    /// updated.
+    ///
+    pub fn open(options: &'a CreateOptions, mut w: W) -> HpkResult<Self> {
+        #[cfg(feature = "sign")]
+        if options.signing_key.is_some() {
+            return Err(HpkError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an opened archive can't be signed",
+            )));
+        }
+        check_alignment(options)?;
+        let start = w.stream_position()?;
+        let hdr = Header::read_from(&mut w)?;
+        if hdr.fragments_per_file != 1 {
+            return Err(HpkError::InvalidHeader);
+        }
+        let layout = hdr.layout();
+
+        let end = w.seek(SeekFrom::End(0))?;
+        let table_start = start
+            .checked_add(hdr.fragmented_filesystem_offset)
+            .ok_or(HpkError::TruncatedFragmentTable)?;
+        match table_start.checked_add(hdr.fragmented_filesystem_length) {
+            Some(n) if n <= end => {}
+            _ => return Err(HpkError::TruncatedFragmentTable),
+        }
+        w.seek(SeekFrom::Start(table_start))?;
+        let mut fragments = Vec::with_capacity(hdr.filesystem_entries());
+        for _ in 0..hdr.filesystem_entries() {
+            fragments.push(Fragment::read_from(&mut w, layout)?);
+        }
+        let mut visited = vec![false; fragments.len()];
+        let transform = options.transform.as_deref();
+        let root = read_tree(
+            &mut w,
+            start,
+            layout,
+            transform,
+            &fragments,
+            0,
+            &mut visited,
+        )?;
+
+        // the signature doesn't match the changed archive anymore
+        #[cfg(feature = "sign")]
+        let root: Vec<_> = root.into_iter().filter(|(n, _)| n != SIGNATURE).collect();
+
+        // keep the checksums of the unchanged files
+        let checksums = match root.iter().find(|(n, _)| n == checksum::CHECKSUMS) {
+            Some((_, Node::File(index))) => {
+                let fragment = &fragments[index - 1];
+                let fragment =
+                    Fragment::new(start.saturating_add(fragment.offset), fragment.length);
+                let r = FragmentedReader::new(&mut w, &[fragment])
+                    .with_transform(options.transform.clone(), start);
+                let length = r.len();
+                Some(checksum::parse(EntryReader::new(r, length)?)?)
+            }
+            _ if options.checksums => Some(ChecksumMap::new()),
+            _ => None,
+        };
+
+        let mut end = u64::from(hdr.data_offset);
+        for f in &fragments {
+            let fragment_end = f.offset.checked_add(f.length);
+            end = cmp::max(end, fragment_end.ok_or(HpkError::OffsetOverflow)?);
+        }
+        w.seek(SeekFrom::Start(
+            start.checked_add(end).ok_or(HpkError::OffsetOverflow)?,
+        ))?;
+
+        Ok(HpkWriter {
+            options,
+            w: Output {
+                inner: w,
+                #[cfg(feature = "sign")]
+                signer: None,
+            },
+            start,
+            layout,
+            data_offset: hdr.data_offset,
+            fragments,
+            root,
+            dirs: vec![],
+            checksums,
+        })
+    }
+}
+
+/// The output of `HpkWriter`, the written bytes are absorbed into the signature of
+/// `CreateOptions::sign_with`
+struct Output<W> {
+    inner: W,
+    #[cfg(feature = "sign")]
+    signer: Option<ed25519_compact::SigningState>,
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        #[cfg(feature = "sign")]
+        if let Some(signer) = &mut self.signer {
+            signer.absorb(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for Output<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Reads the directory at the 0-based fragment `index` and its subdirectories
+fn read_tree<R: Read + Seek>(
+    r: &mut R,
+    start: u64,
+    layout: Layout,
+    transform: Option<&dyn Transform>,
+    fragments: &[Fragment],
+    index: usize,
+    visited: &mut [bool],
+) -> HpkResult<Vec<(String, Node)>> {
+    match visited.get_mut(index) {
+        Some(seen) if !*seen => *seen = true,
+        _ => return Err(HpkError::InvalidFragmentIndex),
+    }
+    let fragment = &fragments[index];
+    r.seek(SeekFrom::Start(start.saturating_add(fragment.offset)))?;
+    let mut buf = read_exact_vec(r, fragment.length)?;
+    if let Some(transform) = transform {
+        transform.decode(fragment.offset, &mut buf);
+    }
+
+    let mut entries = vec![];
+    for raw in format::dir_entries(&buf, layout) {
+        let dent = DirEntry::from_raw(Path::new(""), 0, raw?, NameDecoding::Strict)?;
+        let name = dent.path().to_str().ok_or(HpkError::InvalidDirEntryName)?;
+        let node = if dent.is_dir() {
+            let children = read_tree(
+                r,
+                start,
+                layout,
+                transform,
+                fragments,
+                dent.index(),
+                visited,
+            )?;
+            Node::Dir(Some(dent.index() + 1), children)
+        } else if dent.index() < fragments.len() {
+            Node::File(dent.index() + 1)
+        } else {
+            return Err(HpkError::InvalidFragmentIndex);
+        };
+        entries.push((name.to_string(), node));
+    }
+    Ok(entries)
+}
+
+/// Returns an error if the alignment is not a power of two
+fn check_alignment(options: &CreateOptions) -> HpkResult<()> {
+    match options.alignment {
+        Some(alignment) if !alignment.is_power_of_two() => Err(HpkError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid alignment: {}", alignment),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Splits `fragment` into `count` consecutive fragments
+///
+/// A directory listing is kept in the first fragment, the readers of the directory
+/// tree only read that one. The remaining fragments are empty.
+///
+fn split_fragment(fragment: &Fragment, count: u32, whole: bool) -> impl Iterator<Item = Fragment> {
+    let count = u64::from(count);
+    let size = if whole {
+        fragment.length
+    } else {
+        fragment.length.div_ceil(count)
+    };
+    let (offset, length) = (fragment.offset, fragment.length);
+    (0..count).map(move |i| {
+        let start = cmp::min(i * size, length);
+        Fragment::new(offset + start, cmp::min(size, length - start))
+    })
+}
+
+fn write_content<R: Read, W: Write>(
+    options: &CreateOptions,
+    r: R,
+    w: &mut W,
+    compressor: Option<Compression>,
+    sums: Option<&mut Checksummer>,
+) -> HpkResult<u64> {
+    let mut r = ChecksumReader { inner: r, sums };
+    match compressor {
+        Some(c) if c == options.compress_options.compressor => {
+            compress(&options.compress_options, &mut r, w)
+        }
+        Some(c) => {
+            let co = &options.compress_options;
+            let co = CompressOptions {
+                compressor: c,
+                cancel: co.cancel.clone(),
+                ..*co
+            };
+            compress(&co, &mut r, w)
+        }
+        None => Ok(io::copy(&mut r, w)?),
+    }
+}
+
+/// Returns the fragment index of the file `path`
+fn find_file(entries: &[(String, Node)], path: &Path) -> Option<usize> {
+    let mut entries = entries;
+    let mut names = path.iter().peekable();
+    while let Some(name) = names.next() {
+        let node = match entries.iter().find(|(n, _)| name == n.as_str()) {
+            Some((_, node)) => node,
+            None => return None,
+        };
+        match node {
+            Node::File(index) if names.peek().is_none() => return Some(*index),
+            Node::File(_) => return None,
+            Node::Dir(_, children) => entries = children,
+        }
+    }
+    None
+}
+
+/// Splits the path of a new entry and validates its names
+fn split_new_path(path: &Path) -> HpkResult<Vec<&str>> {
+    let names = split_path(path)?;
+    for name in &names {
+        format::validate_name(name.as_bytes())
+            .map_err(|e| HpkError::InvalidName(path.to_path_buf(), e))?;
+    }
+    Ok(names)
+}
+
+fn split_path(path: &Path) -> HpkResult<Vec<&str>> {
+    path.components()
+        .map(|c| match c {
+            Component::Normal(name) => name.to_str().ok_or(HpkError::InvalidDirEntryName),
+            _ => Err(HpkError::InvalidDirEntryName),
+        })
+        .collect()
+}
+
+/// Returns the entries of the directory `names` below `entries`, the missing
+/// directories are created
+fn dir_entries<'e>(
+    mut entries: &'e mut Vec<(String, Node)>,
+    names: &[&str],
+) -> HpkResult<&'e mut Vec<(String, Node)>> {
+    for (i, &name) in names.iter().enumerate() {
+        let pos = match entries.iter().position(|(n, _)| n == name) {
+            Some(pos) => pos,
+            None => {
+                entries.push((name.to_string(), Node::Dir(None, vec![])));
+                entries.len() - 1
+            }
+        };
+        entries = match &mut entries[pos].1 {
+            Node::Dir(_, entries) => entries,
+            Node::File(_) => return Err(HpkError::NotADirectory(names[..=i].iter().collect())),
+        };
+    }
+    Ok(entries)
+}
@@ -26,22 +26,52 @@ impl From<clap::Error> for Error {
 type CliResult = Result<(), Error>;
 
 fn main() -> CliResult {
-    let matches = App::new("hpk")
+    let app = App::new("hpk")
         .version(crate_version!())
         .about(crate_description!())
         .after_help("https://github.com/nickelc/hpk")
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .subcommand(commands::create::clap())
         .subcommand(commands::extract::clap())
+        .subcommand(commands::cat::clap())
         .subcommand(commands::list::clap())
         .subcommand(commands::print::clap())
-        .get_matches();
+        .subcommand(commands::verify::clap())
+        .subcommand(commands::diff::clap())
+        .subcommand(commands::patch::clap())
+        .subcommand(commands::update::clap())
+        .subcommand(commands::remove::clap())
+        .subcommand(commands::defrag::clap());
+    #[cfg(feature = "watch")]
+    let app = app.subcommand(commands::watch::clap());
+    #[cfg(feature = "convert")]
+    let app = app.subcommand(commands::convert::clap());
+    #[cfg(feature = "pack")]
+    let app = app.subcommand(commands::pack::clap());
+    #[cfg(feature = "fuse")]
+    let app = app.subcommand(commands::mount::clap());
+    let matches = app.get_matches();
 
     match matches.subcommand() {
         ("create", Some(matches)) => commands::create::execute(matches)?,
         ("extract", Some(matches)) => commands::extract::execute(matches)?,
+        ("cat", Some(matches)) => commands::cat::execute(matches)?,
         ("list", Some(matches)) => commands::list::execute(matches)?,
         ("print", Some(matches)) => commands::print::execute(matches)?,
+        ("verify", Some(matches)) => commands::verify::execute(matches)?,
+        ("diff", Some(matches)) => commands::diff::execute(matches)?,
+        ("patch", Some(matches)) => commands::patch::execute(matches)?,
+        ("update", Some(matches)) => commands::update::execute(matches)?,
+        ("remove", Some(matches)) => commands::remove::execute(matches)?,
+        ("defrag", Some(matches)) => commands::defrag::execute(matches)?,
+        #[cfg(feature = "watch")]
+        ("watch", Some(matches)) => commands::watch::execute(matches)?,
+        #[cfg(feature = "convert")]
+        ("convert", Some(matches)) => commands::convert::execute(matches)?,
+        #[cfg(feature = "pack")]
+        ("pack", Some(matches)) => commands::pack::execute(matches)?,
+        #[cfg(feature = "fuse")]
+        ("mount", Some(matches)) => commands::mount::execute(matches)?,
         _ => unreachable!(),
     };
     Ok(())
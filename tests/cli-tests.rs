@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn hpk<P: AsRef<Path>>(cwd: P, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_hpk"))
+        .current_dir(cwd)
+        .args(args)
+        .output()
+        .expect("failed to run hpk");
+    assert!(
+        output.status.success(),
+        "hpk {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn subcommands() {
+    let root = tempfile::Builder::new().prefix("hpk-cli-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let cwd = root.path();
+
+    fs::create_dir_all(cwd.join("input/folder")).unwrap();
+    fs::write(cwd.join("input/a.lua"), "return 1").unwrap();
+    fs::write(cwd.join("input/folder/b.xml"), "<b/>").unwrap();
+
+    let output = hpk(cwd, &["create", "--dry-run", "input", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("compress          8         24 a.lua\n"));
+    assert!(stdout.ends_with("2 files, 1 directories, 12 bytes, about 44 bytes stored\n"));
+    assert!(!cwd.join("test.hpk").exists());
+
+    hpk(cwd, &["create", "input", "test.hpk"]);
+
+    let b_xml = Path::new("folder").join("b.xml");
+
+    let output = hpk(cwd, &["list", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("a.lua\n{}\n", b_xml.display()));
+
+    let output = hpk(cwd, &["list", "test.hpk", "folder/*"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("{}\n", b_xml.display()));
+
+    let output = hpk(cwd, &["list", "-l", "test.hpk", "folder/*"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("codec chunks"));
+    assert!(lines[1].starts_with("ZLIB       1          4 "));
+    assert!(lines[1].ends_with(&format!(" {}", b_xml.display())));
+
+    let output = hpk(cwd, &["list", "--json", "test.hpk", "folder/*"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let b_json = b_xml.display().to_string().replace('\\', "\\\\");
+    assert!(stdout.starts_with(&format!(
+        r#"{{"path":"{}","type":"file","codec":"ZLIB","size":4,"#,
+        b_json
+    )));
+    assert_eq!(stdout.lines().count(), 1);
+
+    let output = hpk(cwd, &["list", "--tree", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        ".\n├── a.lua\n└── folder\n    └── b.xml\n\n1 directories, 2 files\n"
+    );
+
+    let output = hpk(cwd, &["cat", "test.hpk", "folder/b.xml", "a.lua"]);
+    assert_eq!(output.stdout, b"<b/>return 1");
+    let output = hpk(cwd, &["cat", "-i", "test.hpk", "FOLDER/B.xml"]);
+    assert_eq!(output.stdout, b"<b/>");
+
+    hpk(cwd, &["extract", "test.hpk", "out"]);
+    assert_eq!(fs::read(cwd.join("out/a.lua")).unwrap(), b"return 1");
+    assert_eq!(fs::read(cwd.join("out/folder/b.xml")).unwrap(), b"<b/>");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_hpk"))
+        .current_dir(cwd)
+        .args(["extract", "-", "stdin"])
+        .stdin(Stdio::from(fs::File::open(cwd.join("test.hpk")).unwrap()))
+        .status()
+        .expect("failed to run hpk");
+    assert!(status.success());
+    assert_eq!(fs::read(cwd.join("stdin/a.lua")).unwrap(), b"return 1");
+
+    hpk(cwd, &["create", "--xor-key", "5a3c", "input", "xor.hpk"]);
+    let data = fs::read(cwd.join("xor.hpk")).unwrap();
+    assert!(!data.windows(5).any(|w| w == b"a.lua"));
+    hpk(cwd, &["extract", "--xor-key", "5a3c", "xor.hpk", "xor"]);
+    assert_eq!(fs::read(cwd.join("xor/a.lua")).unwrap(), b"return 1");
+
+    let output = hpk(cwd, &["verify", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "4 entries checked, 0 corrupt\n");
+
+    fs::create_dir_all(cwd.join("changes")).unwrap();
+    fs::write(cwd.join("changes/a.lua"), "return 2").unwrap();
+    let output = hpk(cwd, &["patch", "test.hpk", "changes", "patch.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        "1 entries patched, 2 removed entries can't be removed by the patch\n"
+    );
+    let output = hpk(cwd, &["list", "patch.hpk"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a.lua\n");
+
+    hpk(cwd, &["update", "test.hpk", "changes", "a.lua"]);
+    hpk(cwd, &["extract", "test.hpk", "updated"]);
+    assert_eq!(fs::read(cwd.join("updated/a.lua")).unwrap(), b"return 2");
+    assert_eq!(fs::read(cwd.join("updated/folder/b.xml")).unwrap(), b"<b/>");
+
+    // synchronize with the whole directory, folder/b.xml isn't in it
+    let output = hpk(cwd, &["update", "test.hpk", "changes"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "0 added, 0 changed, 1 removed, 1 unchanged\n");
+    let output = hpk(cwd, &["list", "test.hpk"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a.lua\n");
+    hpk(cwd, &["update", "test.hpk", "input", "folder/b.xml"]);
+
+    hpk(cwd, &["remove", "--compact", "test.hpk", "folder"]);
+    let output = hpk(cwd, &["list", "test.hpk"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a.lua\n");
+
+    let output = hpk(cwd, &["print", "--header-only", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("data_offset: 0x24"));
+    assert!(stdout.contains("filesystem entries: 2"));
+
+    let output = hpk(cwd, &["print", "--debug", "test.hpk"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("_unknown2: 0xFF"));
+    assert!(stdout.contains("tree:"));
+}
@@ -1,15 +1,13 @@
-use hpk;
-use tempfile;
-
 use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 macro_rules! assert_path_exists {
     ($p:expr) => {
-        assert!(Path::new($p).exists(), format!("{} does not exist", $p));
+        assert!(Path::new($p).exists(), "{} does not exist", $p);
     };
 }
 
@@ -22,7 +20,7 @@ fn create_extract_and_compress() {
     fn create_file(path: &str, content: Option<&[u8]>) {
         let mut file = fs::File::create(path).unwrap();
         if let Some(content) = content {
-            file.write(content).unwrap();
+            file.write_all(content).unwrap();
         }
     }
 
@@ -104,3 +102,4014 @@ fn create_extract_and_compress() {
         }
     }
 }
+
+#[test]
+fn wide_offsets() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("wide.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_wide_offsets();
+    options.with_default_filedates_format();
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(archive.header().is_wide());
+    assert_eq!(archive.header().data_offset, 52);
+    assert_eq!(archive.header().filesystem_entries(), 5);
+    assert_eq!(
+        archive.read_file("compressed.lst").unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+
+    let mut out = vec![];
+    assert_eq!(archive.copy_file("folder/six_bytes", &mut out).unwrap(), 6);
+    assert_eq!(out, b"ABCDEF");
+    assert!(archive.copy_file("folder", &mut out).is_err());
+
+    let dest = root.path().join("output");
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(fs::read(dest.join("folder/six_bytes")).unwrap(), b"ABCDEF");
+}
+
+#[test]
+fn extended_data_offsets() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    // a 32-bit archive with the header length of the 64-bit variant as data offset
+    for &(wide, data_offset) in &[(false, 52), (false, 56), (true, 64)] {
+        let file = root
+            .path()
+            .join(format!("offset-{}-{}.hpk", wide, data_offset));
+        let mut options = hpk::CreateOptions::new();
+        if wide {
+            options.with_wide_offsets();
+        }
+        options.with_data_offset(data_offset);
+        hpk::create(&options, &dir, &file).unwrap();
+
+        let data = fs::read(&file).unwrap();
+        assert_eq!(&data[4..8], &data_offset.to_le_bytes());
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        assert_eq!(archive.header().is_wide(), wide);
+        assert_eq!(archive.header().data_offset, data_offset);
+        assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+
+        let mut walk = hpk::walk(&file).unwrap();
+        let paths: Vec<_> = walk
+            .by_ref()
+            .map(|e| e.unwrap().path().to_path_buf())
+            .collect();
+        assert_eq!(paths.len(), 4);
+    }
+}
+
+#[test]
+fn big_endian() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    for &wide in &[false, true] {
+        let file = root.path().join(format!("big-endian-{}.hpk", wide));
+        let mut options = hpk::CreateOptions::new();
+        options.with_big_endian();
+        if wide {
+            options.with_wide_offsets();
+        }
+        hpk::create(&options, &dir, &file).unwrap();
+
+        let data = fs::read(&file).unwrap();
+        assert_eq!(&data[..4], b"LUPB");
+        let data_offset = if wide { 52u32 } else { 36 };
+        assert_eq!(&data[4..8], &data_offset.to_be_bytes());
+
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        assert!(archive.header().is_big_endian());
+        assert_eq!(archive.header().is_wide(), wide);
+        assert_eq!(archive.header().filesystem_entries(), 4);
+        assert_eq!(
+            archive.read_file("compressed.lst").unwrap(),
+            b"Hello World, Hello World"
+        );
+        assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+
+        hpk::remove(&file, ["compressed.lst"], true).unwrap();
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        assert!(archive.header().is_big_endian());
+        assert_eq!(archive.header().is_wide(), wide);
+        assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+    }
+}
+
+#[test]
+fn residual_fragments() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("residuals.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("six_bytes"), "ABCDEF").unwrap();
+
+    let mut buf = io::Cursor::new(vec![]);
+    hpk::write_hpk(&Default::default(), &dir, &mut buf).unwrap();
+    let mut data = buf.into_inner();
+
+    // append a residual table with two fragments and patch the header
+    let residual_offset = data.len() as u32;
+    for v in &[0x24u32, 6, 0x30, 2] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    data[16..20].copy_from_slice(&residual_offset.to_le_bytes());
+    data[20..24].copy_from_slice(&2u32.to_le_bytes());
+    fs::write(&file, &data).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().fragments_residual_count, 2);
+    let residuals: Vec<_> = archive
+        .residuals()
+        .iter()
+        .map(|f| (f.offset, f.length))
+        .collect();
+    assert_eq!(residuals, [(0x24, 6), (0x30, 2)]);
+    assert_eq!(archive.read_file("six_bytes").unwrap(), b"ABCDEF");
+}
+
+#[test]
+fn find_entries() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    for i in 0..50 {
+        w.add_file(format!("dir_{}/file_{}.txt", i % 5, i), &b"x"[..])
+            .unwrap();
+    }
+    let data = w.finish().unwrap().into_inner();
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+
+    assert_eq!(archive.find("").unwrap().depth(), 0);
+    assert!(archive.find("dir_3").unwrap().is_dir());
+    let entry = archive.find("dir_2/file_42.txt").unwrap();
+    assert_eq!(entry.path(), Path::new("dir_2/file_42.txt"));
+    assert!(!entry.is_dir());
+    assert!(archive
+        .find(Path::new("dir_2").join("file_42.txt"))
+        .is_some());
+    assert!(archive.find("dir_2/file_43.txt").is_none());
+    assert!(archive.find("file_42.txt").is_none());
+    for entry in archive.entries() {
+        assert_eq!(archive.find(entry.path()).unwrap().index(), entry.index());
+    }
+}
+
+#[test]
+fn fragment_table() {
+    let mut options = hpk::CreateOptions::new();
+    options.with_fragments_per_file(2);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    for i in 0..5 {
+        w.add_file(format!("folder/file_{}", i), &b"content"[..])
+            .unwrap();
+    }
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let header = archive.header();
+    let table = hpk::FragmentTable::read(header, io::Cursor::new(&data)).unwrap();
+    assert!(!table.is_truncated());
+    assert_eq!(table.len(), header.filesystem_entries());
+    let pairs = |f: &[hpk::Fragment]| f.iter().map(|f| (f.offset, f.length)).collect::<Vec<_>>();
+    for (entry, expected) in table.iter().zip(archive.fragments()) {
+        assert_eq!(entry.len(), 2);
+        assert_eq!(pairs(entry), pairs(expected));
+    }
+
+    // cut the file in the middle of the third entry of the table
+    let entry_size = 2 * 8;
+    let cut = header.fragmented_filesystem_offset as usize + 2 * entry_size + 5;
+    let table = hpk::FragmentTable::read(header, io::Cursor::new(&data[..cut])).unwrap();
+    assert!(table.is_truncated());
+    assert_eq!(table.len(), 2);
+    assert_eq!(pairs(table.get(1).unwrap()), pairs(&archive.fragments()[1]));
+    assert!(table.get(2).is_none());
+
+    let table = hpk::FragmentTable::read(header, io::Cursor::new(&data[..10])).unwrap();
+    assert!(table.is_truncated() && table.is_empty());
+}
+
+#[test]
+fn error_kinds() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("six_bytes"), "ABCDEF").unwrap();
+
+    let file = root.path().join("invalid.hpk");
+    fs::write(&file, b"NOPE and some more bytes to fill the header.").unwrap();
+    match hpk::HpkArchive::open(&file) {
+        Err(hpk::Error::InvalidHeader) => {}
+        _ => panic!("expected an invalid header"),
+    }
+
+    let mut buf = io::Cursor::new(vec![]);
+    hpk::write_hpk(&Default::default(), &dir, &mut buf).unwrap();
+    let mut data = buf.into_inner();
+    data.truncate(data.len() - 4);
+    let file = root.path().join("truncated.hpk");
+    fs::write(&file, &data).unwrap();
+    let err = match hpk::HpkArchive::open(&file) {
+        Err(e @ hpk::Error::TruncatedFragmentTable) => e,
+        _ => panic!("expected a truncated fragment table"),
+    };
+    let err: Box<dyn std::error::Error> = Box::new(err);
+    assert_eq!(err.to_string(), "truncated fragment table");
+
+    // the first chunk offset points into the compression header
+    let mut data = b"ZLIB".to_vec();
+    for v in &[6u32, 32768, 8] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    let length = data.len() as u64;
+    match hpk::CompressionHeader::read_from(length, &mut io::Cursor::new(data)) {
+        Err(hpk::Error::InvalidCompressionHeader) => {}
+        _ => panic!("expected an invalid compression header"),
+    }
+
+    // an offset table larger than the entry, and offsets which aren't ascending
+    for offsets in &[&[0xFFFF_FFF0u32][..], &[24, 40, 32], &[20, 64]] {
+        let mut data = b"LZ4 ".to_vec();
+        for v in [10u32, 32768].iter().chain(offsets.iter()) {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.resize(48, 0);
+        let length = data.len() as u64;
+        match hpk::CompressionHeader::read_from(length, &mut io::Cursor::new(data)) {
+            Err(hpk::Error::InvalidCompressionHeader) => {}
+            _ => panic!("expected an invalid compression header for {:?}", offsets),
+        }
+    }
+
+    // a fragment table behind the end of the file
+    let mut data = fs::read(root.path().join("truncated.hpk")).unwrap();
+    data[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+    let options = hpk::CreateOptions::new();
+    match hpk::HpkWriter::open(&options, io::Cursor::new(data)) {
+        Err(hpk::Error::TruncatedFragmentTable) => {}
+        _ => panic!("expected a truncated fragment table"),
+    }
+}
+
+#[test]
+fn parse_bytes() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("scripts/init.lua", &b"return 1"[..]).unwrap();
+    w.add_file("readme.txt", io::Cursor::new("Hello World"))
+        .unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::parse_bytes(&data).unwrap();
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"Hello World");
+
+    // every truncation is an error, never a panic
+    for len in 0..data.len() {
+        let _ = hpk::parse_bytes(&data[..len]);
+    }
+    assert!(hpk::parse_bytes(&data[..data.len() - 4]).is_err());
+
+    // a compressed archive which would decompress to 4 GiB
+    let mut data = b"ZLIB".to_vec();
+    for v in &[u32::MAX, 32768, 16] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    data.extend_from_slice(&[0x78, 0x9c, 0x03, 0x00]);
+    assert!(hpk::parse_bytes(&data).is_err());
+}
+
+#[test]
+fn hpk_writer() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("writer.hpk");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&file).unwrap()).unwrap();
+    w.add_file("scripts/init.lua", &b"return 1"[..]).unwrap();
+    w.add_dir("empty/nested").unwrap();
+    w.add_file("readme.txt", io::Cursor::new("Hello World"))
+        .unwrap();
+    w.add_file("scripts/sub/x.xml", &b"<x/>"[..]).unwrap();
+    let err = w.add_file("scripts/init.lua", &b""[..]).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::DuplicateEntry(p) if p == Path::new("scripts/init.lua")));
+    let err = w.add_dir("readme.txt").unwrap_err();
+    assert!(matches!(err, hpk::HpkError::NotADirectory(p) if p == Path::new("readme.txt")));
+    let err = w.add_file("scripts/init.lua/x", &b""[..]).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::NotADirectory(p) if p == Path::new("scripts/init.lua")));
+    assert!(w.add_file("../escape", &b""[..]).is_err());
+    w.finish().unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let paths: Vec<_> = archive
+        .entries()
+        .iter()
+        .map(|e| (e.path().to_path_buf(), e.is_dir()))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            (PathBuf::new(), true),
+            (PathBuf::from("scripts"), true),
+            (PathBuf::from("scripts/init.lua"), false),
+            (PathBuf::from("scripts/sub"), true),
+            (PathBuf::from("scripts/sub/x.xml"), false),
+            (PathBuf::from("empty"), true),
+            (PathBuf::from("empty/nested"), true),
+            (PathBuf::from("readme.txt"), false),
+        ]
+    );
+    assert_eq!(archive.read_file("scripts/init.lua").unwrap(), b"return 1");
+    assert_eq!(archive.read_file("scripts/sub/x.xml").unwrap(), b"<x/>");
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"Hello World");
+
+    let entry = archive.entry("scripts/init.lua").unwrap();
+    let r = archive.open_entry(entry).unwrap();
+    assert_eq!(r.compression(), hpk::Compression::Zlib);
+}
+
+#[test]
+fn invalid_names() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    let cases = [
+        ("folder/a:b.lua", hpk::NameError::ForbiddenCharacter(b':')),
+        ("aux/x.lua", hpk::NameError::Reserved),
+        ("a\\b.lua", hpk::NameError::Separator),
+    ];
+    for (path, reason) in &cases {
+        match w.add_file(path, &b""[..]) {
+            Err(hpk::HpkError::InvalidName(p, e)) => {
+                assert_eq!(p, Path::new(path));
+                assert_eq!(e, *reason);
+            }
+            other => panic!("{}: {:?}", path, other),
+        }
+    }
+    let long = "x".repeat(70_000);
+    let err = w.add_dir(&long).unwrap_err();
+    assert!(matches!(
+        err,
+        hpk::HpkError::InvalidName(_, hpk::NameError::TooLong(70_000))
+    ));
+    w.add_file("folder/a.lua", &b"ok"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            Path::new(""),
+            Path::new("folder"),
+            Path::new("folder/a.lua")
+        ]
+    );
+}
+
+#[test]
+fn in_memory_archive() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dest = root.path().join("output");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("compressed.lst", &b"Hello World, Hello World"[..])
+        .unwrap();
+    w.add_file("folder/six_bytes", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    assert_eq!(archive.path(), Path::new(""));
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(
+        fs::read(dest.join("compressed.lst")).unwrap(),
+        b"Hello World, Hello World"
+    );
+
+    // a reader without `Seek`
+    let dest = root.path().join("from-reader");
+    hpk::extract_reader(&Default::default(), &data[..], &dest).unwrap();
+    assert_eq!(fs::read(dest.join("folder/six_bytes")).unwrap(), b"ABCDEF");
+
+    // the whole archive compressed
+    let mut compressed = vec![];
+    hpk::compress(
+        &Default::default(),
+        &mut io::Cursor::new(data),
+        &mut compressed,
+    )
+    .unwrap();
+    let mut walk = hpk::walk_reader(io::Cursor::new(compressed)).unwrap();
+    assert!(walk.is_compressed());
+    let dent = walk
+        .find(|e| !e.as_ref().unwrap().is_dir())
+        .unwrap()
+        .unwrap();
+    let mut out = vec![];
+    walk.read_file(&dent, |mut r| {
+        hpk::copy(&mut r, &mut out)?;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(out, b"Hello World, Hello World");
+}
+
+#[test]
+fn iterator_adapters() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/x.txt", &b"x"[..]).unwrap();
+    w.add_file("b.txt", &b"b"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let mut walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    let files: Vec<_> = walk
+        .by_ref()
+        .filter_map(Result::ok)
+        .filter(|e| !e.is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    assert_eq!(files, [Path::new("a/x.txt"), Path::new("b.txt")]);
+    assert!(walk.next().is_none());
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let mut dirs = 0;
+    for entry in &archive {
+        if entry.is_dir() {
+            dirs += 1;
+        }
+    }
+    assert_eq!(dirs, 2);
+    let owned: Vec<hpk::DirEntry> = archive.into_iter().skip(1).collect();
+    assert_eq!(owned.len(), 3);
+}
+
+#[test]
+fn walk_pruning() {
+    fn paths<I: Iterator<Item = Result<hpk::DirEntry, hpk::Error>>>(it: I) -> Vec<PathBuf> {
+        it.map(|e| e.unwrap().path().to_path_buf()).collect()
+    }
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/x.txt", &b"x"[..]).unwrap();
+    w.add_file("b/y.txt", &b"y"[..]).unwrap();
+    w.add_file("b/sub/z.txt", &b"z"[..]).unwrap();
+    w.add_file("c.txt", &b"c"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    let walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    assert_eq!(
+        paths(walk.max_depth(1)),
+        ["", "a", "b", "c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+
+    let mut walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    let mut seen = vec![];
+    while let Some(dent) = walk.next() {
+        let dent = dent.unwrap();
+        if dent.path() == Path::new("b") || dent.path() == Path::new("a/x.txt") {
+            walk.skip_dir();
+        }
+        seen.push(dent.path().to_path_buf());
+    }
+    assert_eq!(
+        seen,
+        ["", "a", "a/x.txt", "b", "c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+
+    // point the fragment of "a" beyond the end, a pruned walk doesn't read it
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let table = archive.header().fragmented_filesystem_offset as usize;
+    let pos = table + archive.entry("a").unwrap().index() * 8;
+    data[pos..pos + 4].copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+
+    let walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    assert!(walk.collect::<Result<Vec<_>, _>>().is_err());
+
+    let walk = hpk::walk_reader(io::Cursor::new(data)).unwrap();
+    let walk = walk.filter_entry(|e| e.path() != Path::new("a"));
+    assert_eq!(
+        paths(walk),
+        ["", "b", "b/y.txt", "b/sub", "b/sub/z.txt", "c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn walk_read_dir() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/x.txt", &b"x"[..]).unwrap();
+    w.add_file("b/y.txt", &b"y"[..]).unwrap();
+    w.add_file("b/sub/z.txt", &b"z"[..]).unwrap();
+    w.add_file("c.txt", &b"c"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    // point the fragment of "a" beyond the end, listing "b" doesn't read it
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let table = archive.header().fragmented_filesystem_offset as usize;
+    let pos = table + archive.entry("a").unwrap().index() * 8;
+    data[pos..pos + 4].copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+
+    let walk = hpk::walk_reader(io::Cursor::new(data)).unwrap();
+    assert_eq!(walk.entry_count(), 8);
+    let names = |path: &str| -> Vec<PathBuf> {
+        let entries = walk.read_dir(path).unwrap();
+        entries.iter().map(|e| e.path().to_path_buf()).collect()
+    };
+    assert_eq!(
+        names(""),
+        ["a", "b", "c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        names("b"),
+        ["b/y.txt", "b/sub"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+    let sub = walk.read_dir("b/sub").unwrap();
+    assert_eq!(sub[0].path(), Path::new("b/sub/z.txt"));
+    assert_eq!(sub[0].depth(), 3);
+
+    assert!(matches!(
+        walk.read_dir("a"),
+        Err(hpk::HpkError::FragmentOutOfBounds(_))
+    ));
+    assert!(matches!(
+        walk.read_dir("c.txt"),
+        Err(hpk::HpkError::EntryNotFound(_))
+    ));
+    assert!(matches!(
+        walk.read_dir("b/missing"),
+        Err(hpk::HpkError::EntryNotFound(_))
+    ));
+}
+
+#[test]
+fn extract_skips_unreadable_entries() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/x.txt", &b"x"[..]).unwrap();
+    w.add_file("b/y.txt", &b"y"[..]).unwrap();
+    w.add_file("c.txt", &b"c"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    // point the fragment of "a" beyond the end
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let table = archive.header().fragmented_filesystem_offset as usize;
+    let pos = table + archive.entry("a").unwrap().index() * 8;
+    data[pos..pos + 4].copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+
+    let file = root.path().join("broken.hpk");
+    fs::write(&file, &data).unwrap();
+    assert!(hpk::HpkArchive::open(&file).is_err());
+
+    let dest = root.path().join("output");
+    let options = hpk::ExtractOptions::new();
+    hpk::extract(&options, &file, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("b/y.txt")).unwrap(), b"y");
+    assert_eq!(fs::read(dest.join("c.txt")).unwrap(), b"c");
+    assert!(!dest.join("a/x.txt").exists());
+
+    let dest = root.path().join("output-stdin");
+    hpk::extract_reader(&options, &data[..], &dest).unwrap();
+    assert_eq!(fs::read(dest.join("c.txt")).unwrap(), b"c");
+}
+
+#[test]
+fn walk_names_only() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/b/x.txt", &b"x"[..]).unwrap();
+    w.add_file("c.txt", &b"c"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let walk = hpk::walk_reader(io::Cursor::new(data))
+        .unwrap()
+        .names_only();
+    let entries: Vec<_> = walk
+        .map(|e| {
+            let e = e.unwrap();
+            (e.path().to_path_buf(), e.depth())
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [("", 0), ("a", 1), ("b", 2), ("x.txt", 3), ("c.txt", 1)]
+            .iter()
+            .map(|&(p, d)| (PathBuf::from(p), d))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn raw_chunks() {
+    let content = "Hello World, ".repeat(20);
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(64);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a.lst", content.as_bytes()).unwrap();
+    w.add_file("b.bin", &b"ABCDEF"[..]).unwrap();
+    w.add_file("empty.bin", &b""[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+
+    let a = archive.entry("a.lst").unwrap();
+    let chunks = archive.chunks(a).unwrap();
+    let lengths: Vec<_> = chunks.iter().map(|c| c.inflated_length).collect();
+    assert_eq!(lengths, [64, 64, 64, 64, 4]);
+    assert_eq!(chunks[0].offset, 16 + 4 * 4);
+    for pair in chunks.windows(2) {
+        assert_eq!(pair[0].offset + pair[0].length, pair[1].offset);
+    }
+    assert!(chunks.iter().any(|c| !c.is_stored()));
+    let last = chunks.last().unwrap();
+    assert_eq!(
+        last.offset + last.length,
+        archive.metadata(a).unwrap().compressed_size
+    );
+    for chunk in &chunks {
+        assert_eq!(
+            archive.chunk_bytes(a, chunk).unwrap().len() as u64,
+            chunk.length
+        );
+    }
+
+    let b = archive.entry("b.bin").unwrap();
+    let chunks = archive.chunks(b).unwrap();
+    assert_eq!(chunks.len(), 1);
+    assert!(chunks[0].is_stored());
+    assert_eq!(&*archive.chunk_bytes(b, &chunks[0]).unwrap(), b"ABCDEF");
+
+    assert!(archive
+        .chunks(archive.entry("empty.bin").unwrap())
+        .unwrap()
+        .is_empty());
+    assert!(archive.chunks(archive.entry("").unwrap()).is_err());
+}
+
+#[test]
+fn scrambled_archive() {
+    let key = hpk::XorKey::new(&b"\x13\x37\xC0\xDE"[..]);
+    let mut options = hpk::CreateOptions::new();
+    options.with_transform(key.clone());
+    options.with_checksums();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("folder/a.lst", "Hello World, ".repeat(100).as_bytes())
+        .unwrap();
+    w.add_file("b.txt", &b"plain text"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    assert!(!data.windows(6).any(|w| w == b"folder"));
+    assert!(!data.windows(10).any(|w| w == b"plain text"));
+
+    let walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    assert!(walk.collect::<Result<Vec<_>, _>>().is_err());
+
+    let walk = hpk::walk_reader(io::Cursor::new(data.clone())).unwrap();
+    let archive = hpk::HpkArchive::from_walk(walk.transform(key.clone())).unwrap();
+    assert_eq!(
+        archive.read_file("folder/a.lst").unwrap(),
+        "Hello World, ".repeat(100).as_bytes()
+    );
+    assert_eq!(archive.read_file("b.txt").unwrap(), b"plain text");
+    assert!(archive.verify().unwrap().is_empty());
+
+    // the compacted archive is scrambled with the same key
+    let mut compacted = io::Cursor::new(vec![]);
+    archive.compact_into(&mut compacted).unwrap();
+    let compacted = compacted.into_inner();
+    assert!(!compacted.windows(10).any(|w| w == b"plain text"));
+    let walk = hpk::walk_reader(io::Cursor::new(compacted)).unwrap();
+    let archive = hpk::HpkArchive::from_walk(walk.transform(key.clone())).unwrap();
+    assert_eq!(archive.read_file("b.txt").unwrap(), b"plain text");
+
+    // updating needs the key to read the directory tree
+    let mut w = hpk::HpkWriter::open(&options, io::Cursor::new(data)).unwrap();
+    w.replace_file("b.txt", &b"replaced"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    let walk = hpk::walk_reader(io::Cursor::new(data)).unwrap();
+    let archive = hpk::HpkArchive::from_walk(walk.transform(key)).unwrap();
+    assert_eq!(archive.read_file("b.txt").unwrap(), b"replaced");
+    assert!(archive.verify().unwrap().is_empty());
+}
+
+#[test]
+fn malformed_fragment_tables() {
+    let content = "Hello World, ".repeat(100);
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a.lst", content.as_bytes()).unwrap();
+    w.add_file("maps/one.xml", &b"one"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let fs_offset = archive.header().fragmented_filesystem_offset as usize;
+    let a = archive.entry("a.lst").unwrap().index();
+    let maps = archive.entry("maps").unwrap().index();
+    let open = |data: Vec<u8>| hpk::HpkArchive::from_reader(io::Cursor::new(data));
+    let set_u32 = |data: &mut Vec<u8>, pos: usize, val: u32| {
+        data[pos..pos + 4].copy_from_slice(&val.to_le_bytes());
+    };
+
+    let mut broken = data.clone();
+    set_u32(&mut broken, 8, 0);
+    assert!(matches!(open(broken), Err(hpk::HpkError::InvalidHeader)));
+
+    let mut broken = data.clone();
+    set_u32(&mut broken, 16, 0x100);
+    set_u32(&mut broken, 20, u32::MAX);
+    assert!(matches!(
+        open(broken),
+        Err(hpk::HpkError::TruncatedFragmentTable)
+    ));
+
+    let mut broken = data.clone();
+    set_u32(&mut broken, fs_offset + maps * 8 + 4, 0xFFFF_FF00);
+    match open(broken) {
+        Err(hpk::HpkError::FragmentOutOfBounds(index)) => assert_eq!(index, maps),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // the directory lists the entries of the root directory again
+    let mut broken = data.clone();
+    let root = broken[fs_offset..fs_offset + 8].to_vec();
+    broken[fs_offset + maps * 8..fs_offset + maps * 8 + 8].copy_from_slice(&root);
+    assert!(matches!(
+        open(broken),
+        Err(hpk::HpkError::InvalidFragmentIndex)
+    ));
+
+    // the content of a.lst ends far behind the end of the archive
+    let mut broken = data;
+    set_u32(&mut broken, fs_offset + a * 8 + 4, 0xFFFF_FF00);
+    let archive = open(broken).unwrap();
+    assert!(archive.read_file("a.lst").is_err());
+    let corrupt = archive.verify().unwrap();
+    assert_eq!(corrupt.len(), 1);
+    assert!(matches!(
+        corrupt[0].corruption,
+        hpk::Corruption::OutOfBounds(_)
+    ));
+}
+
+#[test]
+fn non_utf8_names() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("latin1.hpk");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("cafX.lua", &b"print()"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+    // "café.lua" in Latin-1
+    let pos = data.windows(8).position(|w| w == b"cafX.lua").unwrap();
+    data[pos + 3] = 0xE9;
+    fs::write(&file, &data).unwrap();
+
+    let open = |names| {
+        let walk = hpk::walk(&file)?.name_decoding(names);
+        hpk::HpkArchive::from_walk(walk)
+    };
+    assert!(matches!(
+        open(hpk::NameDecoding::Strict),
+        Err(hpk::HpkError::InvalidDirEntryName)
+    ));
+    let archive = open(hpk::NameDecoding::Lossy).unwrap();
+    assert_eq!(archive.read_file("caf\u{FFFD}.lua").unwrap(), b"print()");
+
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xE9.lua");
+        let archive = open(hpk::NameDecoding::Raw).unwrap();
+        assert_eq!(archive.entries()[1].path(), Path::new(name));
+
+        let dest = root.path().join("output");
+        let mut options = hpk::ExtractOptions::new();
+        options.set_name_decoding(hpk::NameDecoding::Raw);
+        hpk::extract(&options, &file, &dest).unwrap();
+        assert_eq!(fs::read(dest.join(name)).unwrap(), b"print()");
+    }
+}
+
+#[test]
+fn unsafe_entry_paths() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("unsafe.hpk");
+    let dest = root.path().join("output");
+    let absolute = root.path().join("absolute.txt");
+    let absolute = absolute.to_str().unwrap();
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    let placeholder = "A".repeat(absolute.len());
+    w.add_file("XXXparent.txt", &b"parent"[..]).unwrap();
+    w.add_file(&placeholder, &b"absolute"[..]).unwrap();
+    w.add_file("safe.txt", &b"safe"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    let pos = data.windows(3).position(|w| w == b"XXX").unwrap();
+    data[pos..pos + 3].copy_from_slice(b"../");
+    let pos = data
+        .windows(placeholder.len())
+        .position(|w| w == placeholder.as_bytes())
+        .unwrap();
+    data[pos..pos + placeholder.len()].copy_from_slice(absolute.as_bytes());
+    fs::write(&file, &data).unwrap();
+
+    let check = |result: Result<(), hpk::HpkError>, dest: &Path| {
+        match result {
+            Err(hpk::HpkError::UnsafePaths(paths)) => {
+                assert_eq!(paths.len(), 2);
+                assert!(paths.contains(&PathBuf::from("../parent.txt")));
+                assert!(paths.contains(&PathBuf::from(absolute)));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(fs::read(dest.join("safe.txt")).unwrap(), b"safe");
+        assert!(!root.path().join("parent.txt").exists());
+        assert!(!Path::new(absolute).exists());
+    };
+    let options = hpk::ExtractOptions::new();
+    check(hpk::extract(&options, &file, &dest), &dest);
+    // a patch is extracted the same way
+    let patched = root.path().join("patched");
+    check(hpk::apply_patch(&file, &file, &patched), &patched);
+}
+
+#[test]
+fn verify_archive() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("verify.hpk");
+
+    let content = "Hello World, ".repeat(100);
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a.lst", content.as_bytes()).unwrap();
+    w.add_file("b.txt", &b"ABCDEF"[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    fs::write(&file, &data).unwrap();
+    assert!(hpk::verify(&file).unwrap().is_empty());
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let a = &archive.fragments()[archive.entry("a.lst").unwrap().index()][0];
+    let b_index = archive.entry("b.txt").unwrap().index();
+    let fs_offset = archive.header().fragmented_filesystem_offset as usize;
+
+    // damage the zlib stream of a.lst and let b.txt end behind the archive
+    data[a.offset as usize + 20] ^= 0xFF;
+    let pos = fs_offset + b_index * 8 + 4;
+    data[pos..pos + 4].copy_from_slice(&0xFFFFu32.to_le_bytes());
+    fs::write(&file, &data).unwrap();
+
+    let corrupt = hpk::verify(&file).unwrap();
+    assert_eq!(corrupt.len(), 2);
+    assert_eq!(corrupt[0].path, Path::new("a.lst"));
+    match corrupt[0].corruption {
+        hpk::Corruption::Unreadable(hpk::HpkError::Io(ref e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData)
+        }
+        ref c => panic!("unexpected corruption: {:?}", c),
+    }
+    assert_eq!(corrupt[1].path, Path::new("b.txt"));
+    match corrupt[1].corruption {
+        hpk::Corruption::OutOfBounds(ref f) => assert_eq!(f.length, 0xFFFF),
+        ref c => panic!("unexpected corruption: {:?}", c),
+    }
+}
+
+#[test]
+fn corrupt_chunks() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+
+    let content: Vec<u8> = (0..6000)
+        .flat_map(|i| format!("{:08}\n", i).into_bytes())
+        .collect();
+    assert_eq!(content.len(), 54000);
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a.lst", &content[..]).unwrap();
+    let mut data = w.finish().unwrap().into_inner();
+
+    // damage the zlib stream of the last chunk
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data.clone())).unwrap();
+    let entry = archive.entry("a.lst").unwrap();
+    assert_eq!(archive.read_file("a.lst").unwrap(), content);
+    let offset = archive.fragments()[entry.index()][0].offset;
+    let chunks = archive.chunks(entry).unwrap();
+    let last = chunks.last().unwrap();
+    assert!(!last.is_stored());
+    data[(offset + last.offset + last.length / 2) as usize] ^= 0xFF;
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let err = archive.read_file("a.lst").unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+    let mut r = archive.open_entry(archive.entry("a.lst").unwrap()).unwrap();
+    assert!(io::copy(&mut r, &mut io::sink()).is_err());
+    assert!(archive.extract_to(root.path().join("out")).is_err());
+}
+
+#[test]
+fn repack() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let src = root.path().join("zlib.hpk");
+    let lz4 = root.path().join("lz4.hpk");
+    let stored = root.path().join("stored.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::create_dir_all(dir.join("empty")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &src).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.use_lz4();
+    hpk::repack(&options, &src, &lz4).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_compressor(hpk::Compression::None);
+    hpk::repack(&options, &lz4, &stored).unwrap();
+
+    for (file, compression) in &[
+        (&lz4, hpk::Compression::Lz4),
+        (&stored, hpk::Compression::None),
+    ] {
+        let archive = hpk::HpkArchive::open(file).unwrap();
+        assert!(archive.entry("empty").unwrap().is_dir());
+        assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+        let entry = archive.entry("compressed.lst").unwrap();
+        let mut r = archive.open_entry(entry).unwrap();
+        assert_eq!(r.compression(), *compression);
+        let mut buf = vec![];
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"Hello World, Hello World");
+    }
+}
+
+#[test]
+fn update_archive() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("update.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::create_dir_all(dir.join("empty")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+    let fragments = hpk::HpkArchive::open(&file).unwrap().fragments().len();
+
+    let updates = root.path().join("updates");
+    fs::create_dir_all(updates.join("folder/new")).unwrap();
+    fs::write(updates.join("folder/six_bytes"), "GHIJKL").unwrap();
+    fs::write(updates.join("folder/new/script.lua"), "return 2").unwrap();
+    let files = vec![
+        ("folder/six_bytes", updates.join("folder/six_bytes")),
+        (
+            "folder/new/script.lua",
+            updates.join("folder/new/script.lua"),
+        ),
+    ];
+    let options = hpk::CreateOptions::new();
+    hpk::update(&options, &file, files.clone()).unwrap();
+    let len = fs::metadata(&file).unwrap().len();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.fragments().len(), fragments + 2);
+    assert!(archive.entry("empty").unwrap().is_dir());
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"GHIJKL");
+    assert_eq!(
+        archive.read_file("folder/new/script.lua").unwrap(),
+        b"return 2"
+    );
+    assert_eq!(
+        archive.read_file("compressed.lst").unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert!(archive.verify().unwrap().is_empty());
+
+    // replacing with the same content again reuses all fragment indices
+    hpk::update(&options, &file, files).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.fragments().len(), fragments + 2);
+    assert!(fs::metadata(&file).unwrap().len() > len);
+
+    let mut w = hpk::HpkWriter::open(&options, fs::File::open(&file).unwrap()).unwrap();
+    assert!(w.replace_file("folder", &b""[..]).is_err());
+}
+
+#[test]
+fn remove_entries() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("remove.hpk");
+
+    fs::create_dir_all(dir.join("folder/sub")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, ".repeat(100)).unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+    fs::write(dir.join("folder/sub/two_bytes"), "AB").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+    let len = fs::metadata(&file).unwrap().len();
+
+    hpk::remove(&file, ["folder/sub"], false).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(archive.entry("folder/sub").is_none());
+    assert!(archive.entry("folder/sub/two_bytes").is_none());
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+
+    match hpk::remove(&file, ["missing"], false) {
+        Err(hpk::Error::EntryNotFound(path)) => assert_eq!(path, Path::new("missing")),
+        _ => panic!("expected a missing entry"),
+    }
+
+    hpk::remove(&file, ["compressed.lst"], true).unwrap();
+    assert!(fs::metadata(&file).unwrap().len() < len);
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            Path::new(""),
+            Path::new("folder"),
+            Path::new("folder/six_bytes")
+        ]
+    );
+    assert_eq!(archive.fragments().len(), 3);
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+}
+
+#[test]
+fn deterministic_create() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let first = root.path().join("first.hpk");
+    let second = root.path().join("second.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_default_filedates_format();
+    options.with_threads(4);
+    options.deterministic();
+    hpk::create(&options, &dir, &first).unwrap();
+
+    let mtime = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(dir.join("folder/six_bytes"), mtime).unwrap();
+    options.with_threads(1);
+    hpk::create(&options, &dir, &second).unwrap();
+
+    assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+    let archive = hpk::HpkArchive::open(&first).unwrap();
+    let filedates = archive.read_file("_filedates").unwrap();
+    assert!(String::from_utf8(filedates)
+        .unwrap()
+        .lines()
+        .all(|l| l.ends_with("=116444736000000000")));
+}
+
+#[test]
+fn checksums() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("checksums.hpk");
+    let dest = root.path().join("output");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, ".repeat(100)).unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    options.with_checksums();
+    hpk::create(&options, &dir, &file).unwrap();
+    let replacement = root.path().join("replacement");
+    fs::write(&replacement, "GHIJKL").unwrap();
+    hpk::update(&options, &file, [("folder/six_bytes", &replacement)]).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let sums = String::from_utf8(archive.read_file("_checksums").unwrap()).unwrap();
+    assert_eq!(sums.lines().count(), 2);
+    assert!(sums.contains("folder/six_bytes=512:"));
+    assert!(archive.verify().unwrap().is_empty());
+
+    let options = hpk::ExtractOptions::new();
+    archive.extract_with(&options, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("folder/six_bytes")).unwrap(), b"GHIJKL");
+    assert!(!dest.join("_checksums").exists());
+    drop(archive);
+
+    // corrupt the stored content of the uncompressed file
+    let mut data = fs::read(&file).unwrap();
+    let pos = data.windows(6).position(|w| w == b"GHIJKL").unwrap();
+    data[pos] = b'X';
+    fs::write(&file, data).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let corrupt = archive.verify().unwrap();
+    assert_eq!(corrupt.len(), 1);
+    assert!(matches!(
+        corrupt[0].corruption,
+        hpk::Corruption::ChecksumMismatch { chunk: 0 }
+    ));
+
+    let dest = root.path().join("corrupt");
+    match archive.extract_with(&options, &dest) {
+        Err(hpk::HpkError::ChecksumMismatch(path)) => {
+            assert_eq!(path, Path::new("folder/six_bytes"));
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    assert!(!dest.join("folder/six_bytes").exists());
+
+    let mut options = hpk::ExtractOptions::new();
+    options.skip_checksums();
+    archive.extract_with(&options, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("folder/six_bytes")).unwrap(), b"XHIJKL");
+    assert!(dest.join("_checksums").exists());
+}
+
+#[test]
+fn create_excludes() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("excludes.hpk");
+
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::create_dir_all(dir.join("maps/tmp")).unwrap();
+    fs::write(dir.join(".git/config"), "").unwrap();
+    fs::write(dir.join("Thumbs.db"), "").unwrap();
+    fs::write(dir.join("script.lua"), "").unwrap();
+    fs::write(dir.join("script.lua.swp"), "").unwrap();
+    fs::write(dir.join("maps/one.xml"), "").unwrap();
+    fs::write(dir.join("maps/one.bak"), "").unwrap();
+    fs::write(dir.join("maps/two.xml"), "").unwrap();
+    fs::write(dir.join("maps/tmp/three.xml"), "").unwrap();
+    fs::write(dir.join(".hpkignore"), "# backups\n*.bak\n/maps/tmp/\n").unwrap();
+
+    let paths = |options: &hpk::CreateOptions| -> Vec<PathBuf> {
+        hpk::create(options, &dir, &file).unwrap();
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        let dry_run = hpk::dry_run(options, &dir).unwrap();
+        let mut paths: Vec<_> = archive
+            .entries()
+            .iter()
+            .filter(|e| e.depth() > 0)
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        paths.sort();
+        let mut expected: Vec<_> = dry_run.into_iter().map(|e| e.path).collect();
+        expected.sort();
+        assert_eq!(paths, expected);
+        paths
+    };
+
+    let mut options = hpk::CreateOptions::new();
+    options.set_excludes(&["two.*".into()]);
+    assert_eq!(
+        paths(&options),
+        ["maps", "maps/one.xml", "script.lua"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+
+    let mut options = hpk::CreateOptions::new();
+    options.no_default_excludes();
+    assert_eq!(
+        paths(&options),
+        [
+            ".git",
+            ".git/config",
+            ".hpkignore",
+            "Thumbs.db",
+            "maps",
+            "maps/one.xml",
+            "maps/two.xml",
+            "script.lua",
+            "script.lua.swp"
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn dry_run_estimates() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("estimates.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    let content: String = (0..20_000)
+        .map(|i| format!("local x{} = {}\n", i % 100, i))
+        .collect();
+    fs::write(dir.join("script.lua"), &content).unwrap();
+    fs::write(dir.join("empty.lua"), "").unwrap();
+    fs::write(dir.join("image.png"), &content).unwrap();
+
+    let options = hpk::CreateOptions::new();
+    hpk::create(&options, &dir, &file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let stored = |path: &Path| {
+        let entry = archive.entry(path).unwrap();
+        archive.fragments()[entry.index()][0].length
+    };
+
+    for entry in hpk::dry_run(&options, &dir).unwrap() {
+        let actual = stored(&entry.path);
+        if entry.compress {
+            // within a quarter of the stored size
+            assert!(entry.stored.abs_diff(actual) <= actual / 4, "{:?}", entry);
+        } else {
+            assert_eq!(entry.stored, entry.size);
+            assert_eq!(entry.stored, actual);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn create_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("symlinks.hpk");
+
+    fs::create_dir_all(dir.join("maps")).unwrap();
+    fs::write(dir.join("maps/one.xml"), "one").unwrap();
+    symlink(dir.join("maps"), dir.join("linked")).unwrap();
+
+    let paths = |options: &hpk::CreateOptions| -> Result<Vec<PathBuf>, hpk::Error> {
+        hpk::create(options, &dir, &file)?;
+        let archive = hpk::HpkArchive::open(&file)?;
+        Ok(archive
+            .entries()
+            .iter()
+            .filter(|e| e.depth() > 0)
+            .map(|e| e.path().to_path_buf())
+            .collect())
+    };
+    let expected = |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(PathBuf::from).collect() };
+
+    let mut options = hpk::CreateOptions::new();
+    assert_eq!(
+        paths(&options).unwrap(),
+        expected(&["maps", "maps/one.xml"])
+    );
+
+    options.set_symlink_policy(hpk::SymlinkPolicy::Error);
+    match paths(&options) {
+        Err(hpk::HpkError::Symlink(path)) => assert_eq!(path, dir.join("linked")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    options.set_symlink_policy(hpk::SymlinkPolicy::Follow);
+    let archive_paths = paths(&options).unwrap();
+    assert_eq!(
+        archive_paths,
+        expected(&["linked", "linked/one.xml", "maps", "maps/one.xml"])
+    );
+
+    // a link to a parent directory
+    symlink(&dir, dir.join("maps/loop")).unwrap();
+    match paths(&options) {
+        Err(hpk::HpkError::WalkDir(e)) => assert!(e.loop_ancestor().is_some()),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn create_sort_order() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("sorted.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    for name in &["b.lua", "C.lua", "a.lua", "Ä.lua"] {
+        fs::write(dir.join(name), name).unwrap();
+    }
+
+    let names = |options: &hpk::CreateOptions| -> Result<Vec<String>, hpk::Error> {
+        hpk::create(options, &dir, &file)?;
+        let archive = hpk::HpkArchive::open(&file)?;
+        Ok(archive
+            .entries()
+            .iter()
+            .filter(|e| e.depth() > 0)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect())
+    };
+
+    let mut options = hpk::CreateOptions::new();
+    options.no_default_excludes();
+    assert_eq!(
+        names(&options).unwrap(),
+        ["C.lua", "a.lua", "b.lua", "Ä.lua"]
+    );
+
+    options.set_sort_order(hpk::SortOrder::CaseInsensitive);
+    assert_eq!(
+        names(&options).unwrap(),
+        ["a.lua", "b.lua", "C.lua", "Ä.lua"]
+    );
+
+    options.sort_by(|a, b| b.cmp(a));
+    assert_eq!(
+        names(&options).unwrap(),
+        ["Ä.lua", "b.lua", "a.lua", "C.lua"]
+    );
+}
+
+#[test]
+fn entry_metadata() {
+    let content = "Hello World, ".repeat(100);
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a.lst", content.as_bytes()).unwrap();
+    w.add_file("b.txt", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let entry = archive.entry("a.lst").unwrap();
+    let md = archive.metadata(entry).unwrap();
+    assert_eq!(md.compression, hpk::Compression::Zlib);
+    assert_eq!(md.size, 1300);
+    assert_eq!(md.chunks, 3);
+    assert_eq!(
+        md.compressed_size,
+        archive.fragments()[entry.index()][0].length
+    );
+
+    let md = archive.metadata(archive.entry("b.txt").unwrap()).unwrap();
+    assert_eq!(md.compression, hpk::Compression::None);
+    assert_eq!((md.size, md.compressed_size, md.chunks), (6, 6, 0));
+
+    assert!(archive.metadata(archive.entry("").unwrap()).is_err());
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn incompressible_chunks() {
+    // xorshift noise followed by text
+    let mut state = 0x2545_F491_u32;
+    let mut content: Vec<u8> = (0..1800)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    content.extend_from_slice("Hello World, ".repeat(100).as_bytes());
+
+    let compressors: [fn(&mut hpk::CreateOptions); 3] = [
+        |_| {},
+        hpk::CreateOptions::use_lz4,
+        hpk::CreateOptions::use_zstd,
+    ];
+    for use_compressor in compressors {
+        let mut options = hpk::CreateOptions::new();
+        options.with_chunk_size(512);
+        use_compressor(&mut options);
+        let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+        w.add_file("noise.lst", &content[..]).unwrap();
+        let data = w.finish().unwrap().into_inner();
+
+        let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+        let entry = archive.entry("noise.lst").unwrap();
+        let md = archive.metadata(entry).unwrap();
+        assert_eq!(md.chunks, 7);
+        // the raw chunks are stored without any overhead
+        let header_len = 4 + 4 + 4 + 4 * 7;
+        assert!(md.compressed_size < content.len() as u64 + header_len);
+        assert_eq!(archive.read_file("noise.lst").unwrap(), content);
+
+        let mut r = archive.open_entry(entry).unwrap();
+        r.seek(io::SeekFrom::Start(1000)).unwrap();
+        let mut buf = [0; 100];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], content[1000..1100]);
+    }
+}
+
+#[test]
+fn parallel_compression_across_files() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    // small files, an empty one, a duplicate and one with several chunks in between
+    fs::create_dir_all(dir.join("small")).unwrap();
+    for i in 0..20 {
+        let content = format!("small file {} ", i).repeat(i + 1);
+        fs::write(dir.join(format!("small/{:02}.txt", i)), content).unwrap();
+    }
+    fs::write(dir.join("small/empty.txt"), "").unwrap();
+    fs::write(dir.join("small/same.txt"), "small file 0 ").unwrap();
+    let big: Vec<u8> = (0..5000u32).map(|i| (i * i % 251) as u8).collect();
+    fs::write(dir.join("big.bin"), &big).unwrap();
+    fs::write(dir.join("plain.bik"), "video").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    options.with_extensions(vec!["txt".into(), "bin".into()]);
+    options.with_checksums();
+    options.deduplicate();
+    let mut archives = vec![];
+    for threads in [1, 3, 8] {
+        options.with_threads(threads);
+        let file = root.path().join(format!("threads-{}.hpk", threads));
+        hpk::create(&options, &dir, &file).unwrap();
+        archives.push(fs::read(&file).unwrap());
+    }
+    assert_eq!(archives[0], archives[1]);
+    assert_eq!(archives[0], archives[2]);
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(archives.remove(0))).unwrap();
+    assert!(archive.verify().unwrap().is_empty());
+    assert_eq!(archive.read_file("big.bin").unwrap(), big);
+    assert_eq!(archive.read_file("plain.bik").unwrap(), b"video");
+    assert_eq!(archive.read_file("small/empty.txt").unwrap(), b"");
+    assert_eq!(
+        archive.read_file("small/07.txt").unwrap(),
+        "small file 7 ".repeat(8).as_bytes()
+    );
+}
+
+#[test]
+fn parallel_decompression() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("parallel.hpk");
+    let dest = root.path().join("output");
+
+    let content: Vec<u8> = (0..5000u32).map(|i| (i * i % 251) as u8).collect();
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    options.with_threads(3);
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&file).unwrap()).unwrap();
+    w.add_file("big.lst", &content[..]).unwrap();
+    w.finish().unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.metadata(&archive.entries()[1]).unwrap().chunks, 10);
+    let mut options = hpk::ExtractOptions::new();
+    options.with_threads(4);
+    archive.extract_with(&options, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("big.lst")).unwrap(), content);
+}
+
+#[test]
+fn read_all() {
+    let content = "Hello World, ".repeat(100);
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("scripts/a.lua", content.as_bytes()).unwrap();
+    w.add_file("b.txt", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let files = archive.read_all(1306).unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[Path::new("scripts/a.lua")], content.as_bytes());
+    assert_eq!(files[Path::new("b.txt")], b"ABCDEF");
+
+    match archive.read_all(1305) {
+        Err(hpk::HpkError::SizeLimitExceeded(1305)) => {}
+        other => panic!("unexpected result: {:?}", other.map(|f| f.len())),
+    }
+}
+
+#[test]
+fn overlay_archives() {
+    fn archive(files: &[(&str, &str)]) -> hpk::HpkArchive {
+        let options = hpk::CreateOptions::new();
+        let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+        for (path, content) in files {
+            w.add_file(path, content.as_bytes()).unwrap();
+        }
+        let data = w.finish().unwrap().into_inner();
+        hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap()
+    }
+
+    let base = archive(&[
+        ("a.lua", "base a"),
+        ("b.lua", "base b"),
+        ("maps/one.xml", "one"),
+        ("maps/two.xml", "two"),
+        ("textures/x.dds", "x"),
+    ]);
+    let patch = archive(&[("b.lua", "patch b"), ("maps/three.xml", "three")]);
+    let mods = archive(&[
+        ("textures", "not a dir anymore"),
+        ("maps/one.xml", "mod one"),
+    ]);
+    let overlay = hpk::OverlayArchive::new(vec![base, patch, mods]);
+
+    let paths: Vec<_> = overlay.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            "",
+            "a.lua",
+            "b.lua",
+            "maps",
+            "maps/one.xml",
+            "maps/three.xml",
+            "maps/two.xml",
+            "textures"
+        ]
+        .iter()
+        .map(Path::new)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(overlay.read_file("a.lua").unwrap(), b"base a");
+    assert_eq!(overlay.read_file("b.lua").unwrap(), b"patch b");
+    assert_eq!(overlay.read_file("maps/one.xml").unwrap(), b"mod one");
+    assert_eq!(overlay.read_file("textures").unwrap(), b"not a dir anymore");
+    assert!(overlay.read_file("textures/x.dds").is_err());
+    assert_eq!(overlay.read_dir("maps").unwrap().len(), 3);
+
+    let entry = overlay.entry("maps/three.xml").unwrap();
+    let mut content = String::new();
+    let mut r = overlay.open_entry(entry).unwrap();
+    r.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "three");
+    assert_eq!(overlay.metadata(entry).unwrap().size, 5);
+    assert!(std::ptr::eq(
+        overlay.source("maps/three.xml").unwrap(),
+        &overlay.archives()[1]
+    ));
+}
+
+#[test]
+fn case_insensitive_lookup() {
+    fn archive(files: &[(&str, &str)]) -> hpk::HpkArchive {
+        let options = hpk::CreateOptions::new();
+        let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+        for (path, content) in files {
+            w.add_file(path, content.as_bytes()).unwrap();
+        }
+        let data = w.finish().unwrap().into_inner();
+        hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap()
+    }
+
+    let mut base = archive(&[("Data/Script.lua", "base"), ("Data/Map.xml", "map")]);
+    assert!(base.find("data/script.lua").is_none());
+    base.set_path_lookup(hpk::PathLookup::CaseInsensitive);
+    assert_eq!(base.path_lookup(), hpk::PathLookup::CaseInsensitive);
+    let entry = base.find("data/SCRIPT.LUA").unwrap();
+    assert_eq!(entry.path(), Path::new("Data/Script.lua"));
+    assert_eq!(base.read_file("DATA/script.lua").unwrap(), b"base");
+    assert_eq!(base.read_dir("data").unwrap().len(), 2);
+    base.set_path_lookup(hpk::PathLookup::Exact);
+    assert!(base.find("data/script.lua").is_none());
+
+    let patch = archive(&[("data/script.lua", "patch")]);
+    let overlay = hpk::OverlayArchive::new(vec![base, patch]);
+    assert_eq!(overlay.entries().len(), 6);
+    assert_eq!(overlay.read_file("Data/Script.lua").unwrap(), b"base");
+
+    let overlay = hpk::OverlayArchive::with_path_lookup(
+        overlay.into_archives(),
+        hpk::PathLookup::CaseInsensitive,
+    );
+    let paths: Vec<_> = overlay.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(
+        paths,
+        ["", "data", "Data/Map.xml", "data/script.lua"]
+            .iter()
+            .map(Path::new)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(overlay.read_file("Data/Script.lua").unwrap(), b"patch");
+    assert_eq!(overlay.read_file("data/map.XML").unwrap(), b"map");
+    assert_eq!(overlay.read_dir("DATA").unwrap().len(), 2);
+}
+
+#[test]
+fn print_tree() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/b/f.txt", &b"x"[..]).unwrap();
+    w.add_file("a/g.lua", &b"yy"[..]).unwrap();
+    w.add_file("c/h", &b"z"[..]).unwrap();
+    w.add_file("top", &b"q"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+
+    let mut out = vec![];
+    archive
+        .print_tree(&mut out, &hpk::TreeOptions::new())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        ".\n\
+         ├── a\n\
+         │   ├── b\n\
+         │   │   └── f.txt\n\
+         │   └── g.lua\n\
+         ├── c\n\
+         │   └── h\n\
+         └── top\n\
+         \n\
+         3 directories, 4 files\n"
+    );
+
+    let mut options = hpk::TreeOptions::new();
+    options.show_sizes();
+    options.show_compression();
+    let mut out = vec![];
+    archive.print_tree(&mut out, &options).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("                 .\n                 ├── a\n"));
+    assert!(out.contains("\n         2 ZLIB  │   └── g.lua\n"));
+}
+
+#[test]
+fn print_json() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("a/f.txt", &b"x"[..]).unwrap();
+    w.add_file("b.lua", &b"return 1"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+
+    let mut out = vec![];
+    archive
+        .print_json(&mut out, archive.entries().iter().skip(1))
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with(r#"{"path":"a","type":"dir","fragments":[{"offset":"#));
+    let fragment = &archive.fragments()[archive.entries()[3].index()][0];
+    assert_eq!(
+        lines[2],
+        format!(
+            r#"{{"path":"b.lua","type":"file","codec":"ZLIB","size":8,"stored":{},"chunks":1,"fragments":[{{"offset":{},"length":{}}}]}}"#,
+            fragment.length, fragment.offset, fragment.length
+        )
+    );
+    let f_txt = Path::new("a").join("f.txt");
+    let f_txt = f_txt.to_string_lossy().replace('\\', "\\\\");
+    assert!(lines[1].starts_with(&format!(r#"{{"path":"{}","type":"file","#, f_txt)));
+}
+
+#[test]
+fn debug_dump() {
+    let content = "Hello World, ".repeat(100);
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("maps/a.lst", content.as_bytes()).unwrap();
+    w.add_file("b.txt", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let mut out = vec![];
+    archive.debug().dump(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("  identifier: BPUL\n"));
+    assert!(out.contains("  _unknown2: 0xFF\n"));
+    assert!(out.contains("  _unknown5: 0x1\n"));
+    assert!(out.contains("  filesystem entries: 4\n"));
+    assert!(out.contains("  dir:  / index=1\n"));
+    assert!(out.contains("    dir:  maps index="));
+    assert!(out.contains("      file: a.lst index="));
+    assert!(out.contains("compressed: ZLIB inflated_length=1300 chunk_size=512 chunks=3"));
+    assert_eq!(out.matches("chunk: 0x").count(), 3);
+    assert!(out.contains("      stored: 6 bytes\n"));
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn empty_files_and_dirs() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let dest = root.path().join("output");
+
+    fs::create_dir_all(dir.join("empty_dir")).unwrap();
+    fs::create_dir_all(dir.join("folder/nested_empty")).unwrap();
+    fs::write(dir.join("empty.lst"), "").unwrap();
+    fs::write(dir.join("folder/empty.txt"), "").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let check = |archive: &hpk::HpkArchive| {
+        for name in &["empty.lst", "folder/empty.txt"] {
+            let entry = archive.entry(name).unwrap();
+            assert!(archive.read_file(name).unwrap().is_empty());
+            assert_eq!(archive.metadata(entry).unwrap().size, 0);
+            let mut buf = vec![];
+            let mut r = archive.open_entry(entry).unwrap();
+            assert_eq!(r.read_to_end(&mut buf).unwrap(), 0);
+        }
+        assert!(archive.read_dir("empty_dir").unwrap().is_empty());
+        assert!(archive.read_dir("folder/nested_empty").unwrap().is_empty());
+        assert!(archive.verify().unwrap().is_empty());
+    };
+
+    for (i, compressor) in ["zlib", "lz4", "zstd"].iter().enumerate() {
+        let file = root.path().join(format!("{}.hpk", compressor));
+        let mut options = hpk::CreateOptions::new();
+        options.with_checksums();
+        match i {
+            1 => options.use_lz4(),
+            2 => options.use_zstd(),
+            _ => {}
+        }
+        hpk::create(&options, &dir, &file).unwrap();
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        check(&archive);
+        assert!(archive.read_all(u64::MAX).unwrap()[Path::new("empty.lst")].is_empty());
+
+        let dest = dest.join(compressor);
+        archive.extract_to(&dest).unwrap();
+        assert!(dest.join("empty_dir").is_dir());
+        assert!(dest.join("folder/nested_empty").is_dir());
+        assert_eq!(fs::read(dest.join("empty.lst")).unwrap(), b"");
+        assert_eq!(fs::read(dest.join("folder/empty.txt")).unwrap(), b"");
+        drop(archive);
+
+        // replace a file with empty content and compact the archive
+        let replacement = root.path().join("replacement");
+        fs::write(&replacement, "").unwrap();
+        hpk::update(&options, &file, [("folder/six_bytes", &replacement)]).unwrap();
+        hpk::remove(&file, ["empty.lst"], true).unwrap();
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        assert!(archive.read_file("folder/six_bytes").unwrap().is_empty());
+        assert!(archive.entry("empty.lst").is_none());
+        assert!(archive.read_dir("empty_dir").unwrap().is_empty());
+        assert!(archive.verify().unwrap().is_empty());
+    }
+
+    // an archive without any entries
+    let empty = root.path().join("empty_input");
+    let file = root.path().join("empty.hpk");
+    fs::create_dir(&empty).unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &empty, &file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.entries().len(), 1);
+    assert!(archive.read_dir("").unwrap().is_empty());
+    archive.extract_to(dest.join("empty")).unwrap();
+}
+
+#[test]
+fn random_access() {
+    let content: Vec<u8> = (0..1300u32).map(|i| (i % 251) as u8).collect();
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(512);
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("folder/a.lst", &content[..]).unwrap();
+    w.add_file("folder/b.txt", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let mut r = archive
+        .open_entry(archive.entry("folder/a.lst").unwrap())
+        .unwrap();
+    assert_eq!(r.len(), 1300);
+    for &pos in &[1000, 10, 510, 1299, 0] {
+        let end = std::cmp::min(pos as usize + 4, content.len());
+        let mut buf = vec![0; end - pos as usize];
+        r.seek(io::SeekFrom::Start(pos)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &content[pos as usize..end]);
+    }
+    r.seek(io::SeekFrom::End(-3)).unwrap();
+    let mut tail = vec![];
+    r.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &content[1297..]);
+    assert_eq!(r.seek(io::SeekFrom::Current(5)).unwrap(), 1305);
+    assert_eq!(r.read(&mut [0; 4]).unwrap(), 0);
+    assert!(r.seek(io::SeekFrom::End(-1301)).is_err());
+
+    let mut r = archive
+        .open_entry(archive.entry("folder/b.txt").unwrap())
+        .unwrap();
+    r.seek(io::SeekFrom::Start(2)).unwrap();
+    let mut buf = String::new();
+    r.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "CDEF");
+
+    let names: Vec<_> = archive
+        .read_dir("folder")
+        .unwrap()
+        .iter()
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    assert_eq!(
+        names,
+        [Path::new("folder/a.lst"), Path::new("folder/b.txt")]
+    );
+    assert_eq!(archive.read_dir("").unwrap().len(), 1);
+    assert!(archive.read_dir("folder/a.lst").is_err());
+}
+
+#[test]
+fn probe_compression() {
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    impl Seek for Broken {
+        fn seek(&mut self, _: io::SeekFrom) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    for data in &[&b""[..], b"Z", b"ZLI", b"LZ4"] {
+        let mut r = io::Cursor::new(data);
+        let compression = hpk::get_compression(&mut r).unwrap();
+        assert_eq!(compression, hpk::Compression::None);
+        assert_eq!(r.position(), 0);
+    }
+    let compression = hpk::get_compression(&mut io::Cursor::new(b"ZLIB")).unwrap();
+    assert_eq!(compression, hpk::Compression::Zlib);
+    assert!(hpk::get_compression(&mut Broken).is_err());
+}
+
+#[test]
+fn extract_sparse_files() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("sparse.hpk");
+    let dest = root.path().join("output");
+
+    let mut map = vec![0u8; 1024 * 1024];
+    map[..4].copy_from_slice(b"MAP1");
+    map[500_000] = 0xFF;
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("map.bin"), &map).unwrap();
+    fs::write(dir.join("zeros.bin"), vec![0u8; 64 * 1024]).unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    let mut options = hpk::ExtractOptions::new();
+    options.sparse_files();
+    hpk::extract(&options, &file, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("map.bin")).unwrap(), map);
+    assert_eq!(
+        fs::read(dest.join("zeros.bin")).unwrap(),
+        vec![0u8; 64 * 1024]
+    );
+}
+
+#[test]
+fn archive_stats() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("stats.hpk");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&file).unwrap()).unwrap();
+    w.add_file("scripts/a.lua", "return 1; ".repeat(100).as_bytes())
+        .unwrap();
+    w.add_file("scripts/b.LUA", &b"return 2"[..]).unwrap();
+    w.add_file("readme", &b"ABCDEF"[..]).unwrap();
+    w.finish().unwrap();
+
+    let stats = hpk::ArchiveStats::compute(&hpk::HpkArchive::open(&file).unwrap()).unwrap();
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.dirs, 1);
+    assert_eq!(stats.size, 1014);
+    assert!(stats.ratio() < 0.5);
+    assert_eq!(stats.padding, 0);
+
+    let exts: Vec<_> = stats.extensions.iter().map(|e| &e.extension[..]).collect();
+    assert_eq!(exts, ["", "lua"]);
+    assert_eq!(stats.extensions[1].files, 2);
+    assert_eq!(stats.extensions[1].size, 1008);
+    assert_eq!(stats.extensions[0].ratio(), 1.0);
+
+    let largest: Vec<_> = stats.largest.iter().map(|e| e.path.as_path()).collect();
+    assert_eq!(
+        largest,
+        [
+            Path::new("scripts/a.lua"),
+            Path::new("scripts/b.LUA"),
+            Path::new("readme")
+        ]
+    );
+    assert_eq!(stats.largest[0].compression, hpk::Compression::Zlib);
+
+    // the removed file leaves a gap in the data section
+    hpk::remove(&file, ["scripts/b.LUA"], false).unwrap();
+    let stats = hpk::ArchiveStats::compute(&hpk::HpkArchive::open(&file).unwrap()).unwrap();
+    assert_eq!(stats.files, 2);
+    assert!(stats.padding >= 8);
+}
+
+#[test]
+fn archive_manifest() {
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    w.add_file("folder/a.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("b.txt", &b"ABCDEF"[..]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    let manifest = archive.manifest().unwrap();
+    assert!(!manifest.compressed);
+    assert_eq!(manifest.header.data_offset, 36);
+    assert_eq!(manifest.fragments.len(), 4);
+    assert_eq!(manifest.entries.len(), 4);
+
+    let entry = &manifest.entries[2];
+    assert_eq!(entry.path, Path::new("folder/a.lst"));
+    assert_eq!(entry.depth, 2);
+    let md = entry.metadata.unwrap();
+    assert_eq!((md.compression, md.size), (hpk::Compression::Zlib, 11));
+    assert!(manifest.entries[1].metadata.is_none());
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["header"]["data_offset"], 36);
+        assert_eq!(json["entries"][2]["path"], "folder/a.lst");
+        assert_eq!(json["entries"][2]["metadata"]["compression"], "Zlib");
+        assert_eq!(json["entries"][3]["metadata"]["size"], 6);
+    }
+}
+
+#[test]
+fn diff_archives() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let a = root.path().join("a.hpk");
+    let b = root.path().join("b.hpk");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&a).unwrap()).unwrap();
+    w.add_file("same.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("recompressed.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("changed.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("removed/file.txt", &b"ABC"[..]).unwrap();
+    w.finish().unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.compress_if(|path| path != Path::new("recompressed.lst"));
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&b).unwrap()).unwrap();
+    w.add_file("same.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("recompressed.lst", &b"Hello World"[..]).unwrap();
+    w.add_file("changed.lst", &b"Hello Earth"[..]).unwrap();
+    w.add_file("added.txt", &b"DEF"[..]).unwrap();
+    w.finish().unwrap();
+
+    let changes: Vec<_> = hpk::diff(&a, &b)
+        .unwrap()
+        .into_iter()
+        .map(|e| (e.path, e.kind, e.is_dir))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (PathBuf::from("added.txt"), hpk::DiffKind::Added, false),
+            (PathBuf::from("changed.lst"), hpk::DiffKind::Modified, false),
+            (PathBuf::from("removed"), hpk::DiffKind::Removed, true),
+            (
+                PathBuf::from("removed/file.txt"),
+                hpk::DiffKind::Removed,
+                false
+            ),
+        ]
+    );
+}
+
+#[test]
+fn patch_archives() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let old = root.path().join("old");
+    let new = root.path().join("new");
+    for dir in &[&old, &new] {
+        fs::create_dir_all(dir.join("maps")).unwrap();
+        fs::write(dir.join("same.lst"), "Hello World").unwrap();
+        fs::write(dir.join("maps/same.bin"), "map").unwrap();
+    }
+    fs::write(old.join("changed.lua"), "return 1").unwrap();
+    fs::write(new.join("changed.lua"), "return 2").unwrap();
+    fs::write(old.join("removed.txt"), "ABC").unwrap();
+    fs::create_dir_all(new.join("maps/empty")).unwrap();
+    fs::write(new.join("maps/added.bin"), "new map").unwrap();
+
+    let options = hpk::CreateOptions::new();
+    let old_file = root.path().join("old.hpk");
+    hpk::create(&options, &old, &old_file).unwrap();
+
+    let patch = root.path().join("patch.hpk");
+    for old in &[&old, &old_file] {
+        let changes: Vec<_> = hpk::patch(&options, old, &new, &patch)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.path, e.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (PathBuf::from("changed.lua"), hpk::DiffKind::Modified),
+                (PathBuf::from("maps/added.bin"), hpk::DiffKind::Added),
+                (PathBuf::from("maps/empty"), hpk::DiffKind::Added),
+                (PathBuf::from("removed.txt"), hpk::DiffKind::Removed),
+            ]
+        );
+
+        let archive = hpk::HpkArchive::open(&patch).unwrap();
+        let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+        let expected = ["", "changed.lua", "maps", "maps/added.bin", "maps/empty"];
+        assert_eq!(paths, expected.iter().map(Path::new).collect::<Vec<_>>());
+        assert_eq!(
+            archive.metadata(&archive.entries()[1]).unwrap().compression,
+            hpk::Compression::Zlib
+        );
+    }
+
+    let overlay = hpk::OverlayArchive::new(vec![
+        hpk::HpkArchive::open(&old_file).unwrap(),
+        hpk::HpkArchive::open(&patch).unwrap(),
+    ]);
+    assert_eq!(overlay.read_file("changed.lua").unwrap(), b"return 2");
+    assert_eq!(overlay.read_file("maps/added.bin").unwrap(), b"new map");
+    assert_eq!(overlay.read_file("same.lst").unwrap(), b"Hello World");
+}
+
+#[test]
+fn patch_deltas() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let old = root.path().join("old");
+    let new = root.path().join("new");
+    fs::create_dir_all(old.join("maps")).unwrap();
+    fs::create_dir_all(new.join("maps")).unwrap();
+
+    // xorshift noise which doesn't compress
+    let mut x = 0x2545_F491_4F6C_DD1Du64;
+    let content: Vec<u8> = (0..200_000)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect();
+    let mut changed = content.clone();
+    changed[100_000..100_010].copy_from_slice(b"0123456789");
+    fs::write(old.join("maps/big.dat"), &content).unwrap();
+    fs::write(new.join("maps/big.dat"), &changed).unwrap();
+    fs::write(old.join("small.lst"), "old").unwrap();
+    fs::write(new.join("small.lst"), "new").unwrap();
+
+    let old_file = root.path().join("old.hpk");
+    hpk::create(&hpk::CreateOptions::new(), &old, &old_file).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.store_deltas(64 * 1024);
+    let patch = root.path().join("patch.hpk");
+    hpk::patch(&options, &old_file, &new, &patch).unwrap();
+    assert!(fs::metadata(&patch).unwrap().len() < 10_000);
+
+    let archive = hpk::HpkArchive::open(&patch).unwrap();
+    assert_eq!(archive.read_file("_deltas").unwrap(), b"maps/big.dat\n");
+    assert_eq!(archive.read_file("small.lst").unwrap(), b"new");
+    let old_archive = hpk::HpkArchive::open(&old_file).unwrap();
+    assert_eq!(
+        archive.read_patched(&old_archive, "maps/big.dat").unwrap(),
+        changed
+    );
+
+    let dest = root.path().join("dest");
+    hpk::apply_patch(&old_file, &patch, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("maps/big.dat")).unwrap(), changed);
+    assert_eq!(fs::read(dest.join("small.lst")).unwrap(), b"new");
+    assert!(!dest.join("_deltas").exists());
+
+    // the delta doesn't apply to other content
+    let err = hpk::apply_patch(&new, &patch, root.path().join("other")).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn write_at_offset() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let mut buf = io::Cursor::new(vec![0xFF; 512]);
+    buf.seek(io::SeekFrom::Start(512)).unwrap();
+    hpk::write_hpk(&Default::default(), &dir, &mut buf).unwrap();
+
+    let data = buf.into_inner();
+    assert_eq!(&data[..512], &[0xFF; 512][..]);
+    assert_eq!(&data[512..516], b"BPUL");
+
+    let file = root.path().join("container.bin");
+    fs::write(&file, &data).unwrap();
+
+    let mut walk = hpk::walk_at(&file, 512).unwrap();
+    assert!(!walk.is_compressed());
+
+    let mut files = vec![];
+    while let Some(dent) = walk.next() {
+        let dent = dent.unwrap();
+        if !dent.is_dir() {
+            walk.read_file(&dent, |mut r| {
+                let mut out = vec![];
+                hpk::copy(&mut r, &mut out)?;
+                files.push((dent.path().to_path_buf(), out));
+                Ok(())
+            })
+            .unwrap();
+        }
+    }
+    files.sort();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].0, Path::new("compressed.lst"));
+    assert_eq!(files[0].1, b"Hello World, Hello World");
+    assert_eq!(files[1].0, Path::new("folder/six_bytes"));
+    assert_eq!(files[1].1, b"ABCDEF");
+}
+
+#[test]
+fn cancel_create_and_extract() {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Asks another thread to cancel when the entry `at` starts and waits for it
+    struct CancelAt {
+        at: usize,
+        requests: Mutex<mpsc::Sender<mpsc::Sender<()>>>,
+    }
+
+    impl hpk::Progress for CancelAt {
+        fn on_entry(&self, _path: &Path, index: usize, _total: usize) {
+            if index == self.at {
+                let (done, wait) = mpsc::channel();
+                self.requests.lock().unwrap().send(done).unwrap();
+                wait.recv().unwrap();
+            }
+        }
+    }
+
+    // cancels the token on request and returns the time of the cancellation
+    let cancel_thread = |token: hpk::CancelToken| {
+        let (requests, rx) = mpsc::channel::<mpsc::Sender<()>>();
+        let canceller = thread::spawn(move || {
+            let done = rx.recv().ok()?;
+            token.cancel();
+            let cancelled = Instant::now();
+            done.send(()).unwrap();
+            Some(cancelled)
+        });
+        (Mutex::new(requests), canceller)
+    };
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    fs::create_dir(&dir).unwrap();
+
+    // pseudo random data to keep the zlib encoder busy
+    let mut seed = 0x2545_F491_u32;
+    for i in 0..8 {
+        let data: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        fs::write(dir.join(format!("file{:02}.bin", i)), data).unwrap();
+    }
+
+    // cancelled before the start
+    let token = hpk::CancelToken::new();
+    token.cancel();
+    let mut options = hpk::CreateOptions::new();
+    options.set_cancel_token(token);
+    let file = root.path().join("cancelled.hpk");
+    match hpk::create(&options, &dir, &file) {
+        Err(hpk::HpkError::Cancelled(completed)) => assert!(completed.is_empty()),
+        r => panic!("expected cancellation: {:?}", r),
+    }
+    assert!(!file.exists());
+
+    // cancelled from another thread before the fourth file
+    let token = hpk::CancelToken::new();
+    let (requests, canceller) = cancel_thread(token.clone());
+    let mut options = hpk::CreateOptions::new();
+    options.set_cancel_token(token);
+    options.set_progress(CancelAt { at: 3, requests });
+    let result = hpk::create(&options, &dir, &file);
+    let finished = Instant::now();
+    drop(options);
+    let cancelled = canceller.join().unwrap().expect("Should have cancelled");
+    match result {
+        Err(hpk::HpkError::Cancelled(completed)) => assert_eq!(
+            completed,
+            ["file00.bin", "file01.bin", "file02.bin"].map(PathBuf::from)
+        ),
+        r => panic!("expected cancellation: {:?}", r),
+    }
+    assert!(finished.duration_since(cancelled) < Duration::from_secs(2));
+    assert!(!file.exists());
+
+    // extraction
+    let mut options = hpk::CreateOptions::new();
+    options.with_extensions(vec![]);
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let token = hpk::CancelToken::new();
+    let mut options = hpk::ExtractOptions::new();
+    options.set_cancel_token(token.clone());
+    token.cancel();
+    let dest = root.path().join("extracted");
+    match hpk::extract(&options, &file, &dest) {
+        Err(hpk::HpkError::Cancelled(completed)) => assert!(completed.is_empty()),
+        r => panic!("expected cancellation: {:?}", r),
+    }
+
+    // cancelled from another thread while the third file is extracted
+    let token = hpk::CancelToken::new();
+    let (requests, canceller) = cancel_thread(token.clone());
+    let mut options = hpk::ExtractOptions::new();
+    options.set_cancel_token(token);
+    options.set_progress(CancelAt { at: 2, requests });
+    let result = hpk::extract(&options, &file, &dest);
+    let finished = Instant::now();
+    drop(options);
+    let cancelled = canceller.join().unwrap().expect("Should have cancelled");
+    match result {
+        Err(hpk::HpkError::Cancelled(completed)) => {
+            assert_eq!(completed, ["file00.bin", "file01.bin"].map(PathBuf::from))
+        }
+        r => panic!("expected cancellation: {:?}", r),
+    }
+    assert!(finished.duration_since(cancelled) < Duration::from_secs(2));
+    assert!(dest.join("file01.bin").exists());
+    assert!(!dest.join("file02.bin").exists());
+}
+
+#[test]
+fn resume_extraction() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct CancelAfter {
+        token: hpk::CancelToken,
+        bytes: AtomicU64,
+    }
+
+    impl hpk::Progress for CancelAfter {
+        fn on_bytes(&self, written: u64) {
+            if self.bytes.fetch_add(written, Ordering::SeqCst) + written > 200_000 {
+                self.token.cancel();
+            }
+        }
+    }
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("resume.hpk");
+    let dest = root.path().join("output");
+    let state = root.path().join("extract.state");
+
+    let big: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "Hello World").unwrap();
+    fs::write(dir.join("big.bin"), &big).unwrap();
+    fs::write(dir.join("c.txt"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    let token = hpk::CancelToken::new();
+    let mut options = hpk::ExtractOptions::new();
+    options.resume_state(&state);
+    options.set_cancel_token(token.clone());
+    options.set_progress(CancelAfter {
+        token,
+        bytes: AtomicU64::new(0),
+    });
+    match hpk::extract(&options, &file, &dest) {
+        Err(hpk::HpkError::Cancelled(completed)) => assert_eq!(completed, [Path::new("a.txt")]),
+        r => panic!("expected cancellation: {:?}", r),
+    }
+    let len = fs::metadata(dest.join("big.bin")).unwrap().len();
+    assert!(len > 0 && len < big.len() as u64);
+    assert!(!dest.join("c.txt").exists());
+    assert!(fs::read_to_string(&state)
+        .unwrap()
+        .starts_with("done\ta.txt\n"));
+
+    // the completed file isn't extracted again
+    fs::write(dest.join("a.txt"), "changed").unwrap();
+
+    let mut options = hpk::ExtractOptions::new();
+    options.resume_state(&state);
+    hpk::extract(&options, &file, &dest).unwrap();
+    assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"changed");
+    assert_eq!(fs::read(dest.join("big.bin")).unwrap(), big);
+    assert_eq!(fs::read(dest.join("c.txt")).unwrap(), b"ABCDEF");
+    assert!(!state.exists());
+}
+
+#[test]
+fn atomic_create() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("atomic.hpk");
+    let tmp = root.path().join("atomic.hpk.tmp");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "Hello World").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+    assert!(!tmp.exists());
+    let original = fs::read(&file).unwrap();
+
+    // the existing archive survives a failed run
+    let token = hpk::CancelToken::new();
+    token.cancel();
+    let mut options = hpk::CreateOptions::new();
+    options.set_cancel_token(token);
+    assert!(hpk::create(&options, &dir, &file).is_err());
+    assert_eq!(fs::read(&file).unwrap(), original);
+    assert!(!tmp.exists());
+
+    fs::write(dir.join("b.txt"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.read_file("b.txt").unwrap(), b"ABCDEF");
+
+    // written in place, a cancelled run removes the archive
+    let token = hpk::CancelToken::new();
+    token.cancel();
+    let mut options = hpk::CreateOptions::new();
+    options.set_atomic(false);
+    options.set_cancel_token(token);
+    assert!(hpk::create(&options, &dir, &file).is_err());
+    assert!(!file.exists());
+}
+
+#[test]
+fn defrag_archive() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("defrag.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("a.txt"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/b.lua"), "return 1").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    // split the fragments of the files into two like the game's archives
+    let dirs: Vec<_> = hpk::HpkArchive::open(&file)
+        .unwrap()
+        .entries()
+        .iter()
+        .filter(|e| e.is_dir())
+        .map(|e| e.index())
+        .collect();
+    let mut data = fs::read(&file).unwrap();
+    let u32_at = |data: &[u8], pos: usize| {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&data[pos..pos + 4]);
+        u32::from_le_bytes(buf)
+    };
+    let fs_offset = u32_at(&data, 28) as usize;
+    let fs_length = u32_at(&data, 32) as usize;
+    let mut table = vec![];
+    for (index, pos) in (fs_offset..fs_offset + fs_length).step_by(8).enumerate() {
+        let (offset, length) = (u32_at(&data, pos), u32_at(&data, pos + 4));
+        let split = if dirs.contains(&index) {
+            length
+        } else {
+            length / 2
+        };
+        for &(offset, length) in &[(offset, split), (offset + split, length - split)] {
+            table.extend_from_slice(&offset.to_le_bytes());
+            table.extend_from_slice(&length.to_le_bytes());
+        }
+    }
+    data.truncate(fs_offset);
+    data.extend_from_slice(&table);
+    data[8..12].copy_from_slice(&2u32.to_le_bytes());
+    data[32..36].copy_from_slice(&(table.len() as u32).to_le_bytes());
+    fs::write(&file, &data).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().fragments_per_file, 2);
+    assert_eq!(archive.read_file("folder/b.lua").unwrap(), b"return 1");
+    drop(archive);
+
+    hpk::defrag(&file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().fragments_per_file, 1);
+    assert!(archive.fragments().iter().all(|f| f.len() == 1));
+    assert_eq!(
+        archive.read_file("a.txt").unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert_eq!(archive.read_file("folder/b.lua").unwrap(), b"return 1");
+    assert!(fs::metadata(&file).unwrap().len() < data.len() as u64);
+}
+
+#[test]
+fn fragments_per_file() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("fragments.hpk");
+
+    fs::create_dir_all(dir.join("folder/empty")).unwrap();
+    fs::write(dir.join("a.txt"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/b.lua"), "return 1").unwrap();
+    fs::write(dir.join("folder/c.bin"), "AB").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_fragments_per_file(3);
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().fragments_per_file, 3);
+    assert_eq!(archive.header().filesystem_entries(), 6);
+    assert!(archive.fragments().iter().all(|f| f.len() == 3));
+    let a = &archive.fragments()[archive.entry("a.txt").unwrap().index()];
+    assert_eq!(a[0].length, 8);
+    assert_eq!(a[1].offset, a[0].offset + 8);
+    assert_eq!(a[2].length, 8);
+    assert_eq!(
+        archive.read_file("a.txt").unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert_eq!(archive.read_file("folder/b.lua").unwrap(), b"return 1");
+    assert_eq!(archive.read_file("folder/c.bin").unwrap(), b"AB");
+    assert!(archive.verify().unwrap().is_empty());
+
+    let dest = root.path().join("output");
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(fs::read(dest.join("folder/b.lua")).unwrap(), b"return 1");
+    assert!(dest.join("folder/empty").is_dir());
+}
+
+#[test]
+fn custom_data_offset() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("offset.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "Hello World").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_data_offset(64);
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let data = fs::read(&file).unwrap();
+    assert_eq!(&data[4..8], &64u32.to_le_bytes());
+    assert!(data[36..64].iter().all(|&b| b == 0));
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().data_offset, 64);
+    assert!(!archive.header().is_wide());
+    assert!(archive.fragments().iter().all(|f| f[0].offset >= 64));
+    assert_eq!(archive.read_file("a.txt").unwrap(), b"Hello World");
+    drop(archive);
+
+    // updating keeps the offset
+    fs::write(dir.join("a.txt"), "ABCDEF").unwrap();
+    hpk::update(
+        &hpk::CreateOptions::new(),
+        &file,
+        vec![("a.txt", dir.join("a.txt"))],
+    )
+    .unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().data_offset, 64);
+    assert_eq!(archive.read_file("a.txt").unwrap(), b"ABCDEF");
+    drop(archive);
+
+    hpk::defrag(&file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.header().data_offset, 64);
+    assert_eq!(archive.read_file("a.txt").unwrap(), b"ABCDEF");
+
+    // the content can't overlap the header
+    for &(offset, wide) in &[(20, false), (36, true), (48, true)] {
+        let mut options = hpk::CreateOptions::new();
+        options.with_data_offset(offset);
+        if wide {
+            options.with_wide_offsets();
+        }
+        assert!(hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).is_err());
+    }
+}
+
+#[test]
+fn archive_api() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::create_dir_all(dir.join("empty_folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let file = root.path().join("test.hpk");
+    hpk::create(&Default::default(), &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            Path::new(""),
+            Path::new("compressed.lst"),
+            Path::new("empty_folder"),
+            Path::new("folder"),
+            Path::new("folder/six_bytes"),
+        ]
+    );
+    assert_eq!(
+        archive.read_file("compressed.lst").unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert_eq!(archive.read_file("folder/six_bytes").unwrap(), b"ABCDEF");
+    assert!(matches!(
+        archive.read_file("folder"),
+        Err(hpk::HpkError::EntryNotFound(_))
+    ));
+    assert!(matches!(
+        archive.read_file("missing"),
+        Err(hpk::HpkError::EntryNotFound(_))
+    ));
+
+    let dest = root.path().join("extracted");
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(
+        fs::read(dest.join("compressed.lst")).unwrap(),
+        b"Hello World, Hello World"
+    );
+    assert_eq!(fs::read(dest.join("folder/six_bytes")).unwrap(), b"ABCDEF");
+    assert!(dest.join("empty_folder").is_dir());
+}
+
+#[test]
+fn lz4_round_trip() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let content = "Hello World, ".repeat(10_000);
+    fs::create_dir_all(dir.join("scripts")).unwrap();
+    fs::write(dir.join("scripts/init.lua"), &content).unwrap();
+    fs::write(dir.join("data.csv"), "a;b;c").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.use_lz4();
+    options.with_chunk_size(4096);
+    let file = root.path().join("lz4.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(!archive.is_compressed());
+    assert_eq!(
+        archive.read_file("scripts/init.lua").unwrap(),
+        content.as_bytes()
+    );
+    assert_eq!(archive.read_file("data.csv").unwrap(), b"a;b;c");
+
+    options.compress();
+    let file = root.path().join("lz4-compressed.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+    assert_eq!(&fs::read(&file).unwrap()[..4], b"LZ4 ");
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(archive.is_compressed());
+    assert_eq!(
+        archive.read_file("scripts/init.lua").unwrap(),
+        content.as_bytes()
+    );
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn zstd_round_trip() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let content = "Hello World, ".repeat(10_000);
+    fs::create_dir_all(dir.join("scripts")).unwrap();
+    fs::write(dir.join("scripts/init.lua"), &content).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_compressor(hpk::Compression::Zstd);
+    options.compress();
+    let file = root.path().join("zstd.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+    assert_eq!(&fs::read(&file).unwrap()[..4], b"ZSTD");
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(archive.is_compressed());
+    assert_eq!(
+        archive.read_file("scripts/init.lua").unwrap(),
+        content.as_bytes()
+    );
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_compressor(hpk::Compression::None);
+    let file = root.path().join("stored.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let mut walk = hpk::walk(&file).unwrap();
+    while let Some(Ok(dent)) = walk.next() {
+        walk.read_file(&dent, |mut r| {
+            assert!(!hpk::get_compression(&mut r)?.is_compressed());
+            assert_eq!(r.len(), content.len() as u64);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn open_entry_streaming() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    fs::create_dir_all(dir.join("scripts")).unwrap();
+    fs::write(dir.join("scripts/init.lua"), &content).unwrap();
+    fs::write(dir.join("raw.dat"), &content).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(4096);
+    let file = root.path().join("test.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+
+    let entry = archive.entry("scripts/init.lua").unwrap();
+    let mut r = archive.open_entry(entry).unwrap();
+    assert_eq!(r.compression(), hpk::Compression::Zlib);
+    let mut out = vec![];
+    let mut buf = [0; 1000];
+    loop {
+        let n = r.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(out, content);
+
+    let entry = archive.entry("raw.dat").unwrap();
+    let mut r = archive.open_entry(entry).unwrap();
+    assert_eq!(r.compression(), hpk::Compression::None);
+    let mut out = vec![];
+    r.read_to_end(&mut out).unwrap();
+    assert_eq!(out, content);
+
+    let entry = archive.entry("scripts").unwrap();
+    assert!(archive.open_entry(entry).is_err());
+}
+
+#[test]
+fn parallel_compression() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::create_dir_all(dir.join("textures")).unwrap();
+    fs::write(dir.join("textures/big.dds"), &content).unwrap();
+    fs::write(dir.join("small.lua"), "return 1").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(4096);
+    let single = root.path().join("single.hpk");
+    hpk::create(&options, &dir, &single).unwrap();
+
+    options.with_threads(4);
+    let parallel = root.path().join("parallel.hpk");
+    hpk::create(&options, &dir, &parallel).unwrap();
+
+    assert_eq!(fs::read(&single).unwrap(), fs::read(&parallel).unwrap());
+
+    let archive = hpk::HpkArchive::open(&parallel).unwrap();
+    assert_eq!(archive.read_file("textures/big.dds").unwrap(), content);
+}
+
+#[test]
+fn extract_include_exclude() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("filter.hpk");
+    let dest = root.path().join("output");
+
+    let options = hpk::CreateOptions::new();
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&file).unwrap()).unwrap();
+    w.add_file("scripts/init.lua", &b"return 1"[..]).unwrap();
+    w.add_file("scripts/ui/menu.lua", &b"return 2"[..]).unwrap();
+    w.add_file("scripts/ui/menu.xml", &b"<menu/>"[..]).unwrap();
+    w.add_file("textures/a.dds", &b"DDS"[..]).unwrap();
+    w.finish().unwrap();
+
+    let mut options = hpk::ExtractOptions::new();
+    options.set_paths(&["scripts/**".into()]);
+    options.set_excludes(&["*.xml".into()]);
+    hpk::extract(&options, &file, &dest).unwrap();
+
+    assert!(dest.join("scripts/init.lua").exists());
+    assert!(dest.join("scripts/ui/menu.lua").exists());
+    assert!(!dest.join("scripts/ui/menu.xml").exists());
+    assert!(!dest.join("textures/a.dds").exists());
+}
+
+type Entries = Vec<(PathBuf, usize, usize)>;
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<(Entries, u64)>>);
+
+impl hpk::Progress for Recorder {
+    fn on_entry(&self, path: &Path, index: usize, total: usize) {
+        let mut state = self.0.lock().unwrap();
+        state.0.push((path.to_path_buf(), index, total));
+    }
+
+    fn on_bytes(&self, written: u64) {
+        self.0.lock().unwrap().1 += written;
+    }
+}
+
+#[test]
+fn progress() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("progress.hpk");
+    let dest = root.path().join("output");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let expected = vec![
+        (PathBuf::from("compressed.lst"), 0, 2),
+        (PathBuf::from("folder/six_bytes"), 1, 2),
+    ];
+
+    let recorder = Recorder::default();
+    let mut options = hpk::CreateOptions::new();
+    options.set_progress(recorder.clone());
+    hpk::create(&options, &dir, &file).unwrap();
+    {
+        let state = recorder.0.lock().unwrap();
+        assert_eq!(state.0, expected);
+        assert_eq!(state.1, 30);
+    }
+
+    let recorder = Recorder::default();
+    let mut options = hpk::ExtractOptions::new();
+    options.set_progress(recorder.clone());
+    hpk::extract(&options, &file, &dest).unwrap();
+    let state = recorder.0.lock().unwrap();
+    assert_eq!(state.0, expected);
+    assert_eq!(state.1, 30);
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_interface() {
+    use hpk::ffi::*;
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("ffi.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+
+    let c_dir = CString::new(dir.to_str().unwrap()).unwrap();
+    let c_file = CString::new(file.to_str().unwrap()).unwrap();
+    unsafe {
+        assert_eq!(hpk_create_from_dir(c_dir.as_ptr(), c_file.as_ptr()), 0);
+
+        let archive = hpk_open(c_file.as_ptr());
+        assert!(!archive.is_null());
+        assert_eq!(hpk_entry_count(archive), 2);
+        assert_eq!(hpk_entry_is_dir(archive, 0), 1);
+        assert_eq!(hpk_entry_is_dir(archive, 1), 0);
+        assert_eq!(hpk_entry_is_dir(archive, 2), -1);
+
+        let mut buf = [0u8; 32];
+        let len = hpk_entry_path(archive, 1, ptr::null_mut(), 0);
+        assert_eq!(len, 16);
+        let len = hpk_entry_path(archive, 1, buf.as_mut_ptr().cast(), buf.len());
+        assert_eq!(&buf[..len as usize], b"folder/six_bytes");
+
+        let path = CString::new("folder/six_bytes").unwrap();
+        assert_eq!(
+            hpk_read_entry(archive, path.as_ptr(), ptr::null_mut(), 0),
+            6
+        );
+        let before = buf;
+        assert_eq!(
+            hpk_read_entry(archive, path.as_ptr(), buf.as_mut_ptr(), 5),
+            6
+        );
+        assert_eq!(buf, before);
+        let len = hpk_read_entry(archive, path.as_ptr(), buf.as_mut_ptr(), buf.len());
+        assert_eq!(&buf[..len as usize], b"ABCDEF");
+
+        let folder = CString::new("folder").unwrap();
+        assert_eq!(
+            hpk_read_entry(archive, folder.as_ptr(), ptr::null_mut(), 0),
+            -1
+        );
+
+        let missing = CString::new("missing").unwrap();
+        assert_eq!(
+            hpk_read_entry(archive, missing.as_ptr(), buf.as_mut_ptr(), 32),
+            -1
+        );
+        let msg = CStr::from_ptr(hpk_last_error()).to_str().unwrap();
+        assert!(msg.contains("missing"));
+        hpk_close(archive);
+
+        let missing = CString::new(root.path().join("missing.hpk").to_str().unwrap()).unwrap();
+        assert!(hpk_open(missing.as_ptr()).is_null());
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tokio_archive() {
+    use tokio::io::AsyncReadExt;
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("tokio.hpk");
+    let dest = root.path().join("output");
+
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("big.lst"), &content).unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let archive = hpk::tokio::HpkArchive::open(&file).await.unwrap();
+        assert_eq!(
+            archive.read_file("folder/six_bytes").await.unwrap(),
+            b"ABCDEF"
+        );
+
+        let entry = archive.entry("big.lst").unwrap();
+        assert_eq!(archive.metadata(entry).await.unwrap().size, 200_000);
+        let mut stream = archive.open_entry(entry).unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, content);
+
+        let entry = archive.entry("folder").unwrap();
+        assert!(archive.open_entry(entry).is_err());
+
+        archive.extract_to(&dest).await.unwrap();
+    });
+    assert_eq!(fs::read(dest.join("big.lst")).unwrap(), content);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_archive() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("mmap.hpk");
+    let dest = root.path().join("output");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("compressed.lst"), "Hello World, Hello World").unwrap();
+    fs::write(dir.join("folder/six_bytes"), "ABCDEF").unwrap();
+    hpk::create(&hpk::CreateOptions::new(), &dir, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open_mmap(&file).unwrap();
+    assert!(archive.is_mapped());
+    assert_eq!(
+        archive.read_file("compressed.lst").unwrap(),
+        b"Hello World, Hello World"
+    );
+
+    let entry = archive.entry("folder/six_bytes").unwrap();
+    let chunks = archive.chunks(entry).unwrap();
+    let bytes = archive.chunk_bytes(entry, &chunks[0]).unwrap();
+    assert!(matches!(bytes, std::borrow::Cow::Borrowed(b"ABCDEF")));
+    let mut r = archive.open_entry(entry).unwrap();
+    let mut buf = vec![];
+    r.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"ABCDEF");
+
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(
+        fs::read(dest.join("compressed.lst")).unwrap(),
+        b"Hello World, Hello World"
+    );
+}
+
+#[test]
+fn compress_filter() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let content = "Hello World, ".repeat(1000);
+    fs::create_dir_all(dir.join("Textures")).unwrap();
+    fs::write(dir.join("Textures/a.dds"), &content).unwrap();
+    fs::write(dir.join("readme.txt"), &content).unwrap();
+
+    fn compressed(file: &Path) -> Vec<(PathBuf, bool)> {
+        let mut walk = hpk::walk(file).unwrap();
+        let mut result = vec![];
+        while let Some(Ok(dent)) = walk.next() {
+            walk.read_file(&dent, |mut r| {
+                let c = hpk::get_compression(&mut r)?.is_compressed();
+                result.push((dent.path().to_path_buf(), c));
+                Ok(())
+            })
+            .unwrap();
+        }
+        result
+    }
+
+    let mut options = hpk::CreateOptions::new();
+    let file = root.path().join("default.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+    assert_eq!(
+        compressed(&file),
+        [
+            (PathBuf::from("Textures/a.dds"), true),
+            (PathBuf::from("readme.txt"), false)
+        ]
+    );
+
+    options.compress_if(|path| !path.starts_with("Textures"));
+    let file = root.path().join("filter.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+    assert_eq!(
+        compressed(&file),
+        [
+            (PathBuf::from("Textures/a.dds"), false),
+            (PathBuf::from("readme.txt"), true)
+        ]
+    );
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.read_file("readme.txt").unwrap(), content.as_bytes());
+}
+
+#[test]
+fn filedates_round_trip() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("a.lua"), "return 1").unwrap();
+    fs::write(dir.join("folder/b.xml"), "<b/>").unwrap();
+
+    let mtime = filetime::FileTime::from_unix_time(1_300_000_000, 0);
+    filetime::set_file_mtime(dir.join("a.lua"), mtime).unwrap();
+    filetime::set_file_mtime(dir.join("folder/b.xml"), mtime).unwrap();
+
+    let mtime_of = |path: &Path| {
+        let md = fs::metadata(path).unwrap();
+        filetime::FileTime::from_last_modification_time(&md).unix_seconds()
+    };
+
+    for short in &[false, true] {
+        let mut options = hpk::CreateOptions::new();
+        if *short {
+            options.with_short_filedates_format();
+        } else {
+            options.with_default_filedates_format();
+        }
+        let file = root.path().join(format!("filedates-{}.hpk", short));
+        hpk::create(&options, &dir, &file).unwrap();
+
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        assert!(archive.entry("_filedates").is_some());
+
+        let dest = root.path().join(format!("extracted-{}", short));
+        archive.extract_to(&dest).unwrap();
+        assert_eq!(mtime_of(&dest.join("a.lua")), 1_300_000_000);
+        assert_eq!(mtime_of(&dest.join("folder/b.xml")), 1_300_000_000);
+
+        let dest = root.path().join(format!("skipped-{}", short));
+        let mut options = hpk::ExtractOptions::new();
+        options.skip_filedates();
+        archive.extract_with(&options, &dest).unwrap();
+        assert_ne!(mtime_of(&dest.join("a.lua")), 1_300_000_000);
+        assert!(dest.join("_filedates").exists());
+    }
+}
+
+#[test]
+fn write_sources() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+
+    let mut memory = hpk::vfs::MemorySource::new();
+    let files = [
+        ("B.lst", "upper ".repeat(50)),
+        ("a/one.lua", "local x = 1\n".repeat(100)),
+        ("a/two.lua", "local x = 1\n".repeat(100)),
+        ("a/sub/three.txt", "three".to_string()),
+        ("skipped.bak", "old".to_string()),
+        (".hpkignore", "*.bak\n".to_string()),
+    ];
+    for (path, content) in &files {
+        fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+        fs::write(dir.join(path), content).unwrap();
+        memory.add_file(path, content.as_bytes());
+    }
+    fs::create_dir_all(dir.join("empty")).unwrap();
+    memory.add_dir("empty");
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_default_filedates_format();
+    options.deterministic();
+    options.deduplicate();
+    options.set_sort_order(hpk::SortOrder::CaseInsensitive);
+    let pack = |source: &dyn hpk::vfs::Source| {
+        let mut out = io::Cursor::new(vec![]);
+        hpk::write_source(&options, source, &mut out).unwrap();
+        out.into_inner()
+    };
+    let mut expected = io::Cursor::new(vec![]);
+    hpk::write_hpk(&options, &dir, &mut expected).unwrap();
+    let expected = expected.into_inner();
+    assert_eq!(pack(&hpk::vfs::FsSource::new(&dir)), expected);
+    assert_eq!(pack(&memory), expected);
+
+    // archive to archive without the scripts and the old filedates
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(expected)).unwrap();
+    let mut options = hpk::CreateOptions::new();
+    options.set_excludes(&["*.lua".to_string(), "_filedates".to_string()]);
+    let mut out = io::Cursor::new(vec![]);
+    hpk::write_source(&options, &archive, &mut out).unwrap();
+    let repacked = hpk::HpkArchive::from_reader(out).unwrap();
+    let paths: Vec<_> = repacked.entries().iter().map(|e| e.path()).collect();
+    let expected = ["", "B.lst", "a", "a/sub", "a/sub/three.txt", "empty"];
+    assert_eq!(paths, expected.iter().map(Path::new).collect::<Vec<_>>());
+    assert_eq!(repacked.read_file("a/sub/three.txt").unwrap(), b"three");
+
+    let err = hpk::vfs::Source::open(&memory, Path::new("a"))
+        .err()
+        .unwrap();
+    assert!(matches!(err, hpk::HpkError::EntryNotFound(_)));
+}
+
+#[test]
+fn data_order() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+
+    let mut source = hpk::vfs::MemorySource::new();
+    for path in &["a.dds", "a.lua", "b/c.lua", "b/d.dds", "e.txt"] {
+        source.add_file(path, path.as_bytes());
+    }
+    let data_order = |options: &hpk::CreateOptions| {
+        let mut out = io::Cursor::new(vec![]);
+        hpk::write_source(options, &source, &mut out).unwrap();
+        let archive = hpk::HpkArchive::from_reader(out).unwrap();
+        let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+        assert_eq!(
+            paths,
+            ["", "a.dds", "a.lua", "b", "b/c.lua", "b/d.dds", "e.txt"]
+                .iter()
+                .map(Path::new)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(archive.read_file("b/d.dds").unwrap(), b"b/d.dds");
+
+        let mut files: Vec<_> = archive.entries().iter().filter(|e| !e.is_dir()).collect();
+        files.sort_by_key(|e| archive.fragments()[e.index()][0].offset);
+        files
+            .iter()
+            .map(|e| e.path().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let mut options = hpk::CreateOptions::new();
+    assert_eq!(
+        data_order(&options),
+        ["a.dds", "a.lua", "b/c.lua", "b/d.dds", "e.txt"]
+    );
+
+    options.set_data_order(hpk::DataOrder::Extensions(vec!["LUA".into()]));
+    assert_eq!(
+        data_order(&options),
+        ["a.lua", "b/c.lua", "a.dds", "b/d.dds", "e.txt"]
+    );
+
+    let profile = root.path().join("profile.txt");
+    fs::write(&profile, "# startup\nb\\d.dds\n\n/e.txt\nmissing.lua\n").unwrap();
+    options.set_data_order(hpk::DataOrder::read_profile(&profile).unwrap());
+    assert_eq!(
+        data_order(&options),
+        ["b/d.dds", "e.txt", "a.dds", "a.lua", "b/c.lua"]
+    );
+
+    options.order_data_by(|a, b| b.cmp(a));
+    assert_eq!(
+        data_order(&options),
+        ["e.txt", "b/d.dds", "b/c.lua", "a.lua", "a.dds"]
+    );
+}
+
+#[test]
+fn aligned_files() {
+    let mut options = hpk::CreateOptions::new();
+    options.with_alignment(4096);
+    let mut writer = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    writer
+        .add_file("a.lua", &b"return 1".repeat(100)[..])
+        .unwrap();
+    writer
+        .add_file_with("b.dds", &[1u8; 5000][..], hpk::Compression::None)
+        .unwrap();
+    writer
+        .add_file("c.lua", &b"return 2".repeat(100)[..])
+        .unwrap();
+    writer.add_file("d.txt", &b"stored"[..]).unwrap();
+    let out = writer.finish().unwrap();
+
+    let archive = hpk::HpkArchive::from_reader(out).unwrap();
+    let offset = |path: &str| {
+        let entry = archive.entry(path).unwrap();
+        archive.fragments()[entry.index()][0].offset
+    };
+    assert_ne!(offset("a.lua") % 4096, 0);
+    assert_eq!(offset("b.dds") % 4096, 0);
+    assert_eq!(offset("d.txt") % 4096, 0);
+    assert_eq!(archive.read_file("b.dds").unwrap(), [1u8; 5000]);
+    assert_eq!(archive.read_file("c.lua").unwrap(), b"return 2".repeat(100));
+    assert_eq!(archive.read_file("d.txt").unwrap(), b"stored");
+
+    options.with_alignment(3000);
+    let err = hpk::HpkWriter::new(&options, io::Cursor::new(vec![]))
+        .err()
+        .unwrap();
+    assert!(matches!(err, hpk::HpkError::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn raw_fragments() {
+    let mut options = hpk::CreateOptions::new();
+    options.with_fragments_per_file(2);
+    let mut writer = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    writer
+        .add_file("main.lua", &b"return 1\n".repeat(500)[..])
+        .unwrap();
+    writer.add_file("blob.bin", &b"opaque"[..]).unwrap();
+    let source = hpk::HpkArchive::from_reader(writer.finish().unwrap()).unwrap();
+
+    let options = hpk::CreateOptions::new();
+    let mut writer = hpk::HpkWriter::new(&options, io::Cursor::new(vec![])).unwrap();
+    for entry in source.entries().iter().filter(|e| !e.is_dir()) {
+        let mut bytes = vec![];
+        for fragment in &source.fragments()[entry.index()] {
+            bytes.extend(source.read_fragment_raw(fragment).unwrap());
+        }
+        let fragment = writer.write_fragment_raw(entry.path(), &bytes).unwrap();
+        assert_eq!(fragment.length, bytes.len() as u64);
+    }
+    assert!(writer.write_fragment_raw("blob.bin", b"twice").is_err());
+    let copy = hpk::HpkArchive::from_reader(writer.finish().unwrap()).unwrap();
+
+    let main = copy.entry("main.lua").unwrap();
+    assert!(copy.metadata(main).unwrap().compression.is_compressed());
+    assert_eq!(
+        copy.read_file("main.lua").unwrap(),
+        b"return 1\n".repeat(500)
+    );
+    assert_eq!(copy.read_file("blob.bin").unwrap(), b"opaque");
+    let stored = |archive: &hpk::HpkArchive, path: &str| {
+        let entry = archive.entry(path).unwrap();
+        let fragments = &archive.fragments()[entry.index()];
+        fragments
+            .iter()
+            .flat_map(|f| archive.read_fragment_raw(f).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(stored(&copy, "main.lua"), stored(&source, "main.lua"));
+
+    let header = copy.header();
+    let end = header.fragmented_filesystem_offset + header.fragmented_filesystem_length;
+    let beyond = hpk::Fragment::new(end, 1);
+    assert!(copy.read_fragment_raw(&beyond).is_err());
+}
+
+#[test]
+fn deduplicate_files() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("dedup.hpk");
+
+    let content = "local x = 1\n".repeat(100);
+    fs::create_dir_all(dir.join("mod_a")).unwrap();
+    fs::create_dir_all(dir.join("mod_b")).unwrap();
+    fs::write(dir.join("mod_a/init.lua"), &content).unwrap();
+    fs::write(dir.join("mod_b/main.lua"), &content).unwrap();
+    // same length, different content
+    fs::write(dir.join("mod_b/other.lua"), content.replace('1', "2")).unwrap();
+    // same content, stored uncompressed
+    fs::write(dir.join("mod_b/copy.txt"), &content).unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_checksums();
+    hpk::create(&options, &dir, &file).unwrap();
+    let size = fs::metadata(&file).unwrap().len();
+
+    options.deduplicate();
+    hpk::create(&options, &dir, &file).unwrap();
+    assert!(fs::metadata(&file).unwrap().len() < size);
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let fragment = |path: &str| {
+        let entry = archive.entry(path).unwrap();
+        archive.fragments()[entry.index()][0].clone()
+    };
+    let (a, b) = (fragment("mod_a/init.lua"), fragment("mod_b/main.lua"));
+    assert_eq!((a.offset, a.length), (b.offset, b.length));
+    assert_ne!(a.offset, fragment("mod_b/other.lua").offset);
+    assert_ne!(a.offset, fragment("mod_b/copy.txt").offset);
+    assert_eq!(
+        archive.read_file("mod_b/main.lua").unwrap(),
+        content.as_bytes()
+    );
+    assert!(archive.verify().unwrap().is_empty());
+
+    let dest = root.path().join("output");
+    archive.extract_to(&dest).unwrap();
+    assert_eq!(
+        fs::read(dest.join("mod_b/main.lua")).unwrap(),
+        content.as_bytes()
+    );
+}
+
+#[test]
+fn extract_duplicates() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("duplicates.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.lua"), "return 1").unwrap();
+    fs::write(dir.join("b.lua"), "return 1").unwrap();
+    fs::write(dir.join("c.lua"), "return 2").unwrap();
+    let mut options = hpk::CreateOptions::new();
+    options.deduplicate();
+    hpk::create(&options, &dir, &file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+
+    for &policy in &[
+        hpk::DuplicatePolicy::Extract,
+        hpk::DuplicatePolicy::HardLink,
+        hpk::DuplicatePolicy::Copy,
+    ] {
+        let dest = root.path().join(format!("{:?}", policy));
+        // an existing file is replaced instead of written through a link
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("b.lua"), "old").unwrap();
+
+        let mut options = hpk::ExtractOptions::new();
+        options.set_duplicate_policy(policy);
+        archive.extract_with(&options, &dest).unwrap();
+        for (name, content) in &[
+            ("a.lua", "return 1"),
+            ("b.lua", "return 1"),
+            ("c.lua", "return 2"),
+        ] {
+            assert_eq!(fs::read_to_string(dest.join(name)).unwrap(), *content);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |name: &str| fs::metadata(dest.join(name)).unwrap().ino();
+            let linked = policy == hpk::DuplicatePolicy::HardLink;
+            assert_eq!(inode("a.lua") == inode("b.lua"), linked);
+            assert_ne!(inode("a.lua"), inode("c.lua"));
+        }
+    }
+}
+
+#[test]
+fn extract_existing_files() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("existing.hpk");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.lua"), "return 1").unwrap();
+    fs::write(dir.join("README"), "new").unwrap();
+    let mut options = hpk::CreateOptions::new();
+    options.with_default_filedates_format();
+    hpk::create(&options, &dir, &file).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+
+    for &(policy, status) in &[
+        (hpk::ExistingPolicy::Overwrite, "overwritten"),
+        (hpk::ExistingPolicy::Skip, "skipped"),
+        (hpk::ExistingPolicy::Rename, "renamed"),
+    ] {
+        let dest = root.path().join(format!("{:?}", policy));
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("a.lua"), "old").unwrap();
+        fs::write(dest.join("a.1.lua"), "old").unwrap();
+        fs::write(dest.join("README"), "old").unwrap();
+
+        let audit = root.path().join(format!("{:?}.jsonl", policy));
+        let mut options = hpk::ExtractOptions::new();
+        options.set_existing_policy(policy);
+        options.audit_manifest(fs::File::create(&audit).unwrap());
+        #[cfg(feature = "sha256")]
+        options.audit_digests();
+        archive.extract_with(&options, &dest).unwrap();
+
+        let log = fs::read_to_string(&audit).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        // the filedates aren't written to a file, they aren't recorded
+        assert_eq!(lines.len(), 3);
+        assert!(!log.contains("_filedates"));
+        assert!(lines[..2]
+            .iter()
+            .all(|l| l.contains(&format!(r#""status":"{}""#, status))));
+
+        let (a, readme) = match policy {
+            hpk::ExistingPolicy::Rename => ("a.2.lua", "README.1"),
+            _ => ("a.lua", "README"),
+        };
+        let extracted = policy != hpk::ExistingPolicy::Skip;
+        assert_eq!(
+            fs::read_to_string(dest.join(a)).unwrap() == "return 1",
+            extracted
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join(readme)).unwrap() == "new",
+            extracted
+        );
+        assert_eq!(fs::read_to_string(dest.join("a.1.lua")).unwrap(), "old");
+        // the digest of "return 1"
+        let digest =
+            r#""digest":"486d9affb60dbb0063b03d8e23a6ccf6364ce203dc3a9f56f20e750eb41ecade""#;
+        assert_eq!(log.contains(digest), extracted && cfg!(feature = "sha256"));
+    }
+}
+
+#[test]
+fn sync_directory() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("sync.hpk");
+
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::create_dir_all(dir.join("old/nested")).unwrap();
+    fs::write(dir.join("a.lua"), "return 1").unwrap();
+    fs::write(dir.join("folder/b.xml"), "<b/>").unwrap();
+    fs::write(dir.join("folder/c.txt"), "unchanged").unwrap();
+    fs::write(dir.join("old/nested/x.txt"), "removed").unwrap();
+    let mtime = filetime::FileTime::from_unix_time(1_300_000_000, 0);
+    for name in &["a.lua", "folder/b.xml", "folder/c.txt"] {
+        filetime::set_file_mtime(dir.join(name), mtime).unwrap();
+    }
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_short_filedates_format();
+    hpk::create(&options, &dir, &file).unwrap();
+
+    fs::write(dir.join("a.lua"), "return 2").unwrap();
+    fs::write(dir.join("d.txt"), "added").unwrap();
+    fs::remove_dir_all(dir.join("old")).unwrap();
+    // a newer modification time alone doesn't change the file
+    filetime::set_file_mtime(dir.join("folder/b.xml"), filetime::FileTime::now()).unwrap();
+
+    // the format of the existing `_filedates` entry is kept
+    let options = hpk::CreateOptions::new();
+    let summary = hpk::sync_dir(&options, &dir, &file).unwrap();
+    assert_eq!(summary.added, [Path::new("d.txt")]);
+    assert_eq!(summary.changed, [Path::new("a.lua")]);
+    assert_eq!(summary.removed, [Path::new("old")]);
+    assert_eq!(summary.unchanged, 2);
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.read_file("a.lua").unwrap(), b"return 2");
+    assert_eq!(archive.read_file("d.txt").unwrap(), b"added");
+    assert_eq!(archive.read_file("folder/c.txt").unwrap(), b"unchanged");
+    assert!(archive.entry("old").is_none());
+    let filedates = String::from_utf8(archive.read_file("_filedates").unwrap()).unwrap();
+    assert!(filedates.contains("d.txt="));
+    assert!(filedates.contains(&format!(
+        "folder/c.txt={}",
+        (1_300_000_000 + 11_644_473_600i64) * 10_000_000 / 2000
+    )));
+    drop(archive);
+
+    // nothing to do, the archive isn't written
+    let data = fs::read(&file).unwrap();
+    let summary = hpk::sync_dir(&options, &dir, &file).unwrap();
+    assert!(summary.is_empty());
+    assert_eq!(summary.unchanged, 4);
+    assert_eq!(fs::read(&file).unwrap(), data);
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn watch_directory() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("watched.hpk");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.lua"), "return 1").unwrap();
+
+    let cancel = hpk::CancelToken::new();
+    let mut options = hpk::CreateOptions::new();
+    options.set_cancel_token(cancel.clone());
+    assert!(hpk::watch(&options, &dir, dir.join("inside.hpk"), |_| {}).is_err());
+
+    let (tx, rx) = mpsc::channel();
+    let watcher = {
+        let (dir, file) = (dir.clone(), file.clone());
+        std::thread::spawn(move || {
+            hpk::watch(&options, dir, file, |result| {
+                tx.send(result.unwrap()).unwrap()
+            })
+        })
+    };
+    let timeout = Duration::from_secs(10);
+    // the archive is created first
+    assert!(rx.recv_timeout(timeout).unwrap().is_empty());
+    assert!(file.exists());
+
+    fs::write(dir.join("b.xml"), "<b/>").unwrap();
+    fs::write(dir.join("a.lua"), "return 2").unwrap();
+    let mut summary = rx.recv_timeout(timeout).unwrap();
+    // the events of the second file may come too late for the first update
+    while summary.changed.is_empty() {
+        summary = rx.recv_timeout(timeout).unwrap();
+    }
+    cancel.cancel();
+    watcher.join().unwrap().unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert_eq!(archive.read_file("a.lua").unwrap(), b"return 2");
+    assert_eq!(archive.read_file("b.xml").unwrap(), b"<b/>");
+}
+
+#[test]
+fn split_volumes() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("archive.hpk");
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("a.dat"), "0123456789".repeat(500)).unwrap();
+    fs::write(dir.join("folder/b.xml"), "<b/>".repeat(100)).unwrap();
+
+    // the volumes of an older and larger archive
+    for index in 0..=10 {
+        fs::write(hpk::volume_path(&file, index), "old").unwrap();
+    }
+
+    let mut options = hpk::CreateOptions::new();
+    options.split_volumes(1000);
+    hpk::create(&options, &dir, &file).unwrap();
+    assert!(!file.exists());
+    assert!(!hpk::volume_path(&file, 9).exists());
+    assert!(!hpk::volume_path(&file, 10).exists());
+
+    let r = hpk::VolumeReader::open(&file).unwrap();
+    let volumes = r.volumes();
+    assert_eq!(volumes, r.len().div_ceil(1000) as usize);
+    assert!(volumes >= 5);
+    assert_eq!(
+        root.path().join("archive_000.hpk"),
+        hpk::volume_path(&file, 0)
+    );
+    for index in 0..volumes - 1 {
+        let len = fs::metadata(hpk::volume_path(&file, index)).unwrap().len();
+        assert_eq!(len, 1000);
+    }
+
+    let archive = hpk::HpkArchive::open_volumes(&file).unwrap();
+    assert_eq!(
+        archive.read_file("a.dat").unwrap(),
+        "0123456789".repeat(500).as_bytes()
+    );
+    assert_eq!(
+        archive.read_file("folder/b.xml").unwrap(),
+        "<b/>".repeat(100).as_bytes()
+    );
+
+    options.compress();
+    hpk::create(&options, &dir, &file).unwrap();
+    let archive = hpk::HpkArchive::open_volumes(&file).unwrap();
+    assert!(archive.is_compressed());
+    assert_eq!(
+        archive.read_file("folder/b.xml").unwrap(),
+        "<b/>".repeat(100).as_bytes()
+    );
+
+    let err = hpk::VolumeReader::open(root.path().join("missing.hpk")).err();
+    assert!(matches!(err, Some(hpk::HpkError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound));
+    assert!(hpk::VolumeWriter::create(&file, 0).is_err());
+}
+
+#[cfg(feature = "convert")]
+#[test]
+fn convert_zip_and_tar() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    let file = root.path().join("archive.hpk");
+
+    fs::create_dir_all(dir.join("folder/empty")).unwrap();
+    fs::write(dir.join("a.txt"), "first file").unwrap();
+    fs::write(dir.join("folder/b.xml"), "<b/>".repeat(1000)).unwrap();
+    let mtime = filetime::FileTime::from_unix_time(1_300_000_000, 0);
+    for name in &["a.txt", "folder/b.xml"] {
+        filetime::set_file_mtime(dir.join(name), mtime).unwrap();
+    }
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_short_filedates_format();
+    options.with_checksums();
+    hpk::create(&options, &dir, &file).unwrap();
+
+    for ext in &["zip", "tar"] {
+        let converted = root.path().join(format!("archive.{}", ext));
+        let back = root.path().join(format!("{}.hpk", ext));
+        hpk::convert(&options, &file, &converted).unwrap();
+        hpk::convert(&options, &converted, &back).unwrap();
+
+        let archive = hpk::HpkArchive::open(&back).unwrap();
+        assert_eq!(archive.read_file("a.txt").unwrap(), b"first file");
+        assert_eq!(
+            archive.read_file("folder/b.xml").unwrap(),
+            "<b/>".repeat(1000).as_bytes()
+        );
+        assert!(archive.entry("folder/empty").unwrap().is_dir());
+        let filedates = String::from_utf8(archive.read_file("_filedates").unwrap()).unwrap();
+        assert!(filedates.contains(&format!(
+            "a.txt={}",
+            (1_300_000_000 + 11_644_473_600i64) * 10_000_000 / 2000
+        )));
+        assert!(filedates.lines().all(|l| !l.starts_with("_checksums")));
+    }
+
+    // the names of `tar -C input -cf dotted.tar .` start with `./`
+    fn append<W: Write>(tar: &mut tar::Builder<W>, name: &str, data: &[u8]) {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(if name.ends_with('/') {
+            tar::EntryType::Directory
+        } else {
+            tar::EntryType::Regular
+        });
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append(&header, data).unwrap();
+    }
+    let tarball = |name: &str, entries: &[&str]| {
+        let path = root.path().join(name);
+        let mut tar = tar::Builder::new(fs::File::create(&path).unwrap());
+        for entry in entries {
+            append(&mut tar, entry, entry.as_bytes());
+        }
+        tar.into_inner().unwrap();
+        path
+    };
+    let dotted = tarball(
+        "dotted.tar",
+        &["./", "./a.txt", "./folder/", "./folder/b.xml"],
+    );
+    let back = root.path().join("dotted.hpk");
+    hpk::convert(&options, &dotted, &back).unwrap();
+    let archive = hpk::HpkArchive::open(&back).unwrap();
+    assert_eq!(archive.read_file("a.txt").unwrap(), b"./a.txt");
+    assert_eq!(
+        archive.read_file("folder/b.xml").unwrap(),
+        b"./folder/b.xml"
+    );
+    assert!(archive.entry("folder").unwrap().is_dir());
+
+    for entry in &["../evil.txt", "/etc/evil.txt", "./folder/../../evil.txt"] {
+        let unsafe_tar = tarball("unsafe.tar", &[entry]);
+        let err = hpk::convert(&options, &unsafe_tar, &back).unwrap_err();
+        assert!(
+            matches!(err, hpk::HpkError::InvalidDirEntryName),
+            "{}",
+            entry
+        );
+    }
+
+    let err = hpk::convert(&options, &file, root.path().join("archive.rar")).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidInput));
+}
+
+#[cfg(feature = "pack")]
+#[test]
+fn pack_manifest() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path();
+    fs::create_dir_all(dir.join("build/lua")).unwrap();
+    fs::create_dir_all(dir.join("assets/maps/sub")).unwrap();
+    fs::write(dir.join("build/lua/main.lua"), "return 1").unwrap();
+    fs::write(dir.join("assets/maps/a.bin"), "map ".repeat(100)).unwrap();
+    fs::write(dir.join("assets/maps/sub/b.bin"), "sub ".repeat(100)).unwrap();
+    fs::write(dir.join("intro.lst"), "intro ".repeat(100)).unwrap();
+    fs::write(
+        dir.join("manifest.toml"),
+        r#"
+        [[entries]]
+        path = "video/intro.lst"
+        source = "intro.lst"
+        compression = "none"
+
+        [[entries]]
+        path = "maps"
+        source = "assets/maps"
+        compression = "lz4"
+
+        [[entries]]
+        path = "scripts/main.lua"
+        source = "build/lua/main.lua"
+        "#,
+    )
+    .unwrap();
+
+    let file = dir.join("packed.hpk");
+    let manifest = hpk::PackManifest::read(dir.join("manifest.toml")).unwrap();
+    hpk::pack(&hpk::CreateOptions::new(), &manifest, &file).unwrap();
+
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    let paths: Vec<_> = archive.entries().iter().map(|e| e.path()).collect();
+    let expected = [
+        "",
+        "video",
+        "video/intro.lst",
+        "maps",
+        "maps/a.bin",
+        "maps/sub",
+        "maps/sub/b.bin",
+        "scripts",
+        "scripts/main.lua",
+    ];
+    assert_eq!(paths, expected.iter().map(Path::new).collect::<Vec<_>>());
+
+    let codec = |path: &str| {
+        let entry = archive.entry(path).unwrap();
+        archive.metadata(entry).unwrap().compression
+    };
+    assert_eq!(codec("video/intro.lst"), hpk::Compression::None);
+    assert_eq!(codec("maps/sub/b.bin"), hpk::Compression::Lz4);
+    assert_eq!(codec("scripts/main.lua"), hpk::Compression::Zlib);
+    assert_eq!(
+        archive.read_file("maps/sub/b.bin").unwrap(),
+        "sub ".repeat(100).as_bytes()
+    );
+
+    let json = r#"{"entries": [{"path": "a.lua", "source": "build/lua/main.lua", "compression": "ZSTD"}]}"#;
+    let manifest = hpk::PackManifest::from_json(json).unwrap();
+    assert_eq!(
+        manifest.entries[0].compression,
+        Some(hpk::Compression::Zstd)
+    );
+
+    let err = hpk::PackManifest::from_toml(
+        "[[entries]]\npath = \"a\"\nsource = \"b\"\ncompression = \"rar\"",
+    )
+    .unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData));
+
+    let mut manifest = hpk::PackManifest::read(dir.join("manifest.toml")).unwrap();
+    let twice = manifest.entries[2].clone();
+    manifest.entries.push(twice);
+    let mut out = io::Cursor::new(vec![]);
+    let err = hpk::write_pack(&hpk::CreateOptions::new(), &manifest, &mut out).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::DuplicateEntry(_)));
+}
+
+#[cfg(feature = "sha256")]
+#[test]
+fn sha256_manifest() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("folder/a.lst"), "a ".repeat(100)).unwrap();
+    fs::write(dir.join("b.txt"), "abc").unwrap();
+
+    let mut options = hpk::CreateOptions::new();
+    options.with_sha256_manifest();
+    let file = root.path().join("mod.hpk");
+    hpk::create(&options, &dir, &file).unwrap();
+
+    let manifest = root.path().join("mod.hpk.sha256");
+    assert_eq!(hpk::sha256_manifest_path(&file), manifest);
+    let content = fs::read_to_string(&manifest).unwrap();
+    let lines: Vec<_> = content.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with("  mod.hpk"));
+    assert!(
+        lines.contains(&"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  b.txt")
+    );
+    assert!(hpk::verify_sha256_manifest(&file, &manifest)
+        .unwrap()
+        .is_empty());
+
+    let content = content.replace("  b.txt", "  c.txt");
+    fs::write(&manifest, content).unwrap();
+    let corrupt = hpk::verify_sha256_manifest(&file, &manifest).unwrap();
+    let corrupt: Vec<_> = corrupt
+        .iter()
+        .map(|c| (c.path.to_str().unwrap(), c.corruption.to_string()))
+        .collect();
+    assert_eq!(
+        corrupt,
+        [
+            ("c.txt", "missing in the archive".to_string()),
+            ("b.txt", "not listed in the manifest".to_string()),
+        ]
+    );
+
+    hpk::write_sha256_manifest(&file).unwrap();
+    let replacement = root.path().join("replacement");
+    fs::write(&replacement, "xyz").unwrap();
+    hpk::update(&hpk::CreateOptions::new(), &file, [("b.txt", &replacement)]).unwrap();
+    let corrupt = hpk::verify_sha256_manifest(&file, &manifest).unwrap();
+    let paths: Vec<_> = corrupt.iter().map(|c| c.path.to_str().unwrap()).collect();
+    assert_eq!(paths, ["mod.hpk", "b.txt"]);
+    assert!(matches!(
+        corrupt[1].corruption,
+        hpk::Corruption::Sha256Mismatch
+    ));
+
+    fs::write(&manifest, "not a digest  mod.hpk\n").unwrap();
+    let err = hpk::verify_sha256_manifest(&file, &manifest).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData));
+}
+
+#[cfg(feature = "sign")]
+#[test]
+fn signed_archives() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let dir = root.path().join("input");
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    fs::write(dir.join("folder/a.lua"), "return 1").unwrap();
+    fs::write(dir.join("b.lst"), "b ".repeat(100)).unwrap();
+
+    let secret_key = [7; 32];
+    let public_key = hpk::signing_public_key(&secret_key);
+    let other_key = hpk::signing_public_key(&[8; 32]);
+
+    for variant in 0..3 {
+        let mut options = hpk::CreateOptions::new();
+        options.sign_with(secret_key);
+        options.with_checksums();
+        match variant {
+            1 => options.with_wide_offsets(),
+            2 => {
+                options.compress();
+                options.with_fragments_per_file(2);
+            }
+            _ => {}
+        }
+        let file = root.path().join(format!("signed{}.hpk", variant));
+        hpk::create(&options, &dir, &file).unwrap();
+
+        let archive = hpk::HpkArchive::open(&file).unwrap();
+        archive.verify_signature(&public_key).unwrap();
+        assert!(archive.entry("_signature").is_some());
+        assert!(matches!(
+            archive.verify_signature(&other_key),
+            Err(hpk::HpkError::InvalidSignature)
+        ));
+    }
+
+    let file = root.path().join("signed0.hpk");
+    let mut data = fs::read(&file).unwrap();
+    let pos = data.windows(8).position(|w| w == b"return 1").unwrap();
+    data[pos] = b'R';
+    let archive = hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap();
+    assert!(matches!(
+        archive.verify_signature(&public_key),
+        Err(hpk::HpkError::InvalidSignature)
+    ));
+
+    // an update drops the signature of the old content
+    let options = hpk::CreateOptions::new();
+    hpk::update(&options, &file, [("b.lst", dir.join("folder/a.lua"))]).unwrap();
+    let archive = hpk::HpkArchive::open(&file).unwrap();
+    assert!(archive.entry("_signature").is_none());
+    assert!(matches!(
+        archive.verify_signature(&public_key),
+        Err(hpk::HpkError::Unsigned)
+    ));
+
+    let mut options = hpk::CreateOptions::new();
+    options.sign_with(secret_key);
+    let err = hpk::update(&options, &file, [("b.lst", dir.join("b.lst"))]).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidInput));
+}
+
+#[cfg(feature = "testkit")]
+#[test]
+fn testkit_round_trips() {
+    use hpk::testkit::{verify_round_trip, TreeGenerator};
+
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+
+    let configs: Vec<fn(&mut hpk::CreateOptions)> = vec![
+        |_| {},
+        |o| o.compress(),
+        |o| o.use_lz4(),
+        |o| o.with_chunk_size(4096),
+        |o| o.with_wide_offsets(),
+        |o| o.with_big_endian(),
+        |o| o.with_fragments_per_file(3),
+        |o| o.deduplicate(),
+    ];
+    let generate = |seed, dir: &Path| {
+        let mut generator = TreeGenerator::new(seed);
+        generator.max_file_size(16 * 1024);
+        generator.generate(dir).unwrap()
+    };
+    for seed in 0..8 {
+        let dir = root.path().join(format!("tree{}", seed));
+        let paths = generate(seed, &dir);
+        let again = root.path().join("again");
+        assert_eq!(generate(seed, &again), paths);
+        fs::remove_dir_all(again).unwrap();
+
+        for configure in &configs {
+            let mut options = hpk::CreateOptions::new();
+            configure(&mut options);
+            verify_round_trip(&options, &dir).unwrap();
+        }
+    }
+
+    let dir = root.path().join("tree0");
+    fs::write(dir.join("script.lua"), include_bytes!("valid32.lua")).unwrap();
+    let mut options = hpk::CreateOptions::new();
+    options.cripple_lua_files();
+    let err = verify_round_trip(&options, &dir).unwrap_err();
+    assert!(matches!(err, hpk::HpkError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn concurrent_entry_readers() {
+    let root = tempfile::Builder::new().prefix("hpk-tests").tempdir();
+    let root = root.expect("Should have created a temp director");
+    let file = root.path().join("archive.hpk");
+
+    let content = |i: usize| format!("file {} ", i).repeat(2000 + i * 10);
+    let mut options = hpk::CreateOptions::new();
+    options.with_chunk_size(4096);
+    let mut w = hpk::HpkWriter::new(&options, fs::File::create(&file).unwrap()).unwrap();
+    for i in 0..16 {
+        // stored and compressed files
+        let name = format!("file_{}.{}", i, if i % 2 == 0 { "lst" } else { "txt" });
+        w.add_file(name, content(i).as_bytes()).unwrap();
+    }
+    w.finish().unwrap();
+
+    let data = fs::read(&file).unwrap();
+    let archives = [
+        hpk::HpkArchive::open(&file).unwrap(),
+        hpk::HpkArchive::from_reader(io::Cursor::new(data)).unwrap(),
+    ];
+    for archive in &archives {
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                s.spawn(move || {
+                    for round in 0..8 {
+                        for i in (0..16).map(|i| (i + t + round) % 16) {
+                            let name =
+                                format!("file_{}.{}", i, if i % 2 == 0 { "lst" } else { "txt" });
+                            let mut r = archive.open_entry(archive.entry(&name).unwrap()).unwrap();
+                            let mut buf = String::new();
+                            r.read_to_string(&mut buf).unwrap();
+                            assert_eq!(buf, content(i), "{}", name);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}